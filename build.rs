@@ -0,0 +1,13 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    // The gRPC service is generated from its protobuf definition, compiled without protoc
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/spect.proto");
+        let descriptors = protox::compile(["proto/spect.proto"], ["proto"])
+            .expect("proto/spect.proto is a valid protobuf definition");
+        tonic_prost_build::configure()
+            .compile_fds(descriptors)
+            .expect("The gRPC service can be generated");
+    }
+}
@@ -0,0 +1,216 @@
+//! Checks on monitored spectra which raise alerts for shift crews, e.g. when a detector stops
+//! counting or its gain drifts. Checks are evaluated by ResourceManager::run_checks and their
+//! alerts delivered to observers of EventKind::Alert.
+use super::analysis::{self, ComparisonTest};
+use super::histogram::Histogram;
+use super::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// What a check tests. Regions of interest are [low, high) on the x axis of the histogram.
+#[derive(Debug, Clone)]
+pub enum CheckKind {
+    /// The counts per second in a region fall below min_rate
+    LowRate { low: f32, high: f32, min_rate: f64 },
+    /// The centroid of the counts in a region is further than tolerance from expected
+    CentroidDrift {
+        low: f32,
+        high: f32,
+        expected: f64,
+        tolerance: f64,
+    },
+    /// The spectrum is unlikely to share the shape of a reference, by a p-value below
+    /// min_p_value
+    ShapeChange {
+        reference: Box<Histogram>,
+        test: ComparisonTest,
+        min_p_value: f64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckSpec {
+    pub id: Uuid,
+    pub name: String,
+    pub histogram_id: Uuid,
+    pub kind: CheckKind,
+    /// How often the check runs
+    pub interval: Duration,
+}
+
+/// A check starting or stopping to fail
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub check_id: Uuid,
+    pub check: String,
+    pub histogram_id: Uuid,
+    /// True when the check starts failing and false when it passes again
+    pub active: bool,
+    /// The measured rate, centroid, or p-value
+    pub value: f64,
+    pub message: String,
+}
+
+fn region(gram: &Histogram, low: f32, high: f32) -> impl Iterator<Item = (f32, f64)> + '_ {
+    gram.iter_bins()
+        .filter(move |(x, _, _)| *x >= low && *x < high)
+        .map(|(x, _, count)| (x, count))
+}
+
+/// A check with the state carried between evaluations
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub spec: CheckSpec,
+    last_run: Option<Instant>,
+    // The region's integral at the last run, for rates
+    last_integral: f64,
+    failing: bool,
+}
+
+impl Check {
+    pub fn new(spec: CheckSpec) -> Self {
+        Self {
+            spec,
+            last_run: None,
+            last_integral: 0.0,
+            failing: false,
+        }
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.last_run
+            .is_none_or(|last| now.duration_since(last) >= self.spec.interval)
+    }
+
+    pub fn is_failing(&self) -> bool {
+        self.failing
+    }
+
+    /// Run the check, returning an alert if it started or stopped failing
+    pub fn evaluate(&mut self, gram: &Histogram, now: Instant) -> Option<Alert> {
+        let last_run = self.last_run.replace(now);
+        let (value, failing, message) = match &self.spec.kind {
+            CheckKind::LowRate {
+                low,
+                high,
+                min_rate,
+            } => {
+                let integral: f64 = region(gram, *low, *high).map(|(_, count)| count).sum();
+                let previous = std::mem::replace(&mut self.last_integral, integral);
+                // The first run, or a clear of the histogram, only sets the baseline
+                let elapsed = now.saturating_duration_since(last_run?).as_secs_f64();
+                if integral < previous || elapsed <= 0.0 {
+                    return None;
+                }
+                let rate = (integral - previous) / elapsed;
+                let message = format!("rate {rate:.3}/s is below {min_rate}/s");
+                (rate, rate < *min_rate, message)
+            }
+            CheckKind::CentroidDrift {
+                low,
+                high,
+                expected,
+                tolerance,
+            } => {
+                let (sum, weighted) = region(gram, *low, *high)
+                    .fold((0.0, 0.0), |(sum, weighted), (x, count)| {
+                        (sum + count, weighted + count * x as f64)
+                    });
+                if sum <= 0.0 {
+                    return None;
+                }
+                let centroid = weighted / sum;
+                let message =
+                    format!("centroid {centroid:.3} is not within {tolerance} of {expected}");
+                (centroid, (centroid - expected).abs() > *tolerance, message)
+            }
+            CheckKind::ShapeChange {
+                reference,
+                test,
+                min_p_value,
+            } => {
+                let p_value = analysis::compare(gram, reference, *test).ok()?.p_value;
+                let message =
+                    format!("p-value {p_value:.3e} against the reference is below {min_p_value}");
+                (p_value, p_value < *min_p_value, message)
+            }
+        };
+        if failing == self.failing {
+            return None;
+        }
+        self.failing = failing;
+        Some(Alert {
+            check_id: self.spec.id,
+            check: self.spec.name.clone(),
+            histogram_id: self.spec.histogram_id,
+            active: failing,
+            value,
+            message: match failing {
+                true => message,
+                false => String::from("check passes again"),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_checks() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "ge_e",
+                AxisSpec::new("ge_e", "Energy", 100, 0.0, 100.0).unwrap(),
+                None,
+            )
+        };
+        let mut gram = Histogram::new(spec);
+        let check_spec = |kind| CheckSpec {
+            id: Uuid::new_v4(),
+            name: String::from("check"),
+            histogram_id: gram.spec.id,
+            kind,
+            interval: Duration::from_secs(10),
+        };
+        let mut rate = Check::new(check_spec(CheckKind::LowRate {
+            low: 40.0,
+            high: 60.0,
+            min_rate: 1.0,
+        }));
+        let mut drift = Check::new(check_spec(CheckKind::CentroidDrift {
+            low: 40.0,
+            high: 60.0,
+            expected: 50.0,
+            tolerance: 1.0,
+        }));
+
+        let start = Instant::now();
+        for _ in 0..100 {
+            gram.fill(50.5, None).unwrap();
+        }
+        assert!(rate.evaluate(&gram, start).is_none());
+        assert!(drift.evaluate(&gram, start).is_none());
+        assert!(!rate.is_due(start + Duration::from_secs(5)));
+
+        // 5 counts in 10 s is below the minimum rate, and the peak moved to 52.5
+        for _ in 0..500 {
+            gram.fill(52.5, None).unwrap();
+        }
+        let later = start + Duration::from_secs(10);
+        assert!(rate.is_due(later));
+        assert!(rate.evaluate(&gram, later).is_none());
+        let alert = drift.evaluate(&gram, later).unwrap();
+        assert!(alert.active);
+        assert!((alert.value - 52.17).abs() < 0.01);
+        assert!(drift.evaluate(&gram, later).is_none());
+
+        let alert = rate
+            .evaluate(&gram, later + Duration::from_secs(10))
+            .unwrap();
+        assert!(alert.active);
+        assert_eq!(alert.value, 0.0);
+    }
+}
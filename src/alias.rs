@@ -0,0 +1,139 @@
+//! Renaming of raw variables to canonical names as events arrive, so that one histogram
+//! configuration serves DAQ setups which name their channels differently
+use super::data_blob::DataBlob;
+
+/// A raw variable renamed to a canonical name, with its values mapped to scale * raw + offset.
+/// Flags are renamed as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alias {
+    pub raw: String,
+    pub canonical: String,
+    pub scale: f32,
+    pub offset: f32,
+}
+
+impl Alias {
+    pub fn new(raw: &str, canonical: &str) -> Self {
+        Self {
+            raw: raw.to_string(),
+            canonical: canonical.to_string(),
+            scale: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    pub fn with_scale(mut self, scale: f32, offset: f32) -> Self {
+        self.scale = scale;
+        self.offset = offset;
+        self
+    }
+}
+
+// A raw variable taken out of an event to be put back under its canonical name
+enum Taken {
+    Value(f32),
+    Flag(u64),
+    Array(Vec<f32>),
+}
+
+/// The aliases applied to every event, at most one per raw variable
+#[derive(Debug, Clone, Default)]
+pub struct AliasTable {
+    aliases: Vec<Alias>,
+}
+
+impl AliasTable {
+    /// Add an alias, replacing any of the same raw variable
+    pub fn set(&mut self, alias: Alias) {
+        match self
+            .aliases
+            .iter_mut()
+            .find(|existing| existing.raw == alias.raw)
+        {
+            Some(existing) => *existing = alias,
+            None => self.aliases.push(alias),
+        }
+    }
+
+    pub fn get(&self, raw: &str) -> Option<&Alias> {
+        self.aliases.iter().find(|alias| alias.raw == raw)
+    }
+
+    /// Remove the alias of a raw variable, returning false if there was none
+    pub fn remove(&mut self, raw: &str) -> bool {
+        let n_aliases = self.aliases.len();
+        self.aliases.retain(|alias| alias.raw != raw);
+        n_aliases != self.aliases.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Alias> {
+        self.aliases.iter()
+    }
+
+    /// Rename every raw variable in the event. Every raw variable is taken out before any is put
+    /// back, so aliases may swap names.
+    pub fn apply(&self, data: &mut DataBlob) {
+        let taken: Vec<(&Alias, Taken)> = self
+            .aliases
+            .iter()
+            .filter_map(|alias| {
+                let taken = if let Some(value) = data.remove(&alias.raw) {
+                    Taken::Value(value)
+                } else if let Some(values) = data.remove_array(&alias.raw) {
+                    Taken::Array(values)
+                } else {
+                    Taken::Flag(data.remove_flag(&alias.raw)?)
+                };
+                Some((alias, taken))
+            })
+            .collect();
+        for (alias, taken) in taken {
+            let map = |raw: f32| alias.scale * raw + alias.offset;
+            match taken {
+                Taken::Value(value) => data.insert(&alias.canonical, map(value)),
+                Taken::Flag(bits) => data.insert_flag(&alias.canonical, bits),
+                Taken::Array(values) => {
+                    data.insert_array(&alias.canonical, values.into_iter().map(map).collect())
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        let mut table = AliasTable::default();
+        table.set(Alias::new("adc_3", "si_e").with_scale(2.0, 1.0));
+        table.set(Alias::new("left", "right"));
+        table.set(Alias::new("right", "left"));
+        table.set(Alias::new("trig", "trigger"));
+        table.set(Alias::new("hits", "gamma_e").with_scale(0.5, 0.0));
+
+        let mut data = DataBlob::new();
+        data.insert("adc_3", 10.0);
+        data.insert("left", 1.0);
+        data.insert("right", 2.0);
+        data.insert_flag("trig", 0b101);
+        data.insert_array("hits", vec![2.0, 4.0]);
+        table.apply(&mut data);
+        assert_eq!(data.find("adc_3"), None);
+        assert_eq!(data.find("si_e"), Some(&21.0));
+        assert_eq!(data.find("left"), Some(&2.0));
+        assert_eq!(data.find("right"), Some(&1.0));
+        assert_eq!(data.find_flag("trigger"), Some(0b101));
+        assert_eq!(data.find_array("gamma_e"), Some(&[1.0, 2.0][..]));
+        assert_eq!(data.len(), 3);
+
+        assert!(table.remove("trig"));
+        assert!(!table.remove("trig"));
+        assert_eq!(table.get("adc_3").unwrap().canonical, "si_e");
+    }
+}
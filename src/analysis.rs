@@ -0,0 +1,215 @@
+//! Statistical comparisons between spectra, e.g. to raise an alarm when a detector's response
+//! in one run differs from the previous run.
+use super::error::HistogramError;
+use super::histogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ComparisonTest {
+    /// Chi-square test of two unweighted histograms being drawn from the same distribution.
+    /// Works for 1D and 2D histograms.
+    ChiSquare,
+    /// Kolmogorov-Smirnov test on the cumulative distributions of two 1D histograms. Binning
+    /// makes the p-value conservative.
+    KolmogorovSmirnov,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    pub statistic: f64,
+    /// The probability of a statistic at least this large if the histograms share a
+    /// distribution. Small values mean the spectra differ.
+    pub p_value: f64,
+    /// Degrees of freedom, for chi-square tests
+    pub ndf: Option<usize>,
+}
+
+/// Compare two histograms with compatible axes. Only the contents of the bins are compared, not
+/// the under- and overflows.
+pub fn compare(
+    a: &Histogram,
+    b: &Histogram,
+    test: ComparisonTest,
+) -> Result<Comparison, HistogramError> {
+    if !a.is_compatible(b) {
+        return Err(HistogramError::IncompatibleAxes(
+            a.spec.name.clone(),
+            b.spec.name.clone(),
+        ));
+    }
+    let total_a: f64 = a.data.iter().sum();
+    let total_b: f64 = b.data.iter().sum();
+    if total_a <= 0.0 || total_b <= 0.0 {
+        return Err(HistogramError::InsufficientData(0));
+    }
+    match test {
+        ComparisonTest::ChiSquare => Ok(chi_square(&a.data, &b.data, total_a, total_b)),
+        ComparisonTest::KolmogorovSmirnov => {
+            if a.spec.y_axis.is_some() {
+                return Err(HistogramError::WrongDimensions);
+            }
+            Ok(kolmogorov_smirnov(&a.data, &b.data, total_a, total_b))
+        }
+    }
+}
+
+fn chi_square(a: &[f64], b: &[f64], total_a: f64, total_b: f64) -> Comparison {
+    let mut statistic = 0.0;
+    let mut n_bins = 0;
+    for (count_a, count_b) in a.iter().zip(b.iter()) {
+        let sum = count_a + count_b;
+        if sum > 0.0 {
+            let difference = total_b * count_a - total_a * count_b;
+            statistic += difference * difference / sum;
+            n_bins += 1;
+        }
+    }
+    statistic /= total_a * total_b;
+    let ndf = n_bins.max(2) - 1;
+    Comparison {
+        statistic,
+        p_value: upper_gamma(ndf as f64 / 2.0, statistic / 2.0),
+        ndf: Some(ndf),
+    }
+}
+
+fn kolmogorov_smirnov(a: &[f64], b: &[f64], total_a: f64, total_b: f64) -> Comparison {
+    let (mut cumulative_a, mut cumulative_b) = (0.0, 0.0);
+    let mut statistic: f64 = 0.0;
+    for (count_a, count_b) in a.iter().zip(b.iter()) {
+        cumulative_a += count_a / total_a;
+        cumulative_b += count_b / total_b;
+        statistic = statistic.max((cumulative_a - cumulative_b).abs());
+    }
+    let n_effective = (total_a * total_b / (total_a + total_b)).sqrt();
+    let lambda = (n_effective + 0.12 + 0.11 / n_effective) * statistic;
+    Comparison {
+        statistic,
+        p_value: kolmogorov_probability(lambda),
+        ndf: None,
+    }
+}
+
+// The probability of the Kolmogorov distribution exceeding lambda
+fn kolmogorov_probability(lambda: f64) -> f64 {
+    if lambda < 0.2 {
+        return 1.0;
+    }
+    let mut sum = 0.0;
+    let mut sign = 1.0;
+    for k in 1..=100 {
+        let term = (-2.0 * (k * k) as f64 * lambda * lambda).exp();
+        sum += sign * term;
+        if term < 1e-12 {
+            break;
+        }
+        sign = -sign;
+    }
+    (2.0 * sum).clamp(0.0, 1.0)
+}
+
+// The natural log of the gamma function, by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let mut series = 1.000_000_000_190_015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+// The regularized upper incomplete gamma function Q(s, x), which is the chi-square p-value
+// for s = ndf / 2 and x = chi2 / 2
+fn upper_gamma(s: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefactor = (-x + s * x.ln() - ln_gamma(s)).exp();
+    if x < s + 1.0 {
+        // Series for the lower function, which converges quickly here
+        let (mut term, mut sum, mut n) = (1.0 / s, 1.0 / s, s);
+        for _ in 0..500 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-14 {
+                break;
+            }
+        }
+        (1.0 - sum * prefactor).clamp(0.0, 1.0)
+    } else {
+        // Continued fraction for the upper function, by the modified Lentz method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - s;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut fraction = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - s);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < tiny { tiny } else { d };
+            c = b + an / c;
+            c = if c.abs() < tiny { tiny } else { c };
+            d = 1.0 / d;
+            let delta = d * c;
+            fraction *= delta;
+            if (delta - 1.0).abs() < 1e-14 {
+                break;
+            }
+        }
+        (fraction * prefactor).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+
+    fn histogram(data: &[f64]) -> Histogram {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "e",
+                AxisSpec::new("e", "e", data.len(), 0.0, data.len() as f32).unwrap(),
+                None,
+            )
+        };
+        let mut gram = Histogram::new(spec);
+        gram.data.copy_from_slice(data);
+        gram
+    }
+
+    #[test]
+    fn test_compare() {
+        // Chi-square p-values match tables: Q(1, 3.841) = 0.05 and Q(10, 18.307) = 0.05
+        assert!((upper_gamma(0.5, 3.841 / 2.0) - 0.05).abs() < 1e-4);
+        assert!((upper_gamma(5.0, 18.307 / 2.0) - 0.05).abs() < 1e-4);
+        assert!((kolmogorov_probability(1.358) - 0.05).abs() < 1e-3);
+
+        let a = histogram(&[100.0, 200.0, 300.0, 200.0, 100.0]);
+        let same = histogram(&[200.0, 400.0, 600.0, 400.0, 200.0]);
+        let shifted = histogram(&[0.0, 100.0, 200.0, 300.0, 200.0]);
+        for test in [ComparisonTest::ChiSquare, ComparisonTest::KolmogorovSmirnov] {
+            let comparison = compare(&a, &same, test).unwrap();
+            assert!(comparison.statistic.abs() < 1e-9);
+            assert!(comparison.p_value > 0.99);
+            assert!(compare(&a, &shifted, test).unwrap().p_value < 1e-6);
+        }
+        assert_eq!(
+            compare(&a, &same, ComparisonTest::ChiSquare).unwrap().ndf,
+            Some(4)
+        );
+        assert!(compare(&a, &histogram(&[1.0; 4]), ComparisonTest::ChiSquare).is_err());
+        assert!(compare(&a, &histogram(&[0.0; 5]), ComparisonTest::ChiSquare).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use super::error::HistogramError;
+use super::histogram::{AxisSpec, FillMode, HistSpec, Histogram, OutOfRangePolicy, ValuePolicy};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A histogram which many threads can fill at once without a lock, e.g. one decoder thread per
+/// digitizer filling a shared spectrum. Bins hold the bits of an f64 updated by compare and swap.
+/// Share it between threads with an Arc.
+///
+/// Only the binning of the spec is used: auto ranging, rolling windows, error tracking, and fill
+/// modes other than Value are not supported, and out of range values must be either clamped or
+/// ignored. Values which are not finite follow the nan policy.
+#[derive(Debug)]
+pub struct AtomicHistogram {
+    spec: HistSpec,
+    bins: Vec<AtomicU64>,
+    filled: AtomicU64,
+    out_of_range: AtomicU64,
+    non_finite: AtomicU64,
+}
+
+impl AtomicHistogram {
+    pub fn new(spec: HistSpec) -> Result<Self, HistogramError> {
+        let unsupported = if spec.auto_range.is_some() {
+            Some("auto ranging")
+        } else if spec.window.is_some() {
+            Some("rolling windows")
+        } else if spec.track_errors {
+            Some("error tracking")
+        } else if spec.fill_mode != FillMode::Value {
+            Some("fill modes other than Value")
+        } else if !matches!(
+            spec.out_of_range,
+            OutOfRangePolicy::Clamp | OutOfRangePolicy::Ignore
+        ) {
+            Some("out of range policies other than Clamp and Ignore")
+        } else {
+            None
+        };
+        if let Some(feature) = unsupported {
+            return Err(HistogramError::Unsupported(
+                spec.name.clone(),
+                feature.to_string(),
+            ));
+        }
+        let bins = (0..spec.get_n_bins())
+            .map(|_| AtomicU64::new(0.0_f64.to_bits()))
+            .collect();
+        Ok(Self {
+            spec,
+            bins,
+            filled: AtomicU64::new(0),
+            out_of_range: AtomicU64::new(0),
+            non_finite: AtomicU64::new(0),
+        })
+    }
+
+    pub fn get_spec(&self) -> &HistSpec {
+        &self.spec
+    }
+
+    // Find the bin of a value on an axis, clamping if the policy asks for it
+    fn place(&self, axis: &AxisSpec, value: f32) -> Option<usize> {
+        match axis.get_bin(value) {
+            Ok(bin) => Some(bin),
+            Err(_) if self.spec.out_of_range != OutOfRangePolicy::Clamp => None,
+            Err(_) if axis.wrap(value) < axis.minimum => Some(0),
+            Err(_) => Some(axis.bins - 1),
+        }
+    }
+
+    pub fn fill(
+        &self,
+        x_value: f32,
+        y_value: Option<f32>,
+    ) -> Result<Option<usize>, HistogramError> {
+        self.fill_weighted(x_value, y_value, 1.0)
+    }
+
+    /// Fill the histogram with a weight, returning the bin which was incremented. Returns None if
+    /// the value was out of range.
+    pub fn fill_weighted(
+        &self,
+        x_value: f32,
+        y_value: Option<f32>,
+        weight: f64,
+    ) -> Result<Option<usize>, HistogramError> {
+        if let Some(value) = std::iter::once(x_value)
+            .chain(y_value)
+            .find(|value| !value.is_finite())
+        {
+            if self.spec.nan_policy != ValuePolicy::Skip {
+                self.non_finite.fetch_add(1, Ordering::Relaxed);
+            }
+            return match self.spec.nan_policy {
+                ValuePolicy::Error => Err(HistogramError::NonFinite(value)),
+                _ => Ok(None),
+            };
+        }
+        let bin = match (&self.spec.y_axis, y_value) {
+            (None, None) => self.place(&self.spec.x_axis, x_value),
+            (Some(y_axis), Some(y_value)) => self
+                .place(&self.spec.x_axis, x_value)
+                .zip(self.place(y_axis, y_value))
+                .map(|(x_bin, y_bin)| {
+                    self.spec
+                        .layout
+                        .index(x_bin, y_bin, self.spec.x_axis.bins, y_axis.bins)
+                }),
+            _ => return Err(HistogramError::WrongDimensions),
+        };
+        let Some(bin) = bin else {
+            self.out_of_range.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
+        };
+        // The closure always returns Some, so the update cannot fail
+        let _ = self.bins[bin].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + weight).to_bits())
+        });
+        self.filled.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(bin))
+    }
+
+    pub fn get_n_filled(&self) -> u64 {
+        self.filled.load(Ordering::Relaxed)
+    }
+
+    pub fn get_n_out_of_range(&self) -> u64 {
+        self.out_of_range.load(Ordering::Relaxed)
+    }
+
+    pub fn get_n_non_finite(&self) -> u64 {
+        self.non_finite.load(Ordering::Relaxed)
+    }
+
+    /// Read the contents while other threads keep filling. Every bin is read exactly once, but
+    /// fills landing during the read may be seen in some bins and not others, which is fine for
+    /// display.
+    pub fn snapshot_data(&self) -> Vec<f64> {
+        self.bins
+            .iter()
+            .map(|bin| f64::from_bits(bin.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Copy the contents into an ordinary histogram, e.g. to merge into a ResourceManager
+    pub fn snapshot(&self) -> Histogram {
+        let mut gram = Histogram::new(self.spec.clone());
+        for (bin, content) in self.snapshot_data().into_iter().enumerate() {
+            if content != 0.0 {
+                let (x_bin, y_bin) = gram.bin_coordinates(bin);
+                // The bin comes from the same spec, so it always exists
+                let _ = gram.set_bin_content(x_bin, y_bin, content);
+            }
+        }
+        gram.stats.filled = self.get_n_filled();
+        gram.stats.out_of_range = self.get_n_out_of_range();
+        gram.stats.non_finite = self.get_n_non_finite();
+        gram
+    }
+
+    /// Zero the contents. Fills racing with the reset may survive it.
+    pub fn reset(&self) {
+        for bin in self.bins.iter() {
+            bin.store(0.0_f64.to_bits(), Ordering::Relaxed);
+        }
+        self.filled.store(0, Ordering::Relaxed);
+        self.out_of_range.store(0, Ordering::Relaxed);
+        self.non_finite.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_concurrent_fills() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "shared",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let gram = Arc::new(AtomicHistogram::new(spec.clone()).unwrap());
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let gram = gram.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        gram.fill((i % 10) as f32 + 0.5, None).unwrap();
+                    }
+                    gram.fill(20.0, None).unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(gram.snapshot_data(), vec![400.0; 10]);
+        assert_eq!(gram.get_n_filled(), 4000);
+        assert_eq!(gram.get_n_out_of_range(), 4);
+        let snapshot = gram.snapshot();
+        assert_eq!(snapshot.data, vec![400.0; 10]);
+        assert!(gram.fill(1.0, Some(1.0)).is_err());
+        assert_eq!(gram.fill(f32::NAN, None).unwrap(), None);
+        assert_eq!(gram.get_n_non_finite(), 1);
+
+        gram.reset();
+        assert_eq!(gram.snapshot_data(), vec![0.0; 10]);
+
+        let mut tracked = spec.clone();
+        tracked.track_errors = true;
+        assert!(AtomicHistogram::new(tracked).is_err());
+        for fill_mode in [FillMode::BitMask, FillMode::Symmetric, FillMode::Summary] {
+            let mut unsupported = spec.clone();
+            unsupported.fill_mode = fill_mode;
+            assert!(AtomicHistogram::new(unsupported).is_err());
+        }
+        for out_of_range in [OutOfRangePolicy::Error, OutOfRangePolicy::Overflow] {
+            let mut unsupported = spec.clone();
+            unsupported.out_of_range = out_of_range;
+            assert!(AtomicHistogram::new(unsupported).is_err());
+        }
+        let mut clamped = spec;
+        clamped.out_of_range = OutOfRangePolicy::Clamp;
+        assert!(AtomicHistogram::new(clamped).is_ok());
+    }
+}
@@ -0,0 +1,105 @@
+//! Limits for routine gates worked out from the histogram being gated, such as a window around a
+//! peak or the outline holding most of a particle group
+use super::error::HistogramError;
+use super::histogram::Histogram;
+
+/// Get the window centroid ± k * sigma of the counts in a 1D histogram, using only the bins whose
+/// centres are inside region if given. The window is at least one bin wide.
+pub fn sigma_window(
+    gram: &Histogram,
+    k: f32,
+    region: Option<(f32, f32)>,
+) -> Result<(f32, f32), HistogramError> {
+    if gram.spec.y_axis.is_some() {
+        return Err(HistogramError::WrongDimensions);
+    }
+    let axis = &gram.spec.x_axis;
+    let (mut sum, mut weighted, mut weighted2) = (0.0, 0.0, 0.0);
+    for x_bin in 0..axis.bins {
+        let centre = axis.get_bin_center(x_bin);
+        let count = gram.data[gram.bin_index(x_bin, 0)];
+        if count <= 0.0 || region.is_some_and(|(low, high)| centre < low || centre >= high) {
+            continue;
+        }
+        let centre = centre as f64;
+        sum += count;
+        weighted += count * centre;
+        weighted2 += count * centre * centre;
+    }
+    if sum <= 0.0 {
+        return Err(HistogramError::InsufficientData(0));
+    }
+    let centroid = weighted / sum;
+    let sigma = (weighted2 / sum - centroid * centroid).max(0.0).sqrt() as f32;
+    let half_width = (k * sigma).max(0.5 * axis.get_bin_width());
+    Ok((centroid as f32 - half_width, centroid as f32 + half_width))
+}
+
+/// Get a level above which the bins hold at least fraction of the counts of a histogram, so that
+/// its contours outline the densest region with that share of the counts. The level is halfway
+/// between the emptiest bin inside and the fullest bin outside, so that contours pass between
+/// them. The fraction is clamped to at most 1.
+pub fn containment_level(gram: &Histogram, fraction: f64) -> Result<f64, HistogramError> {
+    let mut counts: Vec<f64> = gram
+        .data
+        .iter()
+        .copied()
+        .filter(|count| *count > 0.0)
+        .collect();
+    let total: f64 = counts.iter().sum();
+    if total <= 0.0 {
+        return Err(HistogramError::InsufficientData(0));
+    }
+    counts.sort_by(|a, b| b.total_cmp(a));
+    let target = fraction.min(1.0) * total;
+    let mut contained = 0.0;
+    let mut n_inside = counts.len();
+    for (idx, count) in counts.iter().enumerate() {
+        contained += count;
+        if contained >= target {
+            n_inside = idx + 1;
+            break;
+        }
+    }
+    let lowest_inside = counts[n_inside - 1];
+    let highest_outside = counts[n_inside..]
+        .iter()
+        .copied()
+        .find(|count| *count < lowest_inside)
+        .unwrap_or(0.0);
+    Ok(0.5 * (lowest_inside + highest_outside))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_auto_gates() {
+        let mut gram = Histogram::new(HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("e", "e", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        });
+        assert!(sigma_window(&gram, 2.0, None).is_err());
+        gram.fill_weighted(3.5, None, 1.0).unwrap();
+        gram.fill_weighted(5.5, None, 1.0).unwrap();
+        gram.fill_weighted(9.5, None, 4.0).unwrap();
+        // Centroid 4.5 and sigma 1 between 2 and 7
+        assert_eq!(
+            sigma_window(&gram, 2.0, Some((2.0, 7.0))).unwrap(),
+            (2.5, 6.5)
+        );
+        assert_eq!(
+            sigma_window(&gram, 2.0, Some((9.0, 10.0))).unwrap(),
+            (9.0, 10.0)
+        );
+
+        assert_eq!(containment_level(&gram, 0.5).unwrap(), 2.5);
+        assert_eq!(containment_level(&gram, 0.8).unwrap(), 0.5);
+    }
+}
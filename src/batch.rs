@@ -0,0 +1,97 @@
+use super::data_blob::DataBlob;
+use super::error::SourceError;
+use rustc_hash::FxHashMap;
+
+/// The values of one variable across a batch of events. Events where valid is false did not
+/// record the variable; without a mask every value is valid.
+#[derive(Debug, Clone, Copy)]
+pub struct Column<'a> {
+    pub values: &'a [f32],
+    pub valid: Option<&'a [bool]>,
+}
+
+impl Column<'_> {
+    pub fn get(&self, row: usize) -> Option<f32> {
+        let is_valid = self.valid.is_none_or(|valid| valid[row]);
+        is_valid.then(|| self.values[row])
+    }
+
+    pub fn is_valid(&self, row: usize) -> bool {
+        self.valid.is_none_or(|valid| valid[row])
+    }
+}
+
+/// A batch of events stored as one column per variable, as read from Parquet or Arrow files.
+/// Filling from a batch bins whole columns at once instead of building a DataBlob per event.
+#[derive(Debug, Clone, Default)]
+pub struct ColumnBatch<'a> {
+    n_rows: usize,
+    columns: FxHashMap<String, Column<'a>>,
+}
+
+impl<'a> ColumnBatch<'a> {
+    pub fn new(n_rows: usize) -> Self {
+        Self {
+            n_rows,
+            columns: FxHashMap::default(),
+        }
+    }
+
+    /// Add the column of a variable. The values and mask must have one entry per row.
+    pub fn add_column(
+        &mut self,
+        variable: &str,
+        values: &'a [f32],
+        valid: Option<&'a [bool]>,
+    ) -> Result<(), SourceError> {
+        let length = valid.map_or(values.len(), |valid| valid.len().min(values.len()));
+        if values.len() != self.n_rows || valid.is_some_and(|valid| valid.len() != self.n_rows) {
+            return Err(SourceError::ColumnLength(
+                variable.to_string(),
+                length,
+                self.n_rows,
+            ));
+        }
+        self.columns
+            .insert(variable.to_string(), Column { values, valid });
+        Ok(())
+    }
+
+    pub fn get_n_rows(&self) -> usize {
+        self.n_rows
+    }
+
+    pub fn get_column(&self, variable: &str) -> Option<&Column<'a>> {
+        self.columns.get(variable)
+    }
+
+    /// Build the event of one row, for stages which need whole events
+    pub fn get_row(&self, row: usize) -> DataBlob {
+        let mut data = DataBlob::default();
+        for (variable, column) in self.columns.iter() {
+            if let Some(value) = column.get(row) {
+                data.insert(variable, value);
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch() {
+        let energies = [1.0, 2.0, 3.0];
+        let valid = [true, false, true];
+        let mut batch = ColumnBatch::new(3);
+        batch.add_column("e", &energies, Some(&valid)).unwrap();
+        assert!(batch.add_column("t", &energies[..2], None).is_err());
+        assert!(batch.add_column("t", &energies, Some(&valid[..2])).is_err());
+
+        assert_eq!(batch.get_column("e").unwrap().get(1), None);
+        assert_eq!(batch.get_row(2).find("e"), Some(&3.0));
+        assert_eq!(batch.get_row(1).find("e"), None);
+    }
+}
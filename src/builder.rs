@@ -0,0 +1,301 @@
+use super::data_blob::DataBlob;
+use super::error::SourceError;
+use super::source::DataSource;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// A single timestamped detector readout, as produced by most DAQ systems
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hit {
+    pub detector: String,
+    pub timestamp: u64,
+    pub values: Vec<(String, f32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BuilderSpec {
+    /// Hits within this many timestamp units of the first hit of an event belong to the event.
+    /// A window of 0 is taken as 1, so that only hits at the same timestamp are coincident.
+    pub coincidence_window: u64,
+    /// How far out of order hits may arrive, in timestamp units. Events are held until every
+    /// source has moved this far past their end.
+    pub tolerance: u64,
+    /// The length of a timestamp unit in seconds, to give each event the time of its first hit.
+    /// Events are not timestamped if None.
+    pub seconds_per_tick: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BuilderStats {
+    pub hits: u64,
+    /// Hits which arrived after their event had already been built
+    pub late_hits: u64,
+    /// Hits dropped because the same detector already fired in the event
+    pub duplicate_hits: u64,
+    pub events: u64,
+}
+
+#[derive(Debug, Default)]
+struct SourceBuffer {
+    hits: VecDeque<Hit>,
+    /// The latest timestamp seen, or None if the source has not sent anything yet
+    latest: Option<u64>,
+}
+
+/// Groups hits from one or more sources into events using a coincidence window. Each value of
+/// a hit becomes the variable "{detector}_{name}" in the event, and the time of the hit relative
+/// to the start of the event becomes "{detector}_dt".
+#[derive(Debug)]
+pub struct EventBuilder {
+    spec: BuilderSpec,
+    sources: Vec<SourceBuffer>,
+    /// Timestamp before which events have already been emitted
+    built_until: Option<u64>,
+    stats: BuilderStats,
+}
+
+impl EventBuilder {
+    pub fn new(spec: BuilderSpec) -> Self {
+        Self {
+            spec,
+            sources: vec![],
+            built_until: None,
+            stats: BuilderStats::default(),
+        }
+    }
+
+    pub fn get_stats(&self) -> &BuilderStats {
+        &self.stats
+    }
+
+    /// Buffer a hit from a source. Sources are numbered from zero.
+    pub fn push(&mut self, source: usize, hit: Hit) {
+        self.stats.hits += 1;
+        if self.built_until.is_some_and(|until| hit.timestamp < until) {
+            self.stats.late_hits += 1;
+            return;
+        }
+        if source >= self.sources.len() {
+            self.sources.resize_with(source + 1, SourceBuffer::default);
+        }
+        let buffer = &mut self.sources[source];
+        buffer.latest = Some(
+            buffer
+                .latest
+                .map_or(hit.timestamp, |latest| latest.max(hit.timestamp)),
+        );
+        let idx = buffer
+            .hits
+            .partition_point(|buffered| buffered.timestamp <= hit.timestamp);
+        buffer.hits.insert(idx, hit);
+    }
+
+    /// Get the next complete event, if one is ready
+    pub fn pop_event(&mut self) -> Option<DataBlob> {
+        self.build(false)
+    }
+
+    /// Get the next event, regardless of whether later hits could still arrive for it. Use once
+    /// the sources are exhausted.
+    pub fn flush(&mut self) -> Option<DataBlob> {
+        self.build(true)
+    }
+
+    fn build(&mut self, force: bool) -> Option<DataBlob> {
+        let start = self
+            .sources
+            .iter()
+            .filter_map(|buffer| buffer.hits.front())
+            .map(|hit| hit.timestamp)
+            .min()?;
+        let window = self.spec.coincidence_window.max(1);
+        let end = start.saturating_add(window);
+        if !force {
+            // Sources which have never sent a hit cannot hold back the others
+            let safe = self
+                .sources
+                .iter()
+                .filter_map(|buffer| buffer.latest)
+                .map(|latest| latest.saturating_sub(self.spec.tolerance))
+                .min()?;
+            if end > safe {
+                return None;
+            }
+        }
+
+        let mut event = DataBlob::new();
+        if let Some(seconds_per_tick) = self.spec.seconds_per_tick {
+            event.set_timestamp(start as f64 * seconds_per_tick);
+        }
+        for buffer in self.sources.iter_mut() {
+            // Every hit is at or after the start, and an empty window would take none of them
+            while buffer
+                .hits
+                .front()
+                .is_some_and(|hit| hit.timestamp - start < window)
+            {
+                let hit = buffer.hits.pop_front().expect("Front hit exists");
+                let dt_name = format!("{}_dt", hit.detector);
+                if event.find(&dt_name).is_some() {
+                    self.stats.duplicate_hits += 1;
+                    continue;
+                }
+                event.insert(&dt_name, (hit.timestamp - start) as f32);
+                for (name, value) in hit.values.iter() {
+                    event.insert(&format!("{}_{name}", hit.detector), *value);
+                }
+            }
+        }
+        self.built_until = Some(end);
+        self.stats.events += 1;
+        Some(event)
+    }
+}
+
+/// A producer of hits, tagged with the index of the source they came from
+pub trait HitSource {
+    /// Get the next hit, returning None once the source is exhausted
+    fn next_hit(&mut self) -> Result<Option<(usize, Hit)>, SourceError>;
+}
+
+/// Adapts any iterator of (source, hit) into a HitSource
+#[derive(Debug)]
+pub struct IterHitSource<I: Iterator<Item = (usize, Hit)>> {
+    iter: I,
+}
+
+impl<I: Iterator<Item = (usize, Hit)>> IterHitSource<I> {
+    pub fn new(iter: I) -> Self {
+        Self { iter }
+    }
+}
+
+impl<I: Iterator<Item = (usize, Hit)>> HitSource for IterHitSource<I> {
+    fn next_hit(&mut self) -> Result<Option<(usize, Hit)>, SourceError> {
+        Ok(self.iter.next())
+    }
+}
+
+/// Turns a HitSource into a DataSource by running its hits through an EventBuilder
+#[derive(Debug)]
+pub struct BuiltSource<H: HitSource> {
+    hits: H,
+    builder: EventBuilder,
+    exhausted: bool,
+}
+
+impl<H: HitSource> BuiltSource<H> {
+    pub fn new(hits: H, spec: BuilderSpec) -> Self {
+        Self {
+            hits,
+            builder: EventBuilder::new(spec),
+            exhausted: false,
+        }
+    }
+
+    pub fn get_builder(&self) -> &EventBuilder {
+        &self.builder
+    }
+}
+
+impl<H: HitSource> DataSource for BuiltSource<H> {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        while !self.exhausted {
+            if let Some(event) = self.builder.pop_event() {
+                return Ok(Some(event));
+            }
+            match self.hits.next_hit()? {
+                Some((source, hit)) => self.builder.push(source, hit),
+                None => self.exhausted = true,
+            }
+        }
+        Ok(self.builder.flush())
+    }
+
+    /// Stop reading hits, so that the events still in the coincidence window are built and
+    /// returned regardless of whether more of their hits would have arrived
+    fn stop(&mut self) {
+        self.exhausted = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(detector: &str, timestamp: u64, energy: f32) -> Hit {
+        Hit {
+            detector: detector.to_string(),
+            timestamp,
+            values: vec![(String::from("energy"), energy)],
+        }
+    }
+
+    #[test]
+    fn test_event_builder() {
+        let spec = BuilderSpec {
+            coincidence_window: 100,
+            tolerance: 50,
+            seconds_per_tick: Some(1e-3),
+        };
+        let hits = vec![
+            (0, hit("si", 1000, 1.0)),
+            (1, hit("hpge", 1040, 2.0)),
+            // Out of order within the tolerance
+            (0, hit("si_back", 1020, 3.0)),
+            (0, hit("si", 2000, 4.0)),
+            (1, hit("hpge", 2030, 5.0)),
+            (1, hit("hpge", 2060, 6.0)),
+            (0, hit("si", 5000, 7.0)),
+        ];
+        let mut source = BuiltSource::new(IterHitSource::new(hits.into_iter()), spec);
+
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("si_energy"), Some(&1.0));
+        assert_eq!(event.find("si_back_energy"), Some(&3.0));
+        assert_eq!(event.find("hpge_energy"), Some(&2.0));
+        assert_eq!(event.find("hpge_dt"), Some(&40.0));
+        assert_eq!(event.get_timestamp(), Some(1.0));
+
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("si_energy"), Some(&4.0));
+        assert_eq!(event.find("hpge_energy"), Some(&5.0));
+
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.len(), 2);
+        assert!(source.next_event().unwrap().is_none());
+
+        let stats = source.get_builder().get_stats();
+        assert_eq!(stats.events, 3);
+        assert_eq!(stats.duplicate_hits, 1);
+
+        let mut builder = EventBuilder::new(spec);
+        builder.push(0, hit("si", 1000, 1.0));
+        builder.push(0, hit("si", 1500, 1.0));
+        assert!(builder.pop_event().is_some());
+        builder.push(0, hit("si", 1010, 1.0));
+        assert_eq!(builder.get_stats().late_hits, 1);
+    }
+
+    #[test]
+    fn test_exact_coincidences() {
+        let spec = BuilderSpec {
+            coincidence_window: 0,
+            tolerance: 0,
+            seconds_per_tick: None,
+        };
+        let hits = vec![
+            (0, hit("si", 1000, 1.0)),
+            (1, hit("hpge", 1000, 2.0)),
+            (1, hit("hpge", 1001, 3.0)),
+        ];
+        let mut source = BuiltSource::new(IterHitSource::new(hits.into_iter()), spec);
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("si_energy"), Some(&1.0));
+        assert_eq!(event.find("hpge_energy"), Some(&2.0));
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("hpge_energy"), Some(&3.0));
+        assert!(source.next_event().unwrap().is_none());
+        assert_eq!(source.get_builder().get_stats().events, 2);
+    }
+}
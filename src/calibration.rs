@@ -0,0 +1,356 @@
+//! Energy calibrations of raw variables, fitted to peaks of known energy or matched to a
+//! reference channel, and applied by the manager to every event, so that a detector can be
+//! recalibrated while data are taken
+use super::data_blob::DataBlob;
+use super::error::{CalibrationError, HistogramError};
+use super::histogram::Histogram;
+use serde::{Deserialize, Serialize};
+
+/// A polynomial from raw values to calibrated values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    /// The calibrated value of raw is the sum of coefficients[i] * raw^i
+    pub coefficients: Vec<f64>,
+}
+
+impl Calibration {
+    pub fn linear(offset: f64, gain: f64) -> Self {
+        Self {
+            coefficients: vec![offset, gain],
+        }
+    }
+
+    pub fn apply(&self, raw: f64) -> f64 {
+        self.coefficients
+            .iter()
+            .rev()
+            .fold(0.0, |value, coefficient| value * raw + coefficient)
+    }
+}
+
+/// A calibration of one variable into another. The output may be the input, to calibrate it in
+/// place.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationSpec {
+    pub input: String,
+    pub output: String,
+    pub calibration: Calibration,
+}
+
+/// A calibration fitted to peaks
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationFit {
+    pub calibration: Calibration,
+    /// The known energy less the calibrated centroid of each peak
+    pub residuals: Vec<f64>,
+}
+
+impl CalibrationFit {
+    pub fn get_rms_residual(&self) -> f64 {
+        let sum2: f64 = self.residuals.iter().map(|r| r * r).sum();
+        (sum2 / self.residuals.len().max(1) as f64).sqrt()
+    }
+}
+
+/// Fit a polynomial of the given order taking the centroids of peaks, in raw units, to their
+/// known energies by least squares. There must be more peaks than the order.
+pub fn fit_calibration(
+    centroids: &[f64],
+    energies: &[f64],
+    order: usize,
+) -> Result<CalibrationFit, CalibrationError> {
+    if centroids.len() != energies.len() {
+        return Err(CalibrationError::MismatchedPeaks(
+            centroids.len(),
+            energies.len(),
+        ));
+    }
+    if centroids.len() <= order {
+        return Err(CalibrationError::TooFewPeaks(centroids.len(), order));
+    }
+    // Solve the normal equations, with the augmented column holding the sums of energy * x^row
+    let n = order + 1;
+    let mut matrix: Vec<Vec<f64>> = (0..n)
+        .map(|row| {
+            let mut equation: Vec<f64> = (0..n)
+                .map(|col| centroids.iter().map(|x| x.powi((row + col) as i32)).sum())
+                .collect();
+            equation.push(
+                centroids
+                    .iter()
+                    .zip(energies)
+                    .map(|(x, energy)| energy * x.powi(row as i32))
+                    .sum(),
+            );
+            equation
+        })
+        .collect();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))
+            .unwrap_or(col);
+        matrix.swap(col, pivot);
+        let pivot_equation = matrix[col].clone();
+        if pivot_equation[col].abs() < f64::EPSILON {
+            return Err(CalibrationError::Degenerate);
+        }
+        for (row, equation) in matrix.iter_mut().enumerate() {
+            if row != col {
+                let factor = equation[col] / pivot_equation[col];
+                for (value, pivot_value) in equation.iter_mut().zip(&pivot_equation).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    let calibration = Calibration {
+        coefficients: (0..n)
+            .map(|row| matrix[row][n] / matrix[row][row])
+            .collect(),
+    };
+    let residuals = centroids
+        .iter()
+        .zip(energies)
+        .map(|(x, energy)| energy - calibration.apply(*x))
+        .collect();
+    Ok(CalibrationFit {
+        calibration,
+        residuals,
+    })
+}
+
+/// Find the centroid of the highest peak of a 1D histogram with its maximum inside region, using
+/// the bins around the maximum holding at least half of it. Returns None if the region is empty.
+pub fn find_peak(gram: &Histogram, region: (f32, f32)) -> Result<Option<f64>, HistogramError> {
+    if gram.spec.y_axis.is_some() {
+        return Err(HistogramError::WrongDimensions);
+    }
+    let axis = &gram.spec.x_axis;
+    let count = |x_bin: usize| gram.data[gram.bin_index(x_bin, 0)];
+    let Some(max_bin) = axis
+        .get_bin_range(region.0, region.1)?
+        .filter(|x_bin| count(*x_bin) > 0.0)
+        .max_by(|a, b| count(*a).total_cmp(&count(*b)))
+    else {
+        return Ok(None);
+    };
+    let half_max = 0.5 * count(max_bin);
+    let mut low = max_bin;
+    while low > 0 && count(low - 1) >= half_max {
+        low -= 1;
+    }
+    let mut high = max_bin;
+    while high + 1 < axis.bins && count(high + 1) >= half_max {
+        high += 1;
+    }
+    let (mut sum, mut weighted) = (0.0, 0.0);
+    for x_bin in low..=high {
+        sum += count(x_bin);
+        weighted += count(x_bin) * axis.get_bin_center(x_bin) as f64;
+    }
+    Ok(Some(weighted / sum))
+}
+
+/// Where gain_match looks for one peak in the reference, and in the channel, where a gain shift
+/// may have moved it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeakRegion {
+    pub reference: (f32, f32),
+    pub channel: (f32, f32),
+}
+
+impl PeakRegion {
+    /// Look for the peak in the same region of both, for gains which have barely moved
+    pub fn same(region: (f32, f32)) -> Self {
+        Self {
+            reference: region,
+            channel: region,
+        }
+    }
+}
+
+/// Get the calibration taking the peaks of a channel onto the same peaks in a reference, e.g. to
+/// line up the channels of an array after a high voltage change. Each peak is searched for in
+/// its regions of the two histograms; one peak gives a gain alone, and more give a gain and an
+/// offset. The residuals are in the units of the reference.
+pub fn gain_match(
+    gram: &Histogram,
+    reference: &Histogram,
+    regions: &[PeakRegion],
+) -> Result<CalibrationFit, CalibrationError> {
+    let mut centroids = vec![];
+    let mut targets = vec![];
+    for region in regions.iter() {
+        for (found, histogram, region) in [
+            (&mut centroids, gram, region.channel),
+            (&mut targets, reference, region.reference),
+        ] {
+            match find_peak(histogram, region)? {
+                Some(centroid) => found.push(centroid),
+                None => {
+                    return Err(CalibrationError::NoPeak(
+                        histogram.spec.name.clone(),
+                        region.0,
+                        region.1,
+                    ));
+                }
+            }
+        }
+    }
+    match centroids[..] {
+        [] => Err(CalibrationError::TooFewPeaks(0, 0)),
+        [centroid] if centroid != 0.0 => {
+            let calibration = Calibration::linear(0.0, targets[0] / centroid);
+            Ok(CalibrationFit {
+                calibration,
+                residuals: vec![0.0],
+            })
+        }
+        [_] => Err(CalibrationError::Degenerate),
+        _ => fit_calibration(&centroids, &targets, 1),
+    }
+}
+
+/// The calibrations applied to each event, in the order they were added so that one may use the
+/// output of an earlier one
+#[derive(Debug, Clone, Default)]
+pub struct CalibrationSet {
+    calibrations: Vec<CalibrationSpec>,
+}
+
+impl CalibrationSet {
+    /// Add a calibration, replacing any with the same output
+    pub fn set(&mut self, spec: CalibrationSpec) {
+        match self
+            .calibrations
+            .iter_mut()
+            .find(|existing| existing.output == spec.output)
+        {
+            Some(existing) => *existing = spec,
+            None => self.calibrations.push(spec),
+        }
+    }
+
+    pub fn get(&self, output: &str) -> Option<&CalibrationSpec> {
+        self.calibrations.iter().find(|spec| spec.output == output)
+    }
+
+    /// Remove the calibration of an output, returning false if there was none
+    pub fn remove(&mut self, output: &str) -> bool {
+        let n_calibrations = self.calibrations.len();
+        self.calibrations.retain(|spec| spec.output != output);
+        n_calibrations != self.calibrations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calibrations.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CalibrationSpec> {
+        self.calibrations.iter()
+    }
+
+    /// Write the calibrated value of every input in the event, element by element for arrays
+    pub fn apply(&self, data: &mut DataBlob) {
+        for spec in self.calibrations.iter() {
+            let calibrate = |raw: &f32| spec.calibration.apply(*raw as f64) as f32;
+            if let Some(raw) = data.find(&spec.input) {
+                let value = calibrate(raw);
+                data.insert(&spec.output, value);
+            } else if let Some(raw) = data.find_array(&spec.input) {
+                let values = raw.iter().map(calibrate).collect();
+                data.insert_array(&spec.output, values);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_calibration() {
+        // Three 152Eu lines at a gain of 0.5 keV per channel and an offset of 2 keV
+        let energies = [121.78, 344.28, 1408.01];
+        let centroids: Vec<f64> = energies.iter().map(|e| (e - 2.0) / 0.5).collect();
+        let fit = fit_calibration(&centroids, &energies, 1).unwrap();
+        assert!((fit.calibration.coefficients[0] - 2.0).abs() < 1e-9);
+        assert!((fit.calibration.coefficients[1] - 0.5).abs() < 1e-12);
+        assert!(fit.get_rms_residual() < 1e-9);
+        assert!(fit_calibration(&centroids[..2], &energies[..2], 2).is_err());
+        assert!(fit_calibration(&centroids, &energies[..2], 1).is_err());
+        assert!(fit_calibration(&[5.0, 5.0], &[1.0, 2.0], 1).is_err());
+
+        let quadratic = Calibration {
+            coefficients: vec![1.0, 2.0, 3.0],
+        };
+        assert_eq!(quadratic.apply(2.0), 17.0);
+
+        let mut set = CalibrationSet::default();
+        set.set(CalibrationSpec {
+            input: String::from("adc"),
+            output: String::from("energy"),
+            calibration: Calibration::linear(2.0, 0.5),
+        });
+        set.set(CalibrationSpec {
+            input: String::from("strips"),
+            output: String::from("strips"),
+            calibration: Calibration::linear(0.0, 2.0),
+        });
+        let mut data = DataBlob::new();
+        data.insert("adc", 100.0);
+        data.insert_array("strips", vec![1.0, 3.0]);
+        set.apply(&mut data);
+        assert_eq!(data.find("energy"), Some(&52.0));
+        assert_eq!(data.find_array("strips"), Some(&[2.0, 6.0][..]));
+        assert!(set.remove("energy") && !set.remove("energy"));
+    }
+
+    #[test]
+    fn test_gain_match() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "ge_0",
+                AxisSpec::new("ge_0", "ge_0", 100, 0.0, 100.0).unwrap(),
+                None,
+            )
+        };
+        let mut reference = Histogram::new(spec.clone());
+        let mut channel = Histogram::new(HistSpec {
+            name: String::from("ge_1"),
+            ..spec
+        });
+        // Peaks at 20.5 and 60.5 in the reference, and 10.5 and 30.5 in the channel
+        for (gram, peaks) in [(&mut reference, [20.5, 60.5]), (&mut channel, [10.5, 30.5])] {
+            for peak in peaks {
+                gram.fill_weighted(peak - 1.0, None, 5.0).unwrap();
+                gram.fill_weighted(peak, None, 20.0).unwrap();
+                gram.fill_weighted(peak + 1.0, None, 5.0).unwrap();
+            }
+        }
+        assert_eq!(find_peak(&channel, (0.0, 20.0)).unwrap(), Some(10.5));
+        assert_eq!(find_peak(&channel, (40.0, 50.0)).unwrap(), None);
+
+        let regions = [
+            PeakRegion {
+                reference: (15.0, 25.0),
+                channel: (5.0, 15.0),
+            },
+            PeakRegion {
+                reference: (50.0, 70.0),
+                channel: (25.0, 35.0),
+            },
+        ];
+        let both = gain_match(&channel, &reference, &regions).unwrap();
+        assert!((both.calibration.coefficients[1] - 2.0).abs() < 1e-9);
+        assert!((both.calibration.coefficients[0] + 0.5).abs() < 1e-9);
+        let one = gain_match(&channel, &reference, &[PeakRegion::same((25.0, 100.0))]).unwrap();
+        assert!((one.calibration.coefficients[1] - 60.5 / 30.5).abs() < 1e-12);
+        // The reference has no peak where the channel has its second
+        let moved = PeakRegion::same((25.0, 35.0));
+        assert!(gain_match(&channel, &reference, &[moved]).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Orchestrates campaign-style offline analysis: a catalog of runs, each with its files and
+//! metadata, from which a selection of runs is replayed through a manager one run at a time.
+use super::compression::{self, Input};
+use super::error::{ResourceError, RunError, SourceError};
+use super::manager::ResourceManager;
+use super::source::DataSource;
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CatalogEntry {
+    /// The files of the run, replayed in order
+    pub paths: Vec<PathBuf>,
+    pub metadata: FxHashMap<String, String>,
+}
+
+/// What a catalog replay did for one run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunReplay {
+    pub number: u32,
+    /// Events read from the files of the run
+    pub n_events: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RunCatalog {
+    runs: BTreeMap<u32, CatalogEntry>,
+}
+
+impl RunCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a file of a run. Metadata is merged into the run's existing metadata.
+    pub fn add(&mut self, number: u32, path: &Path, metadata: FxHashMap<String, String>) {
+        let entry = self.runs.entry(number).or_default();
+        entry.paths.push(path.to_path_buf());
+        entry.metadata.extend(metadata);
+    }
+
+    pub fn get(&self, number: u32) -> Option<&CatalogEntry> {
+        self.runs.get(&number)
+    }
+
+    /// Run numbers in the catalog, in ascending order
+    pub fn list_runs(&self) -> Vec<u32> {
+        self.runs.keys().copied().collect()
+    }
+
+    /// Get the catalogued runs in a selection such as "120-135,140". Numbers in the selection
+    /// which are not in the catalog are left out.
+    pub fn select(&self, selection: &str) -> Result<Vec<u32>, RunError> {
+        let mut selected = vec![];
+        for part in selection
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            let bad = || RunError::BadSelection(part.to_string());
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (first.trim(), last.trim()),
+                None => (part, part),
+            };
+            let first: u32 = first.parse().map_err(|_| bad())?;
+            let last: u32 = last.parse().map_err(|_| bad())?;
+            if first > last {
+                return Err(bad());
+            }
+            selected.extend(self.runs.range(first..=last).map(|(number, _)| *number));
+        }
+        selected.sort_unstable();
+        selected.dedup();
+        Ok(selected)
+    }
+
+    /// Replay the selected runs through a manager in order, beginning and ending a run around
+    /// each so that run bookkeeping and clear policies apply as they would have online.
+    /// make_source turns each opened (and decompressed) file into the source to read it with.
+    /// Per-run copies of histograms are kept by the manager; see
+    /// ResourceManager::set_run_snapshots.
+    pub fn replay<S: DataSource>(
+        &self,
+        manager: &mut ResourceManager,
+        runs: &[u32],
+        mut make_source: impl FnMut(Input) -> Result<S, SourceError>,
+    ) -> Result<Vec<RunReplay>, ResourceError> {
+        let mut replays = vec![];
+        for number in runs.iter() {
+            let entry = self.runs.get(number).ok_or(RunError::UnknownRun(*number))?;
+            manager.begin_run(*number, entry.metadata.clone())?;
+            let mut n_events = 0;
+            for path in entry.paths.iter() {
+                let mut source = make_source(compression::open(path).map_err(SourceError::from)?)?;
+                n_events += manager.process_source(&mut source)?;
+            }
+            manager.end_run()?;
+            replays.push(RunReplay {
+                number: *number,
+                n_events,
+            });
+        }
+        Ok(replays)
+    }
+}
+
+// Every test writes an event log to a file
+#[cfg(all(test, feature = "io"))]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+    use crate::record::{EventReader, EventRecorder};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_catalog_replay() {
+        let mut catalog = RunCatalog::new();
+        let mut paths = vec![];
+        for (number, n_events) in [(120, 3), (121, 5), (135, 2), (140, 1)] {
+            let path = std::env::temp_dir().join(format!("spect_catalog_{}.evt", Uuid::new_v4()));
+            let mut recorder = EventRecorder::create(&path).unwrap();
+            for _ in 0..n_events {
+                let mut blob = DataBlob::new();
+                blob.insert("x", 1.0);
+                recorder.record(&blob).unwrap();
+            }
+            recorder.flush().unwrap();
+            let mut metadata = FxHashMap::default();
+            metadata.insert(String::from("target"), String::from("CD2"));
+            catalog.add(number, &path, metadata);
+            paths.push(path);
+        }
+        assert_eq!(catalog.select("121-139, 140").unwrap(), vec![121, 135, 140]);
+        assert!(catalog.select("135-120").is_err());
+
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("x", AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(), None)
+        };
+        manager.add_histogram(spec).unwrap();
+        manager.set_run_snapshots(Some("*"));
+        let runs = catalog.select("120-135").unwrap();
+        let replays = catalog
+            .replay(&mut manager, &runs, |input| Ok(EventReader::new(input)?))
+            .unwrap();
+        let n_events: Vec<usize> = replays.iter().map(|replay| replay.n_events).collect();
+        assert_eq!(n_events, vec![3, 5, 2]);
+        assert_eq!(manager.get_run_snapshot(121, "x").unwrap().data[1], 5.0);
+        assert_eq!(manager.get_run_history().len(), 3);
+        assert_eq!(manager.get_run_history()[0].metadata["target"], "CD2");
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
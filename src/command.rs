@@ -0,0 +1,784 @@
+//! A command interface mirroring the spectrum, gate, apply, sbind, and clear commands of SpecTcl,
+//! so that displayers and Tcl scripts written for SpecTcl can drive a manager. Commands are Tcl
+//! words, grouped with braces or quotes, and every reply is one line of JSON in the form of the
+//! SpecTcl REST interface: {"status": "OK", "detail": ...}, or a status of "ERROR" with the
+//! message as the detail.
+use super::cut::{Cut, Cut1D, Cut2D, CutSpec};
+use super::error::{CommandError, ResourceError};
+use super::histogram::{AxisSpec, FillMode, HistSpec, OutOfRangePolicy};
+use super::manager::ResourceManager;
+use super::pattern;
+use serde_json::{Value, json};
+#[cfg(feature = "io")]
+use std::io::{BufRead, BufReader, Write};
+#[cfg(feature = "io")]
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(feature = "io")]
+use std::sync::mpsc::{Receiver, Sender, channel};
+#[cfg(feature = "io")]
+use std::thread::JoinHandle;
+use uuid::Uuid;
+
+const SPECTRUM_USAGE: &str = "spectrum -list ?pattern? | spectrum -delete -all | \
+    spectrum -delete name ?name...? | spectrum ?-new? name type {parameters} {{low high bins}...}";
+const GATE_USAGE: &str = "gate -list ?pattern? | gate ?-new? name s {parameter {low high}} | \
+    gate ?-new? name c {{x y} {{x y}...}}";
+const APPLY_USAGE: &str = "apply gate spectrum ?spectrum...?";
+const UNGATE_USAGE: &str = "ungate spectrum ?spectrum...?";
+const SBIND_USAGE: &str = "sbind -all | sbind -list ?pattern? | sbind name ?name...?";
+const UNBIND_USAGE: &str = "unbind -all | unbind name ?name...?";
+const CLEAR_USAGE: &str = "clear -all | clear name ?name...?";
+
+/// Split text into Tcl words. Braces group a word, which may hold nested braces, and quotes
+/// group a word in which a backslash escapes the next character.
+pub fn split_words(text: &str) -> Result<Vec<String>, CommandError> {
+    let unbalanced = || CommandError::Unbalanced(text.to_string());
+    let mut words = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(&first) = chars.peek() {
+        if first.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut word = String::new();
+        match first {
+            '{' => {
+                chars.next();
+                let mut depth = 1;
+                loop {
+                    let c = chars.next().ok_or_else(unbalanced)?;
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '"' => {
+                chars.next();
+                loop {
+                    match chars.next().ok_or_else(unbalanced)? {
+                        '"' => break,
+                        '\\' => word.push(chars.next().ok_or_else(unbalanced)?),
+                        c => word.push(c),
+                    }
+                }
+            }
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+            }
+        }
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn parse_number<T: std::str::FromStr>(word: &str) -> Result<T, CommandError> {
+    word.parse()
+        .map_err(|_| CommandError::BadNumber(word.to_string()))
+}
+
+// Read a {low high bins} axis description
+fn parse_axis(variable: &str, word: &str) -> Result<AxisSpec, CommandError> {
+    let words = split_words(word)?;
+    let [low, high, bins] = words.as_slice() else {
+        return Err(CommandError::Usage(SPECTRUM_USAGE));
+    };
+    let axis = AxisSpec::new(
+        variable,
+        variable,
+        parse_number(bins)?,
+        parse_number(low)?,
+        parse_number(high)?,
+    )
+    .map_err(ResourceError::from)?;
+    Ok(axis)
+}
+
+// Read an {x y} point
+fn parse_point(word: &str) -> Result<(f32, f32), CommandError> {
+    match split_words(word)?.as_slice() {
+        [x, y] => Ok((parse_number(x)?, parse_number(y)?)),
+        _ => Err(CommandError::Usage(GATE_USAGE)),
+    }
+}
+
+// The SpecTcl type of a spectrum
+fn get_type(spec: &HistSpec) -> &'static str {
+    match (spec.fill_mode, &spec.y_axis) {
+        (FillMode::BitMask, _) => "b",
+        (FillMode::Summary, _) => "s",
+        (FillMode::Symmetric, _) => "g2",
+        (FillMode::Value, None) => "1",
+        (FillMode::Value, Some(_)) => "2",
+    }
+}
+
+fn describe_axis(axis: &AxisSpec) -> Value {
+    json!({"low": axis.minimum, "high": axis.maximum, "bins": axis.bins})
+}
+
+fn describe_spectrum(manager: &ResourceManager, spec: &HistSpec) -> Value {
+    let axes: Vec<&AxisSpec> = std::iter::once(&spec.x_axis)
+        .chain(spec.y_axis.as_ref())
+        .collect();
+    // The x variable of a summary spectrum is not used, so the y pattern is its only parameter
+    let parameters: Vec<&str> = match spec.fill_mode {
+        FillMode::Summary => axes[1..]
+            .iter()
+            .map(|axis| axis.variable.as_str())
+            .collect(),
+        _ => axes.iter().map(|axis| axis.variable.as_str()).collect(),
+    };
+    json!({
+        "name": spec.name,
+        "type": get_type(spec),
+        "parameters": parameters,
+        "axes": axes.iter().map(|axis| describe_axis(axis)).collect::<Vec<Value>>(),
+        "chantype": "double",
+        "gate": spec
+            .cuts_to_check
+            .first()
+            .and_then(|id| manager.get_cut_spec(id).ok())
+            .map(|cut| cut.name.clone()),
+    })
+}
+
+fn find_spectrum(manager: &ResourceManager, name: &str) -> Result<HistSpec, CommandError> {
+    manager
+        .list_histograms("")
+        .into_iter()
+        .find(|spec| spec.name == name)
+        .cloned()
+        .ok_or_else(|| CommandError::UnknownSpectrum(name.to_string()))
+}
+
+fn find_gate(manager: &ResourceManager, name: &str) -> Option<Uuid> {
+    manager
+        .list_cuts()
+        .into_iter()
+        .find(|(_, spec)| spec.name == name)
+        .map(|(_, spec)| spec.id)
+}
+
+/// Runs SpecTcl commands against a manager, keeping the spectra bound to displayer slots
+#[derive(Debug, Default)]
+pub struct CommandInterpreter {
+    // The spectrum bound to each slot; unbinding leaves a hole for the next binding to reuse
+    bindings: Vec<Option<Uuid>>,
+}
+
+impl CommandInterpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the (slot, ID) of every bound spectrum still in the manager
+    pub fn get_bindings(&self, manager: &ResourceManager) -> Vec<(usize, Uuid)> {
+        self.bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, id)| id.map(|id| (slot, id)))
+            .filter(|(_, id)| manager.get_histogram_spec(id).is_ok())
+            .collect()
+    }
+
+    /// Run a command and return the JSON reply, as sent to clients
+    pub fn respond(&mut self, manager: &mut ResourceManager, line: &str) -> String {
+        let reply = match self.execute(manager, line) {
+            Ok(detail) => json!({"status": "OK", "detail": detail}),
+            Err(e) => json!({"status": "ERROR", "detail": e.to_string()}),
+        };
+        reply.to_string()
+    }
+
+    /// Run a command, returning the detail of its reply
+    pub fn execute(
+        &mut self,
+        manager: &mut ResourceManager,
+        line: &str,
+    ) -> Result<Value, CommandError> {
+        let words = split_words(line)?;
+        let Some((command, args)) = words.split_first() else {
+            return Ok(Value::Null);
+        };
+        let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+        match command.as_str() {
+            "spectrum" => self.spectrum(manager, &args),
+            "gate" => gate(manager, &args),
+            "apply" => apply(manager, &args),
+            "ungate" => ungate(manager, &args),
+            "sbind" => self.sbind(manager, &args),
+            "unbind" => self.unbind(manager, &args),
+            "clear" => clear(manager, &args),
+            _ => Err(CommandError::UnknownCommand(command.clone())),
+        }
+    }
+
+    fn spectrum(
+        &mut self,
+        manager: &mut ResourceManager,
+        args: &[&str],
+    ) -> Result<Value, CommandError> {
+        match args {
+            ["-list"] => list_spectra(manager, "*"),
+            ["-list", pattern] => list_spectra(manager, pattern),
+            ["-delete", "-all"] => {
+                for spec in manager
+                    .list_histograms("")
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                {
+                    manager.remove_histogram(&spec.id)?;
+                }
+                self.bindings.clear();
+                Ok(Value::Null)
+            }
+            ["-delete", names @ ..] if !names.is_empty() => {
+                // Find every spectrum first so that a bad name leaves the manager unchanged
+                let mut ids = names
+                    .iter()
+                    .map(|name| find_spectrum(manager, name).map(|spec| spec.id))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ids.sort_unstable();
+                ids.dedup();
+                for id in ids.iter() {
+                    manager.remove_histogram(id)?;
+                    self.release(id);
+                }
+                Ok(Value::Null)
+            }
+            ["-new", name, kind, parameters, axes, ..] | [name, kind, parameters, axes, ..]
+                if !name.starts_with('-') =>
+            {
+                create_spectrum(manager, name, kind, parameters, axes)
+            }
+            _ => Err(CommandError::Usage(SPECTRUM_USAGE)),
+        }
+    }
+
+    fn sbind(&mut self, manager: &ResourceManager, args: &[&str]) -> Result<Value, CommandError> {
+        let names: Vec<String> = match args {
+            ["-all"] => manager
+                .list_histograms("")
+                .iter()
+                .map(|spec| spec.name.clone())
+                .collect(),
+            ["-list", rest @ ..] if rest.len() < 2 => {
+                let pattern = rest.first().copied().unwrap_or("*");
+                let bindings: Vec<Value> = self
+                    .get_bindings(manager)
+                    .into_iter()
+                    .filter_map(|(slot, id)| {
+                        let spec = manager.get_histogram_spec(&id).ok()?;
+                        pattern::matches(pattern, &spec.name)
+                            .then(|| json!({"spectrum": spec.name, "binding": slot}))
+                    })
+                    .collect();
+                return Ok(Value::Array(bindings));
+            }
+            [] => return Err(CommandError::Usage(SBIND_USAGE)),
+            names => names.iter().map(|name| name.to_string()).collect(),
+        };
+        for name in names.iter() {
+            let id = find_spectrum(manager, name)?.id;
+            if self.get_slot(&id).is_some() {
+                continue;
+            }
+            match self.bindings.iter_mut().find(|slot| slot.is_none()) {
+                Some(slot) => *slot = Some(id),
+                None => self.bindings.push(Some(id)),
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    fn unbind(&mut self, manager: &ResourceManager, args: &[&str]) -> Result<Value, CommandError> {
+        match args {
+            [] => return Err(CommandError::Usage(UNBIND_USAGE)),
+            ["-all"] => self.bindings.clear(),
+            names => {
+                for name in names {
+                    let id = find_spectrum(manager, name)?.id;
+                    self.release(&id);
+                }
+            }
+        }
+        Ok(Value::Null)
+    }
+
+    fn get_slot(&self, id: &Uuid) -> Option<usize> {
+        self.bindings.iter().position(|slot| *slot == Some(*id))
+    }
+
+    fn release(&mut self, id: &Uuid) {
+        if let Some(slot) = self.get_slot(id) {
+            self.bindings[slot] = None;
+        }
+    }
+}
+
+fn list_spectra(manager: &ResourceManager, pattern: &str) -> Result<Value, CommandError> {
+    Ok(Value::Array(
+        manager
+            .list_histograms("")
+            .into_iter()
+            .filter(|spec| pattern::matches(pattern, &spec.name))
+            .map(|spec| describe_spectrum(manager, spec))
+            .collect(),
+    ))
+}
+
+fn create_spectrum(
+    manager: &mut ResourceManager,
+    name: &str,
+    kind: &str,
+    parameters: &str,
+    axes: &str,
+) -> Result<Value, CommandError> {
+    if find_spectrum(manager, name).is_ok() {
+        return Err(CommandError::DuplicateSpectrum(name.to_string()));
+    }
+    let parameters = split_words(parameters)?;
+    let axes = split_words(axes)?;
+    let (fill_mode, y_parameter) = match (kind, parameters.as_slice(), axes.len()) {
+        ("1", [_], 1) => (FillMode::Value, None),
+        ("b", [_], 1) => (FillMode::BitMask, None),
+        ("2", [_, y], 2) => (FillMode::Value, Some(y)),
+        ("g2", [_, y], 2) => (FillMode::Symmetric, Some(y)),
+        // The first axis of a summary spectrum is of the channel numbers, the second of the
+        // values of the channels
+        ("s", [y], 2) => (FillMode::Summary, Some(y)),
+        ("1" | "b" | "2" | "g2" | "s", _, _) => return Err(CommandError::Usage(SPECTRUM_USAGE)),
+        _ => return Err(CommandError::UnknownType(kind.to_string())),
+    };
+    let x_axis = parse_axis(&parameters[0], &axes[0])?;
+    let y_axis = match y_parameter {
+        Some(y) => Some(parse_axis(y, &axes[1])?),
+        None => None,
+    };
+    manager.add_histogram(HistSpec {
+        id: Uuid::nil(),
+        title: name.to_string(),
+        out_of_range: OutOfRangePolicy::Ignore,
+        fill_mode,
+        ..HistSpec::new(name, x_axis, y_axis)
+    })?;
+    Ok(json!(name))
+}
+
+fn gate(manager: &mut ResourceManager, args: &[&str]) -> Result<Value, CommandError> {
+    match args {
+        ["-list"] => list_gates(manager, "*"),
+        ["-list", pattern] => list_gates(manager, pattern),
+        ["-new", name, kind, description] | [name, kind, description] if !name.starts_with('-') => {
+            create_gate(manager, name, kind, description)
+        }
+        _ => Err(CommandError::Usage(GATE_USAGE)),
+    }
+}
+
+fn list_gates(manager: &ResourceManager, pattern: &str) -> Result<Value, CommandError> {
+    let mut saved = manager.export_cuts();
+    saved.retain(|cut| pattern::matches(pattern, &cut.spec.name));
+    saved.sort_by(|a, b| a.spec.name.cmp(&b.spec.name));
+    let gates = saved
+        .into_iter()
+        .map(|cut| {
+            let parameters: Vec<&String> = std::iter::once(&cut.spec.x_variable)
+                .chain(cut.spec.y_variable.as_ref())
+                .collect();
+            match cut.kind.as_str() {
+                Cut1D::KIND => json!({
+                    "name": cut.spec.name,
+                    "type": "s",
+                    "parameters": parameters,
+                    "low": cut.parameters["low"],
+                    "high": cut.parameters["high"],
+                }),
+                Cut2D::KIND => {
+                    let points: Vec<Value> = cut.parameters["x_values"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .zip(cut.parameters["y_values"].as_array().into_iter().flatten())
+                        .map(|(x, y)| json!({"x": x, "y": y}))
+                        .collect();
+                    json!({
+                        "name": cut.spec.name,
+                        "type": "c",
+                        "parameters": parameters,
+                        "points": points,
+                    })
+                }
+                // Cuts SpecTcl has no type for are listed under their kind
+                kind => json!({
+                    "name": cut.spec.name,
+                    "type": kind,
+                    "parameters": parameters,
+                    "description": cut.parameters,
+                }),
+            }
+        })
+        .collect();
+    Ok(Value::Array(gates))
+}
+
+// Gates named like an existing gate replace it, keeping its ID so that spectra gated on it follow
+fn create_gate(
+    manager: &mut ResourceManager,
+    name: &str,
+    kind: &str,
+    description: &str,
+) -> Result<Value, CommandError> {
+    let description = split_words(description)?;
+    let existing = find_gate(manager, name);
+    let id = existing.unwrap_or_else(Uuid::new_v4);
+    let cut: Box<dyn Cut> = match (kind, description.as_slice()) {
+        ("s", [parameter, limits]) => {
+            let (low, high) = parse_point(limits)?;
+            let spec = CutSpec {
+                id,
+                name: name.to_string(),
+                x_variable: parameter.clone(),
+                y_variable: None,
+            };
+            Box::new(Cut1D::new(spec, low, high).map_err(ResourceError::from)?)
+        }
+        ("c", [parameters, points]) => {
+            let [x_variable, y_variable] = split_words(parameters)?
+                .try_into()
+                .map_err(|_| CommandError::Usage(GATE_USAGE))?;
+            let (mut x_values, mut y_values): (Vec<f32>, Vec<f32>) = split_words(points)?
+                .iter()
+                .map(|point| parse_point(point))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .unzip();
+            // SpecTcl contours are closed implicitly
+            if let (Some(&x), Some(&y)) = (x_values.first(), y_values.first())
+                && (x_values.last() != Some(&x) || y_values.last() != Some(&y))
+            {
+                x_values.push(x);
+                y_values.push(y);
+            }
+            let spec = CutSpec {
+                id,
+                name: name.to_string(),
+                x_variable,
+                y_variable: Some(y_variable),
+            };
+            Box::new(Cut2D::new(spec, x_values, y_values).map_err(ResourceError::from)?)
+        }
+        ("s" | "c", _) => return Err(CommandError::Usage(GATE_USAGE)),
+        _ => return Err(CommandError::UnknownType(kind.to_string())),
+    };
+    match existing {
+        Some(_) => manager.replace_cut(cut)?,
+        None => _ = manager.add_cut(cut)?,
+    }
+    Ok(json!(name))
+}
+
+// Gate spectra on a gate, replacing the gates they were checked against
+fn apply(manager: &mut ResourceManager, args: &[&str]) -> Result<Value, CommandError> {
+    let [gate, spectra @ ..] = args else {
+        return Err(CommandError::Usage(APPLY_USAGE));
+    };
+    if spectra.is_empty() {
+        return Err(CommandError::Usage(APPLY_USAGE));
+    }
+    let id = find_gate(manager, gate).ok_or_else(|| CommandError::UnknownGate(gate.to_string()))?;
+    set_gates(manager, spectra, vec![id])
+}
+
+fn ungate(manager: &mut ResourceManager, args: &[&str]) -> Result<Value, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::Usage(UNGATE_USAGE));
+    }
+    set_gates(manager, args, vec![])
+}
+
+fn set_gates(
+    manager: &mut ResourceManager,
+    spectra: &[&str],
+    gates: Vec<Uuid>,
+) -> Result<Value, CommandError> {
+    // Find every spectrum first so that a bad name leaves the manager unchanged
+    let specs = spectra
+        .iter()
+        .map(|name| find_spectrum(manager, name))
+        .collect::<Result<Vec<_>, _>>()?;
+    for mut spec in specs {
+        spec.cuts_to_check = gates.clone();
+        manager.update_histogram_spec(spec)?;
+    }
+    Ok(Value::Null)
+}
+
+fn clear(manager: &mut ResourceManager, args: &[&str]) -> Result<Value, CommandError> {
+    let ids: Vec<Uuid> = match args {
+        [] => return Err(CommandError::Usage(CLEAR_USAGE)),
+        ["-all"] => manager
+            .list_histograms("")
+            .iter()
+            .map(|spec| spec.id)
+            .collect(),
+        names => names
+            .iter()
+            .map(|name| find_spectrum(manager, name).map(|spec| spec.id))
+            .collect::<Result<_, _>>()?,
+    };
+    for id in ids.iter() {
+        manager.clear_histogram(id)?;
+    }
+    Ok(Value::Null)
+}
+
+// A command line read by a connection, with where to send its reply
+#[cfg(feature = "io")]
+type Request = (String, Sender<String>);
+
+/// Accepts commands over TCP from a background thread, one per line, replying to each with a
+/// line of JSON. The manager stays on the analysis thread, which runs the commands waiting
+/// whenever it calls process.
+#[cfg(feature = "io")]
+#[derive(Debug)]
+pub struct CommandServer {
+    interpreter: CommandInterpreter,
+    requests: Receiver<Request>,
+    address: SocketAddr,
+}
+
+#[cfg(feature = "io")]
+impl CommandServer {
+    /// Start listening on an address, e.g. "0.0.0.0:8001"
+    pub fn serve(address: impl ToSocketAddrs) -> std::io::Result<(Self, JoinHandle<()>)> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let (sender, requests) = channel();
+        let handle = std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                // A client hanging up is not a problem for the server
+                std::thread::spawn(move || {
+                    #[cfg(feature = "tracing")]
+                    let peer = stream.peer_addr().ok();
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(?peer, "Command client connected");
+                    #[cfg(feature = "tracing")]
+                    if let Err(e) = converse(stream, sender) {
+                        tracing::debug!(?peer, "Command client hung up: {e}");
+                    }
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = converse(stream, sender);
+                });
+            }
+        });
+        Ok((
+            Self {
+                interpreter: CommandInterpreter::new(),
+                requests,
+                address,
+            },
+            handle,
+        ))
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_interpreter(&self) -> &CommandInterpreter {
+        &self.interpreter
+    }
+
+    /// Run every command waiting, returning the number run
+    pub fn process(&mut self, manager: &mut ResourceManager) -> usize {
+        let mut n_run = 0;
+        while let Ok((line, reply)) = self.requests.try_recv() {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(command = %line, "Running command");
+            let _ = reply.send(self.interpreter.respond(manager, &line));
+            n_run += 1;
+        }
+        n_run
+    }
+}
+
+#[cfg(feature = "io")]
+fn converse(mut stream: TcpStream, requests: Sender<Request>) -> std::io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (sender, reply) = channel();
+        // The server has been dropped if either end of the exchange fails
+        if requests.send((line, sender)).is_err() {
+            break;
+        }
+        let Ok(reply) = reply.recv() else {
+            break;
+        };
+        writeln!(stream, "{reply}")?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::manager::ConflictPolicy;
+    #[cfg(feature = "io")]
+    use std::time::Duration;
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words("gate -new g c {{x y} {{0 0} {1 0}}} \"a \\\"b\\\"\"").unwrap(),
+            vec!["gate", "-new", "g", "c", "{x y} {{0 0} {1 0}}", "a \"b\""]
+        );
+        assert!(split_words("spectrum a 1 {x {0 1 2}").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut manager = ResourceManager::new();
+        let mut interpreter = CommandInterpreter::new();
+        let mut run =
+            |manager: &mut ResourceManager, line: &str| interpreter.execute(manager, line);
+
+        run(&mut manager, "spectrum si_e 1 si_e {{0 10 10}}").unwrap();
+        run(
+            &mut manager,
+            "spectrum -new pid 2 {de e} {{0 10 10} {0 10 10}}",
+        )
+        .unwrap();
+        assert!(matches!(
+            run(&mut manager, "spectrum si_e 1 si_e {{0 10 10}}"),
+            Err(CommandError::DuplicateSpectrum(_))
+        ));
+        assert!(matches!(
+            run(&mut manager, "spectrum x q x {{0 10 10}}"),
+            Err(CommandError::UnknownType(_))
+        ));
+        let listed = run(&mut manager, "spectrum -list p*").unwrap();
+        assert_eq!(listed[0]["type"], "2");
+        assert_eq!(listed[0]["parameters"], json!(["de", "e"]));
+        assert_eq!(
+            listed[0]["axes"][1],
+            json!({"low": 0.0, "high": 10.0, "bins": 10})
+        );
+
+        run(&mut manager, "gate -new e_window s {si_e {2 4}}").unwrap();
+        run(&mut manager, "gate blob c {{e de} {{1 1} {5 1} {5 5}}}").unwrap();
+        let gates = run(&mut manager, "gate -list").unwrap();
+        assert_eq!(gates[0]["name"], "blob");
+        assert_eq!(gates[0]["points"].as_array().unwrap().len(), 4);
+        assert_eq!(gates[1]["low"], 2.0);
+        run(&mut manager, "apply e_window si_e").unwrap();
+
+        let si_e = find_spectrum(&manager, "si_e").unwrap().id;
+        for e in [1.0, 3.0, 3.5] {
+            let mut data = DataBlob::new();
+            data.insert("si_e", e);
+            manager.update(data).unwrap();
+        }
+        assert_eq!(manager.get_histogram_data(&si_e).unwrap()[3], 2.0);
+        // Redefining the gate keeps the spectra gated on it
+        run(&mut manager, "gate e_window s {si_e {0 2}}").unwrap();
+        assert_eq!(manager.list_cuts().len(), 2);
+        let mut data = DataBlob::new();
+        data.insert("si_e", 1.0);
+        manager.update(data).unwrap();
+        assert_eq!(manager.get_histogram_data(&si_e).unwrap()[1], 1.0);
+        assert_eq!(
+            run(&mut manager, "spectrum -list si_e").unwrap()[0]["gate"],
+            "e_window"
+        );
+        // Redefinition is not a conflict under a stricter policy
+        manager.set_conflict_policy(ConflictPolicy::Error);
+        run(&mut manager, "gate e_window s {si_e {4 6}}").unwrap();
+        assert_eq!(manager.list_cuts().len(), 2);
+        manager.set_conflict_policy(ConflictPolicy::Rename);
+        run(&mut manager, "gate -new e_window s {si_e {4 6}}").unwrap();
+        assert_eq!(manager.list_cuts().len(), 2);
+        run(&mut manager, "ungate si_e").unwrap();
+        run(&mut manager, "clear -all").unwrap();
+        assert_eq!(
+            manager
+                .get_histogram_data(&si_e)
+                .unwrap()
+                .iter()
+                .sum::<f64>(),
+            0.0
+        );
+
+        run(&mut manager, "sbind -all").unwrap();
+        run(&mut manager, "unbind pid").unwrap();
+        run(&mut manager, "spectrum -new ring 1 si_e {{0 10 10}}").unwrap();
+        run(&mut manager, "sbind ring si_e").unwrap();
+        let bindings = run(&mut manager, "sbind -list").unwrap();
+        assert_eq!(
+            bindings,
+            json!([
+                {"spectrum": "ring", "binding": 0},
+                {"spectrum": "si_e", "binding": 1},
+            ])
+        );
+        // A bad name deletes nothing
+        let n_spectra = manager.list_histograms("").len();
+        assert!(run(&mut manager, "spectrum -delete ring nothing").is_err());
+        assert_eq!(manager.list_histograms("").len(), n_spectra);
+        run(&mut manager, "spectrum -delete ring ring").unwrap();
+        assert_eq!(interpreter.get_bindings(&manager), vec![(1, si_e)]);
+
+        let mut manager = ResourceManager::new();
+        let reply = interpreter.respond(&mut manager, "spectrum -list");
+        assert_eq!(reply, r#"{"detail":[],"status":"OK"}"#);
+        let reply = interpreter.respond(&mut manager, "clear nothing");
+        assert_eq!(
+            reply,
+            r#"{"detail":"No spectrum named 'nothing'","status":"ERROR"}"#
+        );
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_server() {
+        let mut manager = ResourceManager::new();
+        let (mut server, _) = CommandServer::serve("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(server.get_address()).unwrap();
+        stream
+            .write_all(b"spectrum si_e 1 si_e {{0 10 10}}\n")
+            .unwrap();
+        let mut n_run = 0;
+        for _ in 0..500 {
+            n_run += server.process(&mut manager);
+            if n_run > 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(n_run, 1);
+        let mut reply = String::new();
+        BufReader::new(&stream).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "{\"detail\":\"si_e\",\"status\":\"OK\"}\n");
+        assert_eq!(manager.list_histograms("").len(), 1);
+    }
+}
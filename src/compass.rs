@@ -0,0 +1,214 @@
+//! Reads the list-mode output of CAEN CoMPASS, in its binary or CSV format, as hits for an
+//! EventBuilder. Each hit is from detector "b{board}_c{channel}" with the values energy,
+//! energy_short, energy_calibrated (if recorded), and flags. Timestamps are in picoseconds.
+use super::builder::{Hit, HitSource};
+use super::compression::{self, Input};
+use super::error::SourceError;
+use super::source::read_or_eof;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+// Bits of the binary file header saying which fields each hit has
+const HAS_ENERGY: u16 = 0x1;
+const HAS_CALIBRATED: u16 = 0x2;
+const HAS_ENERGY_SHORT: u16 = 0x4;
+const HAS_WAVEFORM: u16 = 0x8;
+
+fn make_hit(board: u16, channel: u16, timestamp: u64, values: Vec<(String, f32)>) -> Hit {
+    Hit {
+        detector: format!("b{board}_c{channel}"),
+        timestamp,
+        values,
+    }
+}
+
+/// Reads hits from a CoMPASS binary file (the format written since CoMPASS 2). Waveforms are
+/// skipped. Hits are tagged with their board as the source for the event builder.
+#[derive(Debug)]
+pub struct CompassReader<R: Read> {
+    reader: R,
+    header: u16,
+}
+
+impl CompassReader<Input> {
+    pub fn open(path: &Path) -> Result<Self, SourceError> {
+        Self::new(compression::open(path)?)
+    }
+}
+
+impl<R: Read> CompassReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, SourceError> {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+        Ok(Self {
+            reader,
+            header: u16::from_le_bytes(header),
+        })
+    }
+
+    fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], SourceError> {
+        let mut bytes = [0u8; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl<R: Read> HitSource for CompassReader<R> {
+    fn next_hit(&mut self) -> Result<Option<(usize, Hit)>, SourceError> {
+        let mut board = [0u8; 2];
+        if !read_or_eof(&mut self.reader, &mut board)? {
+            return Ok(None);
+        }
+        let board = u16::from_le_bytes(board);
+        let channel = u16::from_le_bytes(self.read_bytes()?);
+        let timestamp = u64::from_le_bytes(self.read_bytes()?);
+        let mut values = vec![];
+        if self.header & HAS_ENERGY != 0 {
+            let energy = u16::from_le_bytes(self.read_bytes()?);
+            values.push((String::from("energy"), energy as f32));
+        }
+        if self.header & HAS_CALIBRATED != 0 {
+            let energy = f64::from_le_bytes(self.read_bytes()?);
+            values.push((String::from("energy_calibrated"), energy as f32));
+        }
+        if self.header & HAS_ENERGY_SHORT != 0 {
+            let energy = u16::from_le_bytes(self.read_bytes()?);
+            values.push((String::from("energy_short"), energy as f32));
+        }
+        let flags = u32::from_le_bytes(self.read_bytes()?);
+        values.push((String::from("flags"), flags as f32));
+        if self.header & HAS_WAVEFORM != 0 {
+            let _code: [u8; 1] = self.read_bytes()?;
+            let n_samples = u32::from_le_bytes(self.read_bytes()?);
+            let mut samples = (&mut self.reader).take(u64::from(n_samples) * 2);
+            std::io::copy(&mut samples, &mut std::io::sink())?;
+        }
+        Ok(Some((
+            board as usize,
+            make_hit(board, channel, timestamp, values),
+        )))
+    }
+}
+
+/// Reads hits from a CoMPASS CSV file, whose first line names the columns, e.g.
+/// "BOARD;CHANNEL;TIMETAG;ENERGY;ENERGYSHORT;FLAGS". Columns other than these are ignored.
+#[derive(Debug)]
+pub struct CompassCsvReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+    // The index of each column read, with the value each optional column is stored as
+    board: usize,
+    channel: usize,
+    timestamp: usize,
+    values: Vec<(String, usize)>,
+    flags: Option<usize>,
+}
+
+impl CompassCsvReader<Input> {
+    pub fn open(path: &Path) -> Result<Self, SourceError> {
+        Self::new(compression::open(path)?)
+    }
+}
+
+impl<R: BufRead> CompassCsvReader<R> {
+    pub fn new(reader: R) -> Result<Self, SourceError> {
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        let columns: Vec<&str> = header.trim().split(';').collect();
+        let find = |name: &str| columns.iter().position(|column| *column == name);
+        let require = |name: &str| {
+            find(name).ok_or_else(|| SourceError::Decode(format!("CSV has no column {name}")))
+        };
+        let values = [
+            ("ENERGY", "energy"),
+            ("ENERGYSHORT", "energy_short"),
+            ("CALIB_ENERGY", "energy_calibrated"),
+        ]
+        .iter()
+        .filter_map(|(column, name)| Some((name.to_string(), find(column)?)))
+        .collect();
+        Ok(Self {
+            board: require("BOARD")?,
+            channel: require("CHANNEL")?,
+            timestamp: require("TIMETAG")?,
+            values,
+            flags: find("FLAGS"),
+            lines,
+        })
+    }
+}
+
+fn parse<T: std::str::FromStr>(fields: &[&str], idx: usize) -> Result<T, SourceError> {
+    let field = fields.get(idx).copied().unwrap_or("");
+    field
+        .trim()
+        .parse()
+        .map_err(|_| SourceError::Decode(format!("bad CSV field '{field}'")))
+}
+
+impl<R: BufRead> HitSource for CompassCsvReader<R> {
+    fn next_hit(&mut self) -> Result<Option<(usize, Hit)>, SourceError> {
+        let line = loop {
+            match self.lines.next().transpose()? {
+                Some(line) if line.trim().is_empty() => continue,
+                Some(line) => break line,
+                None => return Ok(None),
+            }
+        };
+        let fields: Vec<&str> = line.split(';').collect();
+        let board: u16 = parse(&fields, self.board)?;
+        let channel: u16 = parse(&fields, self.channel)?;
+        let timestamp: u64 = parse(&fields, self.timestamp)?;
+        let mut values = vec![];
+        for (name, idx) in self.values.iter() {
+            values.push((name.clone(), parse(&fields, *idx)?));
+        }
+        if let Some(idx) = self.flags {
+            let field = fields.get(idx).copied().unwrap_or("").trim();
+            let flags = u32::from_str_radix(field.trim_start_matches("0x"), 16)
+                .map_err(|_| SourceError::Decode(format!("bad CSV flags '{field}'")))?;
+            values.push((String::from("flags"), flags as f32));
+        }
+        Ok(Some((
+            board as usize,
+            make_hit(board, channel, timestamp, values),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compass_formats() {
+        let mut bytes = (HAS_ENERGY | HAS_WAVEFORM).to_le_bytes().to_vec();
+        for (channel, energy) in [(3u16, 1200u16), (4, 800)] {
+            bytes.extend(1u16.to_le_bytes());
+            bytes.extend(channel.to_le_bytes());
+            bytes.extend(5000u64.to_le_bytes());
+            bytes.extend(energy.to_le_bytes());
+            bytes.extend(0x4000u32.to_le_bytes());
+            bytes.push(1);
+            bytes.extend(2u32.to_le_bytes());
+            bytes.extend([0u8; 4]);
+        }
+        let mut reader = CompassReader::new(bytes.as_slice()).unwrap();
+        let (board, hit) = reader.next_hit().unwrap().unwrap();
+        assert_eq!(board, 1);
+        assert_eq!(hit.detector, "b1_c3");
+        assert_eq!(hit.timestamp, 5000);
+        assert_eq!(hit.values[0], (String::from("energy"), 1200.0));
+        let (_, hit) = reader.next_hit().unwrap().unwrap();
+        assert_eq!(hit.values[1], (String::from("flags"), 16384.0));
+        assert!(reader.next_hit().unwrap().is_none());
+
+        let csv = "BOARD;CHANNEL;TIMETAG;ENERGY;ENERGYSHORT;FLAGS\n0;2;123456;1500;300;0x4000\n";
+        let mut reader = CompassCsvReader::new(csv.as_bytes()).unwrap();
+        let (_, hit) = reader.next_hit().unwrap().unwrap();
+        assert_eq!(hit.detector, "b0_c2");
+        assert_eq!(hit.timestamp, 123456);
+        assert_eq!(hit.values[1], (String::from("energy_short"), 300.0));
+        assert_eq!(hit.values[2], (String::from("flags"), 16384.0));
+        assert!(reader.next_hit().unwrap().is_none());
+    }
+}
@@ -0,0 +1,156 @@
+//! Opens input files transparently whether they are plain or compressed with gzip, zstd, or lz4,
+//! as list-mode data almost always is. The compression is detected from the magic bytes at the
+//! start of the file and decompressed as it is read. Each format needs its feature enabled.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+
+/// A decompressed stream of bytes read from an input file
+pub type Input = BufReader<Box<dyn Read + Send>>;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl Compression {
+    /// Detect the compression of a stream from its first bytes
+    pub fn detect(start: &[u8]) -> Self {
+        if start.starts_with(&GZIP_MAGIC) {
+            Self::Gzip
+        } else if start.starts_with(&ZSTD_MAGIC) {
+            Self::Zstd
+        } else if start.starts_with(&LZ4_MAGIC) {
+            Self::Lz4
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Open a file, decompressing it if it is compressed
+pub fn open(path: &Path) -> Result<Input, Error> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+// The error for a compressed stream whose feature is not enabled
+#[cfg(not(all(feature = "gzip", feature = "zstd", feature = "lz4")))]
+fn needs_feature(format: &str) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("input is {format} compressed, which needs the {format} feature"),
+    )
+}
+
+/// Wrap a stream in a decompressor if it is compressed
+pub fn decompress<R: BufRead + Send + 'static>(mut reader: R) -> Result<Input, Error> {
+    let inner: Box<dyn Read + Send> = match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => return Err(needs_feature("gzip")),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(ZstdDecoder::new(reader)?),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => return Err(needs_feature("zstd")),
+        #[cfg(feature = "lz4")]
+        Compression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+        #[cfg(not(feature = "lz4"))]
+        Compression::Lz4 => return Err(needs_feature("lz4")),
+    };
+    Ok(BufReader::new(inner))
+}
+
+/// Decodes every frame of a zstd stream, as parallel compressors write many
+#[cfg(feature = "zstd")]
+struct ZstdDecoder<R: BufRead> {
+    frame: Option<ruzstd::decoding::StreamingDecoder<R, ruzstd::decoding::FrameDecoder>>,
+}
+
+#[cfg(feature = "zstd")]
+impl<R: BufRead> ZstdDecoder<R> {
+    fn new(reader: R) -> Result<Self, Error> {
+        let frame = ruzstd::decoding::StreamingDecoder::new(reader)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Self { frame: Some(frame) })
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<R: BufRead> Read for ZstdDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            let Some(frame) = &mut self.frame else {
+                return Ok(0);
+            };
+            let n_read = frame.read(buf)?;
+            if n_read > 0 || buf.is_empty() {
+                return Ok(n_read);
+            }
+            let mut reader = self.frame.take().expect("Frame exists").into_inner();
+            if !reader.fill_buf()?.is_empty() {
+                *self = Self::new(reader)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress() {
+        let data: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let read_all = |bytes: Vec<u8>| {
+            let mut out = vec![];
+            decompress(std::io::Cursor::new(bytes))?.read_to_end(&mut out)?;
+            Ok::<_, Error>(out)
+        };
+        assert_eq!(read_all(data.clone()).unwrap(), data);
+
+        let path = std::env::temp_dir().join(format!("spect_compression_{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let mut out = vec![];
+        open(&path).unwrap().read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        std::fs::remove_file(&path).unwrap();
+
+        #[cfg(feature = "gzip")]
+        {
+            use std::io::Write;
+            let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(&data).unwrap();
+            let member = encoder.finish().unwrap();
+            // Concatenated gzip files are read as one
+            let out = read_all([member.clone(), member].concat()).unwrap();
+            assert_eq!(out, [data.clone(), data.clone()].concat());
+        }
+        #[cfg(not(feature = "gzip"))]
+        assert!(read_all(GZIP_MAGIC.to_vec()).is_err());
+
+        #[cfg(feature = "zstd")]
+        {
+            use ruzstd::encoding::{CompressionLevel, compress_to_vec};
+            let frame = compress_to_vec(data.as_slice(), CompressionLevel::Fastest);
+            let out = read_all([frame.clone(), frame].concat()).unwrap();
+            assert_eq!(out.len(), data.len() * 2);
+        }
+
+        #[cfg(feature = "lz4")]
+        {
+            use std::io::Write;
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(vec![]);
+            encoder.write_all(&data).unwrap();
+            assert_eq!(read_all(encoder.finish().unwrap()).unwrap(), data);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Contours of 2D histograms by marching squares, e.g. to gate on a particle group by its outline
+use super::cut::{Cut2D, CutSpec};
+use super::error::{CutError, HistogramError};
+use super::geometry;
+use super::histogram::{AxisSpec, Histogram};
+use rustc_hash::FxHashMap;
+use uuid::Uuid;
+
+/// A closed line through the points where the contents of a histogram cross a level. The last
+/// vertex repeats the first, as for Cut2D.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contour {
+    pub level: f64,
+    pub x_values: Vec<f32>,
+    pub y_values: Vec<f32>,
+}
+
+impl Contour {
+    pub fn get_area(&self) -> f32 {
+        geometry::area(&self.x_values, &self.y_values)
+    }
+
+    pub fn to_cut(&self, spec: CutSpec) -> Result<Cut2D, CutError> {
+        Cut2D::new(spec, self.x_values.clone(), self.y_values.clone())
+    }
+}
+
+// An edge between two neighbouring points of the padded grid, by the point at its lower left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    Horizontal(usize, usize),
+    Vertical(usize, usize),
+}
+
+/// Find the contours of a 2D histogram at a level, treating each bin as a point at its centre.
+/// The histogram is surrounded by empty bins, so contours of blobs touching the edges are closed
+/// along the axis limits.
+pub fn find_contours(gram: &Histogram, level: f64) -> Result<Vec<Contour>, HistogramError> {
+    let Some(y_axis) = &gram.spec.y_axis else {
+        return Err(HistogramError::WrongDimensions);
+    };
+    let x_axis = &gram.spec.x_axis;
+    let (x_bins, y_bins) = gram.get_dimensions();
+    let padding = level.min(0.0) - 1.0;
+    let value = |x: usize, y: usize| {
+        if x == 0 || y == 0 || x > x_bins || y > y_bins {
+            padding
+        } else {
+            gram.data[gram.bin_index(x - 1, y - 1)]
+        }
+    };
+    // Where the level crosses an edge, in axis units
+    let point_on = |edge: Edge| {
+        let ((x0, y0), (x1, y1)) = match edge {
+            Edge::Horizontal(x, y) => ((x, y), (x + 1, y)),
+            Edge::Vertical(x, y) => ((x, y), (x, y + 1)),
+        };
+        let (a, b) = (value(x0, y0), value(x1, y1));
+        let t = ((level - a) / (b - a)) as f32;
+        let to_axis = |grid: f32, axis: &AxisSpec| {
+            let position = axis.minimum + (grid - 0.5) * axis.get_bin_width();
+            position.clamp(axis.minimum, axis.maximum)
+        };
+        (
+            to_axis(x0 as f32 + t * (x1 - x0) as f32, x_axis),
+            to_axis(y0 as f32 + t * (y1 - y0) as f32, y_axis),
+        )
+    };
+
+    // Each cell of four grid points gives zero, one, or two segments joining its edges
+    let mut segments: Vec<(Edge, Edge)> = vec![];
+    for x in 0..=x_bins {
+        for y in 0..=y_bins {
+            let corners = [
+                value(x, y),
+                value(x + 1, y),
+                value(x + 1, y + 1),
+                value(x, y + 1),
+            ];
+            let case = corners
+                .iter()
+                .enumerate()
+                .fold(0, |case, (bit, v)| case | (((*v >= level) as usize) << bit));
+            let (bottom, right) = (Edge::Horizontal(x, y), Edge::Vertical(x + 1, y));
+            let (top, left) = (Edge::Horizontal(x, y + 1), Edge::Vertical(x, y));
+            // Saddles are resolved by the average of the corners
+            let is_centre_above = corners.iter().sum::<f64>() / 4.0 >= level;
+            match case {
+                1 | 14 => segments.push((left, bottom)),
+                2 | 13 => segments.push((bottom, right)),
+                3 | 12 => segments.push((left, right)),
+                4 | 11 => segments.push((right, top)),
+                6 | 9 => segments.push((bottom, top)),
+                7 | 8 => segments.push((left, top)),
+                5 if is_centre_above => segments.extend([(left, top), (bottom, right)]),
+                5 => segments.extend([(left, bottom), (right, top)]),
+                10 if is_centre_above => segments.extend([(left, bottom), (right, top)]),
+                10 => segments.extend([(left, top), (bottom, right)]),
+                _ => (),
+            }
+        }
+    }
+
+    // Every crossed edge is shared by exactly two segments, so chaining them gives closed loops
+    let mut by_edge: FxHashMap<Edge, Vec<usize>> = FxHashMap::default();
+    for (idx, (a, b)) in segments.iter().enumerate() {
+        by_edge.entry(*a).or_default().push(idx);
+        by_edge.entry(*b).or_default().push(idx);
+    }
+    let mut is_used = vec![false; segments.len()];
+    let mut contours = vec![];
+    for start in 0..segments.len() {
+        if is_used[start] {
+            continue;
+        }
+        is_used[start] = true;
+        let (first, mut edge) = segments[start];
+        let mut points = vec![point_on(first)];
+        while edge != first {
+            points.push(point_on(edge));
+            let next = by_edge[&edge].iter().find(|idx| !is_used[**idx]).copied();
+            let Some(next) = next else {
+                break;
+            };
+            is_used[next] = true;
+            let (a, b) = segments[next];
+            edge = if a == edge { b } else { a };
+        }
+        points.push(points[0]);
+        let (x_values, y_values) = points.into_iter().unzip();
+        contours.push(Contour {
+            level,
+            x_values,
+            y_values,
+        });
+    }
+    Ok(contours)
+}
+
+/// Pick the contour enclosing a point, or the one with the largest area if no point is given
+pub fn select_contour(contours: &[Contour], point: Option<(f32, f32)>) -> Option<&Contour> {
+    match point {
+        Some((x, y)) => contours
+            .iter()
+            .filter(|contour| {
+                let spec = CutSpec {
+                    id: Uuid::nil(),
+                    name: String::new(),
+                    x_variable: String::new(),
+                    y_variable: Some(String::new()),
+                };
+                contour.to_cut(spec).is_ok_and(|cut| cut.contains(x, y))
+            })
+            // The innermost contour holding the point, for levels with nested contours
+            .min_by(|a, b| a.get_area().total_cmp(&b.get_area())),
+        None => contours
+            .iter()
+            .max_by(|a, b| a.get_area().total_cmp(&b.get_area())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{HistSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_contours() {
+        let mut gram = Histogram::new(HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "pid",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("y", "y", 10, 0.0, 10.0).unwrap()),
+            )
+        });
+        // A 3x3 blob in the middle and a single bin in the corner
+        for x in 3..6 {
+            for y in 3..6 {
+                gram.fill_weighted(x as f32 + 0.5, Some(y as f32 + 0.5), 10.0)
+                    .unwrap();
+            }
+        }
+        gram.fill_weighted(0.5, Some(0.5), 10.0).unwrap();
+
+        let contours = find_contours(&gram, 5.0).unwrap();
+        assert_eq!(contours.len(), 2);
+        let blob = select_contour(&contours, None).unwrap();
+        // Halfway between the centres of the filled and empty bins around the blob
+        assert!((blob.get_area() - 8.5).abs() < 1e-4);
+        let corner = select_contour(&contours, Some((0.5, 0.5))).unwrap();
+        assert_eq!(corner.x_values.first(), corner.x_values.last());
+        assert!(corner.x_values.iter().all(|x| (0.0..=1.0).contains(x)));
+        assert!(select_contour(&contours, Some((8.0, 8.0))).is_none());
+
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("blob"),
+            x_variable: String::from("x"),
+            y_variable: Some(String::from("y")),
+        };
+        let cut = blob.to_cut(spec).unwrap();
+        assert!(cut.contains(4.5, 4.5) && !cut.contains(2.0, 2.0));
+        assert!(find_contours(&gram, 20.0).unwrap().is_empty());
+    }
+}
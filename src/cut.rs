@@ -1,9 +1,14 @@
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::data_blob::DataBlob;
 use super::error::CutError;
+use super::filter::GateCondition;
+use super::geometry;
+use super::histogram::wrap_periodic;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CutSpec {
     pub id: Uuid,
     pub name: String,
@@ -16,6 +21,292 @@ pub trait Cut: std::fmt::Debug {
     fn is_valid(&self) -> bool;
     fn reset(&mut self);
     fn get_spec(&self) -> &CutSpec;
+    /// The name this kind of cut is registered under in a CutRegistry
+    fn get_kind(&self) -> &str;
+    /// Everything beyond the spec needed to rebuild the cut with its registered factory
+    fn get_parameters(&self) -> serde_json::Value;
+    /// Check if a point in the variables of the cut is inside it, for cuts which can be tested
+    /// geometrically. Returns None if the cut cannot test the point.
+    fn contains_point(&self, _x: f32, _y: Option<f32>) -> Option<bool> {
+        None
+    }
+}
+
+/// A test on the bits of a flag variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlagCondition {
+    Equals(u64),
+    /// Every bit of the mask is set
+    AllSet(u64),
+    /// At least one bit of the mask is set
+    AnySet(u64),
+    /// No bit of the mask is set
+    NoneSet(u64),
+}
+
+impl FlagCondition {
+    pub fn is_satisfied(&self, bits: u64) -> bool {
+        match self {
+            Self::Equals(value) => bits == *value,
+            Self::AllSet(mask) => bits & mask == *mask,
+            Self::AnySet(mask) => bits & mask != 0,
+            Self::NoneSet(mask) => bits & mask == 0,
+        }
+    }
+}
+
+/// Gates on a flag variable of the event. Events without the flag are outside the cut.
+#[derive(Debug)]
+pub struct FlagCut {
+    spec: CutSpec,
+    condition: FlagCondition,
+    is_valid: bool,
+}
+
+impl Cut for FlagCut {
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn is_inside(&mut self, blob: &DataBlob) {
+        self.is_valid = blob
+            .find_flag(&self.spec.x_variable)
+            .is_some_and(|bits| self.condition.is_satisfied(bits));
+    }
+
+    fn reset(&mut self) {
+        self.is_valid = false;
+    }
+
+    fn get_spec(&self) -> &CutSpec {
+        &self.spec
+    }
+
+    fn get_kind(&self) -> &str {
+        Self::KIND
+    }
+
+    fn get_parameters(&self) -> serde_json::Value {
+        serde_json::to_value(self.condition).expect("Cut parameters are always serializable")
+    }
+}
+
+impl FlagCut {
+    pub const KIND: &str = "FlagCut";
+
+    /// The flag is named by the x_variable of the spec
+    pub fn new(spec: CutSpec, condition: FlagCondition) -> Result<Self, CutError> {
+        if spec.y_variable.is_some() {
+            return Err(CutError::BadParameters(String::from(
+                "Flag cuts test a single flag",
+            )));
+        }
+        Ok(Self {
+            spec,
+            condition,
+            is_valid: false,
+        })
+    }
+
+    pub fn from_parameters(
+        spec: CutSpec,
+        parameters: &serde_json::Value,
+    ) -> Result<Self, CutError> {
+        let condition = serde_json::from_value(parameters.clone())
+            .map_err(|e| CutError::BadParameters(e.to_string()))?;
+        Self::new(spec, condition)
+    }
+
+    pub fn get_condition(&self) -> FlagCondition {
+        self.condition
+    }
+}
+
+/// Gates on the number of entries in an array variable, such as the number of gammas detected
+/// in an event. Events without the array have a multiplicity of zero.
+#[derive(Debug)]
+pub struct MultiplicityCut {
+    spec: CutSpec,
+    minimum: usize,
+    maximum: Option<usize>,
+    is_valid: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MultiplicityParameters {
+    minimum: usize,
+    maximum: Option<usize>,
+}
+
+impl Cut for MultiplicityCut {
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn is_inside(&mut self, blob: &DataBlob) {
+        let multiplicity = blob.get_multiplicity(&self.spec.x_variable);
+        self.is_valid = multiplicity >= self.minimum
+            && self.maximum.is_none_or(|maximum| multiplicity <= maximum);
+    }
+
+    fn reset(&mut self) {
+        self.is_valid = false;
+    }
+
+    fn get_spec(&self) -> &CutSpec {
+        &self.spec
+    }
+
+    fn get_kind(&self) -> &str {
+        Self::KIND
+    }
+
+    fn get_parameters(&self) -> serde_json::Value {
+        serde_json::to_value(MultiplicityParameters {
+            minimum: self.minimum,
+            maximum: self.maximum,
+        })
+        .expect("Cut parameters are always serializable")
+    }
+}
+
+impl MultiplicityCut {
+    pub const KIND: &str = "MultiplicityCut";
+
+    /// Accept events whose array named by the x_variable of the spec has between minimum and
+    /// maximum entries, inclusive. With no maximum, any multiplicity of at least minimum passes.
+    pub fn new(spec: CutSpec, minimum: usize, maximum: Option<usize>) -> Result<Self, CutError> {
+        if spec.y_variable.is_some() || maximum.is_some_and(|maximum| maximum < minimum) {
+            return Err(CutError::BadParameters(format!(
+                "Invalid multiplicity range {minimum} to {maximum:?}"
+            )));
+        }
+        Ok(Self {
+            spec,
+            minimum,
+            maximum,
+            is_valid: false,
+        })
+    }
+
+    pub fn from_parameters(
+        spec: CutSpec,
+        parameters: &serde_json::Value,
+    ) -> Result<Self, CutError> {
+        let parameters: MultiplicityParameters = serde_json::from_value(parameters.clone())
+            .map_err(|e| CutError::BadParameters(e.to_string()))?;
+        Self::new(spec, parameters.minimum, parameters.maximum)
+    }
+}
+
+/// The shape of another cut bound to a different set of variables, so that one gate drawn once
+/// can be applied to many channels. The spec gives the ID, name, and variables of the binding;
+/// the shape is looked up at evaluation time, so editing the shape changes every binding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CutBinding {
+    pub spec: CutSpec,
+    pub shape_id: Uuid,
+}
+
+impl CutBinding {
+    fn evaluate(&self, cuts: &FxHashMap<Uuid, Box<dyn Cut>>, data: &DataBlob) -> bool {
+        let shape = match cuts.get(&self.shape_id) {
+            Some(shape) => shape,
+            None => return false,
+        };
+        let x = match data.find(&self.spec.x_variable) {
+            Some(x) => *x,
+            None => return false,
+        };
+        let y = match &self.spec.y_variable {
+            Some(y_variable) => match data.find(y_variable) {
+                Some(y) => Some(*y),
+                None => return false,
+            },
+            None => None,
+        };
+        shape.contains_point(x, y).unwrap_or(false)
+    }
+}
+
+/// A cut made by combining other cuts, which may themselves be compound
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompoundCut {
+    pub id: Uuid,
+    pub name: String,
+    pub condition: GateCondition,
+}
+
+/// How often a cut has been evaluated and how often events were inside it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CutStats {
+    pub evaluated: u64,
+    pub accepted: u64,
+}
+
+impl CutStats {
+    /// Get the fraction of evaluated events which were inside the cut
+    pub fn get_acceptance(&self) -> Option<f64> {
+        (self.evaluated > 0).then(|| self.accepted as f64 / self.evaluated as f64)
+    }
+}
+
+/// Evaluates cuts on demand while processing one event, so that each cut is evaluated at most
+/// once and cuts nothing asks about are never evaluated
+#[derive(Debug)]
+pub struct CutEvaluation<'a> {
+    cuts: &'a mut FxHashMap<Uuid, Box<dyn Cut>>,
+    bindings: &'a FxHashMap<Uuid, CutBinding>,
+    compounds: &'a FxHashMap<Uuid, CompoundCut>,
+    evaluated: &'a mut FxHashMap<Uuid, bool>,
+    data: &'a DataBlob,
+}
+
+impl<'a> CutEvaluation<'a> {
+    /// Start evaluating an event. The evaluated map is scratch space, kept by the caller to avoid
+    /// allocating per event.
+    pub fn new(
+        cuts: &'a mut FxHashMap<Uuid, Box<dyn Cut>>,
+        bindings: &'a FxHashMap<Uuid, CutBinding>,
+        compounds: &'a FxHashMap<Uuid, CompoundCut>,
+        evaluated: &'a mut FxHashMap<Uuid, bool>,
+        data: &'a DataBlob,
+    ) -> Self {
+        evaluated.clear();
+        Self {
+            cuts,
+            bindings,
+            compounds,
+            evaluated,
+            data,
+        }
+    }
+
+    /// Check if the event is inside a cut, binding, or compound cut, returning None if there is
+    /// nothing with the ID
+    pub fn check(&mut self, id: &Uuid) -> Option<bool> {
+        if let Some(result) = self.evaluated.get(id) {
+            return Some(*result);
+        }
+        let compounds = self.compounds;
+        let result = if let Some(cut) = self.cuts.get_mut(id) {
+            cut.is_inside(self.data);
+            cut.is_valid()
+        } else if let Some(compound) = compounds.get(id) {
+            // Marked as failing while in progress, so that a cycle which slipped past validation
+            // cannot recurse forever
+            self.evaluated.insert(*id, false);
+            compound.condition.is_satisfied(self)
+        } else {
+            self.bindings.get(id)?.evaluate(self.cuts, self.data)
+        };
+        self.evaluated.insert(*id, result);
+        Some(result)
+    }
+
+    pub fn get_n_evaluated(&self) -> usize {
+        self.evaluated.len()
+    }
 }
 
 #[derive(Debug)]
@@ -23,6 +314,8 @@ pub struct Cut1D {
     spec: CutSpec,
     low: f32,
     high: f32,
+    // The (minimum, maximum) of one period for cuts on periodic variables
+    period: Option<(f32, f32)>,
     is_valid: bool,
 }
 
@@ -33,7 +326,7 @@ impl Cut for Cut1D {
 
     fn is_inside(&mut self, blob: &DataBlob) {
         self.is_valid = match blob.find(&self.spec.x_variable) {
-            Some(x) => *x > self.low && *x < self.high,
+            Some(x) => self.contains(*x),
             None => false,
         };
     }
@@ -45,9 +338,73 @@ impl Cut for Cut1D {
     fn get_spec(&self) -> &CutSpec {
         &self.spec
     }
+
+    fn get_kind(&self) -> &str {
+        Self::KIND
+    }
+
+    fn get_parameters(&self) -> serde_json::Value {
+        serde_json::to_value(Cut1DParameters {
+            low: self.low,
+            high: self.high,
+            period: self.period,
+        })
+        .expect("Cut parameters are always serializable")
+    }
+
+    fn contains_point(&self, x: f32, _y: Option<f32>) -> Option<bool> {
+        Some(self.contains(x))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cut1DParameters {
+    low: f32,
+    high: f32,
+    period: Option<(f32, f32)>,
 }
 
 impl Cut1D {
+    pub const KIND: &str = "Cut1D";
+
+    /// Get the (low, high) of the window
+    pub fn get_limits(&self) -> (f32, f32) {
+        (self.low, self.high)
+    }
+
+    pub fn get_period(&self) -> Option<(f32, f32)> {
+        self.period
+    }
+
+    pub fn contains(&self, x: f32) -> bool {
+        match self.period {
+            None => x > self.low && x < self.high,
+            Some((minimum, maximum)) => {
+                let x = wrap_periodic(x, minimum, maximum);
+                // A wrap-around interval such as 350 to 10 degrees spans the seam of the period
+                if self.low < self.high {
+                    x > self.low && x < self.high
+                } else {
+                    x > self.low || x < self.high
+                }
+            }
+        }
+    }
+
+    pub fn from_parameters(
+        spec: CutSpec,
+        parameters: &serde_json::Value,
+    ) -> Result<Self, CutError> {
+        let parameters: Cut1DParameters = serde_json::from_value(parameters.clone())
+            .map_err(|e| CutError::BadParameters(e.to_string()))?;
+        match parameters.period {
+            Some((minimum, maximum)) => {
+                Self::new_periodic(spec, parameters.low, parameters.high, minimum, maximum)
+            }
+            None => Self::new(spec, parameters.low, parameters.high),
+        }
+    }
+
     pub fn new(spec: CutSpec, low: f32, high: f32) -> Result<Self, CutError> {
         if low > high || high == low {
             Err(CutError::Invalid1D(low, high))
@@ -56,10 +413,92 @@ impl Cut1D {
                 spec,
                 low,
                 high,
+                period: None,
                 is_valid: false,
             })
         }
     }
+
+    /// Create a cut on a periodic variable with the period [minimum, maximum). The interval is
+    /// taken from low going up to high, so low may be greater than high to wrap around the seam.
+    pub fn new_periodic(
+        spec: CutSpec,
+        low: f32,
+        high: f32,
+        minimum: f32,
+        maximum: f32,
+    ) -> Result<Self, CutError> {
+        if minimum >= maximum {
+            return Err(CutError::Invalid1D(minimum, maximum));
+        }
+        let (low, high) = (
+            wrap_periodic(low, minimum, maximum),
+            wrap_periodic(high, minimum, maximum),
+        );
+        if low == high {
+            return Err(CutError::Invalid1D(low, high));
+        }
+        Ok(Self {
+            spec,
+            low,
+            high,
+            period: Some((minimum, maximum)),
+            is_valid: false,
+        })
+    }
+}
+
+/// Polygons with more vertices than this get a grid to speed up evaluation
+pub const GRID_VERTEX_THRESHOLD: usize = 64;
+pub const DEFAULT_GRID_DIVISIONS: usize = 32;
+
+/// The axis-aligned box enclosing a polygon
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub x_min: f32,
+    pub x_max: f32,
+    pub y_min: f32,
+    pub y_max: f32,
+}
+
+impl BoundingBox {
+    fn enclosing(x_values: &[f32], y_values: &[f32]) -> Self {
+        let fold = |values: &[f32]| {
+            values
+                .iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+                    (min.min(*value), max.max(*value))
+                })
+        };
+        let (x_min, x_max) = fold(x_values);
+        let (y_min, y_max) = fold(y_values);
+        Self {
+            x_min,
+            x_max,
+            y_min,
+            y_max,
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x_min && x <= self.x_max && y >= self.y_min && y <= self.y_max
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GridCell {
+    Inside,
+    Outside,
+    /// An edge passes near the cell, so points in it need the full polygon test
+    Boundary,
+}
+
+/// A coarse grid over the bounding box of a polygon. Cells no edge touches are entirely inside or
+/// outside, so only points in boundary cells need the full test.
+#[derive(Debug, Clone)]
+struct CutGrid {
+    divisions: usize,
+    cells: Vec<GridCell>,
 }
 
 #[derive(Debug)]
@@ -67,6 +506,10 @@ pub struct Cut2D {
     spec: CutSpec,
     x_values: Vec<f32>,
     y_values: Vec<f32>,
+    bounds: BoundingBox,
+    // dx/dy of each edge, for finding where it crosses a horizontal ray
+    inverse_slopes: Vec<f32>,
+    grid: Option<CutGrid>,
     is_valid: bool,
 }
 
@@ -75,7 +518,6 @@ impl Cut for Cut2D {
         self.is_valid
     }
 
-    // Use even odd rule to determine if the point is inside the polygon
     fn is_inside(&mut self, blob: &DataBlob) {
         self.is_valid = false;
         if let Some(y_name) = &self.spec.y_variable {
@@ -83,28 +525,11 @@ impl Cut for Cut2D {
                 Some(val) => *val,
                 None => return,
             };
-            let y = match blob.find(&y_name) {
+            let y = match blob.find(y_name) {
                 Some(val) => *val,
                 None => return,
             };
-
-            let mut slope: f32;
-            for idx in 0..(self.x_values.len() - 1) {
-                if x == self.x_values[idx] && y == self.y_values[idx] {
-                    self.is_valid = true;
-                    return;
-                }
-
-                slope = (x - self.x_values[idx]) * (self.y_values[idx + 1] - self.y_values[idx])
-                    - (self.x_values[idx + 1] - self.x_values[idx]) * (y - self.y_values[idx]);
-
-                if slope == 0.0 {
-                    self.is_valid = true;
-                    return;
-                } else if (slope < 0.0) != (self.y_values[idx + 1] < self.y_values[idx]) {
-                    self.is_valid = !self.is_valid;
-                }
-            }
+            self.is_valid = self.contains(x, y);
         }
     }
 
@@ -115,21 +540,299 @@ impl Cut for Cut2D {
     fn get_spec(&self) -> &CutSpec {
         &self.spec
     }
+
+    fn get_kind(&self) -> &str {
+        Self::KIND
+    }
+
+    fn get_parameters(&self) -> serde_json::Value {
+        serde_json::to_value(Cut2DParameters {
+            x_values: self.x_values.clone(),
+            y_values: self.y_values.clone(),
+        })
+        .expect("Cut parameters are always serializable")
+    }
+
+    fn contains_point(&self, x: f32, y: Option<f32>) -> Option<bool> {
+        y.map(|y| self.contains(x, y))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Cut2DParameters {
+    x_values: Vec<f32>,
+    y_values: Vec<f32>,
 }
 
 impl Cut2D {
+    pub const KIND: &str = "Cut2D";
+
+    pub fn from_parameters(
+        spec: CutSpec,
+        parameters: &serde_json::Value,
+    ) -> Result<Self, CutError> {
+        let parameters: Cut2DParameters = serde_json::from_value(parameters.clone())
+            .map_err(|e| CutError::BadParameters(e.to_string()))?;
+        Self::new(spec, parameters.x_values, parameters.y_values)
+    }
+
+    /// Polygons with more than GRID_VERTEX_THRESHOLD vertices get a grid automatically
     pub fn new(spec: CutSpec, x_values: Vec<f32>, y_values: Vec<f32>) -> Result<Self, CutError> {
         if spec.y_variable.is_none() || x_values.len() != y_values.len() || x_values.len() < 3 {
             Err(CutError::Invalid2D)
         } else if x_values.first() != x_values.last() || y_values.first() != y_values.last() {
             Err(CutError::Unclosed2D)
         } else {
-            Ok(Self {
+            let inverse_slopes = x_values
+                .windows(2)
+                .zip(y_values.windows(2))
+                .map(|(x, y)| (x[1] - x[0]) / (y[1] - y[0]))
+                .collect();
+            let mut cut = Self {
                 spec,
+                bounds: BoundingBox::enclosing(&x_values, &y_values),
                 x_values,
                 y_values,
+                inverse_slopes,
+                grid: None,
                 is_valid: false,
+            };
+            if cut.x_values.len() > GRID_VERTEX_THRESHOLD {
+                cut.set_grid(Some(DEFAULT_GRID_DIVISIONS));
+            }
+            Ok(cut)
+        }
+    }
+
+    /// Make a cut from the convex hull of a point cloud
+    pub fn from_points(
+        spec: CutSpec,
+        x_values: &[f32],
+        y_values: &[f32],
+    ) -> Result<Self, CutError> {
+        let (x_values, y_values) =
+            geometry::convex_hull(x_values, y_values).ok_or(CutError::Invalid2D)?;
+        Self::new(spec, x_values, y_values)
+    }
+
+    /// Make a cut from a hand-drawn polygon, dropping vertices within tolerance of its outline
+    pub fn new_simplified(
+        spec: CutSpec,
+        x_values: Vec<f32>,
+        y_values: Vec<f32>,
+        tolerance: f32,
+    ) -> Result<Self, CutError> {
+        if x_values.len() != y_values.len() {
+            return Err(CutError::Invalid2D);
+        }
+        let (x_values, y_values) = geometry::simplify(&x_values, &y_values, tolerance);
+        Self::new(spec, x_values, y_values)
+    }
+
+    pub fn get_bounds(&self) -> &BoundingBox {
+        &self.bounds
+    }
+
+    pub fn get_vertices(&self) -> (&[f32], &[f32]) {
+        (&self.x_values, &self.y_values)
+    }
+
+    pub fn get_area(&self) -> f32 {
+        geometry::area(&self.x_values, &self.y_values)
+    }
+
+    pub fn get_centroid(&self) -> Option<(f32, f32)> {
+        geometry::centroid(&self.x_values, &self.y_values)
+    }
+
+    /// Use a grid with divisions cells per axis to accelerate evaluation, or remove it with None
+    pub fn set_grid(&mut self, divisions: Option<usize>) {
+        self.grid = None;
+        let divisions = match divisions {
+            Some(divisions) if divisions > 0 => divisions,
+            _ => return,
+        };
+        let (width, height) = self.cell_size(divisions);
+        let mut cells = vec![None; divisions * divisions];
+        // Mark every cell overlapping the bounding box of an edge
+        for idx in 0..(self.x_values.len() - 1) {
+            let edge =
+                BoundingBox::enclosing(&self.x_values[idx..idx + 2], &self.y_values[idx..idx + 2]);
+            let (x_low, y_low) = self.cell_of(edge.x_min, edge.y_min, divisions);
+            let (x_high, y_high) = self.cell_of(edge.x_max, edge.y_max, divisions);
+            for y_cell in y_low..=y_high {
+                for x_cell in x_low..=x_high {
+                    cells[y_cell * divisions + x_cell] = Some(GridCell::Boundary);
+                }
+            }
+        }
+        let cells = cells
+            .into_iter()
+            .enumerate()
+            .map(|(idx, cell)| {
+                cell.unwrap_or_else(|| {
+                    let x = self.bounds.x_min + ((idx % divisions) as f32 + 0.5) * width;
+                    let y = self.bounds.y_min + ((idx / divisions) as f32 + 0.5) * height;
+                    if self.polygon_contains(x, y) {
+                        GridCell::Inside
+                    } else {
+                        GridCell::Outside
+                    }
+                })
             })
+            .collect();
+        self.grid = Some(CutGrid { divisions, cells });
+    }
+
+    fn cell_size(&self, divisions: usize) -> (f32, f32) {
+        (
+            (self.bounds.x_max - self.bounds.x_min) / divisions as f32,
+            (self.bounds.y_max - self.bounds.y_min) / divisions as f32,
+        )
+    }
+
+    // The (x, y) cell holding a point inside the bounding box
+    fn cell_of(&self, x: f32, y: f32, divisions: usize) -> (usize, usize) {
+        let (width, height) = self.cell_size(divisions);
+        let cell =
+            |value: f32, min: f32, size: f32| (((value - min) / size) as usize).min(divisions - 1);
+        (
+            cell(x, self.bounds.x_min, width),
+            cell(y, self.bounds.y_min, height),
+        )
+    }
+
+    /// Check if a point is inside the polygon
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        if !self.bounds.contains(x, y) {
+            return false;
+        }
+        if let Some(grid) = &self.grid {
+            let (x_cell, y_cell) = self.cell_of(x, y, grid.divisions);
+            match grid.cells[y_cell * grid.divisions + x_cell] {
+                GridCell::Inside => return true,
+                GridCell::Outside => return false,
+                GridCell::Boundary => (),
+            }
+        }
+        self.polygon_contains(x, y)
+    }
+
+    // Use even odd rule to determine if the point is inside the polygon, counting crossings of a
+    // ray from the point towards +x
+    fn polygon_contains(&self, x: f32, y: f32) -> bool {
+        let mut inside = false;
+        for idx in 0..(self.x_values.len() - 1) {
+            let (y0, y1) = (self.y_values[idx], self.y_values[idx + 1]);
+            if (y0 > y) != (y1 > y) {
+                let x_cross = self.x_values[idx] + (y - y0) * self.inverse_slopes[idx];
+                if x < x_cross {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_periodic_cut() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("seam"),
+            x_variable: String::from("phi"),
+            y_variable: None,
+        };
+        assert!(Cut1D::new(spec.clone(), 350.0, 10.0).is_err());
+        assert!(Cut1D::new_periodic(spec.clone(), 10.0, 370.0, 0.0, 360.0).is_err());
+        let mut cut = Cut1D::new_periodic(spec, 350.0, 370.0, 0.0, 360.0).unwrap();
+
+        let mut blob = DataBlob::new();
+        for (phi, inside) in [(355.0, true), (5.0, true), (-5.0, true), (180.0, false)] {
+            blob.insert("phi", phi);
+            cut.is_inside(&blob);
+            assert_eq!(cut.is_valid(), inside, "phi: {phi}");
+        }
+    }
+
+    #[test]
+    fn test_cut_2d_grid() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("ell"),
+            x_variable: String::from("x"),
+            y_variable: Some(String::from("y")),
+        };
+        // An L shape, so that the grid has cells outside the polygon but inside its bounds
+        let x_values = vec![0.0, 4.0, 4.0, 1.0, 1.0, 0.0, 0.0];
+        let y_values = vec![0.0, 0.0, 1.0, 1.0, 4.0, 4.0, 0.0];
+        let mut cut = Cut2D::new(spec, x_values, y_values).unwrap();
+        assert_eq!(
+            cut.get_bounds(),
+            &BoundingBox {
+                x_min: 0.0,
+                x_max: 4.0,
+                y_min: 0.0,
+                y_max: 4.0
+            }
+        );
+        assert!(cut.contains(0.5, 3.5));
+        assert!(cut.contains(3.5, 0.5));
+        assert!(!cut.contains(2.5, 2.5));
+        assert!(!cut.contains(5.0, 0.5));
+
+        let points: Vec<(f32, f32)> = (0..50)
+            .flat_map(|ix| (0..50).map(move |iy| (ix as f32 * 0.1 - 0.5, iy as f32 * 0.1 - 0.5)))
+            .collect();
+        let expected: Vec<bool> = points.iter().map(|(x, y)| cut.contains(*x, *y)).collect();
+        cut.set_grid(Some(8));
+        let gridded: Vec<bool> = points.iter().map(|(x, y)| cut.contains(*x, *y)).collect();
+        assert_eq!(gridded, expected);
+    }
+
+    #[test]
+    fn test_flag_cut() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("no_pileup"),
+            x_variable: String::from("status"),
+            y_variable: None,
+        };
+        let mut cut = FlagCut::new(spec, FlagCondition::NoneSet(0b10)).unwrap();
+        let mut blob = DataBlob::new();
+        cut.is_inside(&blob);
+        assert!(!cut.is_valid());
+        for (bits, inside) in [(0b00, true), (0b01, true), (0b10, false), (0b11, false)] {
+            blob.insert_flag("status", bits);
+            cut.is_inside(&blob);
+            assert_eq!(cut.is_valid(), inside, "bits: {bits:b}");
+        }
+        assert!(FlagCondition::AllSet(0b11).is_satisfied(0b111));
+        assert!(!FlagCondition::AllSet(0b11).is_satisfied(0b101));
+        assert!(FlagCondition::AnySet(0b11).is_satisfied(0b101));
+        assert!(FlagCondition::Equals(1).is_satisfied(1));
+    }
+
+    #[test]
+    fn test_multiplicity_cut() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("fold_2"),
+            x_variable: String::from("gamma"),
+            y_variable: None,
+        };
+        assert!(MultiplicityCut::new(spec.clone(), 3, Some(2)).is_err());
+        let mut cut = MultiplicityCut::new(spec, 2, Some(3)).unwrap();
+        let mut blob = DataBlob::new();
+        for (n_gammas, inside) in [(0, false), (1, false), (2, true), (3, true), (4, false)] {
+            blob.insert_array("gamma", vec![1.0; n_gammas]);
+            cut.is_inside(&blob);
+            assert_eq!(cut.is_valid(), inside, "multiplicity: {n_gammas}");
         }
     }
 }
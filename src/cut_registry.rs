@@ -0,0 +1,215 @@
+use super::cut::{Cut, Cut1D, Cut2D, CutSpec, FlagCut, MultiplicityCut};
+use super::error::CutError;
+#[cfg(feature = "scripting")]
+use super::script::ScriptCut;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A cut in serializable form. The kind selects the factory used to rebuild it, and the
+/// parameters are whatever that kind of cut needs beyond its spec.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedCut {
+    pub kind: String,
+    pub spec: CutSpec,
+    pub parameters: serde_json::Value,
+}
+
+impl SavedCut {
+    pub fn from_cut(cut: &dyn Cut) -> Self {
+        Self {
+            kind: cut.get_kind().to_string(),
+            spec: cut.get_spec().clone(),
+            parameters: cut.get_parameters(),
+        }
+    }
+}
+
+/// Cuts serialize as a SavedCut
+impl Serialize for dyn Cut {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedCut::from_cut(self).serialize(serializer)
+    }
+}
+
+/// Only the built-in kinds of cut can be deserialized directly. Cuts of kinds defined outside
+/// this crate are deserialized as a SavedCut and built by a CutRegistry they are registered with.
+impl<'de> Deserialize<'de> for Box<dyn Cut> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedCut::deserialize(deserializer)?;
+        CutRegistry::default()
+            .build(&saved)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+pub type CutFactory = fn(CutSpec, &serde_json::Value) -> Result<Box<dyn Cut>, CutError>;
+
+/// Maps cut kinds to the factories that rebuild them, so that cut types defined outside this
+/// crate can be saved and restored alongside the built-in ones
+#[derive(Debug, Clone)]
+pub struct CutRegistry {
+    factories: FxHashMap<String, CutFactory>,
+}
+
+impl Default for CutRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            factories: FxHashMap::default(),
+        };
+        registry.register(Cut1D::KIND, |spec, parameters| {
+            Ok(Box::new(Cut1D::from_parameters(spec, parameters)?))
+        });
+        registry.register(Cut2D::KIND, |spec, parameters| {
+            Ok(Box::new(Cut2D::from_parameters(spec, parameters)?))
+        });
+        registry.register(FlagCut::KIND, |spec, parameters| {
+            Ok(Box::new(FlagCut::from_parameters(spec, parameters)?))
+        });
+        registry.register(MultiplicityCut::KIND, |spec, parameters| {
+            Ok(Box::new(MultiplicityCut::from_parameters(
+                spec, parameters,
+            )?))
+        });
+        #[cfg(feature = "scripting")]
+        registry.register(ScriptCut::KIND, |spec, parameters| {
+            Ok(Box::new(ScriptCut::from_parameters(spec, parameters)?))
+        });
+        registry
+    }
+}
+
+impl CutRegistry {
+    /// Register a factory for a kind of cut, replacing any factory already registered for it
+    pub fn register(&mut self, kind: &str, factory: CutFactory) {
+        self.factories.insert(kind.to_string(), factory);
+    }
+
+    pub fn is_registered(&self, kind: &str) -> bool {
+        self.factories.contains_key(kind)
+    }
+
+    pub fn build(&self, saved: &SavedCut) -> Result<Box<dyn Cut>, CutError> {
+        match self.factories.get(&saved.kind) {
+            Some(factory) => factory(saved.spec.clone(), &saved.parameters),
+            None => Err(CutError::UnknownKind(saved.kind.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use uuid::Uuid;
+
+    /// A cut accepting events where a variable is exactly some value
+    #[derive(Debug)]
+    struct EqualsCut {
+        spec: CutSpec,
+        value: f32,
+        is_valid: bool,
+    }
+
+    impl Cut for EqualsCut {
+        fn is_inside(&mut self, blob: &DataBlob) {
+            self.is_valid = blob.find(&self.spec.x_variable) == Some(&self.value);
+        }
+
+        fn is_valid(&self) -> bool {
+            self.is_valid
+        }
+
+        fn reset(&mut self) {
+            self.is_valid = false;
+        }
+
+        fn get_spec(&self) -> &CutSpec {
+            &self.spec
+        }
+
+        fn get_kind(&self) -> &str {
+            "Equals"
+        }
+
+        fn get_parameters(&self) -> serde_json::Value {
+            serde_json::json!(self.value)
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("cut"),
+            x_variable: String::from("x"),
+            y_variable: Some(String::from("y")),
+        };
+        let mut registry = CutRegistry::default();
+        let square = Cut2D::new(
+            spec.clone(),
+            vec![0.0, 1.0, 1.0, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0, 1.0, 0.0],
+        )
+        .unwrap();
+        let saved = SavedCut::from_cut(&square);
+        let json = serde_json::to_string(&saved).unwrap();
+        let restored: SavedCut = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, saved);
+        let mut cut = registry.build(&restored).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("x", 0.5);
+        blob.insert("y", 0.5);
+        cut.is_inside(&blob);
+        assert!(cut.is_valid());
+
+        let equals = SavedCut::from_cut(&EqualsCut {
+            spec: spec.clone(),
+            value: 0.5,
+            is_valid: false,
+        });
+        assert!(matches!(
+            registry.build(&equals),
+            Err(CutError::UnknownKind(_))
+        ));
+        registry.register("Equals", |spec, parameters| {
+            let value = parameters
+                .as_f64()
+                .ok_or_else(|| CutError::BadParameters(parameters.to_string()))?;
+            Ok(Box::new(EqualsCut {
+                spec,
+                value: value as f32,
+                is_valid: false,
+            }))
+        });
+        let mut cut = registry.build(&equals).unwrap();
+        cut.is_inside(&blob);
+        assert!(cut.is_valid());
+    }
+
+    #[test]
+    fn test_serde() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("low"),
+            x_variable: String::from("x"),
+            y_variable: None,
+        };
+        let cuts: Vec<Box<dyn Cut>> = vec![Box::new(Cut1D::new(spec.clone(), 0.0, 1.0).unwrap())];
+        let json = serde_json::to_string(&cuts).unwrap();
+        let mut restored: Vec<Box<dyn Cut>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored[0].get_spec(), &spec);
+        let mut blob = DataBlob::new();
+        blob.insert("x", 0.5);
+        restored[0].is_inside(&blob);
+        assert!(restored[0].is_valid());
+
+        let equals: &dyn Cut = &EqualsCut {
+            spec,
+            value: 0.5,
+            is_valid: false,
+        };
+        let json = serde_json::to_string(equals).unwrap();
+        assert!(serde_json::from_str::<Box<dyn Cut>>(&json).is_err());
+        assert!(serde_json::from_str::<SavedCut>(&json).is_ok());
+    }
+}
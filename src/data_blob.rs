@@ -1,12 +1,361 @@
+use super::pattern;
 use rustc_hash::FxHashMap;
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug)]
+/// The implicit variable holding the time of an event in seconds, which axes made with
+/// AxisSpec::new_time are filled with. It is set by sources whose data carry a time, and by the
+/// manager from the wall clock for events without one.
+pub const TIMESTAMP_VARIABLE: &str = "__timestamp";
+
+/// Separates the segments of a variable path, e.g. "clover.3.energy"
+pub const PATH_SEPARATOR: char = '.';
+
+// A stored variable, which is only part of the event if it was set since the last clear
+#[derive(Debug, Clone)]
+struct Slot<T> {
+    value: T,
+    generation: u64,
+}
+
+/// The variables of one event. Clearing a blob keeps its variable names and array allocations,
+/// so a blob refilled with the same variables every event does not allocate; see BlobPool.
+///
+/// Names may be paths of segments separated by PATH_SEPARATOR, e.g. "clover.3.energy", so that
+/// structured detector data need no naming convention. Paths are queried with find_all and
+/// get_children.
+#[derive(Debug, Clone, Default)]
 pub struct DataBlob {
-    map: FxHashMap<String, f32>,
+    map: FxHashMap<String, Slot<f32>>,
+    // Condition bits such as pile-up flags or trigger types, kept apart from the values
+    flags: FxHashMap<String, Slot<u64>>,
+    // Variables with one value per hit, such as the energies of every gamma in an event
+    arrays: FxHashMap<String, Slot<Vec<f32>>>,
+    // Kept apart from the values, which are too coarse for the seconds of a wall clock
+    timestamp: Option<Slot<f64>>,
+    // Bumped by clear, which leaves every slot from before stale
+    generation: u64,
+    n_values: usize,
 }
 
 impl DataBlob {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove every variable, keeping the storage for the next event
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        self.n_values = 0;
+    }
+
+    /// Release the storage of variables which are not part of the current event
+    pub fn shrink(&mut self) {
+        let generation = self.generation;
+        self.map.retain(|_, slot| slot.generation == generation);
+        self.flags.retain(|_, slot| slot.generation == generation);
+        self.arrays.retain(|_, slot| slot.generation == generation);
+    }
+
+    pub fn insert(&mut self, variable: &str, value: f32) {
+        let generation = self.generation;
+        match self.map.get_mut(variable) {
+            Some(slot) => {
+                if slot.generation != generation {
+                    self.n_values += 1;
+                }
+                *slot = Slot { value, generation };
+            }
+            None => {
+                self.n_values += 1;
+                self.map
+                    .insert(variable.to_string(), Slot { value, generation });
+            }
+        }
+    }
+
     pub fn find(&self, variable: &str) -> Option<&f32> {
-        self.map.get(variable)
+        self.map
+            .get(variable)
+            .filter(|slot| slot.generation == self.generation)
+            .map(|slot| &slot.value)
+    }
+
+    /// Remove a value from the event, returning it if it was there
+    pub fn remove(&mut self, variable: &str) -> Option<f32> {
+        let generation = self.generation;
+        let slot = self
+            .map
+            .get_mut(variable)
+            .filter(|slot| slot.generation == generation)?;
+        // Any generation but the current one leaves the slot stale, keeping it for reuse
+        slot.generation = generation.wrapping_sub(1);
+        self.n_values -= 1;
+        Some(slot.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &f32)> {
+        self.map
+            .iter()
+            .filter(|(_, slot)| slot.generation == self.generation)
+            .map(|(name, slot)| (name.as_str(), &slot.value))
+    }
+
+    /// Find every value whose name matches a glob pattern, in name order. A '*' spans segments,
+    /// so "clover.*" finds everything below clover and "clover.*.energy" the energy of each.
+    pub fn find_all(&self, pattern: &str) -> Vec<(&str, f32)> {
+        let mut found: Vec<_> = self
+            .iter()
+            .filter(|(name, _)| pattern::matches(pattern, name))
+            .map(|(name, value)| (name, *value))
+            .collect();
+        found.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        found
+    }
+
+    /// The distinct segments directly below a path, over values, flags, and arrays, in name
+    /// order, e.g. the crystals "0" and "3" of "clover" for "clover.0.energy" and "clover.3.time"
+    pub fn get_children(&self, path: &str) -> Vec<&str> {
+        let mut children: Vec<&str> = self
+            .iter()
+            .map(|(name, _)| name)
+            .chain(self.iter_flags().map(|(name, _)| name))
+            .chain(self.iter_arrays().map(|(name, _)| name))
+            .filter_map(|name| name.strip_prefix(path)?.strip_prefix(PATH_SEPARATOR))
+            .filter_map(|rest| rest.split(PATH_SEPARATOR).next())
+            .collect();
+        children.sort_unstable();
+        children.dedup();
+        children
+    }
+
+    pub fn insert_flag(&mut self, variable: &str, bits: u64) {
+        let slot = Slot {
+            value: bits,
+            generation: self.generation,
+        };
+        match self.flags.get_mut(variable) {
+            Some(existing) => *existing = slot,
+            None => {
+                self.flags.insert(variable.to_string(), slot);
+            }
+        }
+    }
+
+    /// Set the time of the event, in seconds on the clock of the source
+    pub fn set_timestamp(&mut self, seconds: f64) {
+        self.timestamp = Some(Slot {
+            value: seconds,
+            generation: self.generation,
+        });
+    }
+
+    pub fn get_timestamp(&self) -> Option<f64> {
+        self.timestamp
+            .as_ref()
+            .filter(|slot| slot.generation == self.generation)
+            .map(|slot| slot.value)
+    }
+
+    /// Booleans are stored as flags with a value of 0 or 1
+    pub fn insert_bool(&mut self, variable: &str, value: bool) {
+        self.insert_flag(variable, value as u64);
+    }
+
+    pub fn find_flag(&self, variable: &str) -> Option<u64> {
+        self.flags
+            .get(variable)
+            .filter(|slot| slot.generation == self.generation)
+            .map(|slot| slot.value)
+    }
+
+    pub fn remove_flag(&mut self, variable: &str) -> Option<u64> {
+        let generation = self.generation;
+        let slot = self
+            .flags
+            .get_mut(variable)
+            .filter(|slot| slot.generation == generation)?;
+        slot.generation = generation.wrapping_sub(1);
+        Some(slot.value)
+    }
+
+    pub fn iter_flags(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.flags
+            .iter()
+            .filter(|(_, slot)| slot.generation == self.generation)
+            .map(|(name, slot)| (name.as_str(), slot.value))
+    }
+
+    pub fn insert_array(&mut self, variable: &str, values: Vec<f32>) {
+        let slot = Slot {
+            value: values,
+            generation: self.generation,
+        };
+        match self.arrays.get_mut(variable) {
+            Some(existing) => *existing = slot,
+            None => {
+                self.arrays.insert(variable.to_string(), slot);
+            }
+        }
+    }
+
+    /// Append a value to an array variable, creating it if needed
+    pub fn push(&mut self, variable: &str, value: f32) {
+        let generation = self.generation;
+        match self.arrays.get_mut(variable) {
+            Some(slot) => {
+                if slot.generation != generation {
+                    slot.value.clear();
+                    slot.generation = generation;
+                }
+                slot.value.push(value);
+            }
+            None => self.insert_array(variable, vec![value]),
+        }
+    }
+
+    pub fn find_array(&self, variable: &str) -> Option<&[f32]> {
+        self.arrays
+            .get(variable)
+            .filter(|slot| slot.generation == self.generation)
+            .map(|slot| slot.value.as_slice())
+    }
+
+    /// Remove an array from the event, returning its values if it was there
+    pub fn remove_array(&mut self, variable: &str) -> Option<Vec<f32>> {
+        let generation = self.generation;
+        let slot = self
+            .arrays
+            .get_mut(variable)
+            .filter(|slot| slot.generation == generation)?;
+        slot.generation = generation.wrapping_sub(1);
+        Some(std::mem::take(&mut slot.value))
+    }
+
+    /// The length of an array variable, which is zero if the event does not have it
+    pub fn get_multiplicity(&self, variable: &str) -> usize {
+        self.find_array(variable).map_or(0, |values| values.len())
+    }
+
+    pub fn iter_arrays(&self) -> impl Iterator<Item = (&str, &[f32])> {
+        self.arrays
+            .iter()
+            .filter(|(_, slot)| slot.generation == self.generation)
+            .map(|(name, slot)| (name.as_str(), slot.value.as_slice()))
+    }
+
+    /// The number of values, not counting flags or arrays
+    pub fn len(&self) -> usize {
+        self.n_values
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n_values == 0
+    }
+}
+
+/// A shared stock of cleared blobs, so that sources can refill blobs from earlier events instead
+/// of allocating new ones. Clones share the same stock, e.g. between a source thread and the
+/// thread running the manager.
+#[derive(Debug, Clone)]
+pub struct BlobPool {
+    free: Arc<Mutex<Vec<DataBlob>>>,
+    capacity: usize,
+}
+
+impl BlobPool {
+    /// Make a pool holding at most capacity spare blobs
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            free: Arc::new(Mutex::new(Vec::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Take an empty blob, reusing a returned one if there is any
+    pub fn get(&self) -> DataBlob {
+        self.free
+            .lock()
+            .ok()
+            .and_then(|mut free| free.pop())
+            .unwrap_or_default()
+    }
+
+    /// Return a blob to the pool once it has been processed. It is dropped if the pool is full.
+    pub fn put(&self, mut blob: DataBlob) {
+        blob.clear();
+        if let Ok(mut free) = self.free.lock()
+            && free.len() < self.capacity
+        {
+            free.push(blob);
+        }
+    }
+
+    pub fn get_n_free(&self) -> usize {
+        self.free.lock().map_or(0, |free| free.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_and_refill() {
+        let pool = BlobPool::new(1);
+        let mut blob = pool.get();
+        blob.insert("x", 1.0);
+        blob.insert_flag("pileup", 1);
+        blob.push("gamma_e", 511.0);
+        blob.push("gamma_e", 1274.0);
+        assert_eq!(blob.len(), 1);
+        pool.put(blob);
+        pool.put(DataBlob::new());
+        assert_eq!(pool.get_n_free(), 1);
+
+        let mut blob = pool.get();
+        assert!(blob.is_empty());
+        assert_eq!(blob.find("x"), None);
+        assert_eq!(blob.find_flag("pileup"), None);
+        assert_eq!(blob.get_multiplicity("gamma_e"), 0);
+        assert_eq!(blob.iter().count(), 0);
+
+        blob.insert("x", 2.0);
+        blob.insert("x", 3.0);
+        blob.push("gamma_e", 662.0);
+        assert_eq!(blob.len(), 1);
+        assert_eq!(blob.find("x"), Some(&3.0));
+        assert_eq!(blob.find_array("gamma_e"), Some(&[662.0][..]));
+        assert_eq!(blob.map.len(), 1);
+
+        blob.clear();
+        blob.insert("y", 1.0);
+        blob.shrink();
+        assert_eq!(blob.map.len(), 1);
+        assert!(blob.arrays.is_empty());
+    }
+
+    #[test]
+    fn test_paths() {
+        let mut blob = DataBlob::new();
+        blob.insert("clover.3.energy", 1332.0);
+        blob.insert("clover.3.time", 12.0);
+        blob.insert("clover.0.energy", 1173.0);
+        blob.insert_flag("clover.1.pileup", 1);
+        blob.insert("cloverleaf", 1.0);
+        assert_eq!(
+            blob.find_all("clover.*.energy"),
+            vec![("clover.0.energy", 1173.0), ("clover.3.energy", 1332.0)]
+        );
+        assert_eq!(blob.find_all("clover.*").len(), 3);
+        assert_eq!(blob.find_all("cloverleaf"), vec![("cloverleaf", 1.0)]);
+        assert_eq!(blob.get_children("clover"), vec!["0", "1", "3"]);
+        assert_eq!(blob.get_children("clover.3"), vec!["energy", "time"]);
+        assert!(blob.get_children("clover.3.energy").is_empty());
+
+        assert_eq!(blob.remove("clover.3.time"), Some(12.0));
+        assert_eq!(blob.remove("clover.3.time"), None);
+        assert_eq!(blob.remove_flag("clover.1.pileup"), Some(1));
+        assert_eq!(blob.get_children("clover"), vec!["0", "3"]);
+        assert_eq!(blob.len(), 3);
     }
 }
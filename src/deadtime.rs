@@ -0,0 +1,72 @@
+//! Accounting of the events lost before they reached the histograms, so that quantities taken
+//! from the spectra can be corrected for the fraction of the data which was processed.
+use serde::{Deserialize, Serialize};
+
+/// Events lost at each stage between the DAQ and the manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct LossCounts {
+    /// Events the DAQ counted which never reached the source, e.g. because a sampling reader
+    /// fell behind
+    pub source_overrun: u64,
+    /// Events dropped by a full EventQueue
+    pub buffer_overflow: u64,
+    /// Events the source failed to decode
+    pub decode_failure: u64,
+}
+
+impl LossCounts {
+    pub fn get_total(&self) -> u64 {
+        self.source_overrun + self.buffer_overflow + self.decode_failure
+    }
+
+    pub fn add(&mut self, other: &LossCounts) {
+        self.source_overrun += other.source_overrun;
+        self.buffer_overflow += other.buffer_overflow;
+        self.decode_failure += other.decode_failure;
+    }
+}
+
+/// The events processed and lost over the lifetime of a manager, see
+/// ResourceManager::get_dead_time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DeadTimeReport {
+    /// Events accepted by the manager
+    pub processed: u64,
+    pub losses: LossCounts,
+    /// Events turned away while a run was paused or between runs. These were deliberately not
+    /// taken, so they do not count against the livetime.
+    pub rejected: u64,
+}
+
+impl DeadTimeReport {
+    /// The fraction of the events offered which were processed, or None before any were
+    pub fn get_livetime(&self) -> Option<f64> {
+        let offered = self.processed + self.losses.get_total();
+        (offered > 0).then(|| self.processed as f64 / offered as f64)
+    }
+
+    pub fn get_dead_time(&self) -> Option<f64> {
+        self.get_livetime().map(|livetime| 1.0 - livetime)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_livetime() {
+        let mut report = DeadTimeReport::default();
+        assert_eq!(report.get_livetime(), None);
+        report.processed = 75;
+        report.rejected = 100;
+        report.losses.add(&LossCounts {
+            source_overrun: 10,
+            buffer_overflow: 10,
+            decode_failure: 5,
+        });
+        assert_eq!(report.losses.get_total(), 25);
+        assert_eq!(report.get_livetime(), Some(0.75));
+        assert_eq!(report.get_dead_time(), Some(0.25));
+    }
+}
@@ -0,0 +1,310 @@
+use super::error::HistogramError;
+use super::histogram::{Histogram, Normalization, ProjectionAxis};
+use super::smoothing::Smoothing;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A projection of a matrix gated on its other axis, less background gated the same way and
+/// scaled to the width of the gate. This is the spectrum in coincidence with a gamma-ray
+/// transition, for example.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GatedProjection {
+    /// The axis projected onto; the gates are on the other axis
+    pub onto: ProjectionAxis,
+    pub gate: (f32, f32),
+    /// Background regions, whose sum is scaled by the ratio of the number of gate bins to the
+    /// number of background bins before it is subtracted
+    pub background: Vec<(f32, f32)>,
+}
+
+impl GatedProjection {
+    pub fn apply(&self, matrix: &Histogram) -> Result<Histogram, HistogramError> {
+        let gated_axis = match (self.onto, &matrix.spec.y_axis) {
+            (_, None) => return Err(HistogramError::WrongDimensions),
+            (ProjectionAxis::X, Some(y_axis)) => y_axis,
+            (ProjectionAxis::Y, Some(_)) => &matrix.spec.x_axis,
+        };
+        let n_bins = |(low, high): (f32, f32)| -> Result<usize, HistogramError> {
+            Ok(gated_axis.get_bin_range(low, high)?.len())
+        };
+        let mut projection = matrix.project(self.onto, Some(self.gate))?;
+        let n_gate_bins = n_bins(self.gate)?;
+        let mut n_background_bins = 0;
+        for region in self.background.iter() {
+            n_background_bins += n_bins(*region)?;
+        }
+        for region in self.background.iter() {
+            let mut background = matrix.project(self.onto, Some(*region))?;
+            background.scale(n_gate_bins as f64 / n_background_bins as f64);
+            projection.subtract(&background)?;
+        }
+        Ok(projection)
+    }
+}
+
+/// How a derived histogram is computed from its parent, and from a second histogram for
+/// derivations combining two
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Derivation {
+    Normalized(Normalization),
+    Cumulative,
+    Smoothed(Smoothing),
+    /// Project a matrix onto an axis, summing the bins of the other axis inside range if given
+    Projection {
+        onto: ProjectionAxis,
+        range: Option<(f32, f32)>,
+    },
+    GatedProjection(GatedProjection),
+    /// The parent less scale times another histogram on the same axes
+    Difference {
+        other_id: Uuid,
+        scale: f64,
+    },
+    /// The bin by bin ratio of the parent to another histogram on the same axes
+    Ratio {
+        other_id: Uuid,
+    },
+}
+
+impl Derivation {
+    /// The second histogram the derivation reads, if any
+    pub fn get_other_id(&self) -> Option<Uuid> {
+        match self {
+            Self::Difference { other_id, .. } | Self::Ratio { other_id } => Some(*other_id),
+            _ => None,
+        }
+    }
+
+    pub fn apply(
+        &self,
+        parent: &Histogram,
+        other: Option<&Histogram>,
+    ) -> Result<Histogram, HistogramError> {
+        let other = |id: &Uuid| other.ok_or(HistogramError::MissingInput(*id));
+        match self {
+            Self::Normalized(normalization) => Ok(parent.normalized(*normalization)),
+            Self::Cumulative => parent.cumulative(),
+            Self::Smoothed(smoothing) => parent.smoothed(smoothing),
+            Self::Projection { onto, range } => parent.project(*onto, *range),
+            Self::GatedProjection(projection) => projection.apply(parent),
+            Self::Difference { other_id, scale } => parent.difference(other(other_id)?, *scale),
+            Self::Ratio { other_id } => parent.ratio(other(other_id)?),
+        }
+    }
+}
+
+/// When the manager recomputes a derived histogram whose inputs have changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RefreshMode {
+    /// When it is read, so that histograms nobody looks at cost nothing
+    #[default]
+    OnRead,
+    /// After every update call, so that the copy the manager holds is always current, e.g. for
+    /// exporters and displays reading many histograms at once
+    OnUpdate,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DerivedSpec {
+    pub id: Uuid,
+    pub name: String,
+    pub parent_id: Uuid,
+    pub derivation: Derivation,
+    pub refresh: RefreshMode,
+}
+
+/// A histogram computed from others, which is recomputed whenever an input has changed since it
+/// was last computed
+#[derive(Debug, Clone)]
+pub struct DerivedHistogram {
+    spec: DerivedSpec,
+    gram: Option<Histogram>,
+    // The generations of the inputs that gram was computed from
+    parent_generation: u64,
+    other_generation: Option<u64>,
+}
+
+impl DerivedHistogram {
+    pub fn new(spec: DerivedSpec) -> Self {
+        Self {
+            spec,
+            gram: None,
+            parent_generation: 0,
+            other_generation: None,
+        }
+    }
+
+    pub fn get_spec(&self) -> &DerivedSpec {
+        &self.spec
+    }
+
+    pub fn set_refresh_mode(&mut self, mode: RefreshMode) {
+        self.spec.refresh = mode;
+    }
+
+    /// The histogram as last computed, which may be stale, or None if it never has been
+    pub fn get_cached(&self) -> Option<&Histogram> {
+        self.gram.as_ref()
+    }
+
+    /// Whether an input has changed since the histogram was last computed
+    pub fn is_stale(&self, parent: &Histogram, other: Option<&Histogram>) -> bool {
+        self.gram.is_none()
+            || parent.get_generation() != self.parent_generation
+            || other.map(|other| other.get_generation()) != self.other_generation
+    }
+
+    /// Get the histogram, recomputing it first if it is stale
+    pub fn refresh(
+        &mut self,
+        parent: &Histogram,
+        other: Option<&Histogram>,
+    ) -> Result<&Histogram, HistogramError> {
+        if self.is_stale(parent, other) {
+            let mut gram = self.spec.derivation.apply(parent, other)?;
+            gram.spec.id = self.spec.id;
+            gram.spec.name.clone_from(&self.spec.name);
+            gram.spec.title.clone_from(&self.spec.name);
+            self.parent_generation = parent.get_generation();
+            self.other_generation = other.map(|other| other.get_generation());
+            self.gram = Some(gram);
+        }
+        Ok(self
+            .gram
+            .as_ref()
+            .expect("Derived histogram was just computed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, BinLayout, HistSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_derived() {
+        let mut parent = Histogram::new(HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("e", "e", 4, 0.0, 4.0).unwrap(),
+                None,
+            )
+        });
+        parent.fill_weighted(0.5, None, 1.0).unwrap();
+        parent.fill_weighted(2.5, None, 3.0).unwrap();
+
+        let mut area = DerivedHistogram::new(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: String::from("energy_area"),
+            parent_id: parent.spec.id,
+            derivation: Derivation::Normalized(Normalization::Area),
+            refresh: RefreshMode::OnRead,
+        });
+        let gram = area.refresh(&parent, None).unwrap();
+        assert_eq!(gram.data, vec![0.25, 0.0, 0.75, 0.0]);
+        assert_eq!(gram.bin_error(0).unwrap(), 0.25);
+        assert_eq!(gram.spec.name, "energy_area");
+        assert!(!area.is_stale(&parent, None));
+
+        let peak = Derivation::Normalized(Normalization::Peak).apply(&parent, None);
+        assert_eq!(peak.unwrap().data, vec![1.0 / 3.0, 0.0, 1.0, 0.0]);
+        let mut cumulative = DerivedHistogram::new(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: String::from("energy_cumulative"),
+            parent_id: parent.spec.id,
+            derivation: Derivation::Cumulative,
+            refresh: RefreshMode::OnRead,
+        });
+        assert_eq!(
+            cumulative.refresh(&parent, None).unwrap().data,
+            vec![1.0, 1.0, 4.0, 4.0]
+        );
+
+        parent.fill(3.5, None).unwrap();
+        assert!(cumulative.is_stale(&parent, None));
+        assert_eq!(cumulative.refresh(&parent, None).unwrap().data[3], 5.0);
+        assert_eq!(area.refresh(&parent, None).unwrap().data[3], 0.2);
+    }
+
+    #[test]
+    fn test_gated_projection() {
+        let mut matrix = Histogram::new(HistSpec {
+            layout: BinLayout::ColumnMajor,
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "gg",
+                AxisSpec::new("e1", "e1", 4, 0.0, 4.0).unwrap(),
+                Some(AxisSpec::new("e2", "e2", 6, 0.0, 6.0).unwrap()),
+            )
+        });
+        // A flat background of one count per bin, and a peak at e2 = 2.5 in coincidence with e1
+        // = 1.5
+        for x in 0..4 {
+            for y in 0..6 {
+                matrix.fill(x as f32 + 0.5, Some(y as f32 + 0.5)).unwrap();
+            }
+        }
+        matrix.fill_weighted(1.5, Some(2.5), 5.0).unwrap();
+
+        let gated = GatedProjection {
+            onto: ProjectionAxis::X,
+            gate: (2.0, 3.0),
+            background: vec![(4.0, 6.0)],
+        };
+        let projection = gated.apply(&matrix).unwrap();
+        assert!(projection.spec.y_axis.is_none());
+        assert_eq!(projection.data, vec![0.0, 5.0, 0.0, 0.0]);
+        // 6 counts in the gate and 2 of background scaled by one half
+        assert_eq!(projection.bin_error(1).unwrap(), 6.5f64.sqrt());
+        let onto_y = matrix.project(ProjectionAxis::Y, None).unwrap();
+        assert_eq!(onto_y.data, vec![4.0, 4.0, 9.0, 4.0, 4.0, 4.0]);
+    }
+
+    #[test]
+    fn test_two_inputs() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("e", "e", 3, 0.0, 3.0).unwrap(),
+                None,
+            )
+        };
+        let mut passed = Histogram::new(spec.clone());
+        let mut all = Histogram::new(HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("all"),
+            ..spec
+        });
+        passed.fill_weighted(0.5, None, 3.0).unwrap();
+        all.fill_weighted(0.5, None, 4.0).unwrap();
+        all.fill_weighted(1.5, None, 2.0).unwrap();
+
+        let mut efficiency = DerivedHistogram::new(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: String::from("efficiency"),
+            parent_id: passed.spec.id,
+            derivation: Derivation::Ratio {
+                other_id: all.spec.id,
+            },
+            refresh: RefreshMode::OnUpdate,
+        });
+        assert!(efficiency.refresh(&passed, None).is_err());
+        let gram = efficiency.refresh(&passed, Some(&all)).unwrap();
+        assert_eq!(gram.data, vec![0.75, 0.0, 0.0]);
+        // (3 + 0.75^2 * 4) / 4^2
+        assert!((gram.bin_error(0).unwrap() - (5.25f64 / 16.0).sqrt()).abs() < 1e-12);
+        all.fill(2.5, None).unwrap();
+        assert!(efficiency.is_stale(&passed, Some(&all)));
+        assert_eq!(efficiency.get_cached().unwrap().data[0], 0.75);
+
+        let difference = Derivation::Difference {
+            other_id: all.spec.id,
+            scale: 0.5,
+        };
+        let gram = difference.apply(&passed, Some(&all)).unwrap();
+        assert_eq!(gram.data, vec![1.0, -1.0, -0.5]);
+        assert_eq!(gram.bin_error(0).unwrap(), 2.0);
+    }
+}
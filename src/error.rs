@@ -1,14 +1,43 @@
+//! The errors of every part of the crate. Each error has a stable numeric code from ErrorCode,
+//! for clients which can't match on Rust types, e.g. over FFI or REST. Codes are grouped by
+//! type in blocks of 100; an error wrapping another has the code of the error it wraps, so the
+//! code names the root cause. Codes are never reused or renumbered.
 use thiserror::Error;
 use uuid::Uuid;
 
+/// A stable code identifying the kind of an error
+pub trait ErrorCode {
+    fn get_code(&self) -> u32;
+}
+
 #[derive(Debug, Error)]
 pub enum HistogramError {
     #[error("Histogram has mismatched dimensions")]
     WrongDimensions,
     #[error("Histogram attempted to fill an out of bounds value - min: {0}, max: {1}, val: {2}")]
     OutOfBounds(f32, f32, f32),
-    #[error("Invalid axis created: {0}, bins: {1}, min: {2}, max: {2}")]
+    #[error("Invalid axis {0} - bins: {1}, min: {2}, max: {3}")]
     BadAxis(String, usize, f32, f32),
+    #[error("Bin does not exist - x: {0}, y: {1}")]
+    BadBin(usize, usize),
+    #[error("Requested range does not overlap the axis - low: {0}, high: {1}")]
+    BadRange(f32, f32),
+    #[error("Not enough finite values to suggest an axis: {0}")]
+    InsufficientData(usize),
+    #[error("Histograms {0} and {1} have incompatible axes")]
+    IncompatibleAxes(String, String),
+    #[error("Histogram {0} uses a feature this storage does not support: {1}")]
+    Unsupported(String, String),
+    #[error("Invalid smoothing: {0}")]
+    BadSmoothing(String),
+    #[error("Derived histogram needs histogram {0}, which was not given")]
+    MissingInput(Uuid),
+    #[error("Got {0} bin contents for a histogram of {1} bins")]
+    BadDataLength(usize, usize),
+    #[error("Histogram attempted to fill a value which is not finite: {0}")]
+    NonFinite(f32),
+    #[error("Event is missing a variable of histogram {0}")]
+    MissingVariable(String),
 }
 
 #[derive(Debug, Error)]
@@ -21,12 +50,619 @@ pub enum CutError {
     Unclosed2D,
     #[error("Could not find reference histogram {0}")]
     NoReferenceHistogram(Uuid),
+    #[error("No factory is registered for cuts of kind {0}")]
+    UnknownKind(String),
+    #[error("Cut parameters could not be understood: {0}")]
+    BadParameters(String),
+    #[error("Cut {0} has no geometry to test points against")]
+    NoGeometry(String),
+    #[error("Cut {0} is not on the axes of histogram {1}")]
+    WrongAxes(String, String),
+    #[error("Histogram {0} has no suitable contour at level {1}")]
+    NoContour(String, f64),
+}
+
+#[derive(Debug, Error)]
+pub enum GateFileError {
+    #[error("Gate file IO failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Gate file JSON is invalid: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Could not parse gate file line: {0}")]
+    Parse(String),
+    #[error("Cuts of kind {0} cannot be written to this format")]
+    UnsupportedKind(String),
+    #[error("Gate file holds an invalid cut: {0}")]
+    Cut(#[from] CutError),
+}
+
+#[derive(Debug, Error)]
+pub enum CalibrationError {
+    #[error("Got {0} peak centroids but {1} energies")]
+    MismatchedPeaks(usize, usize),
+    #[error("A calibration of order {1} needs more than {1} peaks, but got {0}")]
+    TooFewPeaks(usize, usize),
+    #[error("The peaks do not determine a calibration")]
+    Degenerate,
+    #[error("Histogram {0} has no peak between {1} and {2}")]
+    NoPeak(String, f32, f32),
+    #[error("Could not search histogram for peaks: {0}")]
+    Histogram(#[from] HistogramError),
+}
+
+#[derive(Debug, Error)]
+pub enum SpectrumFileError {
+    #[error("Spectrum file IO failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Spectrum file is invalid: {0}")]
+    Format(String),
+    #[error("Spectrum file {0} is not in a known format")]
+    UnknownFormat(String),
+}
+
+#[derive(Debug, Error)]
+pub enum RecordError {
+    #[error("Event log IO failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("File is not a Specter event log")]
+    BadHeader,
+    #[error("Unsupported event log version: {0}")]
+    UnsupportedVersion(u16),
+    #[error("Event log is corrupt or truncated")]
+    Corrupt,
+    #[error("Event log exceeded the maximum number of variables")]
+    TooManyVariables,
+    #[error("Array variable {0} is too long to record")]
+    ArrayTooLong(String),
+    #[error("An event has too many {0} to record")]
+    TooManyEntries(&'static str),
+    #[error("Variable name {0} is too long to record")]
+    NameTooLong(String),
+    #[cfg(feature = "parquet")]
+    #[error("Parquet file failed: {0}")]
+    Parquet(#[from] ::parquet::errors::ParquetError),
+}
+
+#[derive(Debug, Error)]
+pub enum RunError {
+    #[error("A run is already in progress")]
+    AlreadyRunning,
+    #[error("No run is in progress")]
+    NotRunning,
+    #[error("The run is not paused")]
+    NotPaused,
+    #[error("Run number {0} has already been used")]
+    DuplicateRunNumber(u32),
+    #[error("Run {0} is not in the catalog")]
+    UnknownRun(u32),
+    #[error("Could not understand run selection '{0}'")]
+    BadSelection(String),
+}
+
+#[derive(Debug, Error)]
+pub enum SourceError {
+    #[error("Data source IO failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Data source failed to read event log: {0}")]
+    Record(#[from] RecordError),
+    #[error("Column {0} has {1} rows but the batch has {2}")]
+    ColumnLength(String, usize, usize),
+    #[error("Data source could not decode an event: {0}")]
+    Decode(String),
+    #[cfg(feature = "kafka")]
+    #[error("Kafka consumer failed: {0}")]
+    Kafka(#[from] ::kafka::Error),
 }
 
 #[derive(Debug, Error)]
 pub enum ResourceError {
     #[error("Specter failed to get histogram with ID {0}")]
     InvalidHistogramID(Uuid),
+    #[error("Specter failed to get cut with ID {0}")]
+    InvalidCutID(Uuid),
+    #[error("Specter failed to get filter with ID {0}")]
+    InvalidFilterID(Uuid),
+    #[error("Specter failed to get transform with ID {0}")]
+    InvalidTransformID(Uuid),
+    #[error("Specter failed to get histogram group with ID {0}")]
+    InvalidGroupID(Uuid),
+    #[error("Specter failed to get observer with ID {0}")]
+    InvalidObserverID(Uuid),
+    #[error("Specter failed to get check with ID {0}")]
+    InvalidCheckID(Uuid),
+    #[error("Specter failed to get derived histogram with ID {0}")]
+    InvalidDerivedID(Uuid),
+    #[error("Specter failed to get rate meter with ID {0}")]
+    InvalidRateID(Uuid),
+    #[error("Specter has no calibration of variable '{0}'")]
+    InvalidCalibration(String),
+    #[error("Specter has no alias of variable '{0}'")]
+    InvalidAlias(String),
+    #[error("Invalid folder for this operation: '{0}'")]
+    InvalidFolder(String),
+    #[error("{0} references cut {1}, which does not exist")]
+    MissingReference(String, Uuid),
+    #[error("{0} references variable '{1}', which no registered schema declares")]
+    UnknownVariable(String, String),
+    #[error("Cuts depend on each other in a cycle: {}", .0.join(" -> "))]
+    DependencyCycle(Vec<String>),
+    #[error("The ID {0} is already in use")]
+    DuplicateID(Uuid),
+    #[error("The name '{0}' is already in use")]
+    DuplicateName(String),
+    #[error("Booking {0} needs {1} bytes, but only {2} bytes of the memory limit are free")]
+    MemoryLimitExceeded(String, usize, usize),
+    #[error("Run {0} has no snapshot of histogram '{1}'")]
+    MissingSnapshot(u32, String),
+    #[error("An edit is already in progress")]
+    EditInProgress,
+    #[error("No edit is in progress")]
+    NoEdit,
+    #[error("Specter failed to get Parquet sink with ID {0}")]
+    InvalidSinkID(Uuid),
+    #[error("Specter has no workspace named '{0}'")]
+    InvalidWorkspace(String),
+    #[error("Cut {0} is not applied to histogram {1}")]
+    CutNotApplied(Uuid, Uuid),
+    /// An error with the operation attempted and the name of the resource it was attempted on
+    #[error("Could not {operation} '{resource}': {source}")]
+    Context {
+        operation: &'static str,
+        resource: String,
+        source: Box<ResourceError>,
+    },
     #[error("Failed to create cut: {0}")]
     CutFailed(#[from] CutError),
+    #[error("Histogram operation failed: {0}")]
+    HistogramFailed(#[from] HistogramError),
+    #[error("Event recording failed: {0}")]
+    RecordFailed(#[from] RecordError),
+    #[error("Data source failed: {0}")]
+    SourceFailed(#[from] SourceError),
+    #[error("Run control failed: {0}")]
+    RunFailed(#[from] RunError),
+    #[error("Calibration failed: {0}")]
+    CalibrationFailed(#[from] CalibrationError),
+    #[cfg(feature = "render")]
+    #[error("Rendering failed: {0}")]
+    RenderFailed(#[from] RenderError),
+}
+
+#[cfg(feature = "render")]
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Render failed to create a directory: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Render failed to draw: {0}")]
+    Draw(String),
+    #[error("Render does not know the image format of {0}, expected .png or .svg")]
+    UnknownFormat(String),
+    #[error("Render failed to encode a PNG: {0}")]
+    Encode(#[from] image::ImageError),
+}
+
+#[cfg(feature = "io")]
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Snapshot failed to write: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Snapshots can only be posted to http:// URLs, not {0}")]
+    BadUrl(String),
+    #[error("Snapshot webhook replied {0}")]
+    Rejected(String),
+    #[cfg(feature = "render")]
+    #[error("Snapshot failed to render: {0}")]
+    Render(#[from] RenderError),
+}
+
+impl ResourceError {
+    /// Wrap the error with the operation attempted and the name of the resource it was on
+    pub fn with_context(self, operation: &'static str, resource: &str) -> Self {
+        Self::Context {
+            operation,
+            resource: resource.to_string(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The error beneath any context
+    pub fn get_root(&self) -> &ResourceError {
+        match self {
+            Self::Context { source, .. } => source.get_root(),
+            _ => self,
+        }
+    }
+}
+
+/// Any error of the crate, for applications which handle them all alike
+#[derive(Debug, Error)]
+pub enum SpectError {
+    #[error("Spect needs at least one data source")]
+    NoSource,
+    #[error("Spect could not start a server: {0}")]
+    Server(std::io::Error),
+    #[error("IO failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Histogram(#[from] HistogramError),
+    #[error("{0}")]
+    Cut(#[from] CutError),
+    #[error("{0}")]
+    Resource(#[from] ResourceError),
+    #[error("{0}")]
+    Source(#[from] SourceError),
+    #[error("{0}")]
+    Record(#[from] RecordError),
+    #[error("{0}")]
+    GateFile(#[from] GateFileError),
+    #[error("{0}")]
+    SpectrumFile(#[from] SpectrumFileError),
+    #[error("{0}")]
+    Command(#[from] CommandError),
+    #[cfg(feature = "render")]
+    #[error("{0}")]
+    Render(#[from] RenderError),
+    #[cfg(feature = "io")]
+    #[error("{0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("{0}")]
+    Plugin(#[from] PluginError),
+    #[cfg(feature = "scripting")]
+    #[error("{0}")]
+    Script(#[from] ScriptError),
+    #[cfg(feature = "xamine")]
+    #[error("{0}")]
+    Xamine(#[from] XamineError),
+}
+
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("Unbalanced braces or quotes in '{0}'")]
+    Unbalanced(String),
+    #[error("Unknown command '{0}'")]
+    UnknownCommand(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("Could not read '{0}' as a number")]
+    BadNumber(String),
+    #[error("Unknown spectrum type '{0}'")]
+    UnknownType(String),
+    #[error("No spectrum named '{0}'")]
+    UnknownSpectrum(String),
+    #[error("A spectrum named '{0}' already exists")]
+    DuplicateSpectrum(String),
+    #[error("No gate named '{0}'")]
+    UnknownGate(String),
+    #[error("{0}")]
+    Resource(#[from] ResourceError),
+}
+
+#[cfg(feature = "scripting")]
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    #[error("Could not read a script: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Script does not compile: {0}")]
+    Compile(String),
+    #[error("Script failed: {0}")]
+    Failed(String),
+    #[error("Script left {0} as something other than a number or an array of numbers")]
+    NotNumber(String),
+    #[error("Script replaced the event with something other than a map")]
+    LostEvent,
+}
+
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error("No unpacker named {0} is registered")]
+    UnknownUnpacker(String),
+    #[error("No transform named {0} is registered")]
+    UnknownTransform(String),
+    #[error("Plugin parameters could not be understood: {0}")]
+    BadParameters(String),
+    #[cfg(feature = "plugins")]
+    #[error("Could not load plugin {0}: {1}")]
+    Load(String, libloading::Error),
+    #[error("Plugin {0} was built for plugin API version {1}, not {2}")]
+    WrongVersion(String, u32, u32),
+}
+
+#[derive(Debug, Error)]
+pub enum XamineError {
+    #[error("Xamine shared memory failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Xamine shared memory names are 4 ASCII characters, not '{0}'")]
+    BadName(String),
+    #[error("Xamine shared memory has no room left for spectrum {0}")]
+    NoSpace(String),
+    #[error("Xamine has no binding slot {0}")]
+    BadSlot(usize),
+}
+
+impl ErrorCode for HistogramError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::WrongDimensions => 101,
+            Self::OutOfBounds(..) => 102,
+            Self::BadAxis(..) => 103,
+            Self::BadBin(..) => 104,
+            Self::BadRange(..) => 105,
+            Self::InsufficientData(_) => 106,
+            Self::IncompatibleAxes(..) => 107,
+            Self::Unsupported(..) => 108,
+            Self::BadSmoothing(_) => 109,
+            Self::MissingInput(_) => 110,
+            Self::BadDataLength(..) => 111,
+            Self::NonFinite(_) => 112,
+            Self::MissingVariable(_) => 113,
+        }
+    }
+}
+
+impl ErrorCode for CutError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Invalid1D(..) => 201,
+            Self::Invalid2D => 202,
+            Self::Unclosed2D => 203,
+            Self::NoReferenceHistogram(_) => 204,
+            Self::UnknownKind(_) => 205,
+            Self::BadParameters(_) => 206,
+            Self::NoGeometry(_) => 207,
+            Self::WrongAxes(..) => 208,
+            Self::NoContour(..) => 209,
+        }
+    }
+}
+
+impl ErrorCode for GateFileError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 301,
+            Self::Json(_) => 302,
+            Self::Parse(_) => 303,
+            Self::UnsupportedKind(_) => 304,
+            Self::Cut(e) => e.get_code(),
+        }
+    }
+}
+
+impl ErrorCode for CalibrationError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::MismatchedPeaks(..) => 401,
+            Self::TooFewPeaks(..) => 402,
+            Self::Degenerate => 403,
+            Self::NoPeak(..) => 404,
+            Self::Histogram(e) => e.get_code(),
+        }
+    }
+}
+
+impl ErrorCode for SpectrumFileError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 501,
+            Self::Format(_) => 502,
+            Self::UnknownFormat(_) => 503,
+        }
+    }
+}
+
+impl ErrorCode for RecordError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 601,
+            Self::BadHeader => 602,
+            Self::UnsupportedVersion(_) => 603,
+            Self::Corrupt => 604,
+            Self::TooManyVariables => 605,
+            Self::ArrayTooLong(_) => 606,
+            #[cfg(feature = "parquet")]
+            Self::Parquet(_) => 607,
+            Self::TooManyEntries(_) => 608,
+            Self::NameTooLong(_) => 609,
+        }
+    }
+}
+
+impl ErrorCode for RunError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::AlreadyRunning => 701,
+            Self::NotRunning => 702,
+            Self::NotPaused => 703,
+            Self::DuplicateRunNumber(_) => 704,
+            Self::UnknownRun(_) => 705,
+            Self::BadSelection(_) => 706,
+        }
+    }
+}
+
+impl ErrorCode for SourceError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 801,
+            Self::Record(e) => e.get_code(),
+            Self::ColumnLength(..) => 802,
+            Self::Decode(_) => 803,
+            #[cfg(feature = "kafka")]
+            Self::Kafka(_) => 804,
+        }
+    }
+}
+
+impl ErrorCode for ResourceError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::InvalidHistogramID(_) => 901,
+            Self::InvalidCutID(_) => 902,
+            Self::InvalidFilterID(_) => 903,
+            Self::InvalidTransformID(_) => 904,
+            Self::InvalidGroupID(_) => 905,
+            Self::InvalidObserverID(_) => 906,
+            Self::InvalidCheckID(_) => 907,
+            Self::InvalidDerivedID(_) => 908,
+            Self::InvalidRateID(_) => 909,
+            Self::InvalidCalibration(_) => 910,
+            Self::InvalidAlias(_) => 911,
+            Self::InvalidFolder(_) => 912,
+            Self::MissingReference(..) => 913,
+            Self::UnknownVariable(..) => 914,
+            Self::DependencyCycle(_) => 915,
+            Self::DuplicateID(_) => 916,
+            Self::DuplicateName(_) => 917,
+            Self::MemoryLimitExceeded(..) => 918,
+            Self::MissingSnapshot(..) => 919,
+            Self::EditInProgress => 920,
+            Self::NoEdit => 921,
+            Self::InvalidSinkID(_) => 922,
+            Self::InvalidWorkspace(_) => 923,
+            Self::CutNotApplied(..) => 924,
+            Self::Context { source, .. } => source.get_code(),
+            Self::CutFailed(e) => e.get_code(),
+            Self::HistogramFailed(e) => e.get_code(),
+            Self::RecordFailed(e) => e.get_code(),
+            Self::SourceFailed(e) => e.get_code(),
+            Self::RunFailed(e) => e.get_code(),
+            Self::CalibrationFailed(e) => e.get_code(),
+            #[cfg(feature = "render")]
+            Self::RenderFailed(e) => e.get_code(),
+        }
+    }
+}
+
+#[cfg(feature = "render")]
+impl ErrorCode for RenderError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 1001,
+            Self::Draw(_) => 1002,
+            Self::UnknownFormat(_) => 1003,
+            Self::Encode(_) => 1004,
+        }
+    }
+}
+
+#[cfg(feature = "io")]
+impl ErrorCode for SnapshotError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 1101,
+            Self::BadUrl(_) => 1102,
+            Self::Rejected(_) => 1103,
+            #[cfg(feature = "render")]
+            Self::Render(e) => e.get_code(),
+        }
+    }
+}
+
+impl ErrorCode for SpectError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::NoSource => 1201,
+            Self::Server(_) => 1202,
+            Self::Io(_) => 1203,
+            Self::Histogram(e) => e.get_code(),
+            Self::Cut(e) => e.get_code(),
+            Self::Resource(e) => e.get_code(),
+            Self::Source(e) => e.get_code(),
+            Self::Record(e) => e.get_code(),
+            Self::GateFile(e) => e.get_code(),
+            Self::SpectrumFile(e) => e.get_code(),
+            Self::Command(e) => e.get_code(),
+            #[cfg(feature = "render")]
+            Self::Render(e) => e.get_code(),
+            #[cfg(feature = "io")]
+            Self::Snapshot(e) => e.get_code(),
+            Self::Plugin(e) => e.get_code(),
+            #[cfg(feature = "scripting")]
+            Self::Script(e) => e.get_code(),
+            #[cfg(feature = "xamine")]
+            Self::Xamine(e) => e.get_code(),
+        }
+    }
+}
+
+impl ErrorCode for CommandError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Unbalanced(_) => 1301,
+            Self::UnknownCommand(_) => 1302,
+            Self::Usage(_) => 1303,
+            Self::BadNumber(_) => 1304,
+            Self::UnknownType(_) => 1305,
+            Self::UnknownSpectrum(_) => 1306,
+            Self::DuplicateSpectrum(_) => 1307,
+            Self::UnknownGate(_) => 1308,
+            Self::Resource(e) => e.get_code(),
+        }
+    }
+}
+
+impl ErrorCode for XamineError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 1401,
+            Self::BadName(_) => 1402,
+            Self::NoSpace(_) => 1403,
+            Self::BadSlot(_) => 1404,
+        }
+    }
+}
+
+impl ErrorCode for PluginError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::UnknownUnpacker(_) => 1601,
+            Self::UnknownTransform(_) => 1602,
+            Self::BadParameters(_) => 1603,
+            #[cfg(feature = "plugins")]
+            Self::Load(..) => 1604,
+            Self::WrongVersion(..) => 1605,
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+impl ErrorCode for ScriptError {
+    fn get_code(&self) -> u32 {
+        match self {
+            Self::Io(_) => 1501,
+            Self::Compile(_) => 1502,
+            Self::Failed(_) => 1503,
+            Self::NotNumber(_) => 1504,
+            Self::LostEvent => 1505,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors() {
+        let axis = HistogramError::BadAxis(String::from("Energy"), 0, 0.0, 10.0);
+        assert_eq!(
+            axis.to_string(),
+            "Invalid axis Energy - bins: 0, min: 0, max: 10"
+        );
+
+        let error =
+            ResourceError::from(HistogramError::NonFinite(f32::NAN)).with_context("fill", "si_e");
+        assert_eq!(
+            error.to_string(),
+            "Could not fill 'si_e': Histogram operation failed: \
+             Histogram attempted to fill a value which is not finite: NaN"
+        );
+        assert!(matches!(
+            error.get_root(),
+            ResourceError::HistogramFailed(HistogramError::NonFinite(_))
+        ));
+        assert_eq!(error.get_code(), 112);
+        assert_eq!(SpectError::from(error).get_code(), 112);
+        let missing = ResourceError::InvalidHistogramID(Uuid::nil());
+        assert_eq!(CommandError::from(missing).get_code(), 901);
+    }
 }
@@ -0,0 +1,71 @@
+use super::cut::CutEvaluation;
+use super::data_blob::DataBlob;
+use super::error::RecordError;
+use super::record::EventRecorder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+#[cfg(feature = "io")]
+use std::path::Path;
+use uuid::Uuid;
+
+/// A combination of cuts an event must satisfy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GateCondition {
+    All(Vec<Uuid>),
+    Any(Vec<Uuid>),
+}
+
+impl GateCondition {
+    pub fn get_cut_ids(&self) -> &[Uuid] {
+        match self {
+            Self::All(ids) | Self::Any(ids) => ids,
+        }
+    }
+
+    /// Evaluate the condition for the current event, stopping at the first cut which decides it.
+    /// Cuts which do not exist are never satisfied.
+    pub fn is_satisfied(&self, cuts: &mut CutEvaluation) -> bool {
+        let is_valid = |id: &Uuid| cuts.check(id).unwrap_or(false);
+        match self {
+            Self::All(ids) => ids.iter().all(is_valid),
+            Self::Any(ids) => ids.iter().any(is_valid),
+        }
+    }
+}
+
+/// Writes the events which satisfy a GateCondition to an event log
+#[derive(Debug)]
+pub struct EventFilter {
+    pub id: Uuid,
+    pub condition: GateCondition,
+    recorder: EventRecorder<BufWriter<File>>,
+}
+
+impl EventFilter {
+    #[cfg(feature = "io")]
+    pub fn create(id: Uuid, condition: GateCondition, path: &Path) -> Result<Self, RecordError> {
+        Ok(Self {
+            id,
+            condition,
+            recorder: EventRecorder::create(path)?,
+        })
+    }
+
+    pub fn process(
+        &mut self,
+        blob: &DataBlob,
+        cuts: &mut CutEvaluation,
+    ) -> Result<(), RecordError> {
+        if self.condition.is_satisfied(cuts) {
+            self.recorder.record(blob)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the output, returning the number of events written
+    pub fn finish(mut self) -> Result<usize, RecordError> {
+        self.recorder.flush()?;
+        Ok(self.recorder.get_n_events())
+    }
+}
@@ -0,0 +1,102 @@
+//! Histograms are organized into folders by their names, e.g. "focal_plane/xavg" is the histogram
+//! "xavg" in the folder "focal_plane". The root folder is the empty string.
+
+pub const SEPARATOR: char = '/';
+
+/// Remove leading, trailing, and repeated separators
+pub fn normalize(path: &str) -> String {
+    path.split(SEPARATOR)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Split a name into its (folder, leaf name)
+pub fn split(name: &str) -> (&str, &str) {
+    let name = name.trim_matches(SEPARATOR);
+    match name.rfind(SEPARATOR) {
+        Some(idx) => (&name[..idx], &name[idx + 1..]),
+        None => ("", name),
+    }
+}
+
+pub fn join(folder: &str, leaf: &str) -> String {
+    let folder = normalize(folder);
+    let leaf = normalize(leaf);
+    if folder.is_empty() {
+        leaf
+    } else {
+        format!("{folder}{SEPARATOR}{leaf}")
+    }
+}
+
+/// Check if a name is inside a folder or any of its subfolders. Everything is inside the root.
+pub fn contains(folder: &str, name: &str) -> bool {
+    let folder = normalize(folder);
+    let name = normalize(name);
+    folder.is_empty()
+        || name
+            .strip_prefix(folder.as_str())
+            .is_some_and(|rest| rest.starts_with(SEPARATOR))
+}
+
+/// Get the immediate subfolder of folder which holds name, if name is nested below folder
+pub fn child_folder(folder: &str, name: &str) -> Option<String> {
+    let folder = normalize(folder);
+    let name = normalize(name);
+    let rest = if folder.is_empty() {
+        name.as_str()
+    } else {
+        name.strip_prefix(folder.as_str())?
+            .strip_prefix(SEPARATOR)?
+    };
+    let (child, _) = rest.split_once(SEPARATOR)?;
+    Some(join(&folder, child))
+}
+
+/// Replace the folder prefix of a name, if it is in the folder
+pub fn reparent(name: &str, from: &str, to: &str) -> Option<String> {
+    let from = normalize(from);
+    let name = normalize(name);
+    if from.is_empty() {
+        return Some(join(to, &name));
+    }
+    let rest = name.strip_prefix(from.as_str())?.strip_prefix(SEPARATOR)?;
+    Some(join(to, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(normalize("/gamma//clover_3/"), "gamma/clover_3");
+        assert_eq!(split("gamma/clover/3"), ("gamma/clover", "3"));
+        assert_eq!(split("xavg"), ("", "xavg"));
+        assert_eq!(join("", "xavg"), "xavg");
+        assert_eq!(join("focal_plane/", "xavg"), "focal_plane/xavg");
+
+        assert!(contains("gamma", "gamma/clover_3"));
+        assert!(contains("gamma", "gamma/clover/3"));
+        assert!(!contains("gamma", "gammas/clover_3"));
+        assert!(!contains("gamma", "gamma"));
+        assert!(contains("", "xavg"));
+
+        assert_eq!(
+            child_folder("", "gamma/clover/3"),
+            Some(String::from("gamma"))
+        );
+        assert_eq!(
+            child_folder("gamma", "gamma/clover/3"),
+            Some(String::from("gamma/clover"))
+        );
+        assert_eq!(child_folder("gamma", "gamma/clover_3"), None);
+
+        assert_eq!(
+            reparent("gamma/clover/3", "gamma", "hpge"),
+            Some(String::from("hpge/clover/3"))
+        );
+        assert_eq!(reparent("si/e", "gamma", "hpge"), None);
+    }
+}
@@ -0,0 +1,293 @@
+//! Reading and writing gates in the formats of other tools: ROOT TCutG macros, as written by
+//! TCutG::SaveAs(".C"), and the JSON cut files of the Specter display app.
+//!
+//! Specter cut files hold a list of cuts tagged by type:
+//! `[{"type": "Cut1D", "name": "...", "x_variable": "...", "min": 0.0, "max": 1.0},
+//!   {"type": "Cut2D", "name": "...", "x_variable": "...", "y_variable": "...",
+//!    "x_values": [...], "y_values": [...]}]`
+
+use super::cut::{Cut, Cut1D, Cut2D, CutSpec};
+use super::cut_registry::SavedCut;
+use super::error::GateFileError;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum SpecterCut {
+    Cut1D {
+        name: String,
+        x_variable: String,
+        min: f32,
+        max: f32,
+    },
+    Cut2D {
+        name: String,
+        x_variable: String,
+        y_variable: String,
+        x_values: Vec<f32>,
+        y_values: Vec<f32>,
+    },
+}
+
+/// Write 1D and 2D cuts to a Specter cut file. Periodic 1D cuts are written as plain windows.
+pub fn write_specter_json<W: Write>(cuts: &[SavedCut], writer: W) -> Result<(), GateFileError> {
+    let cuts = cuts
+        .iter()
+        .map(|saved| match saved.kind.as_str() {
+            Cut1D::KIND => {
+                let cut = Cut1D::from_parameters(saved.spec.clone(), &saved.parameters)?;
+                let (min, max) = cut.get_limits();
+                Ok(SpecterCut::Cut1D {
+                    name: saved.spec.name.clone(),
+                    x_variable: saved.spec.x_variable.clone(),
+                    min,
+                    max,
+                })
+            }
+            Cut2D::KIND => {
+                let cut = Cut2D::from_parameters(saved.spec.clone(), &saved.parameters)?;
+                let (x_values, y_values) = cut.get_vertices();
+                Ok(SpecterCut::Cut2D {
+                    name: saved.spec.name.clone(),
+                    x_variable: saved.spec.x_variable.clone(),
+                    y_variable: saved.spec.y_variable.clone().unwrap_or_default(),
+                    x_values: x_values.to_vec(),
+                    y_values: y_values.to_vec(),
+                })
+            }
+            kind => Err(GateFileError::UnsupportedKind(kind.to_string())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    serde_json::to_writer_pretty(writer, &cuts)?;
+    Ok(())
+}
+
+/// Read the cuts of a Specter cut file. Each cut is given a new ID.
+pub fn read_specter_json<R: Read>(reader: R) -> Result<Vec<SavedCut>, GateFileError> {
+    let cuts: Vec<SpecterCut> = serde_json::from_reader(reader)?;
+    cuts.into_iter()
+        .map(|cut| {
+            let saved = match cut {
+                SpecterCut::Cut1D {
+                    name,
+                    x_variable,
+                    min,
+                    max,
+                } => {
+                    let spec = CutSpec {
+                        id: Uuid::new_v4(),
+                        name,
+                        x_variable,
+                        y_variable: None,
+                    };
+                    SavedCut::from_cut(&Cut1D::new(spec, min, max)?)
+                }
+                SpecterCut::Cut2D {
+                    name,
+                    x_variable,
+                    y_variable,
+                    x_values,
+                    y_values,
+                } => {
+                    let spec = CutSpec {
+                        id: Uuid::new_v4(),
+                        name,
+                        x_variable,
+                        y_variable: Some(y_variable),
+                    };
+                    SavedCut::from_cut(&Cut2D::new(spec, x_values, y_values)?)
+                }
+            };
+            Ok(saved)
+        })
+        .collect()
+}
+
+/// Write a 2D cut as a ROOT macro which recreates it as a TCutG
+pub fn write_tcutg<W: Write>(cut: &SavedCut, mut writer: W) -> Result<(), GateFileError> {
+    if cut.kind != Cut2D::KIND {
+        return Err(GateFileError::UnsupportedKind(cut.kind.clone()));
+    }
+    let cut = Cut2D::from_parameters(cut.spec.clone(), &cut.parameters)?;
+    let spec = cut.get_spec();
+    let (x_values, y_values) = cut.get_vertices();
+    writeln!(writer, "{{")?;
+    writeln!(
+        writer,
+        "   TCutG *cutg = new TCutG(\"{}\",{});",
+        spec.name,
+        x_values.len()
+    )?;
+    writeln!(writer, "   cutg->SetVarX(\"{}\");", spec.x_variable)?;
+    writeln!(
+        writer,
+        "   cutg->SetVarY(\"{}\");",
+        spec.y_variable.as_deref().unwrap_or_default()
+    )?;
+    writeln!(writer, "   cutg->SetTitle(\"Graph\");")?;
+    writeln!(writer, "   cutg->SetFillStyle(1000);")?;
+    for (idx, (x, y)) in x_values.iter().zip(y_values.iter()).enumerate() {
+        writeln!(writer, "   cutg->SetPoint({idx},{x},{y});")?;
+    }
+    writeln!(writer, "   cutg->Draw(\"\");")?;
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+// Get the comma separated arguments of the first call to function on a line
+fn call_arguments<'a>(line: &'a str, function: &str) -> Option<Vec<&'a str>> {
+    let start = line.find(function)? + function.len();
+    let rest = line[start..].trim_start().strip_prefix('(')?;
+    let end = rest.rfind(')')?;
+    Some(
+        rest[..end]
+            .split(',')
+            .map(|arg| arg.trim().trim_matches('"'))
+            .collect(),
+    )
+}
+
+#[derive(Debug, Default)]
+struct PartialTCutG {
+    name: String,
+    x_variable: String,
+    y_variable: String,
+    points: Vec<(usize, f32, f32)>,
+}
+
+impl PartialTCutG {
+    fn finish(mut self) -> Result<SavedCut, GateFileError> {
+        self.points.sort_by_key(|(idx, _, _)| *idx);
+        let (mut x_values, mut y_values): (Vec<f32>, Vec<f32>) =
+            self.points.iter().map(|(_, x, y)| (*x, *y)).unzip();
+        // Graphical cuts drawn in ROOT are closed, but hand-written ones often are not
+        if x_values.first() != x_values.last() || y_values.first() != y_values.last() {
+            x_values.push(x_values[0]);
+            y_values.push(y_values[0]);
+        }
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: self.name,
+            x_variable: self.x_variable,
+            y_variable: Some(self.y_variable),
+        };
+        Ok(SavedCut::from_cut(&Cut2D::new(spec, x_values, y_values)?))
+    }
+}
+
+/// Read every TCutG defined in a ROOT macro. Each cut is given a new ID.
+pub fn read_tcutg<R: Read>(reader: R) -> Result<Vec<SavedCut>, GateFileError> {
+    let parse = |value: &str, line: &str| {
+        value
+            .parse::<f32>()
+            .map_err(|_| GateFileError::Parse(line.to_string()))
+    };
+    let mut cuts = vec![];
+    let mut current: Option<PartialTCutG> = None;
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(args) = call_arguments(line, "new TCutG") {
+            if let Some(cut) = current.take() {
+                cuts.push(cut.finish()?);
+            }
+            current = Some(PartialTCutG {
+                name: args[0].to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let cut = match &mut current {
+            Some(cut) => cut,
+            None => continue,
+        };
+        if let Some(args) = call_arguments(line, "->SetVarX") {
+            cut.x_variable = args[0].to_string();
+        } else if let Some(args) = call_arguments(line, "->SetVarY") {
+            cut.y_variable = args[0].to_string();
+        } else if let Some(args) = call_arguments(line, "->SetPoint") {
+            if args.len() != 3 {
+                return Err(GateFileError::Parse(line.to_string()));
+            }
+            let idx = args[0]
+                .parse::<usize>()
+                .map_err(|_| GateFileError::Parse(line.to_string()))?;
+            cut.points
+                .push((idx, parse(args[1], line)?, parse(args[2], line)?));
+        }
+    }
+    if let Some(cut) = current.take() {
+        cuts.push(cut.finish()?);
+    }
+    Ok(cuts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> SavedCut {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("pid"),
+            x_variable: String::from("de"),
+            y_variable: Some(String::from("e")),
+        };
+        SavedCut::from_cut(
+            &Cut2D::new(
+                spec,
+                vec![0.0, 1.5, 1.5, 0.0, 0.0],
+                vec![0.0, 0.0, 2.5, 2.5, 0.0],
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_tcutg() {
+        let cut = square();
+        let mut macro_text = vec![];
+        write_tcutg(&cut, &mut macro_text).unwrap();
+        let read = read_tcutg(macro_text.as_slice()).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].spec.name, "pid");
+        assert_eq!(read[0].spec.y_variable.as_deref(), Some("e"));
+        assert_eq!(read[0].parameters, cut.parameters);
+
+        let root_macro = r#"{
+   TCutG *cutg = new TCutG("CUTG",3);
+   cutg->SetVarX("x");
+   cutg->SetVarY("y");
+   cutg->SetPoint(0,0,0);
+   cutg->SetPoint(1,1,0);
+   cutg->SetPoint(2,1,1);
+   cutg->Draw("");
+}"#;
+        let read = read_tcutg(root_macro.as_bytes()).unwrap();
+        let cut = Cut2D::from_parameters(read[0].spec.clone(), &read[0].parameters).unwrap();
+        assert_eq!(cut.get_vertices().0, &[0.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_specter_json() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("x"),
+            y_variable: None,
+        };
+        let window = SavedCut::from_cut(&Cut1D::new(spec, 1.0, 2.0).unwrap());
+        let cuts = vec![square(), window];
+        let mut json = vec![];
+        write_specter_json(&cuts, &mut json).unwrap();
+        let read = read_specter_json(json.as_slice()).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, original) in read.iter().zip(cuts.iter()) {
+            assert_eq!(read.kind, original.kind);
+            assert_eq!(read.spec.name, original.spec.name);
+            assert_eq!(read.parameters, original.parameters);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Polygon helpers for building 2D cuts. Polygons are given as separate x and y vertex lists, in
+//! the same form Cut2D takes them, and closed polygons repeat their first vertex at the end.
+
+/// Get the convex hull of a point cloud as a closed, counter-clockwise polygon. Returns None if
+/// the points do not span an area.
+pub fn convex_hull(x_values: &[f32], y_values: &[f32]) -> Option<(Vec<f32>, Vec<f32>)> {
+    let mut points: Vec<(f32, f32)> = x_values
+        .iter()
+        .zip(y_values.iter())
+        .map(|(x, y)| (*x, *y))
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    points.dedup();
+    if points.len() < 3 {
+        return None;
+    }
+
+    // Andrew's monotone chain: build the lower then the upper hull
+    let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    let build_chain = |points: &mut dyn Iterator<Item = &(f32, f32)>| {
+        let mut chain: Vec<(f32, f32)> = vec![];
+        for point in points {
+            while chain.len() >= 2
+                && cross(chain[chain.len() - 2], chain[chain.len() - 1], *point) <= 0.0
+            {
+                chain.pop();
+            }
+            chain.push(*point);
+        }
+        // The last point of each chain starts the other
+        chain.pop();
+        chain
+    };
+    let mut hull = build_chain(&mut points.iter());
+    hull.extend(build_chain(&mut points.iter().rev()));
+    hull.push(hull[0]);
+    if hull.len() < 4 {
+        return None;
+    }
+    Some(hull.into_iter().unzip())
+}
+
+// Twice the signed area, positive for counter-clockwise polygons
+fn signed_double_area(x_values: &[f32], y_values: &[f32]) -> f32 {
+    x_values
+        .windows(2)
+        .zip(y_values.windows(2))
+        .map(|(x, y)| x[0] * y[1] - x[1] * y[0])
+        .sum()
+}
+
+/// Get the area of a closed polygon
+pub fn area(x_values: &[f32], y_values: &[f32]) -> f32 {
+    0.5 * signed_double_area(x_values, y_values).abs()
+}
+
+/// Get the centroid of a closed polygon, or None if it has no area
+pub fn centroid(x_values: &[f32], y_values: &[f32]) -> Option<(f32, f32)> {
+    let double_area = signed_double_area(x_values, y_values);
+    if double_area == 0.0 {
+        return None;
+    }
+    let (x_sum, y_sum) =
+        x_values
+            .windows(2)
+            .zip(y_values.windows(2))
+            .fold((0.0, 0.0), |(x_sum, y_sum), (x, y)| {
+                let cross = x[0] * y[1] - x[1] * y[0];
+                (x_sum + (x[0] + x[1]) * cross, y_sum + (y[0] + y[1]) * cross)
+            });
+    Some((x_sum / (3.0 * double_area), y_sum / (3.0 * double_area)))
+}
+
+// Distance from p to the segment from a to b
+fn segment_distance(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length2 = dx * dx + dy * dy;
+    let t = if length2 == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length2).clamp(0.0, 1.0)
+    };
+    let (x, y) = (a.0 + t * dx, a.1 + t * dy);
+    ((p.0 - x).powi(2) + (p.1 - y).powi(2)).sqrt()
+}
+
+// Douglas-Peucker on an open chain, marking the vertices to keep
+fn simplify_chain(points: &[(f32, f32)], tolerance: f32, keep: &mut [bool]) {
+    if points.len() < 3 {
+        return;
+    }
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (idx, distance) = points[1..points.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(idx, point)| (idx + 1, segment_distance(*point, first, last)))
+        .fold((0, -1.0), |best, candidate| {
+            if candidate.1 > best.1 {
+                candidate
+            } else {
+                best
+            }
+        });
+    if distance > tolerance {
+        keep[idx] = true;
+        simplify_chain(&points[..=idx], tolerance, &mut keep[..=idx]);
+        simplify_chain(&points[idx..], tolerance, &mut keep[idx..]);
+    }
+}
+
+/// Remove vertices of a closed polygon which lie within tolerance of the simplified outline,
+/// using the Douglas-Peucker algorithm. Polygons which would collapse below a triangle are
+/// returned unchanged.
+pub fn simplify(x_values: &[f32], y_values: &[f32], tolerance: f32) -> (Vec<f32>, Vec<f32>) {
+    let points: Vec<(f32, f32)> = x_values
+        .iter()
+        .zip(y_values.iter())
+        .map(|(x, y)| (*x, *y))
+        .collect();
+    if points.len() < 5 {
+        return (x_values.to_vec(), y_values.to_vec());
+    }
+    // Split the ring at the vertex farthest from the first, so both chains have fixed ends
+    let split = (1..points.len() - 1)
+        .max_by(|a, b| {
+            segment_distance(points[*a], points[0], points[0])
+                .total_cmp(&segment_distance(points[*b], points[0], points[0]))
+        })
+        .unwrap_or(1);
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[split] = true;
+    keep[points.len() - 1] = true;
+    simplify_chain(&points[..=split], tolerance, &mut keep[..=split]);
+    simplify_chain(&points[split..], tolerance, &mut keep[split..]);
+
+    let kept: Vec<(f32, f32)> = points
+        .iter()
+        .zip(keep.iter())
+        .filter(|(_, keep)| **keep)
+        .map(|(point, _)| *point)
+        .collect();
+    if kept.len() < 4 {
+        return (x_values.to_vec(), y_values.to_vec());
+    }
+    kept.into_iter().unzip()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convex_hull() {
+        let x_values = [0.0, 2.0, 2.0, 0.0, 1.0, 0.5, 1.5];
+        let y_values = [0.0, 0.0, 2.0, 2.0, 1.0, 1.5, 0.5];
+        let (x, y) = convex_hull(&x_values, &y_values).unwrap();
+        assert_eq!(x, vec![0.0, 2.0, 2.0, 0.0, 0.0]);
+        assert_eq!(y, vec![0.0, 0.0, 2.0, 2.0, 0.0]);
+        assert!(convex_hull(&[0.0, 1.0, 2.0], &[0.0, 1.0, 2.0]).is_none());
+    }
+
+    #[test]
+    fn test_area_and_centroid() {
+        // Clockwise, to check the sign is handled
+        let x_values = [0.0, 0.0, 4.0, 4.0, 0.0];
+        let y_values = [0.0, 2.0, 2.0, 0.0, 0.0];
+        assert_eq!(area(&x_values, &y_values), 8.0);
+        assert_eq!(centroid(&x_values, &y_values), Some((2.0, 1.0)));
+        assert_eq!(centroid(&[0.0, 1.0, 0.0], &[0.0, 1.0, 0.0]), None);
+    }
+
+    #[test]
+    fn test_simplify() {
+        // A square with many nearly collinear vertices along each side
+        let mut x_values = vec![];
+        let mut y_values = vec![];
+        for (x0, y0, x1, y1) in [
+            (0.0, 0.0, 10.0, 0.0),
+            (10.0, 0.0, 10.0, 10.0),
+            (10.0, 10.0, 0.0, 10.0),
+            (0.0, 10.0, 0.0, 0.0),
+        ] {
+            for step in 0..10 {
+                let t = step as f32 / 10.0;
+                let jitter = if step % 2 == 0 { 0.0 } else { 0.01 };
+                x_values.push(x0 + t * (x1 - x0) + jitter);
+                y_values.push(y0 + t * (y1 - y0) + jitter);
+            }
+        }
+        x_values.push(0.0);
+        y_values.push(0.0);
+        let (x, y) = simplify(&x_values, &y_values, 0.1);
+        assert_eq!(x.len(), 5);
+        assert_eq!(x.first(), x.last());
+        assert_eq!(y.first(), y.last());
+        for (x, y) in x.iter().zip(y.iter()) {
+            assert!([*x, *y].iter().all(|v| *v == 0.0 || *v == 10.0));
+        }
+    }
+}
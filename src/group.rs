@@ -0,0 +1,34 @@
+use super::histogram::HistSpec;
+use uuid::Uuid;
+
+/// The placeholder replaced by the channel index when booking histograms from a template
+pub const INDEX_PLACEHOLDER: &str = "{i}";
+
+/// A set of histograms booked together which can be cleared or removed as one
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramGroup {
+    pub id: Uuid,
+    pub name: String,
+    pub members: Vec<Uuid>,
+}
+
+/// Make a copy of a template spec for one index, replacing the placeholder in the name, title,
+/// axis variables and titles, and metadata values. The copy gets a new ID.
+pub fn instantiate(template: &HistSpec, index: usize) -> HistSpec {
+    let index = index.to_string();
+    let substitute = |text: &str| text.replace(INDEX_PLACEHOLDER, &index);
+    let mut spec = template.clone();
+    spec.id = Uuid::new_v4();
+    spec.name = substitute(&template.name);
+    spec.title = substitute(&template.title);
+    spec.x_axis.variable = substitute(&template.x_axis.variable);
+    spec.x_axis.title = substitute(&template.x_axis.title);
+    if let Some(y_axis) = &mut spec.y_axis {
+        y_axis.variable = substitute(&y_axis.variable);
+        y_axis.title = substitute(&y_axis.title);
+    }
+    for value in spec.metadata.values_mut() {
+        *value = substitute(value);
+    }
+    spec
+}
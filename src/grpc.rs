@@ -0,0 +1,544 @@
+//! A gRPC service for control systems and GUIs in other languages, defined in proto/spect.proto:
+//! resource management, SpecTcl-style commands, and a stream of the contents of histograms as
+//! they change. The service runs on its own threads, while the manager stays on the analysis
+//! thread, which handles the waiting requests whenever it calls process.
+use super::command::CommandInterpreter;
+use super::error::ResourceError;
+use super::histogram::{AxisSpec, BinLayout, HistSpec, OutOfRangePolicy};
+use super::manager::ResourceManager;
+use rustc_hash::FxHashMap;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread::JoinHandle;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+/// The messages and service generated from proto/spect.proto
+pub mod proto {
+    tonic::include_proto!("spect");
+}
+
+use proto::spect_server::SpectServer;
+
+// Updates waiting to be sent to a slow client before new ones are dropped
+const WATCH_BUFFER: usize = 16;
+
+type Job = Box<dyn FnOnce(&mut ResourceManager, &mut ServiceState) + Send>;
+
+fn to_status(e: ResourceError) -> Status {
+    match e.get_root() {
+        ResourceError::InvalidHistogramID(_) | ResourceError::InvalidCutID(_) => {
+            Status::not_found(e.to_string())
+        }
+        _ => Status::invalid_argument(e.to_string()),
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(id).map_err(|_| Status::invalid_argument(format!("Bad ID '{id}'")))
+}
+
+fn axis_to_proto(axis: &AxisSpec) -> proto::Axis {
+    proto::Axis {
+        variable: axis.variable.clone(),
+        title: axis.title.clone(),
+        bins: axis.bins as u32,
+        minimum: axis.minimum,
+        maximum: axis.maximum,
+    }
+}
+
+fn axis_from_proto(axis: proto::Axis) -> Result<AxisSpec, Status> {
+    AxisSpec::new(
+        &axis.variable,
+        &axis.title,
+        axis.bins as usize,
+        axis.minimum,
+        axis.maximum,
+    )
+    .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+fn get_info(spec: &HistSpec) -> proto::HistogramInfo {
+    proto::HistogramInfo {
+        id: spec.id.to_string(),
+        name: spec.name.clone(),
+        title: spec.title.clone(),
+        x_axis: Some(axis_to_proto(&spec.x_axis)),
+        y_axis: spec.y_axis.as_ref().map(axis_to_proto),
+    }
+}
+
+// The contents are sent row-major whatever the layout, as HistogramInfo carries no layout
+fn get_data(manager: &ResourceManager, id: &Uuid) -> Result<proto::HistogramData, ResourceError> {
+    let gram = manager.get_histogram(id)?;
+    let (x_bins, y_bins) = gram.get_dimensions();
+    let data = match gram.spec.layout {
+        BinLayout::RowMajor => gram.data.clone(),
+        _ => (0..y_bins)
+            .flat_map(|y_bin| (0..x_bins).map(move |x_bin| gram.data[gram.bin_index(x_bin, y_bin)]))
+            .collect(),
+    };
+    Ok(proto::HistogramData {
+        info: Some(get_info(&gram.spec)),
+        generation: gram.get_generation(),
+        data,
+    })
+}
+
+// A client streaming the contents of histograms
+struct Watcher {
+    // Every histogram is watched if empty
+    ids: Vec<Uuid>,
+    // The generation of each histogram when it was last sent
+    sent: FxHashMap<Uuid, u64>,
+    updates: mpsc::Sender<Result<proto::HistogramData, Status>>,
+}
+
+impl Watcher {
+    // Send every watched histogram which changed, returning false once the client has gone
+    fn publish(&mut self, manager: &ResourceManager) -> bool {
+        let ids: Vec<Uuid> = if self.ids.is_empty() {
+            manager
+                .list_histograms("")
+                .iter()
+                .map(|spec| spec.id)
+                .collect()
+        } else {
+            self.ids.clone()
+        };
+        for id in ids {
+            let Ok(gram) = manager.get_histogram(&id) else {
+                continue;
+            };
+            if self.sent.get(&id) == Some(&gram.get_generation()) {
+                continue;
+            }
+            let Ok(update) = get_data(manager, &id) else {
+                continue;
+            };
+            match self.updates.try_send(Ok(update)) {
+                Ok(()) => {
+                    self.sent.insert(id, gram.get_generation());
+                }
+                // A full buffer is tried again on the next update
+                Err(mpsc::error::TrySendError::Full(_)) => (),
+                Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            }
+        }
+        true
+    }
+}
+
+#[derive(Default)]
+struct ServiceState {
+    interpreter: CommandInterpreter,
+    watchers: Vec<Watcher>,
+}
+
+// Forwards every request to the analysis thread as a job
+struct Service {
+    jobs: Sender<Job>,
+}
+
+impl Service {
+    async fn call<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut ResourceManager, &mut ServiceState) -> Result<T, Status> + Send + 'static,
+    ) -> Result<Response<T>, Status> {
+        let (sender, reply) = oneshot::channel();
+        let job: Job = Box::new(move |manager, state| {
+            let _ = sender.send(job(manager, state));
+        });
+        let unavailable = || Status::unavailable("The manager has shut down");
+        self.jobs.send(job).map_err(|_| unavailable())?;
+        reply.await.map_err(|_| unavailable())?.map(Response::new)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::spect_server::Spect for Service {
+    async fn list_histograms(
+        &self,
+        request: Request<proto::ListHistogramsRequest>,
+    ) -> Result<Response<proto::HistogramList>, Status> {
+        let folder = request.into_inner().folder;
+        self.call(move |manager, _| {
+            Ok(proto::HistogramList {
+                histograms: manager
+                    .list_histograms(&folder)
+                    .into_iter()
+                    .map(get_info)
+                    .collect(),
+            })
+        })
+        .await
+    }
+
+    async fn get_histogram(
+        &self,
+        request: Request<proto::HistogramId>,
+    ) -> Result<Response<proto::HistogramData>, Status> {
+        let id = parse_id(&request.into_inner().id)?;
+        self.call(move |manager, _| get_data(manager, &id).map_err(to_status))
+            .await
+    }
+
+    async fn add_histogram(
+        &self,
+        request: Request<proto::AddHistogramRequest>,
+    ) -> Result<Response<proto::HistogramId>, Status> {
+        let request = request.into_inner();
+        let x_axis = request
+            .x_axis
+            .ok_or_else(|| Status::invalid_argument("A histogram needs an x axis"))?;
+        let spec = HistSpec {
+            id: Uuid::nil(),
+            title: request.title,
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                &request.name,
+                axis_from_proto(x_axis)?,
+                request.y_axis.map(axis_from_proto).transpose()?,
+            )
+        };
+        self.call(move |manager, _| {
+            let id = manager.add_histogram(spec).map_err(to_status)?;
+            Ok(proto::HistogramId { id: id.to_string() })
+        })
+        .await
+    }
+
+    async fn remove_histogram(
+        &self,
+        request: Request<proto::HistogramId>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let id = parse_id(&request.into_inner().id)?;
+        self.call(move |manager, _| {
+            manager.remove_histogram(&id).map_err(to_status)?;
+            Ok(proto::Empty {})
+        })
+        .await
+    }
+
+    async fn clear_histogram(
+        &self,
+        request: Request<proto::HistogramId>,
+    ) -> Result<Response<proto::Empty>, Status> {
+        let id = parse_id(&request.into_inner().id)?;
+        self.call(move |manager, _| {
+            manager.clear_histogram(&id).map_err(to_status)?;
+            Ok(proto::Empty {})
+        })
+        .await
+    }
+
+    async fn list_cuts(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::CutList>, Status> {
+        self.call(|manager, _| {
+            let mut cuts: Vec<proto::Cut> = manager
+                .list_cuts()
+                .into_iter()
+                .map(|(kind, spec)| proto::Cut {
+                    id: spec.id.to_string(),
+                    name: spec.name.clone(),
+                    kind: kind.to_string(),
+                    x_variable: spec.x_variable.clone(),
+                    y_variable: spec.y_variable.clone(),
+                })
+                .collect();
+            cuts.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(proto::CutList { cuts })
+        })
+        .await
+    }
+
+    async fn get_status(
+        &self,
+        _request: Request<proto::Empty>,
+    ) -> Result<Response<proto::Status>, Status> {
+        self.call(|manager, _| {
+            Ok(proto::Status {
+                events: manager.get_event_count(),
+                run: manager.get_run_info().map(|run| run.number),
+            })
+        })
+        .await
+    }
+
+    async fn run_command(
+        &self,
+        request: Request<proto::CommandRequest>,
+    ) -> Result<Response<proto::CommandReply>, Status> {
+        let line = request.into_inner().line;
+        self.call(move |manager, state| {
+            Ok(proto::CommandReply {
+                json: state.interpreter.respond(manager, &line),
+            })
+        })
+        .await
+    }
+
+    type WatchHistogramsStream = ReceiverStream<Result<proto::HistogramData, Status>>;
+
+    async fn watch_histograms(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchHistogramsStream>, Status> {
+        let ids = request
+            .into_inner()
+            .ids
+            .iter()
+            .map(|id| parse_id(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (updates, stream) = mpsc::channel(WATCH_BUFFER);
+        self.call(move |manager, state| {
+            for id in ids.iter() {
+                manager.get_histogram(id).map_err(to_status)?;
+            }
+            state.watchers.push(Watcher {
+                ids,
+                sent: FxHashMap::default(),
+                updates,
+            });
+            Ok(())
+        })
+        .await?;
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+/// Serves the gRPC service from background threads. The analysis thread calls process to handle
+/// the requests waiting and stream the histograms which changed, e.g. once per second. The
+/// service shuts down when the server is stopped or dropped.
+pub struct GrpcServer {
+    jobs: Receiver<Job>,
+    state: ServiceState,
+    address: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for GrpcServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GrpcServer")
+            .field("address", &self.address)
+            .field("n_watchers", &self.state.watchers.len())
+            .finish()
+    }
+}
+
+impl GrpcServer {
+    /// Start serving on an address, e.g. "0.0.0.0:50051"
+    pub fn serve(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        let (sender, jobs) = channel();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            runtime.block_on(async move {
+                let Ok(listener) = tokio::net::TcpListener::from_std(listener) else {
+                    return;
+                };
+                // Requests being handled are finished before stopping
+                let result = tonic::transport::Server::builder()
+                    .add_service(SpectServer::new(Service { jobs: sender }))
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async {
+                        let _ = stopped.await;
+                    })
+                    .await;
+                #[cfg(feature = "tracing")]
+                if let Err(e) = result {
+                    tracing::error!("The gRPC service stopped: {e}");
+                }
+                #[cfg(not(feature = "tracing"))]
+                let _ = result;
+            });
+        });
+        Ok(Self {
+            jobs,
+            state: ServiceState::default(),
+            address,
+            shutdown: Some(shutdown),
+            handle: Some(handle),
+        })
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Handle every request waiting, then send the watched histograms which changed. Returns the
+    /// number of requests handled.
+    pub fn process(&mut self, manager: &mut ResourceManager) -> usize {
+        let mut n_handled = 0;
+        while let Ok(job) = self.jobs.try_recv() {
+            job(manager, &mut self.state);
+            n_handled += 1;
+        }
+        #[cfg(feature = "tracing")]
+        if n_handled > 0 {
+            tracing::debug!(n_handled, "Handled gRPC requests");
+        }
+        self.state
+            .watchers
+            .retain_mut(|watcher| watcher.publish(manager));
+        n_handled
+    }
+
+    /// Stop serving, waiting for the service threads to exit
+    pub fn stop(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            // Requests waiting for process fail, and watch streams end, so that none of them
+            // hold up the shutdown
+            let (_, closed) = channel();
+            self.jobs = closed;
+            self.state.watchers.clear();
+            if let Some(shutdown) = self.shutdown.take() {
+                let _ = shutdown.send(());
+            }
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for GrpcServer {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::spect_client::SpectClient;
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    #[test]
+    fn test_service() {
+        let mut server = GrpcServer::serve("127.0.0.1:0").unwrap();
+        let socket_address = server.get_address();
+        let address = format!("http://{socket_address}");
+        let done = Arc::new(AtomicBool::new(false));
+        let finished = done.clone();
+        // The analysis thread, filling an event whenever it handles requests
+        let analysis = std::thread::spawn(move || {
+            let mut manager = ResourceManager::new();
+            while !finished.load(Ordering::Relaxed) {
+                server.process(&mut manager);
+                let mut data = DataBlob::new();
+                data.insert("si_e", 1.5);
+                manager.update(data).unwrap();
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            manager.list_histograms("").len()
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut client = SpectClient::connect(address).await.unwrap();
+            let axis = proto::Axis {
+                variable: String::from("si_e"),
+                title: String::from("Energy"),
+                bins: 4,
+                minimum: 0.0,
+                maximum: 4.0,
+            };
+            let id = client
+                .add_histogram(proto::AddHistogramRequest {
+                    name: String::from("si_e"),
+                    title: String::from("si_e"),
+                    x_axis: Some(axis),
+                    y_axis: None,
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            let listed = client
+                .list_histograms(proto::ListHistogramsRequest::default())
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(listed.histograms[0].name, "si_e");
+
+            let mut updates = client
+                .watch_histograms(proto::WatchRequest {
+                    ids: vec![id.id.clone()],
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            let first = updates.next().await.unwrap().unwrap();
+            let second = updates.next().await.unwrap().unwrap();
+            assert!(second.generation > first.generation);
+            assert!(second.data[1] > 0.0);
+
+            let reply = client
+                .run_command(proto::CommandRequest {
+                    line: String::from("spectrum -list"),
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(reply.json.contains("\"status\":\"OK\""));
+            let missing = client
+                .get_histogram(proto::HistogramId {
+                    id: Uuid::new_v4().to_string(),
+                })
+                .await;
+            assert_eq!(missing.unwrap_err().code(), tonic::Code::NotFound);
+            let status = client
+                .get_status(proto::Empty {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(status.events > 0);
+        });
+        done.store(true, Ordering::Relaxed);
+        assert_eq!(analysis.join().unwrap(), 1);
+        // The server was dropped with the analysis thread, which closed the listener
+        assert!(std::net::TcpStream::connect(socket_address).is_err());
+    }
+
+    #[test]
+    fn test_row_major_data() {
+        let mut manager = ResourceManager::new();
+        let id = manager
+            .add_histogram(HistSpec {
+                id: Uuid::nil(),
+                layout: BinLayout::UpperTriangle,
+                out_of_range: OutOfRangePolicy::Ignore,
+                ..HistSpec::new(
+                    "sym",
+                    AxisSpec::new("x", "x", 2, 0.0, 2.0).unwrap(),
+                    Some(AxisSpec::new("y", "y", 2, 0.0, 2.0).unwrap()),
+                )
+            })
+            .unwrap();
+        let mut data = DataBlob::new();
+        data.insert("x", 0.5);
+        data.insert("y", 1.5);
+        manager.update(data).unwrap();
+        // The shared bin appears at both (0, 1) and (1, 0)
+        assert_eq!(
+            get_data(&manager, &id).unwrap().data,
+            vec![0.0, 1.0, 1.0, 0.0]
+        );
+    }
+}
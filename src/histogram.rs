@@ -1,13 +1,65 @@
+use super::binning;
+use super::data_blob::TIMESTAMP_VARIABLE;
 use super::error::HistogramError;
+use super::run::ClearPolicy;
+use super::smoothing::Smoothing;
+use super::time::{Duration, Instant};
+use rand::Rng;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::ops::Range;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq)]
+/// Rules for choosing a bin width from a sample of values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BinningRule {
+    /// Width of 2 IQR / n^(1/3); robust against long tails
+    #[default]
+    FreedmanDiaconis,
+    /// Width of 3.49 sigma / n^(1/3); best for roughly normal data
+    Scott,
+}
+
+// Keep suggestions for pathological samples from booking enormous axes
+const MAX_SUGGESTED_BINS: usize = 16384;
+
+/// Wrap a value into the period [minimum, maximum)
+pub fn wrap_periodic(value: f32, minimum: f32, maximum: f32) -> f32 {
+    let wrapped = minimum + (value - minimum).rem_euclid(maximum - minimum);
+    // Rounding can land a tiny negative offset exactly on the upper edge
+    if wrapped >= maximum { minimum } else { wrapped }
+}
+
+/// What the time on an axis of TIMESTAMP_VARIABLE is measured from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TimeOrigin {
+    /// The first event of the current run, e.g. for a count rate over the run
+    RunStart,
+    /// A time on the clock of the timestamps, such as the Unix time of midnight for a stability
+    /// plot over a day. Measuring from a nearby time keeps f32 bins fine grained.
+    Fixed(f64),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AxisSpec {
+    /// The name of the variable filled along the axis
     pub variable: String,
+    /// The label shown on the axis
     pub title: String,
+    /// The unit of the variable, e.g. "keV", or empty if it has none
+    #[serde(default)]
+    pub unit: String,
     pub bins: usize,
     pub minimum: f32,
     pub maximum: f32,
+    /// Periodic axes (e.g. angles) wrap values into [minimum, maximum) instead of overflowing
+    #[serde(default)]
+    pub periodic: bool,
+    /// Where the time of an axis on TIMESTAMP_VARIABLE is measured from, or None to use the
+    /// timestamp as it is
+    #[serde(default)]
+    pub time_origin: Option<TimeOrigin>,
 }
 
 impl AxisSpec {
@@ -25,69 +77,1579 @@ impl AxisSpec {
         Ok(Self {
             variable: variable.to_string(),
             title: title.to_string(),
+            unit: String::new(),
             bins,
             minimum: min,
             maximum: max,
+            periodic: false,
+            time_origin: None,
         })
     }
+    /// Create an axis whose range is one period, such as 0 to 360 degrees
+    pub fn new_periodic(
+        variable: &str,
+        title: &str,
+        bins: usize,
+        min: f32,
+        max: f32,
+    ) -> Result<Self, HistogramError> {
+        let mut axis = Self::new(variable, title, bins, min, max)?;
+        axis.periodic = true;
+        Ok(axis)
+    }
+    /// Create an axis of the time of each event in seconds since an origin, e.g. for a count rate
+    /// against time
+    pub fn new_time(
+        title: &str,
+        bins: usize,
+        min: f32,
+        max: f32,
+        origin: TimeOrigin,
+    ) -> Result<Self, HistogramError> {
+        let mut axis = Self::new(TIMESTAMP_VARIABLE, title, bins, min, max)?.with_unit("s");
+        axis.time_origin = Some(origin);
+        Ok(axis)
+    }
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = unit.to_string();
+        self
+    }
+    /// Get the title with the unit appended, e.g. "Energy (keV)"
+    pub fn get_label(&self) -> String {
+        if self.unit.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} ({})", self.title, self.unit)
+        }
+    }
+    /// Wrap a value into the range of a periodic axis. Values are returned as is for other axes.
+    pub fn wrap(&self, value: f32) -> f32 {
+        if self.periodic && value.is_finite() {
+            wrap_periodic(value, self.minimum, self.maximum)
+        } else {
+            value
+        }
+    }
+    /// Suggest an axis covering every finite value in the sample, with a bin width from the rule.
+    /// Falls back to sqrt(n) bins when the rule gives a zero width.
+    pub fn suggest(
+        variable: &str,
+        values: &[f32],
+        rule: BinningRule,
+    ) -> Result<Self, HistogramError> {
+        let mut sorted: Vec<f32> = values.iter().copied().filter(|v| v.is_finite()).collect();
+        if sorted.len() < 2 {
+            return Err(HistogramError::InsufficientData(sorted.len()));
+        }
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+        let n = sorted.len() as f32;
+        let (min, max) = (sorted[0], sorted[sorted.len() - 1]);
+
+        let mut width = match rule {
+            BinningRule::FreedmanDiaconis => {
+                let quantile = |q: f32| sorted[(q * (n - 1.0)).round() as usize];
+                2.0 * (quantile(0.75) - quantile(0.25)) / n.cbrt()
+            }
+            BinningRule::Scott => {
+                let mean = sorted.iter().sum::<f32>() / n;
+                let variance = sorted.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / (n - 1.0);
+                3.49 * variance.sqrt() / n.cbrt()
+            }
+        };
+        let span = max - min;
+        if span <= 0.0 {
+            // Every value is identical; center a single bin on it
+            return Self::new(variable, variable, 1, min - 0.5, min + 0.5);
+        }
+        if width <= 0.0 {
+            width = span / n.sqrt().ceil();
+        }
+        // One extra bin so the maximum value is inside the exclusive upper edge
+        let bins = ((span / width).floor() as usize + 1).min(MAX_SUGGESTED_BINS);
+        let width = width.max(span / (bins as f32 - 0.5));
+        Self::new(variable, variable, bins, min, min + width * bins as f32)
+    }
     pub fn get_bin_width(&self) -> f32 {
         (self.maximum - self.minimum) / (self.bins as f32)
     }
     pub fn get_bin(&self, value: f32) -> Result<usize, HistogramError> {
-        if value < self.minimum || value >= self.maximum {
+        let value = self.wrap(value);
+        if value.is_nan() || value < self.minimum || value >= self.maximum {
             return Err(HistogramError::OutOfBounds(
                 self.minimum,
                 self.maximum,
                 value,
             ));
         }
-        Ok(((value - self.minimum) / self.get_bin_width()).floor() as usize)
+        // Values just below the maximum may round up onto it
+        Ok((((value - self.minimum) / self.get_bin_width()).floor() as usize).min(self.bins - 1))
+    }
+    pub fn get_bin_low_edge(&self, bin: usize) -> f32 {
+        self.minimum + (bin as f32) * self.get_bin_width()
+    }
+    pub fn get_bin_center(&self, bin: usize) -> f32 {
+        self.minimum + (bin as f32 + 0.5) * self.get_bin_width()
+    }
+    /// Get the bins which overlap the window [low, high), clamped to the axis
+    pub fn get_bin_range(&self, low: f32, high: f32) -> Result<Range<usize>, HistogramError> {
+        if low >= high || high <= self.minimum || low >= self.maximum {
+            return Err(HistogramError::BadRange(low, high));
+        }
+        let width = self.get_bin_width();
+        // A low edge just below the maximum may round up onto it
+        let start =
+            (((low.max(self.minimum) - self.minimum) / width).floor() as usize).min(self.bins - 1);
+        let end = ((high.min(self.maximum) - self.minimum) / width).ceil() as usize;
+        Ok(start..end.clamp(start + 1, self.bins))
+    }
+    /// Axes are compatible if they share a binning; variables and titles may differ
+    pub fn is_compatible(&self, other: &AxisSpec) -> bool {
+        self.bins == other.bins
+            && self.minimum == other.minimum
+            && self.maximum == other.maximum
+            && self.periodic == other.periodic
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// What a histogram does with values which fall outside of an axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutOfRangePolicy {
+    /// Return an OutOfBounds error from fill
+    #[default]
+    Error,
+    /// Drop the value silently
+    Ignore,
+    /// Place the value in the first or last bin of the axis
+    Clamp,
+    /// Count the value in the underflow/overflow counters of the axis
+    Overflow,
+}
+
+/// What a histogram does with a value which is NaN or infinite, or with an event missing one of
+/// its variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ValuePolicy {
+    /// Drop it silently
+    Skip,
+    /// Drop it and count it in the stats of the histogram
+    #[default]
+    Count,
+    /// Count it and return an error, from fill or from the manager update
+    Error,
+}
+
+/// The number of values which fell below or above each axis under the Overflow policy.
+/// A 2D value outside of both axes is counted on both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct OverflowCounts {
+    pub x_underflow: u64,
+    pub x_overflow: u64,
+    pub y_underflow: u64,
+    pub y_overflow: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placement {
+    Bin(usize),
+    Clamped(usize),
+    Underflow,
+    Overflow,
+}
+
+impl Placement {
+    fn get_bin(&self) -> Option<usize> {
+        match self {
+            Self::Bin(bin) | Self::Clamped(bin) => Some(*bin),
+            _ => None,
+        }
+    }
+}
+
+/// Counters describing what happened to the events offered to a histogram
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct HistogramStats {
+    /// Events passed to the histogram by the manager
+    pub offered: u64,
+    /// Events which failed one of the cuts checked by the histogram
+    pub rejected_by_cuts: u64,
+    /// Events which were missing a variable of the histogram, unless its policy skips them
+    pub missing_variables: u64,
+    /// Fills with a NaN or infinite value, unless its policy skips them
+    pub non_finite: u64,
+    /// Fills with a value outside of an axis, whatever the out of range policy did with it
+    pub out_of_range: u64,
+    /// Fills which incremented a bin
+    pub filled: u64,
+}
+
+impl HistogramStats {
+    fn add(&mut self, other: &HistogramStats) {
+        self.offered += other.offered;
+        self.rejected_by_cuts += other.rejected_by_cuts;
+        self.missing_variables += other.missing_variables;
+        self.non_finite += other.non_finite;
+        self.out_of_range += other.out_of_range;
+        self.filled += other.filled;
+    }
+}
+
+/// Configuration for histograms which choose their own axis ranges. The first n_samples fills are
+/// buffered, then each axis is set to span the given percentiles of the buffered values, widened
+/// on each side by padding times the span. The number of bins is kept from the spec.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AutoRangeSpec {
+    pub n_samples: usize,
+    pub lower_percentile: f32,
+    pub upper_percentile: f32,
+    pub padding: f32,
+}
+
+impl AutoRangeSpec {
+    pub fn new(n_samples: usize) -> Self {
+        Self {
+            n_samples,
+            lower_percentile: 0.01,
+            upper_percentile: 0.99,
+            padding: 0.05,
+        }
+    }
+
+    fn choose_range(&self, values: &mut [f32]) -> Option<(f32, f32)> {
+        values.sort_unstable_by(|a, b| a.total_cmp(b));
+        let last = values.len().checked_sub(1)?;
+        let low_index = (self.lower_percentile.clamp(0.0, 1.0) * last as f32).round() as usize;
+        let high_index = (self.upper_percentile.clamp(0.0, 1.0) * last as f32).round() as usize;
+        let (low, high) = (values[low_index], values[high_index.max(low_index)]);
+        // A single repeated value still needs a non-empty range
+        let pad = match high - low {
+            span if span > 0.0 => span * self.padding,
+            _ => low.abs().max(1.0) * 0.5,
+        };
+        Some((low - pad, high + pad))
+    }
+}
+
+/// Restricts a histogram to recent data. The window is split into buckets which age out one at a
+/// time, so the histogram covers between (buckets - 1) / buckets and all of the window.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RollingWindow {
+    /// Keep the fills from the last length of wall-clock time
+    Duration { length: Duration, buckets: usize },
+    /// Keep the fills from the last count events offered to the histogram
+    Events { count: usize, buckets: usize },
+}
+
+impl RollingWindow {
+    fn get_buckets(&self) -> usize {
+        match self {
+            Self::Duration { buckets, .. } | Self::Events { buckets, .. } => (*buckets).max(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WindowBucket {
+    data: Vec<f64>,
+    sum_weights2: Option<Vec<f64>>,
+}
+
+// The oldest bucket is at the front and the bucket being filled is at the back
+#[derive(Debug, Clone)]
+struct WindowState {
+    buckets: VecDeque<WindowBucket>,
+    bucket_start: Instant,
+    bucket_events: usize,
+}
+
+/// The order in which the bins of a 2D histogram are stored in its flat data array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BinLayout {
+    /// Rows of constant y, with x varying fastest: index = y_bin * x_bins + x_bin
+    #[default]
+    RowMajor,
+    /// Columns of constant x, with y varying fastest: index = x_bin * y_bins + y_bin
+    ColumnMajor,
+    /// Only the bins with x_bin <= y_bin, for symmetric matrices with the same binning on both
+    /// axes; bins (x, y) and (y, x) share storage, halving the memory used
+    UpperTriangle,
+}
+
+impl BinLayout {
+    pub fn index(&self, x_bin: usize, y_bin: usize, x_bins: usize, y_bins: usize) -> usize {
+        match self {
+            Self::RowMajor => y_bin * x_bins + x_bin,
+            Self::ColumnMajor => x_bin * y_bins + y_bin,
+            Self::UpperTriangle => {
+                let (low, high) = (x_bin.min(y_bin), x_bin.max(y_bin));
+                high * (high + 1) / 2 + low
+            }
+        }
+    }
+
+    /// Get the (x_bin, y_bin) of an index; bins of an upper triangle have x_bin <= y_bin
+    pub fn coordinates(&self, index: usize, x_bins: usize, y_bins: usize) -> (usize, usize) {
+        match self {
+            Self::RowMajor => (index % x_bins, index / x_bins),
+            Self::ColumnMajor => (index / y_bins, index % y_bins),
+            Self::UpperTriangle => {
+                let mut high = (((8 * index + 1) as f64).sqrt() as usize).saturating_sub(1) / 2;
+                // Correct any rounding of the square root
+                while high * (high + 1) / 2 > index {
+                    high -= 1;
+                }
+                while (high + 1) * (high + 2) / 2 <= index {
+                    high += 1;
+                }
+                (index - high * (high + 1) / 2, high)
+            }
+        }
+    }
+
+    /// Get the number of bins stored for axes with these numbers of bins
+    pub fn get_n_bins(&self, x_bins: usize, y_bins: usize) -> usize {
+        match self {
+            Self::UpperTriangle => x_bins.saturating_mul(x_bins.saturating_add(1)) / 2,
+            _ => x_bins.saturating_mul(y_bins),
+        }
+    }
+
+    /// Get the layout of full copies of the contents, such as downsampled data or slices
+    pub fn get_dense(&self) -> Self {
+        match self {
+            Self::UpperTriangle => Self::RowMajor,
+            layout => *layout,
+        }
+    }
+}
+
+/// How the values of an event become fills
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FillMode {
+    /// Fill at the values of the axis variables
+    #[default]
+    Value,
+    /// A bit mask spectrum, e.g. of trigger bits or error flags: the x variable is read as an
+    /// integer, and x bin n is filled once for each event with bit n set. Flag variables are read
+    /// as they are; values are truncated to integers and negative ones are skipped.
+    BitMask,
+    /// A symmetric matrix, e.g. of gamma-gamma coincidences: each pair of values fills both
+    /// (x, y) and (y, x). If both axes use the same array variable, every pair of distinct entries
+    /// is used. An UpperTriangle layout stores both in one bin, so each pair is filled once.
+    Symmetric,
+    /// A summary spectrum of many channels side by side, e.g. the energy of every crystal: y is
+    /// filled with each variable matching the y variable pattern, at an x of the number its first
+    /// wildcard matched, so "clover.*.energy" fills clover.3.energy at x = 3. Array variables
+    /// are filled at the index of each entry. The x variable is not used.
+    Summary,
+}
+
+/// Only the name and axes are needed to deserialize a spec, e.g. from a configuration file. A
+/// missing ID is generated and everything else takes its default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HistSpec {
+    #[serde(default = "Uuid::new_v4")]
     pub id: Uuid,
     pub name: String,
+    #[serde(default)]
     pub title: String,
     pub x_axis: AxisSpec,
+    #[serde(default)]
     pub y_axis: Option<AxisSpec>,
+    #[serde(default)]
     pub cuts_to_draw: Vec<Uuid>,
+    #[serde(default)]
     pub cuts_to_check: Vec<Uuid>,
+    #[serde(default)]
+    pub layout: BinLayout,
+    #[serde(default)]
+    pub out_of_range: OutOfRangePolicy,
+    /// Store the sum of squared weights per bin so that bin errors are correct for weighted fills
+    #[serde(default)]
+    pub track_errors: bool,
+    /// If set, the axis ranges are replaced by ranges chosen from the first fills
+    #[serde(default)]
+    pub auto_range: Option<AutoRangeSpec>,
+    /// If set, the histogram only reflects recent data
+    #[serde(default)]
+    pub window: Option<RollingWindow>,
+    #[serde(default)]
+    pub clear_policy: ClearPolicy,
+    /// Arbitrary tags for frontends and exporters, e.g. the detector or the person who booked it
+    #[serde(default)]
+    pub metadata: FxHashMap<String, String>,
+    #[serde(default)]
+    pub fill_mode: FillMode,
+    /// What to do with NaN and infinite values
+    #[serde(default)]
+    pub nan_policy: ValuePolicy,
+    /// What to do with events missing a variable of the histogram
+    #[serde(default)]
+    pub missing_policy: ValuePolicy,
 }
 
-#[derive(Debug)]
+// Bytes of one pending fill while waiting for the axes to be auto-ranged
+type PendingFill = (f32, Option<f32>, f64);
+
+impl HistSpec {
+    /// Create a spec with a new ID and everything but the name and axes at the defaults used when
+    /// deserializing, for struct update syntax: HistSpec { track_errors: true, ..HistSpec::new(..) }
+    pub fn new(name: &str, x_axis: AxisSpec, y_axis: Option<AxisSpec>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            title: String::new(),
+            x_axis,
+            y_axis,
+            cuts_to_draw: vec![],
+            cuts_to_check: vec![],
+            layout: BinLayout::default(),
+            out_of_range: OutOfRangePolicy::default(),
+            track_errors: false,
+            auto_range: None,
+            window: None,
+            clear_policy: ClearPolicy::default(),
+            metadata: FxHashMap::default(),
+            fill_mode: FillMode::default(),
+            nan_policy: ValuePolicy::default(),
+            missing_policy: ValuePolicy::default(),
+        }
+    }
+
+    pub fn get_n_bins(&self) -> usize {
+        match &self.y_axis {
+            Some(y_axis) => self.layout.get_n_bins(self.x_axis.bins, y_axis.bins),
+            None => self.x_axis.bins,
+        }
+    }
+
+    /// Estimate the memory a histogram booked from this spec will use, in bytes, without
+    /// allocating it. Saturates rather than overflowing for absurd axes.
+    pub fn estimate_memory(&self) -> usize {
+        let value = std::mem::size_of::<f64>();
+        let mut per_bin = value;
+        let mut per_bucket = value;
+        if self.track_errors {
+            per_bin += value;
+            per_bucket += value;
+        }
+        if let Some(window) = &self.window {
+            per_bin = per_bin.saturating_add(per_bucket.saturating_mul(window.get_buckets()));
+        }
+        let pending = self.auto_range.as_ref().map_or(0, |auto| {
+            auto.n_samples
+                .saturating_mul(std::mem::size_of::<PendingFill>())
+        });
+        self.get_n_bins()
+            .saturating_mul(per_bin)
+            .saturating_add(pending)
+            .saturating_add(ChangeLog::estimate_memory(self.get_n_bins()))
+            .saturating_add(std::mem::size_of::<Histogram>())
+    }
+
+    /// Check if histograms booked from both specs store their contents the same way, so that
+    /// one spec can replace the other without losing the contents
+    pub fn has_same_binning(&self, other: &HistSpec) -> bool {
+        let same_y_axis = match (&self.y_axis, &other.y_axis) {
+            (Some(ours), Some(theirs)) => ours.is_compatible(theirs),
+            (None, None) => true,
+            _ => false,
+        };
+        self.x_axis.is_compatible(&other.x_axis)
+            && same_y_axis
+            && self.layout == other.layout
+            && self.track_errors == other.track_errors
+            && self.auto_range == other.auto_range
+            && self.window == other.window
+    }
+}
+
+/// Histogram contents aggregated down to a coarser binning for display. The data uses the
+/// dense layout of the source histogram; see BinLayout::get_dense.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownsampledData {
+    pub x_bins: usize,
+    pub y_bins: usize,
+    /// Number of original bins summed along each axis per downsampled bin
+    pub x_factor: usize,
+    pub y_factor: usize,
+    pub data: Vec<f64>,
+}
+
+/// The contents of a window of a histogram. Edges include the upper edge of the last bin, and
+/// data uses the dense layout of the source histogram. 1D slices have no y edges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSlice {
+    pub x_edges: Vec<f32>,
+    pub y_edges: Vec<f32>,
+    pub data: Vec<f64>,
+}
+
+/// The axis of a 2D histogram kept by a projection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProjectionAxis {
+    X,
+    Y,
+}
+
+/// How a normalized copy of a histogram is scaled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Normalization {
+    /// The contents sum to 1
+    Area,
+    /// The largest bin is 1
+    Peak,
+}
+
+/// The bins of a histogram which changed after a given generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramDelta {
+    pub generation: u64,
+    pub bins: Vec<(usize, f64)>,
+}
+
+// The bins changed at each generation, oldest first, so that a delta costs as much as the
+// changes it holds rather than the size of the histogram. Entries for bins which changed again
+// later are compacted away, and the oldest are dropped once the log outgrows its limit.
+#[derive(Debug, Clone)]
+struct ChangeLog {
+    entries: Vec<(u64, usize)>,
+    // Changes up to this generation may have been dropped
+    complete_after: u64,
+    limit: usize,
+}
+
+impl ChangeLog {
+    fn new(n_bins: usize) -> Self {
+        let limit = Self::get_limit(n_bins);
+        Self {
+            entries: Vec::with_capacity(limit),
+            complete_after: 0,
+            limit,
+        }
+    }
+
+    fn get_limit(n_bins: usize) -> usize {
+        (n_bins / 4).max(64)
+    }
+
+    // The memory of a full log for a histogram with n_bins
+    fn estimate_memory(n_bins: usize) -> usize {
+        Self::get_limit(n_bins).saturating_mul(std::mem::size_of::<(u64, usize)>())
+    }
+
+    fn record(&mut self, generation: u64, bin: usize) {
+        if self.entries.len() >= self.limit {
+            self.compact();
+        }
+        self.entries.push((generation, bin));
+    }
+
+    fn compact(&mut self) {
+        let mut seen = FxHashSet::default();
+        let mut latest: Vec<(u64, usize)> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|(_, bin)| seen.insert(*bin))
+            .copied()
+            .collect();
+        latest.reverse();
+        let keep = self.limit / 2;
+        if latest.len() > keep {
+            let dropped = latest.len() - keep;
+            self.complete_after = latest[dropped - 1].0;
+            latest.drain(..dropped);
+        }
+        // Refill in place, so that the log never grows past its limit
+        self.entries.clear();
+        self.entries.extend(latest);
+    }
+
+    // The bins changed after since_generation in order, or None if the log no longer holds
+    // every change since then
+    fn get_bins_since(&self, since_generation: u64) -> Option<Vec<usize>> {
+        if since_generation < self.complete_after {
+            return None;
+        }
+        let start = self
+            .entries
+            .partition_point(|(generation, _)| *generation <= since_generation);
+        let mut bins: Vec<usize> = self.entries[start..].iter().map(|(_, bin)| *bin).collect();
+        bins.sort_unstable();
+        bins.dedup();
+        Some(bins)
+    }
+
+    fn get_memory_usage(&self) -> usize {
+        self.entries.capacity() * std::mem::size_of::<(u64, usize)>()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Histogram {
     pub spec: HistSpec,
-    pub data: Vec<u16>,
+    pub data: Vec<f64>,
+    pub stats: HistogramStats,
+    sum_weights2: Option<Vec<f64>>,
+    // Fills waiting for the axes to be auto-ranged
+    pending_fills: Option<Vec<PendingFill>>,
+    overflow: OverflowCounts,
+    window: Option<WindowState>,
+    // Bumped on every change to data, with the bins changed logged against it
+    generation: u64,
+    changes: ChangeLog,
 }
 
 impl Histogram {
     pub fn new(spec: HistSpec) -> Self {
-        let data = match &spec.y_axis {
-            None => vec![0; spec.x_axis.bins],
-            Some(y_axis) => vec![0; spec.x_axis.bins * y_axis.bins],
-        };
-        Self { spec, data }
-    }
-
-    pub fn fill(&mut self, x_value: f32, y_value: Option<f32>) -> Result<usize, HistogramError> {
-        let mut bin = self.spec.x_axis.get_bin(x_value)?;
-        if let Some(y) = y_value {
-            match &self.spec.y_axis {
-                None => return Err(HistogramError::WrongDimensions),
-                Some(y_axis) => {
-                    bin = bin * y_axis.get_bin(y)?;
-                    self.data[bin] += 1;
-                    return Ok(bin);
+        let data = vec![0.0; spec.get_n_bins()];
+        let sum_weights2 = spec.track_errors.then(|| vec![0.0; data.len()]);
+        let pending_fills = spec
+            .auto_range
+            .as_ref()
+            .map(|auto| Vec::with_capacity(auto.n_samples));
+        let changes = ChangeLog::new(data.len());
+        let window = spec.window.map(|_| WindowState {
+            buckets: VecDeque::from([WindowBucket {
+                data: vec![0.0; data.len()],
+                sum_weights2: sum_weights2.clone(),
+            }]),
+            bucket_start: Instant::now(),
+            bucket_events: 0,
+        });
+        Self {
+            spec,
+            data,
+            stats: HistogramStats::default(),
+            sum_weights2,
+            pending_fills,
+            overflow: OverflowCounts::default(),
+            window,
+            generation: 0,
+            changes,
+        }
+    }
+
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Get the memory allocated for the histogram, in bytes
+    pub fn get_memory_usage(&self) -> usize {
+        let bytes = |data: &Vec<f64>| data.capacity() * std::mem::size_of::<f64>();
+        let mut total =
+            std::mem::size_of::<Self>() + bytes(&self.data) + self.changes.get_memory_usage();
+        total += self.sum_weights2.as_ref().map_or(0, bytes);
+        total += self.pending_fills.as_ref().map_or(0, |pending| {
+            pending.capacity() * std::mem::size_of::<PendingFill>()
+        });
+        if let Some(window) = &self.window {
+            for bucket in window.buckets.iter() {
+                total += bytes(&bucket.data) + bucket.sum_weights2.as_ref().map_or(0, bytes);
+            }
+        }
+        total
+    }
+
+    /// Bump the generation after changing the spec, so that observers know to refresh
+    pub fn mark_modified(&mut self) {
+        self.generation += 1;
+    }
+
+    /// Zero the contents, errors, overflow counters, and stats, keeping the spec
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        for (bin, count) in self.data.iter_mut().enumerate() {
+            if *count != 0.0 {
+                *count = 0.0;
+                self.changes.record(self.generation, bin);
+            }
+        }
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            sum_weights2.fill(0.0);
+        }
+        if let Some(window) = &mut self.window {
+            window.buckets.truncate(1);
+            if let Some(bucket) = window.buckets.front_mut() {
+                bucket.data.fill(0.0);
+                if let Some(sum_weights2) = &mut bucket.sum_weights2 {
+                    sum_weights2.fill(0.0);
                 }
             }
-        } else if self.spec.y_axis.is_some() {
-            return Err(HistogramError::WrongDimensions);
+            window.bucket_start = Instant::now();
+            window.bucket_events = 0;
+        }
+        self.overflow = OverflowCounts::default();
+        self.stats = HistogramStats::default();
+    }
+
+    /// Get every bin which changed after since_generation along with its current content, in
+    /// bin order. Passing the returned generation to the next call yields only the newer
+    /// changes. A generation so old that its changes are no longer logged yields every bin.
+    pub fn get_delta(&self, since_generation: u64) -> HistogramDelta {
+        let bins = match self.changes.get_bins_since(since_generation) {
+            Some(bins) => bins.into_iter().map(|bin| (bin, self.data[bin])).collect(),
+            None => self.data.iter().copied().enumerate().collect(),
+        };
+        HistogramDelta {
+            generation: self.generation,
+            bins,
+        }
+    }
+
+    /// Sum neighbouring bins so that the result has at most max_bins_x by max_bins_y bins.
+    /// 1D histograms ignore max_bins_y.
+    pub fn downsample(&self, max_bins_x: usize, max_bins_y: usize) -> DownsampledData {
+        let (x_bins, y_bins) = self.get_dimensions();
+        let x_factor = x_bins.div_ceil(max_bins_x.max(1));
+        let y_factor = y_bins.div_ceil(max_bins_y.max(1));
+        let new_x_bins = x_bins.div_ceil(x_factor);
+        let new_y_bins = y_bins.div_ceil(y_factor);
+
+        let layout = self.spec.layout.get_dense();
+        let mut data = vec![0.0; new_x_bins * new_y_bins];
+        for y_bin in 0..y_bins {
+            for x_bin in 0..x_bins {
+                let new_bin =
+                    layout.index(x_bin / x_factor, y_bin / y_factor, new_x_bins, new_y_bins);
+                data[new_bin] += self.data[self.bin_index(x_bin, y_bin)];
+            }
+        }
+        DownsampledData {
+            x_bins: new_x_bins,
+            y_bins: new_y_bins,
+            x_factor,
+            y_factor,
+            data,
+        }
+    }
+
+    /// Get the bins overlapping the given axis windows. y_range is required for 2D histograms
+    /// and must be None for 1D histograms.
+    pub fn slice(
+        &self,
+        x_range: (f32, f32),
+        y_range: Option<(f32, f32)>,
+    ) -> Result<HistogramSlice, HistogramError> {
+        let x_bins = self.spec.x_axis.get_bin_range(x_range.0, x_range.1)?;
+        let y_bins = match (&self.spec.y_axis, y_range) {
+            (Some(axis), Some((low, high))) => Some((axis, axis.get_bin_range(low, high)?)),
+            (None, None) => None,
+            _ => return Err(HistogramError::WrongDimensions),
+        };
+
+        let x_edges = (x_bins.start..=x_bins.end)
+            .map(|bin| self.spec.x_axis.get_bin_low_edge(bin))
+            .collect();
+        let (y_edges, data) = match y_bins {
+            None => (vec![], self.data[x_bins].to_vec()),
+            Some((axis, y_bins)) => {
+                let y_edges = (y_bins.start..=y_bins.end)
+                    .map(|bin| axis.get_bin_low_edge(bin))
+                    .collect();
+                let (n_x, n_y) = (x_bins.len(), y_bins.len());
+                let mut data = vec![0.0; n_x * n_y];
+                for y_bin in y_bins.clone() {
+                    for x_bin in x_bins.clone() {
+                        let index = self.spec.layout.get_dense().index(
+                            x_bin - x_bins.start,
+                            y_bin - y_bins.start,
+                            n_x,
+                            n_y,
+                        );
+                        data[index] = self.data[self.bin_index(x_bin, y_bin)];
+                    }
+                }
+                (y_edges, data)
+            }
+        };
+        Ok(HistogramSlice {
+            x_edges,
+            y_edges,
+            data,
+        })
+    }
+
+    /// Iterate over every bin as (x center, y center, count). The y center is None for 1D histograms.
+    pub fn iter_bins(&self) -> impl Iterator<Item = (f32, Option<f32>, f64)> + '_ {
+        self.data.iter().enumerate().map(|(bin, count)| {
+            let (x_bin, y_bin) = self.bin_coordinates(bin);
+            (
+                self.spec.x_axis.get_bin_center(x_bin),
+                self.spec
+                    .y_axis
+                    .as_ref()
+                    .map(|axis| axis.get_bin_center(y_bin)),
+                *count,
+            )
+        })
+    }
+
+    /// Iterate over only the bins with non-zero counts, as with iter_bins
+    pub fn iter_nonzero(&self) -> impl Iterator<Item = (f32, Option<f32>, f64)> + '_ {
+        self.iter_bins().filter(|(_, _, count)| *count != 0.0)
+    }
+
+    /// Draw n random (x, y) values distributed as the contents of the histogram, uniformly
+    /// within each bin. The y values are None for 1D histograms. Negative bins are treated as
+    /// empty.
+    pub fn sample(
+        &self,
+        rng: &mut impl Rng,
+        n: usize,
+    ) -> Result<Vec<(f32, Option<f32>)>, HistogramError> {
+        let mut total = 0.0;
+        let cumulative: Vec<f64> = self
+            .data
+            .iter()
+            .map(|count| {
+                total += count.max(0.0);
+                total
+            })
+            .collect();
+        if total <= 0.0 {
+            return Err(HistogramError::InsufficientData(0));
+        }
+        let mut values = Vec::with_capacity(n);
+        for _ in 0..n {
+            let target = rng.random::<f64>() * total;
+            // Skip empty bins by taking the first bin whose running total passes the target
+            let bin = cumulative
+                .partition_point(|sum| *sum <= target)
+                .min(cumulative.len() - 1);
+            let (x_bin, y_bin) = self.bin_coordinates(bin);
+            let mut in_bin = |axis: &AxisSpec, bin: usize| {
+                axis.get_bin_low_edge(bin) + rng.random::<f32>() * axis.get_bin_width()
+            };
+            let x = in_bin(&self.spec.x_axis, x_bin);
+            let y = self.spec.y_axis.as_ref().map(|axis| in_bin(axis, y_bin));
+            values.push((x, y));
+        }
+        Ok(values)
+    }
+
+    /// Get the number of bins along x and y. 1D histograms have a single y bin.
+    pub fn get_dimensions(&self) -> (usize, usize) {
+        (
+            self.spec.x_axis.bins,
+            self.spec.y_axis.as_ref().map_or(1, |axis| axis.bins),
+        )
+    }
+
+    /// Get the index into data of a bin, following the layout of the spec.
+    /// 1D histograms always use a y_bin of 0.
+    pub fn bin_index(&self, x_bin: usize, y_bin: usize) -> usize {
+        let (x_bins, y_bins) = self.get_dimensions();
+        self.spec.layout.index(x_bin, y_bin, x_bins, y_bins)
+    }
+
+    /// Get the (x_bin, y_bin) of an index into data; the inverse of bin_index
+    pub fn bin_coordinates(&self, bin: usize) -> (usize, usize) {
+        let (x_bins, y_bins) = self.get_dimensions();
+        self.spec.layout.coordinates(bin, x_bins, y_bins)
+    }
+
+    pub fn get_bin_content(&self, x_bin: usize, y_bin: usize) -> Result<f64, HistogramError> {
+        let bin = self.checked_bin_index(x_bin, y_bin)?;
+        Ok(self.data[bin])
+    }
+
+    /// Replace the content of a bin, e.g. with a value from a reference spectrum. A tracked error
+    /// becomes the Poisson error of the new content. In a rolling window, the content counts as
+    /// filled now, so it ages out with the bucket being filled.
+    pub fn set_bin_content(
+        &mut self,
+        x_bin: usize,
+        y_bin: usize,
+        content: f64,
+    ) -> Result<(), HistogramError> {
+        let bin = self.checked_bin_index(x_bin, y_bin)?;
+        self.generation += 1;
+        self.data[bin] = content;
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            sum_weights2[bin] = content.abs();
+        }
+        self.changes.record(self.generation, bin);
+        if let Some(window) = &mut self.window {
+            let n_buckets = window.buckets.len();
+            for (idx, bucket) in window.buckets.iter_mut().enumerate() {
+                let is_filling = idx + 1 == n_buckets;
+                bucket.data[bin] = if is_filling { content } else { 0.0 };
+                if let Some(sum_weights2) = &mut bucket.sum_weights2 {
+                    sum_weights2[bin] = if is_filling { content.abs() } else { 0.0 };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add counts to a bin, e.g. to sum spectra read from files. Unlike a weighted fill, the
+    /// counts are taken to be independent, so a tracked error grows by their Poisson error.
+    pub fn add_bin_content(
+        &mut self,
+        x_bin: usize,
+        y_bin: usize,
+        content: f64,
+    ) -> Result<(), HistogramError> {
+        let bin = self.checked_bin_index(x_bin, y_bin)?;
+        self.generation += 1;
+        self.data[bin] += content;
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            sum_weights2[bin] += content.abs();
+        }
+        self.changes.record(self.generation, bin);
+        if let Some(bucket) = self.window.as_mut().and_then(|w| w.buckets.back_mut()) {
+            bucket.data[bin] += content;
+            if let Some(sum_weights2) = &mut bucket.sum_weights2 {
+                sum_weights2[bin] += content.abs();
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the contents with counts for every bin, with x varying fastest as in most spectrum
+    /// files, so that (x_bin, y_bin) is at y_bin * x_bins + x_bin. Bins shared by both halves of
+    /// an upper triangle matrix take the count of the upper half.
+    pub fn load_data(&mut self, counts: &[f64]) -> Result<(), HistogramError> {
+        let (x_bins, y_bins) = self.get_dimensions();
+        if counts.len() != x_bins * y_bins {
+            return Err(HistogramError::BadDataLength(counts.len(), x_bins * y_bins));
+        }
+        self.clear();
+        for (idx, count) in counts.iter().enumerate() {
+            let (x_bin, y_bin) = (idx % x_bins, idx / x_bins);
+            if *count != 0.0 && (self.spec.layout != BinLayout::UpperTriangle || x_bin <= y_bin) {
+                self.set_bin_content(x_bin, y_bin, *count)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn checked_bin_index(&self, x_bin: usize, y_bin: usize) -> Result<usize, HistogramError> {
+        let (x_bins, y_bins) = self.get_dimensions();
+        if x_bin >= x_bins || y_bin >= y_bins {
+            return Err(HistogramError::BadBin(x_bin, y_bin));
+        }
+        Ok(self.bin_index(x_bin, y_bin))
+    }
+
+    /// Get the uncertainty of the content of a bin (an index into data). This is the square root
+    /// of the sum of squared weights if errors are tracked, otherwise the Poisson error.
+    pub fn bin_error(&self, bin: usize) -> Result<f64, HistogramError> {
+        if bin >= self.data.len() {
+            let (x_bin, y_bin) = self.bin_coordinates(bin);
+            return Err(HistogramError::BadBin(x_bin, y_bin));
+        }
+        match &self.sum_weights2 {
+            Some(sum_weights2) => Ok(sum_weights2[bin].sqrt()),
+            None => Ok(self.data[bin].abs().sqrt()),
+        }
+    }
+
+    fn increment(&mut self, bin: usize, weight: f64) {
+        self.generation += 1;
+        self.data[bin] += weight;
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            sum_weights2[bin] += weight * weight;
+        }
+        self.changes.record(self.generation, bin);
+        if let Some(bucket) = self.window.as_mut().and_then(|w| w.buckets.back_mut()) {
+            bucket.data[bin] += weight;
+            if let Some(sum_weights2) = &mut bucket.sum_weights2 {
+                sum_weights2[bin] += weight * weight;
+            }
+        }
+    }
+
+    /// Age out the buckets of a duration windowed histogram which are older than the window at
+    /// the time now. This happens on every fill, but should also be called periodically so that a
+    /// histogram which stops receiving data empties out.
+    pub fn age_window(&mut self, now: Instant) {
+        let (Some(RollingWindow::Duration { length, buckets }), Some(state)) =
+            (self.spec.window, &mut self.window)
+        else {
+            return;
+        };
+        let width = (length.as_nanos() / buckets.max(1) as u128).max(1);
+        let elapsed = now.saturating_duration_since(state.bucket_start).as_nanos();
+        let n_rotations = elapsed / width;
+        if n_rotations == 0 {
+            return;
+        }
+        state.bucket_start = now - Duration::from_nanos((elapsed % width) as u64);
+        for _ in 0..n_rotations.min(buckets as u128 + 1) {
+            self.rotate_window();
+        }
+    }
+
+    // Start a new bucket, removing the contents of the oldest bucket if the window is full
+    fn rotate_window(&mut self) {
+        let (Some(spec), Some(state)) = (self.spec.window, &mut self.window) else {
+            return;
+        };
+        state.bucket_events = 0;
+        state.buckets.push_back(WindowBucket {
+            data: vec![0.0; self.data.len()],
+            sum_weights2: self
+                .sum_weights2
+                .as_ref()
+                .map(|_| vec![0.0; self.data.len()]),
+        });
+        if state.buckets.len() <= spec.get_buckets() {
+            return;
+        }
+        let Some(expired) = state.buckets.pop_front() else {
+            return;
+        };
+        self.generation += 1;
+        for (bin, count) in expired.data.iter().enumerate() {
+            if *count != 0.0 {
+                self.data[bin] -= *count;
+                self.changes.record(self.generation, bin);
+            }
+        }
+        if let (Some(sum_weights2), Some(expired)) = (&mut self.sum_weights2, expired.sum_weights2)
+        {
+            for (bin, weight2) in expired.iter().enumerate() {
+                sum_weights2[bin] -= *weight2;
+            }
+        }
+    }
+
+    /// Count one event offered to a histogram with an event window, rotating the buckets once the
+    /// newest is full. Fills do not count events, as one event may fill a histogram many times,
+    /// so whatever offers the events calls this once per event; the manager does so for its own.
+    pub fn advance_window(&mut self) {
+        let Some(RollingWindow::Events { count, buckets }) = self.spec.window else {
+            return;
+        };
+        let per_bucket = count.div_ceil(buckets.max(1)).max(1);
+        if self
+            .window
+            .as_ref()
+            .is_some_and(|state| state.bucket_events >= per_bucket)
+        {
+            self.rotate_window();
+        }
+        if let Some(state) = &mut self.window {
+            state.bucket_events += 1;
+        }
+    }
+
+    /// Check if the histogram is still buffering fills to choose its axis ranges
+    pub fn is_auto_ranging(&self) -> bool {
+        self.pending_fills.is_some()
+    }
+
+    /// Choose the axis ranges from the fills buffered so far and fill them into the histogram,
+    /// without waiting for the full number of samples. Does nothing if not auto-ranging.
+    pub fn finish_auto_range(&mut self) {
+        let Some(pending) = self.pending_fills.take() else {
+            return;
+        };
+        self.mark_modified();
+        if let Some(auto) = &self.spec.auto_range {
+            let mut x_values: Vec<f32> = pending
+                .iter()
+                .map(|fill| fill.0)
+                .filter(|x| x.is_finite())
+                .collect();
+            if let Some((min, max)) = auto.choose_range(&mut x_values) {
+                self.spec.x_axis.minimum = min;
+                self.spec.x_axis.maximum = max;
+            }
+            if let Some(y_axis) = &mut self.spec.y_axis {
+                let mut y_values: Vec<f32> = pending
+                    .iter()
+                    .filter_map(|fill| fill.1)
+                    .filter(|y| y.is_finite())
+                    .collect();
+                if let Some((min, max)) = auto.choose_range(&mut y_values) {
+                    y_axis.minimum = min;
+                    y_axis.maximum = max;
+                }
+            }
+        }
+        // Values outside of the chosen range are handled by the out of range policy
+        for (x_value, y_value, weight) in pending {
+            let _ = self.fill_weighted(x_value, y_value, weight);
+        }
+    }
+
+    pub fn get_overflow(&self) -> &OverflowCounts {
+        &self.overflow
+    }
+
+    // Place a value on an axis, applying the clamp and error policies
+    fn place(&self, axis: &AxisSpec, value: f32) -> Result<Placement, HistogramError> {
+        let value = axis.wrap(value);
+        let placement = if value < axis.minimum {
+            Placement::Underflow
+        } else if value >= axis.maximum {
+            Placement::Overflow
         } else {
-            self.data[bin] += 1;
-            return Ok(bin);
+            return Ok(Placement::Bin(axis.get_bin(value)?));
+        };
+
+        match self.spec.out_of_range {
+            OutOfRangePolicy::Error => Err(HistogramError::OutOfBounds(
+                axis.minimum,
+                axis.maximum,
+                value,
+            )),
+            OutOfRangePolicy::Clamp => match placement {
+                Placement::Underflow => Ok(Placement::Clamped(0)),
+                _ => Ok(Placement::Clamped(axis.bins - 1)),
+            },
+            OutOfRangePolicy::Ignore | OutOfRangePolicy::Overflow => Ok(placement),
+        }
+    }
+
+    /// Fill the histogram, returning the bin which was incremented. Returns None if the value was
+    /// out of range and the policy of the histogram ignored it or counted it as overflow, or was
+    /// not finite and the nan policy dropped it.
+    pub fn fill(
+        &mut self,
+        x_value: f32,
+        y_value: Option<f32>,
+    ) -> Result<Option<usize>, HistogramError> {
+        self.fill_weighted(x_value, y_value, 1.0)
+    }
+
+    /// Fill the histogram with a weight in place of a single count, as with fill
+    pub fn fill_weighted(
+        &mut self,
+        x_value: f32,
+        y_value: Option<f32>,
+        weight: f64,
+    ) -> Result<Option<usize>, HistogramError> {
+        if let Some(value) = std::iter::once(x_value)
+            .chain(y_value)
+            .find(|value| !value.is_finite())
+        {
+            if self.spec.nan_policy != ValuePolicy::Skip {
+                self.stats.non_finite += 1;
+            }
+            return match self.spec.nan_policy {
+                ValuePolicy::Error => Err(HistogramError::NonFinite(value)),
+                _ => Ok(None),
+            };
+        }
+        if let Some(pending) = &mut self.pending_fills {
+            if self.spec.y_axis.is_some() != y_value.is_some() {
+                return Err(HistogramError::WrongDimensions);
+            }
+            pending.push((x_value, y_value, weight));
+            if self
+                .spec
+                .auto_range
+                .as_ref()
+                .is_none_or(|auto| pending.len() >= auto.n_samples)
+            {
+                self.finish_auto_range();
+            }
+            return Ok(None);
+        }
+
+        let y_placement = match (&self.spec.y_axis, y_value) {
+            (Some(y_axis), Some(y)) => Some(self.place(y_axis, y)),
+            (None, None) => None,
+            _ => return Err(HistogramError::WrongDimensions),
+        };
+        let (x_placement, y_placement) = match (
+            self.place(&self.spec.x_axis, x_value),
+            y_placement.transpose(),
+        ) {
+            (Ok(x_placement), Ok(y_placement)) => (x_placement, y_placement),
+            (Err(e), _) | (_, Err(e)) => {
+                self.stats.out_of_range += 1;
+                return Err(e);
+            }
+        };
+        if !matches!(x_placement, Placement::Bin(_))
+            || y_placement.is_some_and(|y| !matches!(y, Placement::Bin(_)))
+        {
+            self.stats.out_of_range += 1;
+        }
+
+        let bin = match (x_placement.get_bin(), y_placement.map(|y| y.get_bin())) {
+            (Some(x_bin), None) => x_bin,
+            (Some(x_bin), Some(Some(y_bin))) => self.bin_index(x_bin, y_bin),
+            _ => {
+                if self.spec.out_of_range == OutOfRangePolicy::Overflow {
+                    self.count_overflow(x_placement, y_placement);
+                }
+                return Ok(None);
+            }
+        };
+        if let Some(RollingWindow::Duration { .. }) = self.spec.window {
+            self.age_window(Instant::now());
+        }
+        self.increment(bin, weight);
+        self.stats.filled += 1;
+        Ok(Some(bin))
+    }
+
+    /// Fill the histogram from columns of values, with one value per row filled where fill is
+    /// true. Plain axes without auto ranging or rolling windows are binned in tight loops over
+    /// the columns; other histograms are filled value by value, with each row offered to an event
+    /// window as one event. Out of range values are counted
+    /// and otherwise dropped, as under the Ignore policy, and non-finite values are never an error
+    /// whatever the nan policy.
+    pub fn fill_columns(
+        &mut self,
+        x_values: &[f32],
+        y_values: Option<&[f32]>,
+        fill: &[bool],
+    ) -> Result<(), HistogramError> {
+        let n_rows = fill.len();
+        if x_values.len() != n_rows
+            || y_values.is_some_and(|y| y.len() != n_rows)
+            || self.spec.y_axis.is_some() != y_values.is_some()
+        {
+            return Err(HistogramError::WrongDimensions);
+        }
+        let is_plain = |axis: &AxisSpec| !axis.periodic;
+        let vectorize = self.pending_fills.is_none()
+            && self.window.is_none()
+            && matches!(
+                self.spec.out_of_range,
+                OutOfRangePolicy::Ignore | OutOfRangePolicy::Error
+            )
+            && is_plain(&self.spec.x_axis)
+            && self.spec.y_axis.as_ref().is_none_or(is_plain);
+        if !vectorize {
+            for row in 0..n_rows {
+                self.advance_window();
+                if fill[row] {
+                    // Errors are out of range or non-finite values, which the stats already count
+                    let _ = self.fill(x_values[row], y_values.map(|y| y[row]));
+                }
+            }
+            return Ok(());
+        }
+
+        let axis_bins = |axis: &AxisSpec, values: &[f32]| {
+            binning::bin_uniform(axis.minimum, axis.maximum, axis.bins, values)
+        };
+        let x_bins = axis_bins(&self.spec.x_axis, x_values);
+        let indices = match (&self.spec.y_axis, y_values) {
+            (Some(y_axis), Some(y_values)) => {
+                let y_bins = axis_bins(y_axis, y_values);
+                let (n_x, n_y) = (self.spec.x_axis.bins, y_axis.bins);
+                let layout = self.spec.layout;
+                x_bins
+                    .iter()
+                    .zip(y_bins.iter())
+                    .map(|(x_bin, y_bin)| {
+                        if *x_bin == binning::OUTSIDE || *y_bin == binning::OUTSIDE {
+                            binning::OUTSIDE
+                        } else {
+                            layout.index(*x_bin, *y_bin, n_x, n_y)
+                        }
+                    })
+                    .collect()
+            }
+            _ => x_bins,
+        };
+        let is_finite =
+            |row: usize| x_values[row].is_finite() && y_values.is_none_or(|y| y[row].is_finite());
+        for (row, bin) in indices.iter().enumerate().filter(|(row, _)| fill[*row]) {
+            if *bin != binning::OUTSIDE {
+                self.increment(*bin, 1.0);
+                self.stats.filled += 1;
+            } else if is_finite(row) {
+                self.stats.out_of_range += 1;
+            } else if self.spec.nan_policy != ValuePolicy::Skip {
+                self.stats.non_finite += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn count_overflow(&mut self, x_placement: Placement, y_placement: Option<Placement>) {
+        match x_placement {
+            Placement::Underflow => self.overflow.x_underflow += 1,
+            Placement::Overflow => self.overflow.x_overflow += 1,
+            _ => (),
+        }
+        match y_placement {
+            Some(Placement::Underflow) => self.overflow.y_underflow += 1,
+            Some(Placement::Overflow) => self.overflow.y_overflow += 1,
+            _ => (),
+        }
+    }
+
+    pub fn is_compatible(&self, other: &Histogram) -> bool {
+        let y_compatible = match (&self.spec.y_axis, &other.spec.y_axis) {
+            (None, None) => true,
+            (Some(axis), Some(other_axis)) => axis.is_compatible(other_axis),
+            _ => false,
+        };
+        y_compatible
+            && self.spec.layout == other.spec.layout
+            && self.spec.x_axis.is_compatible(&other.spec.x_axis)
+    }
+
+    /// Check that other can be merged into this histogram: the axes must be compatible, and
+    /// neither may auto-range or keep a rolling window, whose state lives outside the bins.
+    pub fn check_mergeable(&self, other: &Histogram) -> Result<(), HistogramError> {
+        if !self.is_compatible(other) {
+            return Err(HistogramError::IncompatibleAxes(
+                self.spec.name.clone(),
+                other.spec.name.clone(),
+            ));
+        }
+        for spec in [&self.spec, &other.spec] {
+            if spec.auto_range.is_some() {
+                return Err(HistogramError::Unsupported(
+                    spec.name.clone(),
+                    String::from("auto ranging"),
+                ));
+            } else if spec.window.is_some() {
+                return Err(HistogramError::Unsupported(
+                    spec.name.clone(),
+                    String::from("rolling windows"),
+                ));
+            }
         }
+        Ok(())
+    }
+
+    /// Add the contents of other into this histogram. The histograms must pass check_mergeable.
+    pub fn merge_from(&mut self, other: &Histogram) -> Result<(), HistogramError> {
+        self.check_mergeable(other)?;
+        self.stats.add(&other.stats);
+        self.overflow.x_underflow += other.overflow.x_underflow;
+        self.overflow.x_overflow += other.overflow.x_overflow;
+        self.overflow.y_underflow += other.overflow.y_underflow;
+        self.overflow.y_overflow += other.overflow.y_overflow;
+        self.generation += 1;
+        for (bin, count) in other.data.iter().enumerate() {
+            if *count != 0.0 {
+                self.data[bin] += *count;
+                self.changes.record(self.generation, bin);
+            }
+        }
+        // Contents of a histogram without error tracking are assumed to be unweighted counts
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            let other_weights2 = other.sum_weights2.as_ref().unwrap_or(&other.data);
+            for (bin, weight2) in other_weights2.iter().enumerate() {
+                sum_weights2[bin] += *weight2;
+            }
+        }
+        Ok(())
+    }
+
+    /// Project a 2D histogram onto one axis, summing the bins of the other axis which overlap
+    /// range, or every bin if no range is given. The projection always tracks errors.
+    pub fn project(
+        &self,
+        onto: ProjectionAxis,
+        range: Option<(f32, f32)>,
+    ) -> Result<Histogram, HistogramError> {
+        let Some(y_axis) = &self.spec.y_axis else {
+            return Err(HistogramError::WrongDimensions);
+        };
+        let (kept, summed) = match onto {
+            ProjectionAxis::X => (&self.spec.x_axis, y_axis),
+            ProjectionAxis::Y => (y_axis, &self.spec.x_axis),
+        };
+        let summed_bins = match range {
+            Some((low, high)) => summed.get_bin_range(low, high)?,
+            None => 0..summed.bins,
+        };
+        let spec = HistSpec {
+            x_axis: kept.clone(),
+            y_axis: None,
+            cuts_to_draw: vec![],
+            cuts_to_check: vec![],
+            layout: BinLayout::RowMajor,
+            track_errors: true,
+            auto_range: None,
+            window: None,
+            fill_mode: FillMode::Value,
+            nan_policy: ValuePolicy::Count,
+            missing_policy: ValuePolicy::Count,
+            ..self.spec.clone()
+        };
+        let mut projection = Histogram::new(spec);
+        projection.generation += 1;
+        let weights2 = self.sum_weights2.as_ref().unwrap_or(&self.data);
+        for kept_bin in 0..kept.bins {
+            for summed_bin in summed_bins.clone() {
+                let bin = match onto {
+                    ProjectionAxis::X => self.bin_index(kept_bin, summed_bin),
+                    ProjectionAxis::Y => self.bin_index(summed_bin, kept_bin),
+                };
+                projection.data[kept_bin] += self.data[bin];
+                if let Some(sum_weights2) = &mut projection.sum_weights2 {
+                    sum_weights2[kept_bin] += weights2[bin].abs();
+                }
+            }
+            projection.changes.record(projection.generation, kept_bin);
+        }
+        Ok(projection)
+    }
+
+    /// Multiply the contents by factor. Tracked errors scale along with them.
+    pub fn scale(&mut self, factor: f64) {
+        self.generation += 1;
+        for (bin, count) in self.data.iter_mut().enumerate() {
+            if *count != 0.0 {
+                *count *= factor;
+                self.changes.record(self.generation, bin);
+            }
+        }
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            for weight2 in sum_weights2.iter_mut() {
+                *weight2 *= factor * factor;
+            }
+        }
+    }
+
+    /// Get a copy scaled by a normalization, for comparing the shapes of spectra with different
+    /// statistics. The copy always tracks errors, so that they are scaled too. An empty histogram
+    /// gives an empty copy.
+    pub fn normalized(&self, normalization: Normalization) -> Histogram {
+        let mut copy = self.with_errors();
+        let norm = match normalization {
+            Normalization::Area => self.data.iter().sum(),
+            Normalization::Peak => self.data.iter().copied().fold(0.0, f64::max),
+        };
+        if norm != 0.0 && norm.is_finite() {
+            copy.scale(1.0 / norm);
+        }
+        copy
+    }
+
+    // A copy which tracks errors, taking them to be Poisson if they were not tracked
+    fn with_errors(&self) -> Histogram {
+        let mut copy = self.clone();
+        if copy.sum_weights2.is_none() {
+            copy.sum_weights2 = Some(copy.data.iter().map(|count| count.abs()).collect());
+            copy.spec.track_errors = true;
+        }
+        copy
+    }
+
+    /// Get this histogram less scale times other, e.g. a spectrum less a background measured
+    /// for a different time. The difference always tracks errors.
+    pub fn difference(&self, other: &Histogram, scale: f64) -> Result<Histogram, HistogramError> {
+        let mut copy = self.with_errors();
+        let mut scaled = other.with_errors();
+        scaled.scale(scale);
+        copy.subtract(&scaled)?;
+        Ok(copy)
+    }
+
+    /// Get the bin by bin ratio of this histogram to other, e.g. for efficiencies. Bins where
+    /// other is empty hold zero. The ratio always tracks errors, which are propagated from both
+    /// histograms as if they were independent.
+    pub fn ratio(&self, other: &Histogram) -> Result<Histogram, HistogramError> {
+        if !self.is_compatible(other) {
+            return Err(HistogramError::IncompatibleAxes(
+                self.spec.name.clone(),
+                other.spec.name.clone(),
+            ));
+        }
+        let mut copy = self.with_errors();
+        copy.generation += 1;
+        let weights2: &[f64] = self.sum_weights2.as_deref().unwrap_or(&self.data);
+        let other_weights2: &[f64] = other.sum_weights2.as_deref().unwrap_or(&other.data);
+        let mut ratio_weights2 = vec![0.0; self.data.len()];
+        for (bin, denominator) in other.data.iter().enumerate() {
+            copy.data[bin] = 0.0;
+            if *denominator != 0.0 {
+                let ratio = self.data[bin] / denominator;
+                copy.data[bin] = ratio;
+                ratio_weights2[bin] = (weights2[bin].abs()
+                    + ratio * ratio * other_weights2[bin].abs())
+                    / (denominator * denominator);
+            }
+            copy.changes.record(copy.generation, bin);
+        }
+        copy.sum_weights2 = Some(ratio_weights2);
+        Ok(copy)
+    }
+
+    /// Get a copy of a 1D histogram where each bin holds the sum of itself and every bin below it
+    pub fn cumulative(&self) -> Result<Histogram, HistogramError> {
+        if self.spec.y_axis.is_some() {
+            return Err(HistogramError::WrongDimensions);
+        }
+        let mut copy = self.clone();
+        copy.generation += 1;
+        let (mut total, mut total_weights2) = (0.0, 0.0);
+        for x_bin in 0..self.spec.x_axis.bins {
+            let bin = self.bin_index(x_bin, 0);
+            total += self.data[bin];
+            copy.data[bin] = total;
+            copy.changes.record(copy.generation, bin);
+            if let (Some(summed), Some(sum_weights2)) = (&mut copy.sum_weights2, &self.sum_weights2)
+            {
+                total_weights2 += sum_weights2[bin];
+                summed[bin] = total_weights2;
+            }
+        }
+        Ok(copy)
+    }
+
+    /// Get a smoothed copy of a 1D histogram. Bins past the ends of the axis are taken to hold the
+    /// same counts as the end bins. Errors are propagated through the kernel, so the copy always
+    /// tracks errors.
+    pub fn smoothed(&self, smoothing: &Smoothing) -> Result<Histogram, HistogramError> {
+        if self.spec.y_axis.is_some() {
+            return Err(HistogramError::WrongDimensions);
+        }
+        let kernel = smoothing.kernel(self.spec.x_axis.get_bin_width())?;
+        let half_width = (kernel.len() / 2) as i64;
+        let bins = self.spec.x_axis.bins;
+        let weights2: &[f64] = self.sum_weights2.as_deref().unwrap_or(&self.data);
+        let mut copy = self.clone();
+        copy.generation += 1;
+        let mut smoothed_weights2 = vec![0.0; self.data.len()];
+        for x_bin in 0..bins {
+            let (mut content, mut error2) = (0.0, 0.0);
+            for (idx, weight) in kernel.iter().enumerate() {
+                let neighbour = (x_bin as i64 + idx as i64 - half_width).clamp(0, bins as i64 - 1);
+                let neighbour = self.bin_index(neighbour as usize, 0);
+                content += weight * self.data[neighbour];
+                error2 += weight * weight * weights2[neighbour].abs();
+            }
+            let bin = self.bin_index(x_bin, 0);
+            copy.data[bin] = content;
+            copy.changes.record(copy.generation, bin);
+            smoothed_weights2[bin] = error2;
+        }
+        copy.sum_weights2 = Some(smoothed_weights2);
+        copy.spec.track_errors = true;
+        Ok(copy)
+    }
+
+    /// Subtract the contents of other from this histogram, e.g. to see what changed between two
+    /// runs. Bin errors add in quadrature; the fill counters are left as they were.
+    pub fn subtract(&mut self, other: &Histogram) -> Result<(), HistogramError> {
+        if !self.is_compatible(other) {
+            return Err(HistogramError::IncompatibleAxes(
+                self.spec.name.clone(),
+                other.spec.name.clone(),
+            ));
+        }
+        self.generation += 1;
+        for (bin, count) in other.data.iter().enumerate() {
+            if *count != 0.0 {
+                self.data[bin] -= *count;
+                self.changes.record(self.generation, bin);
+            }
+        }
+        if let Some(sum_weights2) = &mut self.sum_weights2 {
+            let other_weights2 = other.sum_weights2.as_ref().unwrap_or(&other.data);
+            for (bin, weight2) in other_weights2.iter().enumerate() {
+                sum_weights2[bin] += *weight2;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -95,6 +1657,117 @@ impl Histogram {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_memory() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            track_errors: true,
+            ..HistSpec::new(
+                "pid",
+                AxisSpec::new("e", "e", 100, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("de", "de", 50, 0.0, 10.0).unwrap()),
+            )
+        };
+        let gram = Histogram::new(spec.clone());
+        assert_eq!(spec.estimate_memory(), gram.get_memory_usage());
+        assert_eq!(
+            spec.estimate_memory() - std::mem::size_of::<Histogram>(),
+            5000 * 16 + 1250 * 16
+        );
+
+        let mut huge = spec.clone();
+        huge.x_axis.bins = usize::MAX / 2;
+        huge.y_axis.as_mut().unwrap().bins = usize::MAX / 2;
+        assert_eq!(huge.estimate_memory(), usize::MAX);
+    }
+
+    #[test]
+    fn test_fill_columns() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "pid",
+                AxisSpec::new("e", "e", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("de", "de", 5, 0.0, 5.0).unwrap()),
+            )
+        };
+        let x = [0.5, 9.99, 11.0, 3.5, 4.5];
+        let y = [0.5, 4.5, 1.0, 2.5, 2.5];
+        let fill = [true, true, true, true, false];
+        let mut columns = Histogram::new(spec.clone());
+        columns.fill_columns(&x, Some(&y), &fill).unwrap();
+        let mut values = Histogram::new(spec.clone());
+        for row in 0..4 {
+            let _ = values.fill(x[row], Some(y[row]));
+        }
+        assert_eq!(columns.data, values.data);
+        assert_eq!(columns.stats.filled, 3);
+        assert_eq!(columns.stats.out_of_range, 1);
+        assert!(columns.fill_columns(&x, None, &fill).is_err());
+
+        let mut clamped = spec.clone();
+        clamped.out_of_range = OutOfRangePolicy::Clamp;
+        let mut gram = Histogram::new(clamped);
+        gram.fill_columns(&x, Some(&y), &fill).unwrap();
+        assert_eq!(gram.stats.filled, 4);
+    }
+
+    #[test]
+    fn test_serde() {
+        let spec = HistSpec {
+            title: String::from("Silicon energy"),
+            cuts_to_draw: vec![Uuid::new_v4()],
+            out_of_range: OutOfRangePolicy::Clamp,
+            track_errors: true,
+            auto_range: Some(AutoRangeSpec::new(1000)),
+            window: Some(RollingWindow::Duration {
+                length: Duration::from_secs(60),
+                buckets: 6,
+            }),
+            clear_policy: ClearPolicy::Accumulate,
+            metadata: FxHashMap::from_iter([(String::from("detector"), String::from("si"))]),
+            ..HistSpec::new(
+                "rates/si_e",
+                AxisSpec::new("si_e", "Energy", 100, 0.0, 10.0)
+                    .unwrap()
+                    .with_unit("MeV"),
+                None,
+            )
+        };
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(serde_json::from_str::<HistSpec>(&json).unwrap(), spec);
+
+        // A configuration only needs the name and axes
+        let config = r#"{
+            "name": "si_e",
+            "x_axis": {"variable": "si_e", "title": "Energy", "bins": 100, "minimum": 0, "maximum": 10}
+        }"#;
+        let spec: HistSpec = serde_json::from_str(config).unwrap();
+        assert!(!spec.id.is_nil());
+        assert_eq!(
+            spec.x_axis,
+            AxisSpec::new("si_e", "Energy", 100, 0.0, 10.0).unwrap()
+        );
+        assert_eq!(spec.layout, BinLayout::default());
+        assert!(spec.y_axis.is_none());
+        let built = HistSpec::new("si_e", spec.x_axis.clone(), None);
+        assert_eq!(
+            HistSpec {
+                id: built.id,
+                ..spec
+            },
+            built
+        );
+    }
+
+    #[test]
+    fn test_axis_label() {
+        let axis = AxisSpec::new("si_e", "Energy", 100, 0.0, 10.0).unwrap();
+        assert_eq!(axis.get_label(), "Energy");
+        let axis = axis.with_unit("MeV");
+        assert_eq!(axis.get_label(), "Energy (MeV)");
+    }
+
     #[test]
     fn test_axis() {
         assert!(AxisSpec::new("var", "var", 600, 0.0, 3600.0).is_ok());
@@ -108,18 +1781,25 @@ mod tests {
         assert!(axis.get_bin(-1.0).is_err());
         assert_eq!(axis.variable, "var");
         assert_eq!(axis.title, "var");
+
+        // 9.999999 + 10 rounds to 20 in f32
+        let axis = AxisSpec::new("var", "var", 4, -10.0, 10.0).unwrap();
+        assert_eq!(axis.get_bin(9.999_999).unwrap(), 3);
+        assert_eq!(axis.get_bin_range(9.999_999, 11.0).unwrap(), 3..4);
+        let spec = HistSpec::new("edge", axis, None);
+        let mut gram = Histogram::new(spec);
+        assert_eq!(gram.fill(9.999_999, None).unwrap(), Some(3));
     }
 
     #[test]
     fn test_hist1d() {
         let spec = HistSpec {
-            id: Uuid::new_v4(),
-            name: String::from("test"),
             title: String::from("test"),
-            x_axis: AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
-            y_axis: None,
-            cuts_to_draw: vec![],
-            cuts_to_check: vec![],
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+                None,
+            )
         };
 
         let mut gram = Histogram::new(spec);
@@ -135,13 +1815,12 @@ mod tests {
     #[test]
     fn test_hist2d() {
         let spec = HistSpec {
-            id: Uuid::new_v4(),
-            name: String::from("test"),
             title: String::from("test"),
-            x_axis: AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
-            y_axis: Some(AxisSpec::new("var2", "var2", 600, 0.0, 600.0).unwrap()),
-            cuts_to_draw: vec![],
-            cuts_to_check: vec![],
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+                Some(AxisSpec::new("var2", "var2", 600, 0.0, 600.0).unwrap()),
+            )
         };
 
         let mut gram = Histogram::new(spec);
@@ -156,4 +1835,453 @@ mod tests {
         assert!(gram.spec.cuts_to_draw.is_empty());
         assert!(gram.spec.cuts_to_check.is_empty());
     }
+
+    #[test]
+    fn test_merge() {
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+            None,
+        );
+        let mut gram = Histogram::new(spec.clone());
+        let mut other = Histogram::new(spec.clone());
+        gram.fill(0.5, None).unwrap();
+        other.fill(0.5, None).unwrap();
+        other.fill(10.5, None).unwrap();
+        assert!(gram.merge_from(&other).is_ok());
+        assert_eq!(gram.data[0], 2.0);
+        assert_eq!(gram.data[10], 1.0);
+
+        let mut bad_spec = spec.clone();
+        bad_spec.x_axis = AxisSpec::new("var", "var", 300, 0.0, 600.0).unwrap();
+        assert!(gram.merge_from(&Histogram::new(bad_spec)).is_err());
+        let mut bad_spec = spec.clone();
+        bad_spec.y_axis = Some(AxisSpec::new("var2", "var2", 600, 0.0, 600.0).unwrap());
+        assert!(gram.merge_from(&Histogram::new(bad_spec)).is_err());
+
+        let mut windowed = spec;
+        windowed.window = Some(RollingWindow::Events {
+            count: 10,
+            buckets: 2,
+        });
+        assert!(matches!(
+            gram.merge_from(&Histogram::new(windowed)),
+            Err(HistogramError::Unsupported(..))
+        ));
+    }
+
+    #[test]
+    fn test_delta() {
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+            None,
+        );
+        let mut gram = Histogram::new(spec);
+        assert!(gram.get_delta(0).bins.is_empty());
+        gram.fill(0.5, None).unwrap();
+        gram.fill(3.5, None).unwrap();
+        let delta = gram.get_delta(0);
+        assert_eq!(delta.bins, vec![(0, 1.0), (3, 1.0)]);
+        assert_eq!(delta.generation, gram.get_generation());
+
+        gram.fill(3.5, None).unwrap();
+        let newer = gram.get_delta(delta.generation);
+        assert_eq!(newer.bins, vec![(3, 2.0)]);
+        assert!(gram.get_delta(newer.generation).bins.is_empty());
+
+        // Refilling a bin keeps one change for it
+        for _ in 0..1000 {
+            gram.fill(5.5, None).unwrap();
+        }
+        assert_eq!(gram.get_delta(newer.generation).bins, vec![(5, 1000.0)]);
+        assert!(gram.changes.entries.len() <= gram.changes.limit);
+
+        // Changes too old to be logged any more are sent as every bin
+        let before = gram.get_generation();
+        for bin in 100..500 {
+            gram.fill(bin as f32 + 0.5, None).unwrap();
+        }
+        assert_eq!(gram.get_delta(newer.generation).bins.len(), 600);
+        let latest = gram.get_generation();
+        gram.fill(599.5, None).unwrap();
+        assert_eq!(gram.get_delta(latest).bins, vec![(599, 1.0)]);
+        assert!(gram.get_delta(before).bins.len() >= 400);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+            Some(AxisSpec::new("var2", "var2", 300, 0.0, 300.0).unwrap()),
+        );
+        let mut gram = Histogram::new(spec);
+        gram.fill(0.5, Some(0.5)).unwrap();
+        gram.fill(1.5, Some(1.5)).unwrap();
+        gram.fill(599.5, Some(150.5)).unwrap();
+
+        let small = gram.downsample(100, 100);
+        assert_eq!((small.x_bins, small.y_bins), (100, 100));
+        assert_eq!((small.x_factor, small.y_factor), (6, 3));
+        assert_eq!(small.data[0], 2.0);
+        assert_eq!(small.data[50 * 100 + 99], 1.0);
+        assert_eq!(small.data.iter().sum::<f64>(), 3.0);
+
+        let uneven = gram.downsample(7, 1000);
+        assert_eq!((uneven.x_bins, uneven.y_bins), (7, 300));
+        assert_eq!(uneven.x_factor, 86);
+        assert_eq!(uneven.data.iter().sum::<f64>(), 3.0);
+    }
+
+    #[test]
+    fn test_slice() {
+        let axis = AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap();
+        assert_eq!(axis.get_bin_range(2.5, 4.0).unwrap(), 2..4);
+        assert_eq!(axis.get_bin_range(-5.0, 100.0).unwrap(), 0..10);
+        assert!(axis.get_bin_range(4.0, 2.0).is_err());
+        assert!(axis.get_bin_range(10.0, 20.0).is_err());
+
+        let spec = HistSpec::new(
+            "test",
+            axis,
+            Some(AxisSpec::new("var2", "var2", 10, 0.0, 10.0).unwrap()),
+        );
+        let mut gram = Histogram::new(spec);
+        gram.fill(3.5, Some(5.5)).unwrap();
+        gram.fill(9.5, Some(5.5)).unwrap();
+        let slice = gram.slice((2.0, 4.0), Some((5.0, 7.0))).unwrap();
+        assert_eq!(slice.x_edges, vec![2.0, 3.0, 4.0]);
+        assert_eq!(slice.y_edges, vec![5.0, 6.0, 7.0]);
+        assert_eq!(slice.data, vec![0.0, 1.0, 0.0, 0.0]);
+        assert!(gram.slice((2.0, 4.0), None).is_err());
+    }
+
+    #[test]
+    fn test_iter_bins() {
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 4, 0.0, 4.0).unwrap(),
+            Some(AxisSpec::new("var2", "var2", 2, 0.0, 10.0).unwrap()),
+        );
+        let mut gram = Histogram::new(spec);
+        gram.fill(2.1, Some(7.0)).unwrap();
+        gram.fill(2.9, Some(6.0)).unwrap();
+        assert_eq!(gram.iter_bins().count(), 8);
+        let filled: Vec<_> = gram.iter_nonzero().collect();
+        assert_eq!(filled, vec![(2.5, Some(7.5), 2.0)]);
+
+        let mut spec = gram.spec.clone();
+        spec.y_axis = None;
+        let mut gram = Histogram::new(spec);
+        gram.fill(0.5, None).unwrap();
+        assert_eq!(gram.iter_bins().next(), Some((0.5, None, 1.0)));
+    }
+
+    #[test]
+    fn test_bin_layout() {
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 4, 0.0, 4.0).unwrap(),
+            Some(AxisSpec::new("var2", "var2", 3, 0.0, 3.0).unwrap()),
+        );
+        let mut gram = Histogram::new(spec.clone());
+        assert_eq!(gram.fill(2.5, Some(1.5)).unwrap(), Some(6));
+        assert_eq!(gram.fill(1.5, Some(2.5)).unwrap(), Some(9));
+        assert_eq!(gram.bin_coordinates(9), (1, 2));
+        assert_eq!(gram.get_bin_content(2, 1).unwrap(), 1.0);
+        assert_eq!(gram.get_bin_content(1, 2).unwrap(), 1.0);
+        assert_eq!(gram.get_bin_content(2, 2).unwrap(), 0.0);
+        assert!(gram.get_bin_content(4, 0).is_err());
+        gram.set_bin_content(3, 2, 7.0).unwrap();
+        assert_eq!(gram.data[11], 7.0);
+        assert!(gram.set_bin_content(0, 3, 1.0).is_err());
+
+        let mut column_spec = spec;
+        column_spec.layout = BinLayout::ColumnMajor;
+        let mut column = Histogram::new(column_spec);
+        assert_eq!(column.fill(2.5, Some(1.5)).unwrap(), Some(7));
+        assert_eq!(column.bin_coordinates(7), (2, 1));
+        assert!(column.merge_from(&gram).is_err());
+        let slice = column.slice((2.0, 4.0), Some((1.0, 3.0))).unwrap();
+        assert_eq!(slice.data, vec![1.0, 0.0, 0.0, 0.0]);
+
+        // Loading is in x fastest order whatever the layout
+        let counts: Vec<f64> = (0..12).map(f64::from).collect();
+        column.load_data(&counts).unwrap();
+        assert_eq!(column.get_bin_content(3, 1).unwrap(), 7.0);
+        assert_eq!(column.get_bin_content(2, 1).unwrap(), 6.0);
+        column.add_bin_content(3, 1, 2.0).unwrap();
+        assert_eq!(column.get_bin_content(3, 1).unwrap(), 9.0);
+        assert!(column.load_data(&counts[1..]).is_err());
+        assert!(column.add_bin_content(4, 0, 1.0).is_err());
+    }
+
+    #[test]
+    fn test_out_of_range_policy() {
+        let mut spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("var2", "var2", 10, 0.0, 10.0).unwrap()),
+            )
+        };
+        let mut gram = Histogram::new(spec.clone());
+        assert_eq!(gram.fill(-1.0, Some(5.0)).unwrap(), None);
+        assert_eq!(gram.get_overflow(), &OverflowCounts::default());
+        assert!(gram.data.iter().all(|count| *count == 0.0));
+
+        spec.out_of_range = OutOfRangePolicy::Clamp;
+        let mut gram = Histogram::new(spec.clone());
+        assert_eq!(gram.fill(-1.0, Some(50.0)).unwrap(), Some(90));
+        assert_eq!(gram.fill(10.0, Some(0.5)).unwrap(), Some(9));
+
+        spec.out_of_range = OutOfRangePolicy::Overflow;
+        let mut gram = Histogram::new(spec);
+        assert_eq!(gram.fill(-1.0, Some(5.0)).unwrap(), None);
+        assert_eq!(gram.fill(11.0, Some(11.0)).unwrap(), None);
+        assert_eq!(gram.fill(5.0, Some(-3.0)).unwrap(), None);
+        let overflow = gram.get_overflow();
+        assert_eq!(overflow.x_underflow, 1);
+        assert_eq!(overflow.x_overflow, 1);
+        assert_eq!(overflow.y_underflow, 1);
+        assert_eq!(overflow.y_overflow, 1);
+        assert!(gram.fill(5.0, None).is_err());
+        assert_eq!(gram.stats.out_of_range, 3);
+        assert_eq!(gram.stats.filled, 0);
+    }
+
+    #[test]
+    fn test_nan_policy() {
+        let mut spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Clamp,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        assert!(spec.x_axis.get_bin(f32::NAN).is_err());
+        // Neither clamped nor binned
+        let mut gram = Histogram::new(spec.clone());
+        assert_eq!(gram.fill(f32::NAN, None).unwrap(), None);
+        assert_eq!(gram.fill(f32::INFINITY, None).unwrap(), None);
+        gram.fill_columns(&[1.0, f32::NAN], None, &[true, true])
+            .unwrap();
+        assert_eq!(gram.stats.non_finite, 3);
+        assert_eq!(gram.stats.filled, 1);
+        assert_eq!(gram.data.iter().sum::<f64>(), 1.0);
+
+        spec.nan_policy = ValuePolicy::Skip;
+        let mut gram = Histogram::new(spec.clone());
+        assert_eq!(gram.fill(f32::NAN, None).unwrap(), None);
+        assert_eq!(gram.stats.non_finite, 0);
+
+        spec.nan_policy = ValuePolicy::Error;
+        let mut gram = Histogram::new(spec);
+        assert!(gram.fill(f32::NEG_INFINITY, None).is_err());
+        assert_eq!(gram.stats.non_finite, 1);
+    }
+
+    #[test]
+    fn test_bin_errors() {
+        let mut spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+            None,
+        );
+        let mut poisson = Histogram::new(spec.clone());
+        poisson.fill_weighted(0.5, None, 4.0).unwrap();
+        assert_eq!(poisson.data[0], 4.0);
+        assert_eq!(poisson.bin_error(0).unwrap(), 2.0);
+        assert!(poisson.bin_error(10).is_err());
+
+        spec.track_errors = true;
+        let mut gram = Histogram::new(spec);
+        gram.fill_weighted(0.5, None, 3.0).unwrap();
+        gram.fill_weighted(0.5, None, 4.0).unwrap();
+        assert_eq!(gram.data[0], 7.0);
+        assert_eq!(gram.bin_error(0).unwrap(), 5.0);
+
+        gram.merge_from(&poisson).unwrap();
+        assert_eq!(gram.data[0], 11.0);
+        assert_eq!(gram.bin_error(0).unwrap(), 29.0_f64.sqrt());
+    }
+
+    #[test]
+    fn test_auto_range() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Overflow,
+            auto_range: Some(AutoRangeSpec {
+                n_samples: 101,
+                lower_percentile: 0.0,
+                upper_percentile: 1.0,
+                padding: 0.0,
+            }),
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 1.0).unwrap(),
+                None,
+            )
+        };
+        let mut gram = Histogram::new(spec.clone());
+        for idx in 0..100 {
+            assert_eq!(gram.fill(100.0 + idx as f32, None).unwrap(), None);
+        }
+        assert!(gram.is_auto_ranging());
+        assert!(gram.fill(1.0, Some(1.0)).is_err());
+        gram.fill(200.0, None).unwrap();
+        assert!(!gram.is_auto_ranging());
+        assert_eq!(gram.spec.x_axis.minimum, 100.0);
+        assert_eq!(gram.spec.x_axis.maximum, 200.0);
+        assert_eq!(gram.spec.x_axis.bins, 10);
+        // The maximum is exclusive, so the largest sample lands in the overflow
+        assert_eq!(gram.data.iter().sum::<f64>(), 100.0);
+        assert_eq!(gram.get_overflow().x_overflow, 1);
+        assert!(gram.fill(150.0, None).unwrap().is_some());
+
+        let mut gram = Histogram::new(spec);
+        gram.fill(5.0, None).unwrap();
+        gram.finish_auto_range();
+        assert!(gram.spec.x_axis.minimum < 5.0 && gram.spec.x_axis.maximum > 5.0);
+        assert_eq!(gram.data.iter().sum::<f64>(), 1.0);
+    }
+
+    #[test]
+    fn test_suggest_axis() {
+        let values: Vec<f32> = (0..1000).map(|v| v as f32 * 0.1).collect();
+        let axis = AxisSpec::suggest("var", &values, BinningRule::FreedmanDiaconis).unwrap();
+        assert_eq!(axis.variable, "var");
+        assert_eq!(axis.minimum, 0.0);
+        assert!(axis.maximum > 99.9);
+        // 2 * IQR(49.9) / 10 is 9.98 wide, plus an extra bin for the maximum
+        assert_eq!(axis.bins, 11);
+        assert!(axis.get_bin(99.9).is_ok());
+
+        let axis = AxisSpec::suggest("var", &values, BinningRule::Scott).unwrap();
+        assert!(axis.bins > 5 && axis.bins < 20);
+        assert!(axis.get_bin(99.9).is_ok());
+
+        assert!(AxisSpec::suggest("var", &[1.0, f32::NAN], BinningRule::Scott).is_err());
+        let axis = AxisSpec::suggest("var", &[3.0, 3.0, 3.0], BinningRule::Scott).unwrap();
+        assert_eq!(axis.bins, 1);
+        assert!(axis.get_bin(3.0).is_ok());
+    }
+
+    #[test]
+    fn test_periodic_axis() {
+        let axis = AxisSpec::new_periodic("phi", "phi", 36, 0.0, 360.0).unwrap();
+        assert_eq!(axis.get_bin(365.0).unwrap(), 0);
+        assert_eq!(axis.get_bin(-5.0).unwrap(), 35);
+        assert_eq!(axis.get_bin(720.0).unwrap(), 0);
+        assert_eq!(axis.wrap(-1e-9), 0.0);
+        assert!(!axis.is_compatible(&AxisSpec::new("phi", "phi", 36, 0.0, 360.0).unwrap()));
+
+        let spec = HistSpec::new("test", axis, None);
+        let mut gram = Histogram::new(spec);
+        assert_eq!(gram.fill(-0.5, None).unwrap(), Some(35));
+        assert_eq!(gram.fill(359.5, None).unwrap(), Some(35));
+        assert_eq!(gram.data[35], 2.0);
+    }
+
+    #[test]
+    fn test_rolling_window() {
+        let mut spec = HistSpec {
+            track_errors: true,
+            window: Some(RollingWindow::Events {
+                count: 4,
+                buckets: 2,
+            }),
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let mut gram = Histogram::new(spec.clone());
+        for value in 0..5 {
+            gram.advance_window();
+            gram.fill(value as f32 + 0.5, None).unwrap();
+        }
+        assert_eq!(&gram.data[0..6], &[0.0, 0.0, 1.0, 1.0, 1.0, 0.0]);
+        assert_eq!(gram.bin_error(1).unwrap(), 0.0);
+        // Fills within one event and failed fills do not count as events
+        gram.advance_window();
+        gram.fill(5.5, None).unwrap();
+        gram.fill(5.5, None).unwrap();
+        assert!(gram.fill(5.5, Some(1.0)).is_err());
+        assert_eq!(gram.data.iter().sum::<f64>(), 5.0);
+
+        spec.window = Some(RollingWindow::Duration {
+            length: Duration::from_secs(10),
+            buckets: 5,
+        });
+        let mut gram = Histogram::new(spec);
+        let start = Instant::now();
+        gram.fill(0.5, None).unwrap();
+        gram.age_window(start + Duration::from_secs(3));
+        assert_eq!(gram.data[0], 1.0);
+        // The bucket of the first fill no longer holds any of the bin
+        gram.set_bin_content(0, 0, 3.0).unwrap();
+        gram.age_window(start + Duration::from_secs(9));
+        assert_eq!(gram.data[0], 3.0);
+        gram.age_window(start + Duration::from_secs(20));
+        assert_eq!(gram.data[0], 0.0);
+        assert_eq!(gram.bin_error(0).unwrap(), 0.0);
+        assert_eq!(gram.get_delta(0).bins, vec![(0, 0.0)]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Overflow,
+            track_errors: true,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let mut gram = Histogram::new(spec);
+        gram.fill(1.5, None).unwrap();
+        gram.fill(11.5, None).unwrap();
+        let generation = gram.get_generation();
+        gram.clear();
+        assert!(gram.data.iter().all(|count| *count == 0.0));
+        assert_eq!(gram.bin_error(1).unwrap(), 0.0);
+        assert_eq!(gram.get_overflow(), &OverflowCounts::default());
+        assert_eq!(gram.stats, HistogramStats::default());
+        assert_eq!(gram.get_delta(generation).bins, vec![(1, 0.0)]);
+    }
+
+    #[test]
+    fn test_sample() {
+        use rand::SeedableRng;
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("y", "y", 4, 0.0, 4.0).unwrap()),
+            )
+        };
+        let mut gram = Histogram::new(spec);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        assert!(gram.sample(&mut rng, 1).is_err());
+
+        gram.fill_weighted(2.5, Some(1.5), 3.0).unwrap();
+        gram.fill_weighted(7.5, Some(3.5), 1.0).unwrap();
+        let values = gram.sample(&mut rng, 4000).unwrap();
+        let n_first = values
+            .iter()
+            .filter(|(x, y)| (2.0..3.0).contains(x) && (1.0..2.0).contains(&y.unwrap()))
+            .count();
+        let n_second = values
+            .iter()
+            .filter(|(x, y)| (7.0..8.0).contains(x) && (3.0..4.0).contains(&y.unwrap()))
+            .count();
+        assert_eq!(n_first + n_second, 4000);
+        assert!((2800..3200).contains(&n_first));
+    }
 }
@@ -0,0 +1,172 @@
+//! Consumes events distributed through Kafka, as many facilities do with their DAQ data. Each
+//! message holds one event, encoded as JSON, MessagePack, or anything a user decoder understands.
+use super::data_blob::DataBlob;
+use super::error::SourceError;
+use super::source::DataSource;
+use ::kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+/// A decoder for messages in a format of the user's own
+pub type Decoder = Box<dyn FnMut(&[u8]) -> Result<DataBlob, SourceError> + Send>;
+
+/// How the messages of a topic are encoded. JSON and MessagePack messages are maps from variable
+/// name to a number, a bool (stored as a flag), or a list of numbers (stored as an array).
+pub enum MessageFormat {
+    Json,
+    MsgPack,
+    Custom(Decoder),
+}
+
+impl std::fmt::Debug for MessageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json => write!(f, "Json"),
+            Self::MsgPack => write!(f, "MsgPack"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Flag(bool),
+    Number(f32),
+    Array(Vec<f32>),
+}
+
+fn to_blob(values: FxHashMap<String, Value>) -> DataBlob {
+    let mut blob = DataBlob::new();
+    for (name, value) in values {
+        match value {
+            Value::Flag(value) => blob.insert_bool(&name, value),
+            Value::Number(value) => blob.insert(&name, value),
+            Value::Array(values) => blob.insert_array(&name, values),
+        }
+    }
+    blob
+}
+
+impl MessageFormat {
+    pub fn decode(&mut self, message: &[u8]) -> Result<DataBlob, SourceError> {
+        match self {
+            Self::Json => serde_json::from_slice(message)
+                .map(to_blob)
+                .map_err(|e| SourceError::Decode(e.to_string())),
+            Self::MsgPack => rmp_serde::from_slice(message)
+                .map(to_blob)
+                .map_err(|e| SourceError::Decode(e.to_string())),
+            Self::Custom(decoder) => decoder(message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaSpec {
+    /// Brokers to bootstrap from, e.g. "localhost:9092"
+    pub hosts: Vec<String>,
+    pub topic: String,
+    /// The consumer group whose committed offsets are resumed from
+    pub group: String,
+    /// Where to start if the group has no committed offset: the oldest retained message, or
+    /// only messages produced from now on
+    pub from_earliest: bool,
+}
+
+/// A DataSource consuming a Kafka topic. The offsets of consumed messages are committed for the
+/// consumer group once all their events have been handed out, so that a restarted analysis
+/// resumes where it stopped. A topic never runs out, so next_event waits for messages until the
+/// source is stopped.
+#[derive(Debug)]
+pub struct KafkaSource {
+    consumer: Consumer,
+    format: MessageFormat,
+    pending: VecDeque<DataBlob>,
+    needs_commit: bool,
+    stopped: bool,
+}
+
+impl KafkaSource {
+    pub fn connect(spec: &KafkaSpec, format: MessageFormat) -> Result<Self, SourceError> {
+        let fallback = match spec.from_earliest {
+            true => FetchOffset::Earliest,
+            false => FetchOffset::Latest,
+        };
+        let consumer = Consumer::from_hosts(spec.hosts.clone())
+            .with_topic(spec.topic.clone())
+            .with_group(spec.group.clone())
+            .with_fallback_offset(fallback)
+            .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+            .create()?;
+        Ok(Self {
+            consumer,
+            format,
+            pending: VecDeque::new(),
+            needs_commit: false,
+            stopped: false,
+        })
+    }
+}
+
+impl DataSource for KafkaSource {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.needs_commit {
+                self.consumer.commit_consumed()?;
+                self.needs_commit = false;
+            }
+            if self.stopped {
+                return Ok(None);
+            }
+            let sets = self.consumer.poll()?;
+            #[cfg(feature = "tracing")]
+            tracing::trace!(n_sets = sets.iter().count(), "Polled message sets");
+            for set in sets.iter() {
+                for message in set.messages() {
+                    self.pending.push_back(self.format.decode(message.value)?);
+                }
+                self.consumer.consume_messageset(set)?;
+                self.needs_commit = true;
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stopped = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let mut format = MessageFormat::Json;
+        let blob = format
+            .decode(br#"{"si_e": 1.5, "pileup": true, "gamma_e": [511, 1274.5]}"#)
+            .unwrap();
+        assert_eq!(blob.find("si_e"), Some(&1.5));
+        assert_eq!(blob.find_flag("pileup"), Some(1));
+        assert_eq!(blob.find_array("gamma_e"), Some(&[511.0, 1274.5][..]));
+        assert!(format.decode(b"not json").is_err());
+
+        let mut values = FxHashMap::default();
+        values.insert("si_e", 2.0_f32);
+        let message = rmp_serde::to_vec(&values).unwrap();
+        let blob = MessageFormat::MsgPack.decode(&message).unwrap();
+        assert_eq!(blob.find("si_e"), Some(&2.0));
+
+        let mut format = MessageFormat::Custom(Box::new(|message| {
+            let mut blob = DataBlob::new();
+            blob.insert("length", message.len() as f32);
+            Ok(blob)
+        }));
+        assert_eq!(format.decode(b"abc").unwrap().find("length"), Some(&3.0));
+    }
+}
@@ -1,5 +1,73 @@
+pub mod alert;
+pub mod alias;
+pub mod analysis;
+pub mod atomic;
+pub mod autogate;
+pub mod batch;
+pub mod binning;
+pub mod builder;
+pub mod calibration;
+pub mod catalog;
+pub mod command;
+pub mod compass;
+pub mod compression;
+pub mod contour;
 pub mod cut;
+pub mod cut_registry;
 pub mod data_blob;
+pub mod deadtime;
+pub mod derived;
 pub mod error;
+pub mod filter;
+pub mod folder;
+pub mod gate_file;
+pub mod geometry;
+pub mod group;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod histogram;
+#[cfg(feature = "kafka")]
+pub mod kafka;
 pub mod manager;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod midas;
+pub mod nscldaq;
+pub mod observer;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod pattern;
+pub mod perf;
+pub mod pixie;
+pub mod plugin;
+pub mod prelude;
+pub mod queue;
+pub mod rate;
+pub mod record;
+#[cfg(feature = "render")]
+pub mod render;
+pub mod replay;
+pub mod run;
+pub mod schema;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod shard;
+pub mod sim;
+pub mod smoothing;
+#[cfg(feature = "io")]
+pub mod snapshot;
+pub mod source;
+pub mod spect;
+pub mod spectrum_file;
+pub mod time;
+pub mod transform;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "viewer")]
+pub mod viewer;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod watchdog;
+pub mod workspace;
+#[cfg(feature = "xamine")]
+pub mod xamine;
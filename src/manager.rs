@@ -1,180 +1,5519 @@
-use super::cut::{Cut, Cut1D, Cut2D, CutSpec};
-use super::data_blob::DataBlob;
-use super::error::ResourceError;
-use super::histogram::{HistSpec, Histogram};
-use rustc_hash::FxHashMap;
+use super::alert::{Alert, Check, CheckSpec};
+use super::alias::{Alias, AliasTable};
+use super::autogate;
+use super::batch::ColumnBatch;
+use super::calibration::{self, CalibrationFit, CalibrationSet, CalibrationSpec, PeakRegion};
+use super::contour;
+use super::cut::{CompoundCut, Cut, Cut1D, Cut2D, CutBinding, CutEvaluation, CutSpec, CutStats};
+use super::cut_registry::{CutFactory, CutRegistry, SavedCut};
+use super::data_blob::{BlobPool, DataBlob, TIMESTAMP_VARIABLE};
+use super::deadtime::{DeadTimeReport, LossCounts};
+use super::derived::{Derivation, DerivedHistogram, DerivedSpec, GatedProjection, RefreshMode};
+#[cfg(feature = "render")]
+use super::error::RenderError;
+use super::error::{CutError, HistogramError, ResourceError, SourceError};
+use super::filter::{EventFilter, GateCondition};
+use super::folder;
+use super::group::{self, HistogramGroup};
+use super::histogram::{
+    AxisSpec, BinLayout, BinningRule, DownsampledData, FillMode, HistSpec, Histogram,
+    HistogramDelta, HistogramSlice, HistogramStats, Normalization, ProjectionAxis, TimeOrigin,
+    ValuePolicy,
+};
+use super::observer::{EventKind, ManagerEvent, Observers};
+#[cfg(feature = "parquet")]
+use super::parquet::{ParquetSink, ParquetSpec};
+use super::pattern;
+use super::perf::{PerfReport, Profiler, Stopwatch};
+use super::rate::{RateMeter, RateSpec};
+#[cfg(feature = "io")]
+use super::record::EventReader;
+use super::record::EventRecorder;
+#[cfg(feature = "render")]
+use super::render::{self, RenderSpec};
+use super::run::{ClearPolicy, RunControl, RunInfo, RunState};
+use super::schema::Schema;
+use super::smoothing::Smoothing;
+use super::source::DataSource;
+use super::spectrum_file::Spectrum;
+use super::time::{Instant, SystemTime};
+use super::transform::{EventTransform, Pipeline};
+use super::watchdog::SourceAlert;
+use super::workspace::{CutSubscription, SharedCuts};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::BufWriter;
+#[cfg(any(feature = "io", feature = "render"))]
+use std::path::Path;
+use std::sync::mpsc::Receiver;
 use uuid::Uuid;
 
+/// What to do when adding a histogram or cut whose ID or name is already in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// Replace the existing entry with the same ID. Names are not checked.
+    #[default]
+    Overwrite,
+    /// Refuse to add the new entry
+    Error,
+    /// Add the new entry under a new ID if its ID is taken, and with a numeric suffix on its name
+    /// if its name is taken
+    Rename,
+}
+
+// Get the parent and, if it reads one, the second histogram of a derived histogram
+fn derived_inputs<'a>(
+    histograms: &'a FxHashMap<Uuid, Histogram>,
+    spec: &DerivedSpec,
+) -> Result<(&'a Histogram, Option<&'a Histogram>), ResourceError> {
+    let parent = histograms
+        .get(&spec.parent_id)
+        .ok_or(ResourceError::InvalidHistogramID(spec.parent_id))?;
+    let other = match spec.derivation.get_other_id() {
+        Some(other_id) => Some(
+            histograms
+                .get(&other_id)
+                .ok_or(ResourceError::InvalidHistogramID(other_id))?,
+        ),
+        None => None,
+    };
+    Ok((parent, other))
+}
+
+// Get the (ID, name) a new entry should be added under
+fn resolve_conflict(
+    policy: ConflictPolicy,
+    id: Uuid,
+    name: &str,
+    id_taken: impl Fn(&Uuid) -> bool,
+    name_taken: impl Fn(&str) -> bool,
+) -> Result<(Uuid, String), ResourceError> {
+    match policy {
+        ConflictPolicy::Overwrite => Ok((id, name.to_string())),
+        ConflictPolicy::Error => {
+            if id_taken(&id) {
+                Err(ResourceError::DuplicateID(id))
+            } else if name_taken(name) {
+                Err(ResourceError::DuplicateName(name.to_string()))
+            } else {
+                Ok((id, name.to_string()))
+            }
+        }
+        ConflictPolicy::Rename => {
+            let id = if id_taken(&id) { Uuid::new_v4() } else { id };
+            if !name_taken(name) {
+                return Ok((id, name.to_string()));
+            }
+            let mut suffix = 1;
+            while name_taken(&format!("{name}_{suffix}")) {
+                suffix += 1;
+            }
+            Ok((id, format!("{name}_{suffix}")))
+        }
+    }
+}
+
+/// The memory used by the histograms of a ResourceManager, in bytes
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MemoryReport {
+    /// The (ID, name, bytes) of every histogram, largest first
+    pub histograms: Vec<(Uuid, String, usize)>,
+    pub total: usize,
+    pub limit: Option<usize>,
+}
+
+/// What a ResourceManager processed and wrote, as reported by ResourceManager::shutdown
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShutdownReport {
+    /// Events drained from the source after it was stopped
+    pub drained: usize,
+    /// Events accepted over the lifetime of the manager
+    pub events: u64,
+    /// Events written by the recorder, if recording
+    pub recorded: usize,
+    /// Events written by each filter, by filter ID
+    pub filtered: Vec<(Uuid, usize)>,
+    /// Events written by each Parquet sink, by sink ID
+    #[cfg(feature = "parquet")]
+    pub converted: Vec<(Uuid, usize)>,
+    /// The run ended by the shutdown, if one was in progress
+    pub run: Option<RunInfo>,
+}
+
+/// A histogram in a ResourceManager which may or may not exist, see
+/// ResourceManager::histogram_entry
+#[derive(Debug)]
+pub struct HistogramEntry<'a> {
+    manager: &'a mut ResourceManager,
+    id: Uuid,
+}
+
+impl HistogramEntry<'_> {
+    pub fn get_id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn exists(&self) -> bool {
+        self.manager.histograms.contains_key(&self.id)
+    }
+
+    /// Change the spec of the histogram, if it exists
+    pub fn and_modify(self, f: impl FnOnce(&mut HistSpec)) -> Result<Self, ResourceError> {
+        if let Some(gram) = self.manager.histograms.get(&self.id) {
+            let mut spec = gram.spec.clone();
+            f(&mut spec);
+            spec.id = self.id;
+            self.manager.update_histogram_spec(spec)?;
+        }
+        Ok(self)
+    }
+
+    /// Book the histogram from a spec if it does not exist. The ID of the spec is replaced by
+    /// the ID of the entry.
+    pub fn or_insert(self, spec: HistSpec) -> Result<Uuid, ResourceError> {
+        self.or_insert_with(|| spec)
+    }
+
+    pub fn or_insert_with(self, f: impl FnOnce() -> HistSpec) -> Result<Uuid, ResourceError> {
+        if !self.exists() {
+            let mut spec = f();
+            spec.id = self.id;
+            return self.manager.add_histogram(spec);
+        }
+        Ok(self.id)
+    }
+
+    /// Update the histogram to a spec if it exists, keeping its contents where possible, or book
+    /// it otherwise
+    pub fn insert(self, mut spec: HistSpec) -> Result<Uuid, ResourceError> {
+        spec.id = self.id;
+        if self.exists() {
+            self.manager.update_histogram_spec(spec)?;
+            Ok(self.id)
+        } else {
+            self.manager.add_histogram(spec)
+        }
+    }
+}
+
+/// The state of one resource before a change made during an edit, used to roll the change back
+#[derive(Debug)]
+enum Undo {
+    Histogram(Uuid, Option<Box<Histogram>>),
+    // The spec of a histogram before an edit which kept its contents
+    Spec(Box<HistSpec>),
+    Cut(Uuid, Option<Box<dyn Cut>>),
+    Binding(Uuid, Option<CutBinding>),
+    Compound(Uuid, Option<CompoundCut>),
+    Group(Uuid, Option<HistogramGroup>),
+}
+
 #[derive(Debug)]
 pub struct ResourceManager {
     histograms: FxHashMap<Uuid, Histogram>,
     cuts: FxHashMap<Uuid, Box<dyn Cut>>,
+    recorder: Option<EventRecorder<BufWriter<File>>>,
+    filters: FxHashMap<Uuid, EventFilter>,
+    #[cfg(feature = "parquet")]
+    sinks: FxHashMap<Uuid, ParquetSink>,
+    groups: FxHashMap<Uuid, HistogramGroup>,
+    aliases: AliasTable,
+    transforms: Pipeline,
+    calibrations: CalibrationSet,
+    runs: RunControl,
+    observers: Observers,
+    // Bumped on every change to the manager or its resources
+    generation: u64,
+    cut_generations: FxHashMap<Uuid, u64>,
+    cut_registry: CutRegistry,
+    shared_cuts: Option<CutSubscription>,
+    cut_bindings: FxHashMap<Uuid, CutBinding>,
+    compound_cuts: FxHashMap<Uuid, CompoundCut>,
+    /// The histograms gated on each cut
+    cut_dependents: FxHashMap<Uuid, FxHashSet<Uuid>>,
+    /// Scratch space for the cuts evaluated during an update
+    evaluated_cuts: FxHashMap<Uuid, bool>,
+    // The changes made since begin_edit, if an edit is in progress
+    journal: Option<Vec<Undo>>,
+    // Events accepted by update, and how each cut fared on them
+    n_events: u64,
+    // Events turned away by run control, and lost before reaching the manager
+    n_rejected: u64,
+    losses: LossCounts,
+    cut_stats: FxHashMap<Uuid, CutStats>,
+    profiler: Option<Profiler>,
+    // Histograms copied at the end of each run, by run number
+    run_snapshots: BTreeMap<u32, Vec<Histogram>>,
+    snapshot_pattern: Option<String>,
+    checks: Vec<Check>,
+    derived: FxHashMap<Uuid, DerivedHistogram>,
+    rates: FxHashMap<Uuid, RateMeter>,
+    // The variables declared by sources and users, for validate
+    schema: Schema,
+    // The timestamp of the first event of the current run, which run time axes start from
+    run_start_timestamp: Option<f64>,
+    conflict_policy: ConflictPolicy,
+    memory_limit: Option<usize>,
     // graphs: Vec<Box<dyn Graph>>,
 }
 
+impl Default for ResourceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ResourceManager {
     pub fn new() -> Self {
         Self {
             histograms: FxHashMap::default(),
             cuts: FxHashMap::default(),
+            recorder: None,
+            filters: FxHashMap::default(),
+            #[cfg(feature = "parquet")]
+            sinks: FxHashMap::default(),
+            groups: FxHashMap::default(),
+            aliases: AliasTable::default(),
+            transforms: Pipeline::default(),
+            calibrations: CalibrationSet::default(),
+            runs: RunControl::default(),
+            observers: Observers::default(),
+            generation: 0,
+            cut_generations: FxHashMap::default(),
+            cut_registry: CutRegistry::default(),
+            shared_cuts: None,
+            cut_bindings: FxHashMap::default(),
+            compound_cuts: FxHashMap::default(),
+            cut_dependents: FxHashMap::default(),
+            evaluated_cuts: FxHashMap::default(),
+            journal: None,
+            n_events: 0,
+            n_rejected: 0,
+            losses: LossCounts::default(),
+            cut_stats: FxHashMap::default(),
+            profiler: None,
+            run_snapshots: BTreeMap::new(),
+            snapshot_pattern: None,
+            checks: vec![],
+            derived: FxHashMap::default(),
+            rates: FxHashMap::default(),
+            schema: Schema::default(),
+            run_start_timestamp: None,
+            conflict_policy: ConflictPolicy::default(),
+            memory_limit: None,
             // graphs: vec![],
         }
     }
 
-    pub fn add_histogram(&mut self, spec: HistSpec) -> usize {
-        let _ = self.histograms.insert(spec.id, Histogram::new(spec));
-        self.histograms.len() - 1
+    /// Get the generation of the manager, which increases whenever anything it owns changes.
+    /// Clients can skip refreshing when the generation has not changed since they last looked.
+    pub fn get_generation(&self) -> u64 {
+        self.generation
     }
 
-    pub fn remove_histogram(&mut self, id: &Uuid) -> Result<(), ResourceError> {
-        if !self.histograms.contains_key(id) {
-            Err(ResourceError::InvalidHistogramID(*id))
-        } else {
-            self.histograms.remove_entry(id);
-            Ok(())
+    /// Get the generation of a histogram, which increases whenever its contents or spec change
+    pub fn get_histogram_generation(&self, id: &Uuid) -> Result<u64, ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(gram.get_generation()),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
         }
     }
 
-    pub fn get_histogram_data(&self, id: &Uuid) -> Result<&[u16], ResourceError> {
-        match self.histograms.get(id) {
-            Some(gram) => Ok(&gram.data),
-            None => Err(ResourceError::InvalidHistogramID(*id)),
+    /// Get the manager generation at which a cut was last created or changed
+    pub fn get_cut_generation(&self, id: &Uuid) -> Result<u64, ResourceError> {
+        match self.cut_generations.get(id) {
+            Some(generation) => Ok(*generation),
+            None => Err(ResourceError::InvalidCutID(*id)),
         }
     }
 
-    pub fn get_histogram_spec(&self, id: &Uuid) -> Result<&HistSpec, ResourceError> {
-        match self.histograms.get(id) {
-            Some(gram) => Ok(&gram.spec),
-            None => Err(ResourceError::InvalidHistogramID(*id)),
+    fn bump_generation(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    /// Set what happens when a histogram or cut is added with an ID or name that is already in
+    /// use. The default is to overwrite entries with the same ID.
+    pub fn set_conflict_policy(&mut self, policy: ConflictPolicy) {
+        self.conflict_policy = policy;
+    }
+
+    pub fn get_conflict_policy(&self) -> ConflictPolicy {
+        self.conflict_policy
+    }
+
+    /// Limit the memory histograms may use, in bytes. Booking a histogram which would exceed the
+    /// limit fails instead of allocating, so that a mistyped axis can't take down the process.
+    /// Histograms already booked are kept even if they exceed a new limit.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    pub fn get_memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut histograms: Vec<(Uuid, String, usize)> = self
+            .histograms
+            .values()
+            .map(|gram| {
+                (
+                    gram.spec.id,
+                    gram.spec.name.clone(),
+                    gram.get_memory_usage(),
+                )
+            })
+            .collect();
+        histograms.sort_by_key(|(_, _, bytes)| std::cmp::Reverse(*bytes));
+        let total = histograms.iter().map(|(_, _, bytes)| bytes).sum();
+        MemoryReport {
+            histograms,
+            total,
+            limit: self.memory_limit,
         }
     }
 
-    pub fn add_cut_1d(
+    // Check that booking a histogram from spec would stay within the memory limit. A histogram
+    // it replaces no longer counts against the limit.
+    fn check_memory(&self, spec: &HistSpec) -> Result<(), ResourceError> {
+        self.check_memory_reserved(spec, 0)
+    }
+
+    // Check the memory limit with reserved bytes already promised to other histograms
+    fn check_memory_reserved(&self, spec: &HistSpec, reserved: usize) -> Result<(), ResourceError> {
+        let Some(limit) = self.memory_limit else {
+            return Ok(());
+        };
+        let used: usize = self
+            .histograms
+            .values()
+            .filter(|gram| gram.spec.id != spec.id)
+            .map(|gram| gram.get_memory_usage())
+            .sum::<usize>()
+            + reserved;
+        let needed = spec.estimate_memory();
+        let free = limit.saturating_sub(used);
+        if needed > free {
+            return Err(ResourceError::MemoryLimitExceeded(
+                spec.name.clone(),
+                needed,
+                free,
+            ));
+        }
+        Ok(())
+    }
+
+    // An upper triangle only holds a square matrix
+    fn check_layout(spec: &HistSpec) -> Result<(), ResourceError> {
+        let is_square = spec
+            .y_axis
+            .as_ref()
+            .is_some_and(|y_axis| y_axis.bins == spec.x_axis.bins);
+        if spec.layout == BinLayout::UpperTriangle && !is_square {
+            return Err(HistogramError::WrongDimensions.into());
+        }
+        if spec.fill_mode == FillMode::Summary && spec.y_axis.is_none() {
+            return Err(HistogramError::WrongDimensions.into());
+        }
+        Ok(())
+    }
+
+    fn histogram_name_taken(&self, name: &str) -> bool {
+        self.histograms.values().any(|gram| gram.spec.name == name)
+    }
+
+    /// Check for a cut, binding, or compound cut with the name
+    fn cut_name_taken(&self, name: &str) -> bool {
+        self.cuts.values().any(|cut| cut.get_spec().name == name)
+            || self
+                .cut_bindings
+                .values()
+                .any(|binding| binding.spec.name == name)
+            || self
+                .compound_cuts
+                .values()
+                .any(|compound| compound.name == name)
+    }
+
+    // Apply the conflict policy to the ID and name of a new cut
+    fn resolve_cut_conflict(&self, spec: &mut CutSpec) -> Result<(), ResourceError> {
+        (spec.id, spec.name) = resolve_conflict(
+            self.conflict_policy,
+            spec.id,
+            &spec.name,
+            |id| self.cut_exists(id),
+            |name| self.cut_name_taken(name),
+        )?;
+        Ok(())
+    }
+
+    /// Start an edit. Bookings, removals, and cut changes made until commit can be undone as one
+    /// with rollback, e.g. to leave the manager untouched when a config file fails to load
+    /// halfway through. Histogram contents, filters, transforms, observers, and runs are not
+    /// part of the edit.
+    pub fn begin_edit(&mut self) -> Result<(), ResourceError> {
+        if self.journal.is_some() {
+            return Err(ResourceError::EditInProgress);
+        }
+        self.journal = Some(vec![]);
+        Ok(())
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Keep every change made since begin_edit
+    pub fn commit(&mut self) -> Result<(), ResourceError> {
+        match self.journal.take() {
+            Some(_) => Ok(()),
+            None => Err(ResourceError::NoEdit),
+        }
+    }
+
+    /// Undo every change made since begin_edit
+    pub fn rollback(&mut self) -> Result<(), ResourceError> {
+        let journal = self.journal.take().ok_or(ResourceError::NoEdit)?;
+        self.undo(journal);
+        Ok(())
+    }
+
+    // Undo the changes of a journal, latest first
+    fn undo(&mut self, journal: Vec<Undo>) {
+        for undo in journal.into_iter().rev() {
+            match undo {
+                Undo::Histogram(id, previous) => {
+                    self.unindex_cut_dependents(&id);
+                    match previous {
+                        Some(gram) => {
+                            self.index_cut_dependents(&gram.spec);
+                            if self.histograms.insert(id, *gram).is_none() {
+                                self.observers.notify(ManagerEvent::HistogramAdded(id));
+                            }
+                        }
+                        None => {
+                            self.histograms.remove(&id);
+                            self.observers.notify(ManagerEvent::HistogramRemoved(id));
+                        }
+                    }
+                }
+                Undo::Spec(spec) => {
+                    self.unindex_cut_dependents(&spec.id);
+                    self.index_cut_dependents(&spec);
+                    if let Some(gram) = self.histograms.get_mut(&spec.id) {
+                        gram.spec = *spec;
+                        gram.mark_modified();
+                    }
+                }
+                Undo::Cut(id, previous) => {
+                    match previous {
+                        Some(cut) => {
+                            if let Some(subscription) = &self.shared_cuts {
+                                subscription.publish(cut.as_ref());
+                            }
+                            self.cuts.insert(id, cut)
+                        }
+                        None => self.cuts.remove(&id),
+                    };
+                    self.observers.notify(ManagerEvent::CutModified(id));
+                }
+                Undo::Binding(id, previous) => {
+                    match previous {
+                        Some(binding) => self.cut_bindings.insert(id, binding),
+                        None => self.cut_bindings.remove(&id),
+                    };
+                    self.observers.notify(ManagerEvent::CutModified(id));
+                }
+                Undo::Compound(id, previous) => {
+                    match previous {
+                        Some(compound) => self.compound_cuts.insert(id, compound),
+                        None => self.compound_cuts.remove(&id),
+                    };
+                    self.observers.notify(ManagerEvent::CutModified(id));
+                }
+                Undo::Group(id, previous) => {
+                    match previous {
+                        Some(group) => self.groups.insert(id, group),
+                        None => self.groups.remove(&id),
+                    };
+                }
+            }
+        }
+        self.bump_generation();
+    }
+
+    fn record_undo(&mut self, undo: Undo) {
+        if let Some(journal) = &mut self.journal {
+            journal.push(undo);
+        }
+    }
+
+    // Save the spec of a histogram before changing it, if an edit is in progress. The contents
+    // are not saved, so fills made during the edit survive a rollback.
+    fn journal_spec(&mut self, id: &Uuid) {
+        if let Some(journal) = &mut self.journal
+            && let Some(gram) = self.histograms.get(id)
+        {
+            journal.push(Undo::Spec(Box::new(gram.spec.clone())));
+        }
+    }
+
+    fn insert_cut(&mut self, cut: Box<dyn Cut>) {
+        if let Some(subscription) = &self.shared_cuts {
+            subscription.publish(cut.as_ref());
+        }
+        self.store_cut(cut);
+    }
+
+    // Insert a cut without publishing it to the shared cuts
+    fn store_cut(&mut self, cut: Box<dyn Cut>) {
+        let id = cut.get_spec().id;
+        let generation = self.bump_generation();
+        let previous = self.cuts.insert(id, cut);
+        self.record_undo(Undo::Cut(id, previous));
+        self.cut_generations.insert(id, generation);
+        self.observers.notify(ManagerEvent::CutModified(id));
+    }
+
+    fn index_cut_dependents(&mut self, spec: &HistSpec) {
+        for cut_id in spec.cuts_to_check.iter() {
+            self.cut_dependents
+                .entry(*cut_id)
+                .or_default()
+                .insert(spec.id);
+        }
+    }
+
+    fn unindex_cut_dependents(&mut self, histogram_id: &Uuid) {
+        self.cut_dependents.retain(|_, dependents| {
+            dependents.remove(histogram_id);
+            !dependents.is_empty()
+        });
+    }
+
+    /// Book a histogram. A nil ID is replaced by a newly generated one, and an ID or name already
+    /// in use is handled by the conflict policy. Returns the ID of the histogram.
+    pub fn add_histogram(&mut self, mut spec: HistSpec) -> Result<Uuid, ResourceError> {
+        if spec.id.is_nil() {
+            spec.id = Uuid::new_v4();
+        }
+        (spec.id, spec.name) = resolve_conflict(
+            self.conflict_policy,
+            spec.id,
+            &spec.name,
+            |id| self.histograms.contains_key(id),
+            |name| self.histogram_name_taken(name),
+        )?;
+        Self::check_layout(&spec)?;
+        self.check_memory(&spec)?;
+        Ok(self.book_histogram(spec))
+    }
+
+    /// Book an empty copy of a histogram under a new name, with the same binning and the same
+    /// cuts gating and drawn on it, e.g. to try another gate on the same quantity. A name
+    /// already in use is handled by the conflict policy. Returns the ID of the copy.
+    pub fn duplicate_histogram(
         &mut self,
-        spec: CutSpec,
-        low_value: f32,
-        high_value: f32,
-        histogram_id: &Uuid,
-    ) -> Result<(), ResourceError> {
+        id: &Uuid,
+        new_name: &str,
+    ) -> Result<Uuid, ResourceError> {
+        let mut spec = self
+            .histograms
+            .get(id)
+            .ok_or(ResourceError::InvalidHistogramID(*id))?
+            .spec
+            .clone();
+        spec.id = Uuid::new_v4();
+        spec.name = new_name.to_string();
+        self.add_histogram(spec)
+    }
+
+    // Book a histogram, replacing any histogram with the same ID
+    fn book_histogram(&mut self, spec: HistSpec) -> Uuid {
+        let id = spec.id;
+        self.bump_generation();
+        self.unindex_cut_dependents(&id);
+        self.index_cut_dependents(&spec);
+        let previous = self.histograms.insert(id, Histogram::new(spec));
+        self.record_undo(Undo::Histogram(id, previous.map(Box::new)));
+        self.observers.notify(ManagerEvent::HistogramAdded(id));
+        id
+    }
+
+    /// Get the entry for a histogram, to update its spec or book it if it does not exist
+    pub fn histogram_entry(&mut self, id: Uuid) -> HistogramEntry<'_> {
+        HistogramEntry { manager: self, id }
+    }
+
+    /// Replace the spec of a histogram. The contents are kept if the binning is unchanged,
+    /// otherwise the histogram is rebooked empty.
+    pub fn update_histogram_spec(&mut self, spec: HistSpec) -> Result<(), ResourceError> {
+        let id = spec.id;
+        let gram = self
+            .histograms
+            .get(&id)
+            .ok_or(ResourceError::InvalidHistogramID(id))?;
+        if !gram.spec.has_same_binning(&spec) {
+            Self::check_layout(&spec)?;
+            self.check_memory(&spec)?;
+            self.book_histogram(spec);
+            return Ok(());
+        }
+        self.journal_spec(&id);
+        self.bump_generation();
+        self.unindex_cut_dependents(&id);
+        self.index_cut_dependents(&spec);
+        if let Some(gram) = self.histograms.get_mut(&id) {
+            gram.spec = spec;
+            gram.mark_modified();
+        }
+        Ok(())
+    }
+
+    /// Gate a histogram on a cut, binding, or compound cut, in addition to the cuts already
+    /// gating it. The contents are kept, so clear the histogram to start over with the new gate.
+    pub fn apply_cut(&mut self, histogram_id: &Uuid, cut_id: &Uuid) -> Result<(), ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(ResourceError::InvalidHistogramID(*histogram_id))?;
+        if !self.cut_exists(cut_id) {
+            return Err(ResourceError::InvalidCutID(*cut_id));
+        } else if gram.spec.cuts_to_check.contains(cut_id) {
+            return Ok(());
+        }
+        self.journal_spec(histogram_id);
+        self.bump_generation();
+        self.cut_dependents
+            .entry(*cut_id)
+            .or_default()
+            .insert(*histogram_id);
         if let Some(gram) = self.histograms.get_mut(histogram_id) {
-            gram.spec.cuts_to_draw.push(spec.id);
-        } else {
-            return Err(ResourceError::CutFailed(
-                super::error::CutError::NoReferenceHistogram(*histogram_id),
-            ));
+            gram.spec.cuts_to_check.push(*cut_id);
+            gram.mark_modified();
         }
-        let _ = self
-            .cuts
-            .insert(spec.id, Box::new(Cut1D::new(spec, low_value, high_value)?));
         Ok(())
     }
 
-    pub fn add_cut_2d(
+    /// Stop gating a histogram on a cut, returning an error if the histogram is not gated on it.
+    /// The cut itself is kept.
+    pub fn remove_cut_from_histogram(
         &mut self,
-        spec: CutSpec,
-        x_values: Vec<f32>,
-        y_values: Vec<f32>,
         histogram_id: &Uuid,
+        cut_id: &Uuid,
     ) -> Result<(), ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(ResourceError::InvalidHistogramID(*histogram_id))?;
+        if !self.cuts.contains_key(cut_id) {
+            return Err(ResourceError::InvalidCutID(*cut_id));
+        }
+        if !gram.spec.cuts_to_check.contains(cut_id) {
+            return Err(ResourceError::CutNotApplied(*cut_id, *histogram_id));
+        }
+        self.journal_spec(histogram_id);
+        self.bump_generation();
+        if let Entry::Occupied(mut dependents) = self.cut_dependents.entry(*cut_id) {
+            dependents.get_mut().remove(histogram_id);
+            if dependents.get().is_empty() {
+                dependents.remove();
+            }
+        }
         if let Some(gram) = self.histograms.get_mut(histogram_id) {
-            gram.spec.cuts_to_draw.push(spec.id);
-        } else {
-            return Err(ResourceError::CutFailed(
-                super::error::CutError::NoReferenceHistogram(*histogram_id),
-            ));
+            gram.spec.cuts_to_check.retain(|id| id != cut_id);
+            gram.mark_modified();
         }
-        let _ = self
-            .cuts
-            .insert(spec.id, Box::new(Cut2D::new(spec, x_values, y_values)?));
         Ok(())
     }
 
-    pub fn update(&mut self, data: DataBlob) -> Result<(), ResourceError> {
-        for cut in self.cuts.values_mut() {
-            cut.is_inside(&data);
+    pub fn remove_histogram(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        if !self.histograms.contains_key(id) {
+            Err(ResourceError::InvalidHistogramID(*id))
+        } else {
+            let previous = self.histograms.remove(id);
+            self.record_undo(Undo::Histogram(*id, previous.map(Box::new)));
+            self.unindex_cut_dependents(id);
+            self.bump_generation();
+            self.observers.notify(ManagerEvent::HistogramRemoved(*id));
+            Ok(())
         }
+    }
 
-        let mut passed_cuts: bool;
-        for gram in self.histograms.values_mut() {
-            passed_cuts = true;
-            for cut_id in gram.spec.cuts_to_check.iter() {
-                if let Some(cut) = self.cuts.get(cut_id) {
-                    if !cut.is_valid() {
-                        passed_cuts = false;
-                        break;
-                    }
-                }
+    /// Zero the contents of a histogram
+    pub fn clear_histogram(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        match self.histograms.get_mut(id) {
+            Some(gram) => {
+                gram.clear();
+                self.bump_generation();
+                Ok(())
             }
-            if !passed_cuts {
-                continue;
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Add the contents of a histogram filled elsewhere, such as the shards of a ShardedHistogram
+    /// or the snapshot of an AtomicHistogram, into a histogram of the manager
+    pub fn merge_into_histogram(
+        &mut self,
+        id: &Uuid,
+        other: &Histogram,
+    ) -> Result<(), ResourceError> {
+        let gram = self
+            .histograms
+            .get_mut(id)
+            .ok_or(ResourceError::InvalidHistogramID(*id))?;
+        gram.merge_from(other)?;
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Replace the content of one bin of a histogram
+    pub fn set_bin_content(
+        &mut self,
+        id: &Uuid,
+        x_bin: usize,
+        y_bin: usize,
+        content: f64,
+    ) -> Result<(), ResourceError> {
+        self.edit_histogram_data(id, |gram| gram.set_bin_content(x_bin, y_bin, content))
+    }
+
+    /// Add counts to one bin of a histogram
+    pub fn add_bin_content(
+        &mut self,
+        id: &Uuid,
+        x_bin: usize,
+        y_bin: usize,
+        content: f64,
+    ) -> Result<(), ResourceError> {
+        self.edit_histogram_data(id, |gram| gram.add_bin_content(x_bin, y_bin, content))
+    }
+
+    /// Replace the contents of a histogram with counts from elsewhere, such as a reference
+    /// spectrum from a file or a simulation, for overlay and comparison. See Histogram::load_data
+    /// for the order of the counts.
+    pub fn load_histogram_data(&mut self, id: &Uuid, counts: &[f64]) -> Result<(), ResourceError> {
+        self.edit_histogram_data(id, |gram| gram.load_data(counts))
+    }
+
+    /// Book a histogram holding a spectrum read from a file, filled with its counts, returning
+    /// its ID
+    pub fn import_spectrum(
+        &mut self,
+        spectrum: &Spectrum,
+        variable: &str,
+    ) -> Result<Uuid, ResourceError> {
+        let id = self.add_histogram(spectrum.to_spec(variable)?)?;
+        self.load_histogram_data(&id, &spectrum.counts)?;
+        Ok(id)
+    }
+
+    fn edit_histogram_data(
+        &mut self,
+        id: &Uuid,
+        edit: impl FnOnce(&mut Histogram) -> Result<(), HistogramError>,
+    ) -> Result<(), ResourceError> {
+        let gram = self
+            .histograms
+            .get_mut(id)
+            .ok_or(ResourceError::InvalidHistogramID(*id))?;
+        edit(gram).map_err(|e| ResourceError::from(e).with_context("edit", &gram.spec.name))?;
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Book one histogram per index from a template. Every "{i}" in the name, title, and axis
+    /// variables and titles of the template is replaced by the index, so a template on
+    /// "anode_{i}_energy" over 0..32 books one spectrum per anode. Returns the ID of the group.
+    /// If any member fails to book, the members booked before it are rolled back.
+    pub fn book_array(
+        &mut self,
+        group_name: &str,
+        template: &HistSpec,
+        indices: impl IntoIterator<Item = usize>,
+    ) -> Result<Uuid, ResourceError> {
+        // The bookings are journaled, in the edit in progress if there is one, to be undone
+        let is_editing = self.journal.is_some();
+        let n_journaled = self.journal.get_or_insert_default().len();
+        let members: Result<Vec<Uuid>, ResourceError> = indices
+            .into_iter()
+            .map(|index| self.add_histogram(group::instantiate(template, index)))
+            .collect();
+        let booked = match is_editing {
+            true => self
+                .journal
+                .as_mut()
+                .map(|journal| journal.split_off(n_journaled)),
+            false => self.journal.take(),
+        };
+        let booked = booked.unwrap_or_default();
+        let members = match members {
+            Ok(members) => members,
+            Err(e) => {
+                self.undo(booked);
+                return Err(e);
             }
+        };
+        if let Some(journal) = &mut self.journal {
+            journal.extend(booked);
+        }
+        let id = Uuid::new_v4();
+        self.groups.insert(
+            id,
+            HistogramGroup {
+                id,
+                name: group_name.to_string(),
+                members,
+            },
+        );
+        self.record_undo(Undo::Group(id, None));
+        Ok(id)
+    }
 
-            let x_val = match data.find(&gram.spec.x_axis.variable) {
-                Some(value) => value,
-                None => continue,
-            };
-            if let Some(y_axis) = &gram.spec.y_axis {
-                let y_val = match data.find(&y_axis.variable) {
-                    Some(value) => value,
-                    None => continue,
-                };
-                match gram.fill(*x_val, Some(*y_val)) {
-                    Ok(bin) => println!("Filled bin : {bin}"),
-                    Err(e) => println!("Out of bounds: {e}"),
-                }
-            } else {
-                match gram.fill(*x_val, None) {
-                    Ok(bin) => println!("Filled bin: {bin}"),
-                    Err(e) => println!("Out of bounds: {e}"),
-                }
+    pub fn get_group(&self, id: &Uuid) -> Result<&HistogramGroup, ResourceError> {
+        self.groups
+            .get(id)
+            .ok_or(ResourceError::InvalidGroupID(*id))
+    }
+
+    /// Zero the contents of every histogram in a group
+    pub fn clear_group(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        let group = self
+            .groups
+            .get(id)
+            .ok_or(ResourceError::InvalidGroupID(*id))?;
+        for member in group.members.iter() {
+            if let Some(gram) = self.histograms.get_mut(member) {
+                gram.clear();
             }
         }
+        self.bump_generation();
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::histogram::AxisSpec;
+    /// Remove a group along with every histogram in it
+    pub fn remove_group(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        let group = self
+            .groups
+            .remove(id)
+            .ok_or(ResourceError::InvalidGroupID(*id))?;
+        let members = group.members.clone();
+        self.record_undo(Undo::Group(*id, Some(group)));
+        for member in members.iter() {
+            // Members may already have been removed individually
+            let _ = self.remove_histogram(member);
+        }
+        Ok(())
+    }
 
-    #[test]
-    fn test_managed_histogram() {
-        let mut manager = ResourceManager::new();
-        let spec1 = HistSpec {
-            id: Uuid::new_v4(),
-            name: String::from("test1"),
-            title: String::from("test1"),
-            x_axis: AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
-            y_axis: None,
-            cuts_to_draw: vec![],
-            cuts_to_check: vec![],
-        };
-        let spec2 = HistSpec {
-            id: Uuid::new_v4(),
-            name: String::from("test2"),
-            title: String::from("test2"),
-            x_axis: AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
-            y_axis: Some(AxisSpec::new("var2", "var2", 600, 0.0, 600.0).unwrap()),
-            cuts_to_draw: vec![],
-            cuts_to_check: vec![],
-        };
+    /// Get the specs of every histogram in a folder or its subfolders, sorted by name.
+    /// Use the empty string for the root folder.
+    pub fn list_histograms(&self, folder: &str) -> Vec<&HistSpec> {
+        let mut specs: Vec<&HistSpec> = self
+            .histograms
+            .values()
+            .filter(|gram| folder::contains(folder, &gram.spec.name))
+            .map(|gram| &gram.spec)
+            .collect();
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        specs
+    }
 
-        manager.add_histogram(spec1.clone());
-        manager.add_histogram(spec2.clone());
+    /// Get the immediate subfolders of a folder, sorted by name
+    pub fn list_folders(&self, folder: &str) -> Vec<String> {
+        let mut folders: Vec<String> = self
+            .histograms
+            .values()
+            .filter_map(|gram| folder::child_folder(folder, &gram.spec.name))
+            .collect();
+        folders.sort();
+        folders.dedup();
+        folders
+    }
 
-        let spec_test = manager.get_histogram_spec(&spec1.id);
-        assert!(spec_test.is_ok());
-        assert!(manager.get_histogram_spec(&Uuid::new_v4()).is_err());
-        assert_eq!(*spec_test.unwrap(), spec1);
+    /// Move a histogram into a folder, keeping its leaf name
+    pub fn move_histogram(&mut self, id: &Uuid, folder: &str) -> Result<(), ResourceError> {
+        self.journal_spec(id);
+        match self.histograms.get_mut(id) {
+            Some(gram) => {
+                let (_, leaf) = folder::split(&gram.spec.name);
+                gram.spec.name = folder::join(folder, leaf);
+                gram.mark_modified();
+                self.bump_generation();
+                Ok(())
+            }
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
 
-        assert!(manager.remove_histogram(&spec1.id).is_ok());
-        let spec_test = manager.get_histogram_spec(&spec2.id);
-        assert!(spec_test.is_ok());
-        assert!(manager.get_histogram_spec(&spec1.id).is_err());
-        assert_eq!(*spec_test.unwrap(), spec2);
+    /// Move everything in the folder from (including subfolders) into the folder to, returning the
+    /// number of histograms moved
+    pub fn rename_folder(&mut self, from: &str, to: &str) -> Result<usize, ResourceError> {
+        if folder::normalize(from).is_empty() {
+            return Err(ResourceError::InvalidFolder(from.to_string()));
+        }
+        let moves: Vec<(Uuid, String)> = self
+            .histograms
+            .values()
+            .filter_map(|gram| {
+                folder::reparent(&gram.spec.name, from, to).map(|name| (gram.spec.id, name))
+            })
+            .collect();
+        for (id, name) in moves.iter() {
+            self.journal_spec(id);
+            if let Some(gram) = self.histograms.get_mut(id) {
+                gram.spec.name = name.clone();
+                gram.mark_modified();
+            }
+        }
+        if !moves.is_empty() {
+            self.bump_generation();
+        }
+        Ok(moves.len())
+    }
 
-        manager.remove_histogram(&spec2.id).unwrap();
-        assert_eq!(manager.histograms.len(), 0);
+    /// Remove every histogram in a folder and its subfolders, returning the number removed.
+    /// The root folder cannot be removed.
+    pub fn remove_folder(&mut self, folder: &str) -> Result<usize, ResourceError> {
+        if folder::normalize(folder).is_empty() {
+            return Err(ResourceError::InvalidFolder(folder.to_string()));
+        }
+        let ids: Vec<Uuid> = self
+            .histograms
+            .values()
+            .filter(|gram| folder::contains(folder, &gram.spec.name))
+            .map(|gram| gram.spec.id)
+            .collect();
+        for id in ids.iter() {
+            self.remove_histogram(id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Remove every histogram whose name matches a pattern, e.g. "gamma/clover_*". Returns the
+    /// number of histograms removed.
+    pub fn remove_histograms_matching(&mut self, pattern: &str) -> Result<usize, ResourceError> {
+        let ids = self.find_histograms_matching(pattern);
+        for id in ids.iter() {
+            self.remove_histogram(id)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Zero the contents of every histogram whose name matches a pattern. Returns the number of
+    /// histograms cleared.
+    pub fn reset_histograms_matching(&mut self, pattern: &str) -> usize {
+        let ids = self.find_histograms_matching(pattern);
+        for id in ids.iter() {
+            if let Some(gram) = self.histograms.get_mut(id) {
+                gram.clear();
+            }
+        }
+        if !ids.is_empty() {
+            self.bump_generation();
+        }
+        ids.len()
+    }
+
+    fn find_histograms_matching(&self, pattern: &str) -> Vec<Uuid> {
+        self.histograms
+            .values()
+            .filter(|gram| pattern::matches(pattern, &gram.spec.name))
+            .map(|gram| gram.spec.id)
+            .collect()
+    }
+
+    /// Call callback with every event of the given kinds, returning an ID for unsubscribe
+    pub fn subscribe_callback(
+        &mut self,
+        kinds: &[EventKind],
+        callback: impl FnMut(&ManagerEvent) + Send + 'static,
+    ) -> Uuid {
+        self.observers.add_callback(kinds, Box::new(callback))
+    }
+
+    /// Send every event of the given kinds over a channel. Dropping the receiver unsubscribes.
+    pub fn subscribe(&mut self, kinds: &[EventKind]) -> (Uuid, Receiver<ManagerEvent>) {
+        self.observers.add_channel(kinds)
+    }
+
+    /// Call callback with the histogram and bin every time a histogram is filled
+    pub fn on_fill(&mut self, callback: impl FnMut(&ManagerEvent) + Send + 'static) -> Uuid {
+        self.subscribe_callback(&[EventKind::Fill], callback)
+    }
+
+    pub fn on_histogram_added(
+        &mut self,
+        callback: impl FnMut(&ManagerEvent) + Send + 'static,
+    ) -> Uuid {
+        self.subscribe_callback(&[EventKind::HistogramAdded], callback)
+    }
+
+    pub fn on_cut_modified(
+        &mut self,
+        callback: impl FnMut(&ManagerEvent) + Send + 'static,
+    ) -> Uuid {
+        self.subscribe_callback(&[EventKind::CutModified], callback)
+    }
+
+    /// Remove a callback or channel, returning an error if the ID is not subscribed
+    pub fn unsubscribe(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        match self.observers.remove(id) {
+            true => Ok(()),
+            false => Err(ResourceError::InvalidObserverID(*id)),
+        }
+    }
+
+    pub fn get_histogram(&self, id: &Uuid) -> Result<&Histogram, ResourceError> {
+        self.histograms
+            .get(id)
+            .ok_or(ResourceError::InvalidHistogramID(*id))
+    }
+
+    pub fn get_histogram_data(&self, id: &Uuid) -> Result<&[f64], ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(&gram.data),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    pub fn get_histogram_spec(&self, id: &Uuid) -> Result<&HistSpec, ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(&gram.spec),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Get the counters describing how events offered to a histogram were handled, for working
+    /// out why a spectrum is empty
+    pub fn get_histogram_stats(&self, id: &Uuid) -> Result<&HistogramStats, ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(&gram.stats),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Get histogram contents summed down to at most max_bins_x by max_bins_y bins, e.g. to
+    /// match the pixel size of a display
+    pub fn get_histogram_data_downsampled(
+        &self,
+        id: &Uuid,
+        max_bins_x: usize,
+        max_bins_y: usize,
+    ) -> Result<DownsampledData, ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(gram.downsample(max_bins_x, max_bins_y)),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Get the counts and bin edges of a histogram within the given axis windows
+    pub fn get_histogram_slice(
+        &self,
+        id: &Uuid,
+        x_range: (f32, f32),
+        y_range: Option<(f32, f32)>,
+    ) -> Result<HistogramSlice, ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(gram.slice(x_range, y_range)?),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Get the indices into data of the bins of a histogram whose centers are inside a cut. The
+    /// cut must be on the variables of the histogram axes.
+    pub fn get_bins_in_cut(
+        &self,
+        histogram_id: &Uuid,
+        cut_id: &Uuid,
+    ) -> Result<Vec<usize>, ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(ResourceError::InvalidHistogramID(*histogram_id))?;
+        let cut = self
+            .cuts
+            .get(cut_id)
+            .ok_or(ResourceError::InvalidCutID(*cut_id))?;
+        let cut_spec = cut.get_spec();
+        let y_variable = gram.spec.y_axis.as_ref().map(|axis| &axis.variable);
+        if cut_spec.x_variable != gram.spec.x_axis.variable
+            || cut_spec
+                .y_variable
+                .as_ref()
+                .is_some_and(|y| Some(y) != y_variable)
+        {
+            return Err(CutError::WrongAxes(cut_spec.name.clone(), gram.spec.name.clone()).into());
+        }
+
+        let mut bins = vec![];
+        for (bin, (x, y, _)) in gram.iter_bins().enumerate() {
+            match cut.contains_point(x, y) {
+                Some(true) => bins.push(bin),
+                Some(false) => (),
+                None => return Err(CutError::NoGeometry(cut_spec.name.clone()).into()),
+            }
+        }
+        Ok(bins)
+    }
+
+    /// Sum the contents of the bins of a histogram inside a cut
+    pub fn integrate_in_cut(
+        &self,
+        histogram_id: &Uuid,
+        cut_id: &Uuid,
+    ) -> Result<f64, ResourceError> {
+        let bins = self.get_bins_in_cut(histogram_id, cut_id)?;
+        let data = self.get_histogram_data(histogram_id)?;
+        Ok(bins.iter().map(|bin| data[*bin]).sum())
+    }
+
+    /// Get the bins of a histogram which changed after since_generation. Use a generation of 0
+    /// to get every bin that has ever been filled.
+    pub fn get_histogram_delta(
+        &self,
+        id: &Uuid,
+        since_generation: u64,
+    ) -> Result<HistogramDelta, ResourceError> {
+        match self.histograms.get(id) {
+            Some(gram) => Ok(gram.get_delta(since_generation)),
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Choose the axis ranges of an auto-ranging histogram from the fills buffered so far
+    pub fn finish_auto_range(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        match self.histograms.get_mut(id) {
+            Some(gram) => {
+                let generation = gram.get_generation();
+                gram.finish_auto_range();
+                if gram.get_generation() != generation {
+                    self.bump_generation();
+                }
+                Ok(())
+            }
+            None => Err(ResourceError::InvalidHistogramID(*id)),
+        }
+    }
+
+    /// Register a histogram derived from booked ones, returning its ID. It is recomputed from
+    /// its inputs whenever they have changed, either when it is read or after every update call
+    /// as its RefreshMode says, so it never shows stale data.
+    pub fn add_derived_histogram(&mut self, mut spec: DerivedSpec) -> Result<Uuid, ResourceError> {
+        derived_inputs(&self.histograms, &spec)?;
+        if spec.id.is_nil() {
+            spec.id = Uuid::new_v4();
+        }
+        (spec.id, spec.name) = resolve_conflict(
+            self.conflict_policy,
+            spec.id,
+            &spec.name,
+            |id| self.histograms.contains_key(id) || self.derived.contains_key(id),
+            |name| {
+                self.histogram_name_taken(name)
+                    || self
+                        .derived
+                        .values()
+                        .any(|derived| derived.get_spec().name == name)
+            },
+        )?;
+        let id = spec.id;
+        let mut derived = DerivedHistogram::new(spec);
+        // Compute it straight away, so that a derivation which cannot apply fails here
+        let (parent, other) = derived_inputs(&self.histograms, derived.get_spec())?;
+        derived.refresh(parent, other)?;
+        self.bump_generation();
+        self.derived.insert(id, derived);
+        Ok(id)
+    }
+
+    /// Derive a copy of a histogram scaled to unit area or peak height
+    pub fn add_normalized(
+        &mut self,
+        parent_id: &Uuid,
+        name: &str,
+        normalization: Normalization,
+    ) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *parent_id,
+            derivation: Derivation::Normalized(normalization),
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Derive the cumulative distribution of a 1D histogram
+    pub fn add_cumulative(&mut self, parent_id: &Uuid, name: &str) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *parent_id,
+            derivation: Derivation::Cumulative,
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Derive a smoothed copy of a 1D histogram, e.g. for peak finding on a noisy spectrum
+    pub fn add_smoothed(
+        &mut self,
+        parent_id: &Uuid,
+        name: &str,
+        smoothing: Smoothing,
+    ) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *parent_id,
+            derivation: Derivation::Smoothed(smoothing),
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Derive the background subtracted projection of a matrix gated on its other axis, which
+    /// follows the matrix as it fills
+    pub fn add_gated_projection(
+        &mut self,
+        matrix_id: &Uuid,
+        name: &str,
+        projection: GatedProjection,
+    ) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *matrix_id,
+            derivation: Derivation::GatedProjection(projection),
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Derive the projection of a matrix onto one axis, optionally over a range of the other
+    pub fn add_projection(
+        &mut self,
+        matrix_id: &Uuid,
+        name: &str,
+        onto: ProjectionAxis,
+        range: Option<(f32, f32)>,
+    ) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *matrix_id,
+            derivation: Derivation::Projection { onto, range },
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Derive a histogram less scale times another, e.g. to subtract a background spectrum
+    pub fn add_difference(
+        &mut self,
+        parent_id: &Uuid,
+        other_id: &Uuid,
+        name: &str,
+        scale: f64,
+    ) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *parent_id,
+            derivation: Derivation::Difference {
+                other_id: *other_id,
+                scale,
+            },
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Derive the ratio of a histogram to another, e.g. for an efficiency
+    pub fn add_ratio(
+        &mut self,
+        parent_id: &Uuid,
+        other_id: &Uuid,
+        name: &str,
+    ) -> Result<Uuid, ResourceError> {
+        self.add_derived_histogram(DerivedSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            parent_id: *parent_id,
+            derivation: Derivation::Ratio {
+                other_id: *other_id,
+            },
+            refresh: RefreshMode::OnRead,
+        })
+    }
+
+    /// Get a derived histogram, recomputing it if its inputs have changed since it was last
+    /// computed
+    pub fn get_derived_histogram(&mut self, id: &Uuid) -> Result<&Histogram, ResourceError> {
+        let derived = self
+            .derived
+            .get_mut(id)
+            .ok_or(ResourceError::InvalidDerivedID(*id))?;
+        let (parent, other) = derived_inputs(&self.histograms, derived.get_spec())?;
+        Ok(derived.refresh(parent, other)?)
+    }
+
+    /// Get a derived histogram as it was last computed, without recomputing it. This is current
+    /// for histograms refreshed on update, unless the inputs were changed outside of update.
+    pub fn get_cached_derived_histogram(&self, id: &Uuid) -> Result<&Histogram, ResourceError> {
+        self.derived
+            .get(id)
+            .and_then(|derived| derived.get_cached())
+            .ok_or(ResourceError::InvalidDerivedID(*id))
+    }
+
+    pub fn set_derived_refresh_mode(
+        &mut self,
+        id: &Uuid,
+        mode: RefreshMode,
+    ) -> Result<(), ResourceError> {
+        match self.derived.get_mut(id) {
+            Some(derived) => {
+                derived.set_refresh_mode(mode);
+                Ok(())
+            }
+            None => Err(ResourceError::InvalidDerivedID(*id)),
+        }
+    }
+
+    /// Recompute every derived histogram whose inputs have changed, returning the number
+    /// recomputed. Every histogram is tried; the first error is returned.
+    pub fn refresh_derived_histograms(&mut self) -> Result<usize, ResourceError> {
+        self.refresh_derived_where(|_| true)
+    }
+
+    fn refresh_derived_where(
+        &mut self,
+        wanted: impl Fn(&DerivedSpec) -> bool,
+    ) -> Result<usize, ResourceError> {
+        let mut n_refreshed = 0;
+        let mut result = Ok(());
+        for derived in self.derived.values_mut() {
+            if !wanted(derived.get_spec()) {
+                continue;
+            }
+            let refreshed =
+                derived_inputs(&self.histograms, derived.get_spec()).and_then(|(parent, other)| {
+                    let is_stale = derived.is_stale(parent, other);
+                    derived.refresh(parent, other)?;
+                    Ok(is_stale)
+                });
+            match refreshed {
+                Ok(is_stale) => n_refreshed += is_stale as usize,
+                Err(error) => {
+                    if result.is_ok() {
+                        result = Err(error);
+                    }
+                }
+            }
+        }
+        result.map(|_| n_refreshed)
+    }
+
+    // Bring the derived histograms refreshed on update up to date. One whose derivation no
+    // longer applies keeps its last copy, and reading it with get_derived_histogram says why.
+    fn refresh_derived_on_update(&mut self) {
+        if self
+            .derived
+            .values()
+            .any(|derived| derived.get_spec().refresh == RefreshMode::OnUpdate)
+        {
+            let _ = self.refresh_derived_where(|spec| spec.refresh == RefreshMode::OnUpdate);
+        }
+    }
+
+    pub fn get_derived_spec(&self, id: &Uuid) -> Result<&DerivedSpec, ResourceError> {
+        self.derived
+            .get(id)
+            .map(|derived| derived.get_spec())
+            .ok_or(ResourceError::InvalidDerivedID(*id))
+    }
+
+    /// The derived histograms of a parent
+    pub fn list_derived_histograms(&self, parent_id: &Uuid) -> Vec<&DerivedSpec> {
+        self.derived
+            .values()
+            .map(|derived| derived.get_spec())
+            .filter(|spec| spec.parent_id == *parent_id)
+            .collect()
+    }
+
+    pub fn remove_derived_histogram(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        match self.derived.remove(id) {
+            Some(_) => {
+                self.bump_generation();
+                Ok(())
+            }
+            None => Err(ResourceError::InvalidDerivedID(*id)),
+        }
+    }
+
+    /// Register a check on a monitored histogram, returning its ID. Checks run when run_checks
+    /// is called and their interval has passed.
+    pub fn add_check(&mut self, spec: CheckSpec) -> Result<Uuid, ResourceError> {
+        if !self.histograms.contains_key(&spec.histogram_id) {
+            return Err(ResourceError::InvalidHistogramID(spec.histogram_id));
+        }
+        let id = spec.id;
+        self.checks.retain(|check| check.spec.id != id);
+        self.checks.push(Check::new(spec));
+        Ok(id)
+    }
+
+    pub fn remove_check(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        let n_checks = self.checks.len();
+        self.checks.retain(|check| check.spec.id != *id);
+        match n_checks != self.checks.len() {
+            true => Ok(()),
+            false => Err(ResourceError::InvalidCheckID(*id)),
+        }
+    }
+
+    /// The checks which are currently failing
+    pub fn list_failing_checks(&self) -> Vec<&CheckSpec> {
+        self.checks
+            .iter()
+            .filter(|check| check.is_failing())
+            .map(|check| &check.spec)
+            .collect()
+    }
+
+    /// Run every check which is due, e.g. from a timer once a second. Checks which start or
+    /// stop failing raise alerts, which are sent to observers and returned.
+    pub fn run_checks(&mut self) -> Vec<Alert> {
+        let now = Instant::now();
+        let mut alerts = vec![];
+        for check in self.checks.iter_mut().filter(|check| check.is_due(now)) {
+            if let Some(gram) = self.histograms.get(&check.spec.histogram_id)
+                && let Some(alert) = check.evaluate(gram, now)
+            {
+                alerts.push(alert);
+            }
+        }
+        for alert in alerts.iter() {
+            self.observers.notify(ManagerEvent::Alert(alert.clone()));
+        }
+        alerts
+    }
+
+    /// Send an alert about a watched source to observers, see Spect::builder().watch
+    pub fn raise_source_alert(&mut self, alert: SourceAlert) {
+        self.observers.notify(ManagerEvent::SourceAlert(alert));
+    }
+
+    /// Age out stale data from every rolling window histogram
+    pub fn refresh_windows(&mut self) {
+        let now = Instant::now();
+        let mut changed = false;
+        for gram in self.histograms.values_mut() {
+            let generation = gram.get_generation();
+            gram.age_window(now);
+            changed |= gram.get_generation() != generation;
+        }
+        if changed {
+            self.bump_generation();
+        }
+    }
+
+    /// Add a 1D cut drawn on a histogram. Cuts drawn on a periodic axis are periodic cuts, so
+    /// low may be greater than high to make an interval which wraps around.
+    pub fn add_cut_1d(
+        &mut self,
+        mut spec: CutSpec,
+        low_value: f32,
+        high_value: f32,
+        histogram_id: &Uuid,
+    ) -> Result<(), ResourceError> {
+        self.resolve_cut_conflict(&mut spec)?;
+        let name = spec.name.clone();
+        let cut = match self.histograms.get(histogram_id) {
+            Some(gram) if gram.spec.x_axis.periodic => Cut1D::new_periodic(
+                spec,
+                low_value,
+                high_value,
+                gram.spec.x_axis.minimum,
+                gram.spec.x_axis.maximum,
+            ),
+            Some(_) => Cut1D::new(spec, low_value, high_value),
+            None => {
+                return Err(ResourceError::CutFailed(
+                    super::error::CutError::NoReferenceHistogram(*histogram_id),
+                ));
+            }
+        }
+        .map_err(|e| ResourceError::from(e).with_context("add cut", &name))?;
+        self.journal_spec(histogram_id);
+        if let Some(gram) = self.histograms.get_mut(histogram_id) {
+            gram.spec.cuts_to_draw.push(cut.get_spec().id);
+            gram.mark_modified();
+        }
+        self.insert_cut(Box::new(cut));
+        Ok(())
+    }
+
+    pub fn add_cut_2d(
+        &mut self,
+        mut spec: CutSpec,
+        x_values: Vec<f32>,
+        y_values: Vec<f32>,
+        histogram_id: &Uuid,
+    ) -> Result<(), ResourceError> {
+        if !self.histograms.contains_key(histogram_id) {
+            return Err(ResourceError::CutFailed(
+                super::error::CutError::NoReferenceHistogram(*histogram_id),
+            ));
+        }
+        self.resolve_cut_conflict(&mut spec)?;
+        let name = spec.name.clone();
+        let cut = Cut2D::new(spec, x_values, y_values)
+            .map_err(|e| ResourceError::from(e).with_context("add cut", &name))?;
+        self.journal_spec(histogram_id);
+        if let Some(gram) = self.histograms.get_mut(histogram_id) {
+            gram.spec.cuts_to_draw.push(cut.get_spec().id);
+            gram.mark_modified();
+        }
+        self.insert_cut(Box::new(cut));
+        Ok(())
+    }
+
+    /// Gate on the outline of a blob in a 2D histogram: add a cut from its contour at level
+    /// which encloses point, or from the largest contour if no point is given. The cut is drawn on
+    /// the histogram and uses its variables; its ID is returned.
+    pub fn add_contour_cut(
+        &mut self,
+        histogram_id: &Uuid,
+        name: &str,
+        level: f64,
+        point: Option<(f32, f32)>,
+    ) -> Result<Uuid, ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(CutError::NoReferenceHistogram(*histogram_id))?;
+        let contours = contour::find_contours(gram, level)?;
+        let contour = contour::select_contour(&contours, point)
+            .ok_or_else(|| CutError::NoContour(gram.spec.name.clone(), level))?;
+        let mut spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            x_variable: gram.spec.x_axis.variable.clone(),
+            y_variable: gram.spec.y_axis.as_ref().map(|axis| axis.variable.clone()),
+        };
+        let (x_values, y_values) = (contour.x_values.clone(), contour.y_values.clone());
+        self.resolve_cut_conflict(&mut spec)?;
+        let id = spec.id;
+        self.add_cut_2d(spec, x_values, y_values, histogram_id)?;
+        Ok(id)
+    }
+
+    /// Gate a 1D histogram on its centroid ± k * sigma, using the counts inside region if given,
+    /// e.g. to gate on a peak. Returns the ID of the cut.
+    pub fn add_sigma_cut(
+        &mut self,
+        histogram_id: &Uuid,
+        name: &str,
+        k: f32,
+        region: Option<(f32, f32)>,
+    ) -> Result<Uuid, ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(CutError::NoReferenceHistogram(*histogram_id))?;
+        let (low, high) = autogate::sigma_window(gram, k, region)?;
+        let mut spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            x_variable: gram.spec.x_axis.variable.clone(),
+            y_variable: None,
+        };
+        self.resolve_cut_conflict(&mut spec)?;
+        let id = spec.id;
+        self.add_cut_1d(spec, low, high, histogram_id)?;
+        Ok(id)
+    }
+
+    /// Gate a 2D histogram on the outline of the densest region holding fraction of its counts,
+    /// taking the region around point if given. See add_contour_cut.
+    pub fn add_containment_cut(
+        &mut self,
+        histogram_id: &Uuid,
+        name: &str,
+        fraction: f64,
+        point: Option<(f32, f32)>,
+    ) -> Result<Uuid, ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(CutError::NoReferenceHistogram(*histogram_id))?;
+        let level = autogate::containment_level(gram, fraction)?;
+        self.add_contour_cut(histogram_id, name, level, point)
+    }
+
+    /// Add a cut of any type, including types defined outside this crate. Register a factory for
+    /// its kind with register_cut_kind so that it can be restored from import_cuts. The cut was
+    /// built before it reached the manager, so it cannot be renamed and a conflict is always an
+    /// error under ConflictPolicy::Rename.
+    pub fn add_cut(&mut self, cut: Box<dyn Cut>) -> Result<Uuid, ResourceError> {
+        let mut spec = cut.get_spec().clone();
+        self.resolve_cut_conflict(&mut spec)?;
+        if spec.id != cut.get_spec().id {
+            return Err(ResourceError::DuplicateID(cut.get_spec().id));
+        } else if spec.name != cut.get_spec().name {
+            return Err(ResourceError::DuplicateName(cut.get_spec().name.clone()));
+        }
+        self.insert_cut(cut);
+        Ok(spec.id)
+    }
+
+    /// Replace an existing cut with one carrying the same ID, e.g. to redefine its shape, so that
+    /// histograms gated on it follow. The conflict policy does not apply to a replacement.
+    pub fn replace_cut(&mut self, cut: Box<dyn Cut>) -> Result<(), ResourceError> {
+        let id = cut.get_spec().id;
+        if !self.cuts.contains_key(&id) {
+            return Err(ResourceError::InvalidCutID(id));
+        }
+        self.insert_cut(cut);
+        Ok(())
+    }
+
+    pub fn register_cut_kind(&mut self, kind: &str, factory: CutFactory) {
+        self.cut_registry.register(kind, factory);
+    }
+
+    /// Bind the shape of an existing cut to the variables in spec, returning the ID of the binding.
+    /// Bindings can gate histograms and filters like any other cut, and follow later changes to
+    /// the shape.
+    pub fn bind_cut(&mut self, shape_id: &Uuid, mut spec: CutSpec) -> Result<Uuid, ResourceError> {
+        let shape = self
+            .cuts
+            .get(shape_id)
+            .ok_or(ResourceError::InvalidCutID(*shape_id))?;
+        if shape.get_spec().y_variable.is_some() != spec.y_variable.is_some() {
+            return Err(CutError::WrongAxes(spec.name, shape.get_spec().name.clone()).into());
+        }
+        self.resolve_cut_conflict(&mut spec)?;
+        let id = spec.id;
+        let generation = self.bump_generation();
+        let previous = self.cut_bindings.insert(
+            id,
+            CutBinding {
+                spec,
+                shape_id: *shape_id,
+            },
+        );
+        self.record_undo(Undo::Binding(id, previous));
+        self.cut_generations.insert(id, generation);
+        self.observers.notify(ManagerEvent::CutModified(id));
+        Ok(id)
+    }
+
+    /// Bind the shape of a cut to (x, y) variable pairs, naming each binding after the shape and
+    /// its variables
+    pub fn bind_cut_to_pairs(
+        &mut self,
+        shape_id: &Uuid,
+        pairs: &[(&str, Option<&str>)],
+    ) -> Result<Vec<Uuid>, ResourceError> {
+        let shape_name = self.get_cut_spec(shape_id)?.name.clone();
+        pairs
+            .iter()
+            .map(|(x_variable, y_variable)| {
+                let name = match y_variable {
+                    Some(y_variable) => format!("{shape_name}[{x_variable},{y_variable}]"),
+                    None => format!("{shape_name}[{x_variable}]"),
+                };
+                let spec = CutSpec {
+                    id: Uuid::new_v4(),
+                    name,
+                    x_variable: x_variable.to_string(),
+                    y_variable: y_variable.map(String::from),
+                };
+                self.bind_cut(shape_id, spec)
+            })
+            .collect()
+    }
+
+    pub fn get_cut_binding(&self, id: &Uuid) -> Result<&CutBinding, ResourceError> {
+        self.cut_bindings
+            .get(id)
+            .ok_or(ResourceError::InvalidCutID(*id))
+    }
+
+    /// Get the bindings of a shape
+    pub fn get_cut_bindings(&self, shape_id: &Uuid) -> Vec<&CutBinding> {
+        self.cut_bindings
+            .values()
+            .filter(|binding| binding.shape_id == *shape_id)
+            .collect()
+    }
+
+    pub fn remove_cut_binding(&mut self, id: &Uuid) -> Result<CutBinding, ResourceError> {
+        let binding = self
+            .cut_bindings
+            .remove(id)
+            .ok_or(ResourceError::InvalidCutID(*id))?;
+        self.record_undo(Undo::Binding(*id, Some(binding.clone())));
+        self.bump_generation();
+        self.cut_generations.remove(id);
+        self.observers.notify(ManagerEvent::CutModified(*id));
+        Ok(binding)
+    }
+
+    /// Check for a cut, binding, or compound cut with the ID
+    fn cut_exists(&self, id: &Uuid) -> bool {
+        self.cuts.contains_key(id)
+            || self.cut_bindings.contains_key(id)
+            || self.compound_cuts.contains_key(id)
+    }
+
+    /// Add a cut combining other cuts. Every referenced cut must exist and the compound cut may
+    /// not end up depending on itself.
+    pub fn add_compound_cut(&mut self, mut compound: CompoundCut) -> Result<Uuid, ResourceError> {
+        if let Some(missing) = compound
+            .condition
+            .get_cut_ids()
+            .iter()
+            .find(|id| !self.cut_exists(id) && **id != compound.id)
+        {
+            return Err(ResourceError::MissingReference(
+                compound.name.clone(),
+                *missing,
+            ));
+        }
+        (compound.id, compound.name) = resolve_conflict(
+            self.conflict_policy,
+            compound.id,
+            &compound.name,
+            |id| self.cut_exists(id),
+            |name| self.cut_name_taken(name),
+        )?;
+        let id = compound.id;
+        let previous = self.compound_cuts.insert(id, compound);
+        if let Some(cycle) = self.find_cycle(&id) {
+            match previous {
+                Some(previous) => self.compound_cuts.insert(id, previous),
+                None => self.compound_cuts.remove(&id),
+            };
+            return Err(ResourceError::DependencyCycle(cycle));
+        }
+        self.record_undo(Undo::Compound(id, previous));
+        let generation = self.bump_generation();
+        self.cut_generations.insert(id, generation);
+        self.observers.notify(ManagerEvent::CutModified(id));
+        Ok(id)
+    }
+
+    pub fn get_compound_cut(&self, id: &Uuid) -> Result<&CompoundCut, ResourceError> {
+        self.compound_cuts
+            .get(id)
+            .ok_or(ResourceError::InvalidCutID(*id))
+    }
+
+    // Get the name of any kind of cut for error messages
+    fn get_cut_name(&self, id: &Uuid) -> String {
+        if let Some(cut) = self.cuts.get(id) {
+            cut.get_spec().name.clone()
+        } else if let Some(binding) = self.cut_bindings.get(id) {
+            binding.spec.name.clone()
+        } else if let Some(compound) = self.compound_cuts.get(id) {
+            compound.name.clone()
+        } else {
+            id.to_string()
+        }
+    }
+
+    // Depth first search through compound cuts for a path leading back to start, returned as the
+    // names of the cuts along it
+    fn find_cycle(&self, start: &Uuid) -> Option<Vec<String>> {
+        let mut path = vec![*start];
+        let mut visited = FxHashSet::default();
+        if self.visit_for_cycle(start, start, &mut path, &mut visited) {
+            Some(path.iter().map(|id| self.get_cut_name(id)).collect())
+        } else {
+            None
+        }
+    }
+
+    fn visit_for_cycle(
+        &self,
+        start: &Uuid,
+        current: &Uuid,
+        path: &mut Vec<Uuid>,
+        visited: &mut FxHashSet<Uuid>,
+    ) -> bool {
+        let compound = match self.compound_cuts.get(current) {
+            Some(compound) => compound,
+            None => return false,
+        };
+        for next in compound.condition.get_cut_ids() {
+            path.push(*next);
+            if next == start
+                || (visited.insert(*next) && self.visit_for_cycle(start, next, path, visited))
+            {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+
+    // Get every cut referenced by a histogram, filter, binding, or compound cut
+    fn get_referenced_cuts(&self) -> FxHashSet<Uuid> {
+        let mut referenced = FxHashSet::default();
+        for gram in self.histograms.values() {
+            referenced.extend(gram.spec.cuts_to_check.iter().copied());
+            referenced.extend(gram.spec.cuts_to_draw.iter().copied());
+        }
+        for filter in self.filters.values() {
+            referenced.extend(filter.condition.get_cut_ids().iter().copied());
+        }
+        for compound in self.compound_cuts.values() {
+            referenced.extend(compound.condition.get_cut_ids().iter().copied());
+        }
+        for binding in self.cut_bindings.values() {
+            referenced.insert(binding.shape_id);
+        }
+        referenced
+    }
+
+    /// Remove every cut, binding, and compound cut which no histogram, filter, binding, or
+    /// compound cut refers to, including cuts only referenced by other removed cuts. Returns the
+    /// number of cuts removed.
+    pub fn remove_cuts_unreferenced(&mut self) -> usize {
+        let mut n_removed = 0;
+        loop {
+            let referenced = self.get_referenced_cuts();
+            let unreferenced: Vec<Uuid> = self
+                .cuts
+                .keys()
+                .chain(self.cut_bindings.keys())
+                .chain(self.compound_cuts.keys())
+                .filter(|id| !referenced.contains(id))
+                .copied()
+                .collect();
+            if unreferenced.is_empty() {
+                break;
+            }
+            for id in unreferenced.iter() {
+                if let Some(cut) = self.cuts.remove(id) {
+                    self.record_undo(Undo::Cut(*id, Some(cut)));
+                } else if let Some(binding) = self.cut_bindings.remove(id) {
+                    self.record_undo(Undo::Binding(*id, Some(binding)));
+                } else if let Some(compound) = self.compound_cuts.remove(id) {
+                    self.record_undo(Undo::Compound(*id, Some(compound)));
+                }
+                self.cut_generations.remove(id);
+                self.observers.notify(ManagerEvent::CutModified(*id));
+            }
+            n_removed += unreferenced.len();
+        }
+        if n_removed > 0 {
+            self.bump_generation();
+        }
+        n_removed
+    }
+
+    /// Check every histogram, filter, binding, and compound cut for references to cuts which do
+    /// not exist, and compound cuts for dependency cycles. Histograms may be booked before the
+    /// cuts gating them, so call this once booking is done. Once a schema has been registered,
+    /// references to variables it does not declare are errors too; see validate_variables.
+    pub fn validate(&self) -> Result<(), ResourceError> {
+        for gram in self.histograms.values() {
+            if let Some(missing) = gram
+                .spec
+                .cuts_to_check
+                .iter()
+                .find(|id| !self.cut_exists(id))
+            {
+                return Err(ResourceError::MissingReference(
+                    gram.spec.name.clone(),
+                    *missing,
+                ));
+            }
+        }
+        for filter in self.filters.values() {
+            if let Some(missing) = filter
+                .condition
+                .get_cut_ids()
+                .iter()
+                .find(|id| !self.cut_exists(id))
+            {
+                return Err(ResourceError::MissingReference(
+                    format!("Filter {}", filter.id),
+                    *missing,
+                ));
+            }
+        }
+        for binding in self.cut_bindings.values() {
+            if !self.cuts.contains_key(&binding.shape_id) {
+                return Err(ResourceError::MissingReference(
+                    binding.spec.name.clone(),
+                    binding.shape_id,
+                ));
+            }
+        }
+        for compound in self.compound_cuts.values() {
+            if let Some(missing) = compound
+                .condition
+                .get_cut_ids()
+                .iter()
+                .find(|id| !self.cut_exists(id))
+            {
+                return Err(ResourceError::MissingReference(
+                    compound.name.clone(),
+                    *missing,
+                ));
+            }
+            if let Some(cycle) = self.find_cycle(&compound.id) {
+                return Err(ResourceError::DependencyCycle(cycle));
+            }
+        }
+        if !self.schema.is_empty()
+            && let Some(unknown) = self.validate_variables().into_iter().next()
+        {
+            return Err(unknown);
+        }
+        Ok(())
+    }
+
+    /// Find every reference of a histogram, cut, calibration, or rate meter to a variable no
+    /// registered schema declares, which would otherwise show up as an empty spectrum once data
+    /// flows. Alias names, calibration outputs, and the timestamp are always known. Returns an
+    /// UnknownVariable error for each, sorted by what made the reference.
+    pub fn validate_variables(&self) -> Vec<ResourceError> {
+        let is_known = |variable: &str| {
+            variable == TIMESTAMP_VARIABLE
+                || self.schema.is_known(variable)
+                || self.calibrations.get(variable).is_some()
+                || self.aliases.iter().any(|alias| alias.canonical == variable)
+        };
+        let mut references: Vec<(String, &str)> = vec![];
+        for gram in self.histograms.values() {
+            let spec = &gram.spec;
+            let owner = format!("Histogram '{}'", spec.name);
+            if spec.fill_mode != FillMode::Summary {
+                references.push((owner.clone(), &spec.x_axis.variable));
+            }
+            if let Some(y_axis) = &spec.y_axis {
+                references.push((owner, &y_axis.variable));
+            }
+        }
+        let cut_specs = self
+            .cuts
+            .values()
+            .map(|cut| cut.get_spec())
+            .chain(self.cut_bindings.values().map(|binding| &binding.spec));
+        for spec in cut_specs {
+            let owner = format!("Cut '{}'", spec.name);
+            references.push((owner.clone(), &spec.x_variable));
+            if let Some(y_variable) = &spec.y_variable {
+                references.push((owner, y_variable));
+            }
+        }
+        for spec in self.calibrations.iter() {
+            references.push((format!("Calibration of '{}'", spec.output), &spec.input));
+        }
+        for rate in self.rates.values() {
+            let spec = rate.get_spec();
+            if let Some(variable) = &spec.variable {
+                references.push((format!("Rate meter '{}'", spec.name), variable));
+            }
+        }
+        references.sort();
+        references.dedup();
+        references
+            .into_iter()
+            .filter(|(_, variable)| !is_known(variable))
+            .map(|(owner, variable)| ResourceError::UnknownVariable(owner, variable.to_string()))
+            .collect()
+    }
+
+    /// Get the IDs of the histograms gated on a cut
+    pub fn get_cut_dependents(&self, id: &Uuid) -> Result<Vec<Uuid>, ResourceError> {
+        if !self.cut_exists(id) {
+            return Err(ResourceError::InvalidCutID(*id));
+        }
+        Ok(self
+            .cut_dependents
+            .get(id)
+            .map(|dependents| dependents.iter().copied().collect())
+            .unwrap_or_default())
+    }
+
+    pub fn get_cut_spec(&self, id: &Uuid) -> Result<&CutSpec, ResourceError> {
+        match self.cuts.get(id) {
+            Some(cut) => Ok(cut.get_spec()),
+            None => Err(ResourceError::InvalidCutID(*id)),
+        }
+    }
+
+    /// Get the (kind, spec) of every cut
+    pub fn list_cuts(&self) -> Vec<(&str, &CutSpec)> {
+        self.cuts
+            .values()
+            .map(|cut| (cut.get_kind(), cut.get_spec()))
+            .collect()
+    }
+
+    pub fn export_cuts(&self) -> Vec<SavedCut> {
+        self.cuts
+            .values()
+            .map(|cut| SavedCut::from_cut(cut.as_ref()))
+            .collect()
+    }
+
+    /// Rebuild saved cuts using the registered factories, replacing cuts with the same IDs. Nothing
+    /// is added if any cut fails to build.
+    pub fn import_cuts(&mut self, saved: &[SavedCut]) -> Result<Vec<Uuid>, ResourceError> {
+        // Resolve every conflict and build everything first so that a bad cut leaves the manager
+        // unchanged. The cuts imported before each one count as taken.
+        let mut resolved: Vec<SavedCut> = vec![];
+        for saved in saved.iter() {
+            let mut saved = saved.clone();
+            (saved.spec.id, saved.spec.name) = resolve_conflict(
+                self.conflict_policy,
+                saved.spec.id,
+                &saved.spec.name,
+                |id| self.cut_exists(id) || resolved.iter().any(|other| other.spec.id == *id),
+                |name| {
+                    self.cut_name_taken(name)
+                        || resolved.iter().any(|other| other.spec.name == name)
+                },
+            )?;
+            self.cut_registry.build(&saved)?;
+            resolved.push(saved);
+        }
+        let mut ids = vec![];
+        for saved in resolved.iter() {
+            ids.push(saved.spec.id);
+            let cut = self.cut_registry.build(saved)?;
+            self.insert_cut(cut);
+        }
+        Ok(ids)
+    }
+
+    /// Share cuts with the other managers using a store, see the workspace module. The cuts of
+    /// this manager are published to it and those of the others imported, returning the IDs
+    /// imported. From then on, cuts created or changed here are published, and those published
+    /// elsewhere are imported before each update. A manager shares one store at a time.
+    pub fn share_cuts(&mut self, cuts: &SharedCuts) -> Result<Vec<Uuid>, ResourceError> {
+        let subscription = CutSubscription::new(cuts);
+        for cut in self.cuts.values() {
+            subscription.publish(cut.as_ref());
+        }
+        self.shared_cuts = Some(subscription);
+        self.sync_cuts()
+    }
+
+    /// Stop sharing cuts, keeping those imported so far. Returns the store which was shared.
+    pub fn unshare_cuts(&mut self) -> Option<SharedCuts> {
+        self.shared_cuts
+            .take()
+            .map(|subscription| subscription.get_cuts().clone())
+    }
+
+    /// Import the cuts published to the shared store since the last sync, replacing cuts with
+    /// the same IDs, and return their IDs. This is cheap when nothing was published, and is done
+    /// before every update. Nothing is imported if any cut fails to build, e.g. one of a kind
+    /// not registered with this manager, and those cuts are not tried again until republished.
+    pub fn sync_cuts(&mut self) -> Result<Vec<Uuid>, ResourceError> {
+        let Some(changes) = self
+            .shared_cuts
+            .as_mut()
+            .and_then(|subscription| subscription.take_changes())
+        else {
+            return Ok(vec![]);
+        };
+        let cuts = changes
+            .iter()
+            .map(|saved| self.cut_registry.build(saved))
+            .collect::<Result<Vec<_>, _>>()?;
+        let ids = changes.iter().map(|saved| saved.spec.id).collect();
+        for cut in cuts {
+            self.store_cut(cut);
+        }
+        Ok(ids)
+    }
+
+    /// Combine another manager into this one, e.g. one filled by a worker process.
+    /// Histograms sharing an ID are summed; histograms and cuts only present in other are moved over.
+    /// Shared histograms must be mergeable, and moved histograms pass the same conflict policy,
+    /// layout and memory checks as add_histogram. Everything is checked before anything is modified.
+    pub fn merge(&mut self, mut other: ResourceManager) -> Result<(), ResourceError> {
+        let mut moved: Vec<(Uuid, String)> = vec![];
+        let mut reserved = 0;
+        for (id, gram) in other.histograms.iter() {
+            if let Some(ours) = self.histograms.get(id) {
+                ours.check_mergeable(gram)?;
+                continue;
+            }
+            let (_, name) = resolve_conflict(
+                self.conflict_policy,
+                *id,
+                &gram.spec.name,
+                |_| false,
+                |name| {
+                    self.histogram_name_taken(name) || moved.iter().any(|(_, taken)| taken == name)
+                },
+            )?;
+            Self::check_layout(&gram.spec)?;
+            self.check_memory_reserved(&gram.spec, reserved)?;
+            reserved += gram.spec.estimate_memory();
+            moved.push((*id, name));
+        }
+        for (id, name) in moved {
+            if let Some(gram) = other.histograms.get_mut(&id) {
+                gram.spec.name = name;
+            }
+        }
+
+        self.bump_generation();
+        for (id, gram) in other.histograms {
+            match self.histograms.get_mut(&id) {
+                // Contents are not part of an edit
+                Some(ours) => ours.merge_from(&gram)?,
+                None => {
+                    self.index_cut_dependents(&gram.spec);
+                    self.histograms.insert(id, gram);
+                    self.record_undo(Undo::Histogram(id, None));
+                    self.observers.notify(ManagerEvent::HistogramAdded(id));
+                }
+            }
+        }
+        for (id, cut) in other.cuts {
+            if !self.cuts.contains_key(&id) {
+                self.insert_cut(cut);
+            }
+        }
+        for (id, binding) in other.cut_bindings {
+            if let Entry::Vacant(entry) = self.cut_bindings.entry(id) {
+                entry.insert(binding);
+                self.record_undo(Undo::Binding(id, None));
+            }
+        }
+        for (id, compound) in other.compound_cuts {
+            if let Entry::Vacant(entry) = self.compound_cuts.entry(id) {
+                entry.insert(compound);
+                self.record_undo(Undo::Compound(id, None));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read up to n_events from a source and suggest an axis for each variable from the observed
+    /// values. Variables which were not observed enough to suggest an axis are left out.
+    pub fn suggest_axes(
+        source: &mut dyn DataSource,
+        variables: &[&str],
+        n_events: usize,
+        rule: BinningRule,
+    ) -> Result<Vec<AxisSpec>, ResourceError> {
+        let mut samples: Vec<Vec<f32>> = vec![vec![]; variables.len()];
+        for _ in 0..n_events {
+            let Some(blob) = source.next_event()? else {
+                break;
+            };
+            for (variable, values) in variables.iter().zip(samples.iter_mut()) {
+                if let Some(value) = blob.find(variable) {
+                    values.push(*value);
+                }
+            }
+        }
+        Ok(variables
+            .iter()
+            .zip(samples.iter())
+            .filter_map(|(variable, values)| AxisSpec::suggest(variable, values, rule).ok())
+            .collect())
+    }
+
+    /// Start recording every event passed to update into an event log at path.
+    /// Any previous recording is flushed and closed.
+    #[cfg(feature = "io")]
+    pub fn record_to(&mut self, path: &Path) -> Result<(), ResourceError> {
+        self.stop_recording()?;
+        self.recorder = Some(EventRecorder::create(path)?);
+        Ok(())
+    }
+
+    /// Flush and close the active recording, returning the number of events recorded
+    pub fn stop_recording(&mut self) -> Result<usize, ResourceError> {
+        match self.recorder.take() {
+            Some(mut recorder) => {
+                recorder.flush()?;
+                Ok(recorder.get_n_events())
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Render every histogram in a folder and its subfolders to an image in directory, named by
+    /// its path within the folder, returning the number of images written
+    #[cfg(feature = "render")]
+    pub fn render_folder(
+        &self,
+        folder: &str,
+        directory: &Path,
+        spec: &RenderSpec,
+    ) -> Result<usize, ResourceError> {
+        let cuts = self.export_cuts();
+        let specs = self.list_histograms(folder);
+        for hist_spec in specs.iter() {
+            let name = folder::reparent(&hist_spec.name, folder, "").unwrap_or_default();
+            let path = directory.join(format!("{name}.{}", spec.format.get_extension()));
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(RenderError::from)?;
+            }
+            let drawn: Vec<SavedCut> = cuts
+                .iter()
+                .filter(|cut| hist_spec.cuts_to_draw.contains(&cut.spec.id))
+                .cloned()
+                .collect();
+            render::render_histogram(&self.histograms[&hist_spec.id], &drawn, &path, spec)?;
+        }
+        Ok(specs.len())
+    }
+
+    /// Run every event in the log at path through update, returning the number of events replayed.
+    /// Useful for backfilling histograms and cuts booked after the data was taken.
+    #[cfg(feature = "io")]
+    pub fn replay_from(&mut self, path: &Path) -> Result<usize, ResourceError> {
+        let reader = EventReader::open(path)?;
+        let mut n_events = 0;
+        for blob in reader {
+            self.update(blob?)?;
+            n_events += 1;
+        }
+        Ok(n_events)
+    }
+
+    /// Run every event from a source through update until it is exhausted, returning the number
+    /// of events read. Several sources can be combined into one with a MergedSource.
+    pub fn process_source(&mut self, source: &mut dyn DataSource) -> Result<usize, ResourceError> {
+        let mut n_events = 0;
+        loop {
+            let blob = match source.next_event() {
+                Ok(Some(blob)) => blob,
+                Ok(None) => return Ok(n_events),
+                Err(e) => {
+                    self.count_source_error(&e);
+                    return Err(e.into());
+                }
+            };
+            self.update(blob)?;
+            n_events += 1;
+        }
+    }
+
+    /// Count an error returned by a source towards the losses, if it lost an event. Loops
+    /// reading sources other than process_source should call this with the errors they get.
+    pub fn count_source_error(&mut self, error: &SourceError) {
+        if let SourceError::Decode(_) = error {
+            self.losses.decode_failure += 1;
+        }
+    }
+
+    /// Count events lost before they reached the manager which no source reports, e.g. from
+    /// the scalers of the DAQ
+    pub fn add_losses(&mut self, losses: &LossCounts) {
+        self.losses.add(losses);
+    }
+
+    /// Get the events processed and lost so far, including the losses source reports
+    pub fn get_dead_time(&self, source: Option<&dyn DataSource>) -> DeadTimeReport {
+        let mut losses = self.losses;
+        if let Some(source) = source {
+            losses.add(&source.get_losses());
+        }
+        DeadTimeReport {
+            processed: self.n_events,
+            losses,
+            rejected: self.n_rejected,
+        }
+    }
+
+    /// Declare variables which will be in events, e.g. those made by transforms, adding to any
+    /// declared before
+    pub fn register_schema(&mut self, schema: &Schema) {
+        self.schema.merge(schema);
+    }
+
+    /// Declare the variables of a source, returning false if the source does not know them
+    pub fn register_source_schema(&mut self, source: &dyn DataSource) -> bool {
+        match source.get_schema() {
+            Some(schema) => {
+                self.register_schema(&schema);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// Cleanly finish taking data: stop the source, process the events still buffered in it
+    /// (including events held open by an event builder), end the run in progress, and flush and
+    /// close the recorder and filters. Exporters should be updated afterwards so that they
+    /// publish the final state.
+    pub fn shutdown(
+        &mut self,
+        source: &mut dyn DataSource,
+    ) -> Result<ShutdownReport, ResourceError> {
+        source.stop();
+        let drained = self.process_source(source)?;
+        let run = match self.runs.get_state() {
+            RunState::Stopped => None,
+            RunState::Running | RunState::Paused => Some(self.end_run()?),
+        };
+        let recorded = self.stop_recording()?;
+        let mut filtered = vec![];
+        for (id, filter) in self.filters.drain() {
+            filtered.push((id, filter.finish()?));
+        }
+        #[cfg(feature = "parquet")]
+        let mut converted = vec![];
+        #[cfg(feature = "parquet")]
+        for (id, sink) in self.sinks.drain() {
+            converted.push((id, sink.finish()?));
+        }
+        Ok(ShutdownReport {
+            drained,
+            events: self.n_events,
+            recorded,
+            filtered,
+            #[cfg(feature = "parquet")]
+            converted,
+            run,
+        })
+    }
+
+    /// Write every event satisfying condition to an event log at path, returning the filter ID
+    #[cfg(feature = "io")]
+    pub fn add_filter(
+        &mut self,
+        condition: GateCondition,
+        path: &Path,
+    ) -> Result<Uuid, ResourceError> {
+        if let Some(id) = condition
+            .get_cut_ids()
+            .iter()
+            .find(|id| !self.cut_exists(id))
+        {
+            return Err(ResourceError::InvalidCutID(*id));
+        }
+        let id = Uuid::new_v4();
+        self.filters
+            .insert(id, EventFilter::create(id, condition, path)?);
+        Ok(id)
+    }
+
+    /// Flush and close a filter, returning the number of events it wrote
+    pub fn remove_filter(&mut self, id: &Uuid) -> Result<usize, ResourceError> {
+        match self.filters.remove(id) {
+            Some(filter) => Ok(filter.finish()?),
+            None => Err(ResourceError::InvalidFilterID(*id)),
+        }
+    }
+
+    /// Write the processed events to a Parquet file, after aliases, transforms, and
+    /// calibrations, returning the sink ID. See the parquet module for the columns written.
+    #[cfg(feature = "parquet")]
+    pub fn add_parquet_sink(&mut self, spec: &ParquetSpec) -> Result<Uuid, ResourceError> {
+        if let Some(id) = spec
+            .condition
+            .iter()
+            .flat_map(|condition| condition.get_cut_ids())
+            .find(|id| !self.cut_exists(id))
+        {
+            return Err(ResourceError::InvalidCutID(*id));
+        }
+        let id = Uuid::new_v4();
+        self.sinks.insert(id, ParquetSink::create(spec)?);
+        Ok(id)
+    }
+
+    /// Finish the file of a Parquet sink, returning the number of events it wrote
+    #[cfg(feature = "parquet")]
+    pub fn remove_parquet_sink(&mut self, id: &Uuid) -> Result<usize, ResourceError> {
+        match self.sinks.remove(id) {
+            Some(sink) => Ok(sink.finish()?),
+            None => Err(ResourceError::InvalidSinkID(*id)),
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    fn has_parquet_sinks(&self) -> bool {
+        !self.sinks.is_empty()
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    fn has_parquet_sinks(&self) -> bool {
+        false
+    }
+
+    /// Rename a raw variable of every event to a canonical name, e.g. "adc_17" to "si_3_e", so
+    /// that histograms booked on canonical names work with any DAQ setup. Aliases are applied
+    /// after recording and before the transforms, replacing any alias of the same raw variable.
+    pub fn set_alias(&mut self, alias: Alias) {
+        self.aliases.set(alias);
+    }
+
+    pub fn get_alias(&self, raw: &str) -> Result<&Alias, ResourceError> {
+        self.aliases
+            .get(raw)
+            .ok_or_else(|| ResourceError::InvalidAlias(raw.to_string()))
+    }
+
+    pub fn list_aliases(&self) -> Vec<&Alias> {
+        self.aliases.iter().collect()
+    }
+
+    pub fn remove_alias(&mut self, raw: &str) -> Result<(), ResourceError> {
+        if self.aliases.remove(raw) {
+            Ok(())
+        } else {
+            Err(ResourceError::InvalidAlias(raw.to_string()))
+        }
+    }
+
+    /// Append a stage to the transform pipeline, which runs on every event after recording and
+    /// before cuts and fills. Stages run in the order they were added.
+    pub fn add_transform(&mut self, transform: Box<dyn EventTransform>) -> Uuid {
+        self.transforms.add(transform)
+    }
+
+    pub fn remove_transform(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        if self.transforms.remove(id) {
+            Ok(())
+        } else {
+            Err(ResourceError::InvalidTransformID(*id))
+        }
+    }
+
+    /// Calibrate a variable of every event, after the transforms and before cuts and fills,
+    /// replacing any calibration with the same output. Taking effect from the next event, this
+    /// recalibrates a detector on the fly.
+    pub fn set_calibration(&mut self, spec: CalibrationSpec) {
+        self.calibrations.set(spec);
+    }
+
+    pub fn get_calibration(&self, output: &str) -> Result<&CalibrationSpec, ResourceError> {
+        self.calibrations
+            .get(output)
+            .ok_or_else(|| ResourceError::InvalidCalibration(output.to_string()))
+    }
+
+    pub fn list_calibrations(&self) -> Vec<&CalibrationSpec> {
+        self.calibrations.iter().collect()
+    }
+
+    pub fn remove_calibration(&mut self, output: &str) -> Result<(), ResourceError> {
+        if self.calibrations.remove(output) {
+            Ok(())
+        } else {
+            Err(ResourceError::InvalidCalibration(output.to_string()))
+        }
+    }
+
+    /// Fit a calibration of the x variable of a histogram to the centroids of peaks found in it
+    /// and their known energies, e.g. the lines of a 152Eu source, and calibrate events into
+    /// output with it. Returns the fit, whose residuals show how well the peaks agree.
+    pub fn calibrate_histogram(
+        &mut self,
+        histogram_id: &Uuid,
+        output: &str,
+        centroids: &[f64],
+        energies: &[f64],
+        order: usize,
+    ) -> Result<CalibrationFit, ResourceError> {
+        let gram = self
+            .histograms
+            .get(histogram_id)
+            .ok_or(ResourceError::InvalidHistogramID(*histogram_id))?;
+        let input = gram.spec.x_axis.variable.clone();
+        let fit = calibration::fit_calibration(centroids, energies, order)?;
+        self.set_calibration(CalibrationSpec {
+            input,
+            output: output.to_string(),
+            calibration: fit.calibration.clone(),
+        });
+        Ok(fit)
+    }
+
+    /// Gain match 1D histograms to a reference by the peaks in regions, see
+    /// calibration::gain_match, and calibrate the x variable of each into the output pattern
+    /// with every "{variable}" replaced by the x variable. Nothing is installed unless every
+    /// histogram matches. Returns the fit of each histogram, by ID.
+    pub fn gain_match(
+        &mut self,
+        histogram_ids: &[Uuid],
+        reference_id: &Uuid,
+        regions: &[PeakRegion],
+        output_pattern: &str,
+    ) -> Result<Vec<(Uuid, CalibrationFit)>, ResourceError> {
+        let reference = self
+            .histograms
+            .get(reference_id)
+            .ok_or(ResourceError::InvalidHistogramID(*reference_id))?;
+        let mut fits = vec![];
+        let mut specs = vec![];
+        for id in histogram_ids.iter() {
+            let gram = self
+                .histograms
+                .get(id)
+                .ok_or(ResourceError::InvalidHistogramID(*id))?;
+            let fit = calibration::gain_match(gram, reference, regions)?;
+            let input = gram.spec.x_axis.variable.clone();
+            specs.push(CalibrationSpec {
+                output: output_pattern.replace("{variable}", &input),
+                input,
+                calibration: fit.calibration.clone(),
+            });
+            fits.push((*id, fit));
+        }
+        for spec in specs {
+            self.set_calibration(spec);
+        }
+        Ok(fits)
+    }
+
+    /// Count the events passing a cut, or with a variable, against event time, returning the ID
+    /// of the rate meter. Events are counted after the transforms and calibrations, at their
+    /// timestamp, or the time they arrived if they have none.
+    pub fn add_rate(&mut self, mut spec: RateSpec) -> Uuid {
+        if spec.id.is_nil() {
+            spec.id = Uuid::new_v4();
+        }
+        let id = spec.id;
+        self.rates.insert(id, RateMeter::new(spec));
+        self.bump_generation();
+        id
+    }
+
+    pub fn get_rate(&self, id: &Uuid) -> Result<&RateMeter, ResourceError> {
+        self.rates.get(id).ok_or(ResourceError::InvalidRateID(*id))
+    }
+
+    pub fn list_rates(&self) -> Vec<&RateSpec> {
+        self.rates.values().map(|rate| rate.get_spec()).collect()
+    }
+
+    pub fn remove_rate(&mut self, id: &Uuid) -> Result<(), ResourceError> {
+        match self.rates.remove(id) {
+            Some(_) => {
+                self.bump_generation();
+                Ok(())
+            }
+            None => Err(ResourceError::InvalidRateID(*id)),
+        }
+    }
+
+    /// Begin a new run. Histograms with ClearPolicy::OnNewRun are cleared, as are rate meters.
+    pub fn begin_run(
+        &mut self,
+        number: u32,
+        metadata: FxHashMap<String, String>,
+    ) -> Result<&RunInfo, ResourceError> {
+        self.runs.begin(number, metadata)?;
+        self.run_start_timestamp = None;
+        for gram in self.histograms.values_mut() {
+            if gram.spec.clear_policy == ClearPolicy::OnNewRun {
+                gram.clear();
+            }
+        }
+        for rate in self.rates.values_mut() {
+            rate.clear();
+        }
+        self.bump_generation();
+        Ok(self.runs.get_current().expect("Run was just started"))
+    }
+
+    /// Stop accepting events until the run is resumed
+    pub fn pause_run(&mut self) -> Result<(), ResourceError> {
+        Ok(self.runs.pause()?)
+    }
+
+    pub fn resume_run(&mut self) -> Result<(), ResourceError> {
+        Ok(self.runs.resume()?)
+    }
+
+    /// End the current run. Events are rejected until the next run begins.
+    pub fn end_run(&mut self) -> Result<RunInfo, ResourceError> {
+        let run = self.runs.end()?;
+        if let Some(pattern) = &self.snapshot_pattern {
+            let snapshots = self
+                .histograms
+                .values()
+                .filter(|gram| pattern::matches(pattern, &gram.spec.name))
+                .cloned()
+                .collect();
+            self.run_snapshots.insert(run.number, snapshots);
+        }
+        Ok(run)
+    }
+
+    /// Copy every histogram whose name matches pattern when each run ends, so that runs can be
+    /// compared afterwards. None stops taking snapshots; those already taken are kept.
+    pub fn set_run_snapshots(&mut self, pattern: Option<&str>) {
+        self.snapshot_pattern = pattern.map(str::to_string);
+    }
+
+    /// Run numbers with snapshots, in ascending order
+    pub fn list_run_snapshots(&self) -> Vec<u32> {
+        self.run_snapshots.keys().copied().collect()
+    }
+
+    pub fn get_run_snapshot(&self, run: u32, name: &str) -> Result<&Histogram, ResourceError> {
+        self.run_snapshots
+            .get(&run)
+            .and_then(|snapshots| snapshots.iter().find(|gram| gram.spec.name == name))
+            .ok_or_else(|| ResourceError::MissingSnapshot(run, name.to_string()))
+    }
+
+    /// Remove the snapshots of a run, e.g. to export them or to bound memory in long campaigns
+    pub fn take_run_snapshots(&mut self, run: u32) -> Vec<Histogram> {
+        self.run_snapshots.remove(&run).unwrap_or_default()
+    }
+
+    /// Get the snapshot of a histogram from run minus its snapshot from baseline_run
+    pub fn get_run_difference(
+        &self,
+        name: &str,
+        baseline_run: u32,
+        run: u32,
+    ) -> Result<Histogram, ResourceError> {
+        let mut difference = self.get_run_snapshot(run, name)?.clone();
+        difference.subtract(self.get_run_snapshot(baseline_run, name)?)?;
+        Ok(difference)
+    }
+
+    pub fn get_run_state(&self) -> RunState {
+        self.runs.get_state()
+    }
+
+    /// The current run, or the last completed one, for stamping exports
+    pub fn get_run_info(&self) -> Option<&RunInfo> {
+        self.runs.get_latest()
+    }
+
+    pub fn get_run_history(&self) -> &[RunInfo] {
+        self.runs.get_history()
+    }
+
+    pub fn update(&mut self, data: DataBlob) -> Result<(), ResourceError> {
+        let synced = self.sync_cuts();
+        let result = self.process_event(data).map(|_| ());
+        self.refresh_derived_on_update();
+        synced.and(result)
+    }
+
+    /// Process an event as with update, then return the blob to a pool for the source to refill
+    pub fn update_pooled(&mut self, data: DataBlob, pool: &BlobPool) -> Result<(), ResourceError> {
+        let synced = self.sync_cuts();
+        let result = self.process_event(data).map(|data| {
+            if let Some(data) = data {
+                pool.put(data);
+            }
+        });
+        self.refresh_derived_on_update();
+        synced.and(result)
+    }
+
+    /// Start or stop measuring where the time processing events goes. Profiling reads the clock
+    /// several times per histogram per event, so it is off by default.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        match (enabled, &self.profiler) {
+            (true, None) => self.profiler = Some(Profiler::default()),
+            (false, _) => self.profiler = None,
+            _ => (),
+        }
+    }
+
+    /// Get the performance counters since profiling started or was reset, if profiling
+    pub fn get_perf_report(&self) -> Option<PerfReport> {
+        self.profiler.as_ref().map(|profiler| profiler.get_report())
+    }
+
+    pub fn reset_perf(&mut self) {
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::default());
+        }
+    }
+
+    // Process an event, giving back the blob unless a transform dropped it
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(event = self.n_events))
+    )]
+    fn process_event(&mut self, data: DataBlob) -> Result<Option<DataBlob>, ResourceError> {
+        let mut watch = Stopwatch::new(self.profiler.is_some());
+        let mut times = PerfReport::default();
+        let result = self.process_timed_event(data, &mut watch, &mut times);
+        if let Some(profiler) = &mut self.profiler {
+            times.busy = watch.get_total();
+            profiler.add(&times);
+        }
+        result
+    }
+
+    fn process_timed_event(
+        &mut self,
+        mut data: DataBlob,
+        watch: &mut Stopwatch,
+        times: &mut PerfReport,
+    ) -> Result<Option<DataBlob>, ResourceError> {
+        if !self.runs.accept_event() {
+            self.n_rejected += 1;
+            return Ok(Some(data));
+        }
+        self.n_events += 1;
+        times.events = 1;
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(&data)?;
+        }
+        times.recording += watch.lap();
+
+        if !self.aliases.is_empty() {
+            self.aliases.apply(&mut data);
+        }
+        let data = self.transforms.run(data);
+        let Some(mut data) = data else {
+            times.transforms += watch.lap();
+            return Ok(None);
+        };
+        if !self.calibrations.is_empty() {
+            self.calibrations.apply(&mut data);
+        }
+        let run_start = self.stamp_event(&mut data);
+        times.transforms += watch.lap();
+
+        // Cuts are only evaluated when a filter or histogram asks for them
+        let mut cuts = CutEvaluation::new(
+            &mut self.cuts,
+            &self.cut_bindings,
+            &self.compound_cuts,
+            &mut self.evaluated_cuts,
+            &data,
+        );
+        for filter in self.filters.values_mut() {
+            filter.process(&data, &mut cuts)?;
+        }
+        #[cfg(feature = "parquet")]
+        for sink in self.sinks.values_mut() {
+            sink.process(&data, &mut cuts)?;
+        }
+        times.filters += watch.lap();
+
+        if let Some(timestamp) = data.get_timestamp() {
+            for rate in self.rates.values_mut() {
+                let spec = rate.get_spec();
+                let has_variable = spec.variable.as_ref().is_none_or(|variable| {
+                    data.find(variable).is_some() || data.find_array(variable).is_some()
+                });
+                if has_variable && spec.cut_id.is_none_or(|id| cuts.check(&id) != Some(false)) {
+                    rate.count(timestamp);
+                }
+            }
+        }
+
+        let notify_fills = self.observers.is_listening(EventKind::Fill);
+        let mut changed = false;
+        let mut error = None;
+        for gram in self.histograms.values_mut() {
+            let generation = gram.get_generation();
+            let result = Self::offer_event(
+                gram,
+                &data,
+                run_start,
+                &mut cuts,
+                notify_fills.then_some(&mut self.observers),
+                watch,
+                times,
+            );
+            changed |= gram.get_generation() != generation;
+            // Every histogram is still offered the event, so only the first error is kept
+            if error.is_none() {
+                error = result.err().map(|e| (e, gram.spec.name.clone()));
+            }
+        }
+        for (id, inside) in self.evaluated_cuts.iter() {
+            let stats = self.cut_stats.entry(*id).or_default();
+            stats.evaluated += 1;
+            stats.accepted += u64::from(*inside);
+        }
+        if changed {
+            self.bump_generation();
+        }
+        match error {
+            Some((e, name)) => Err(ResourceError::from(e).with_context("fill", &name)),
+            None => Ok(Some(data)),
+        }
+    }
+
+    /// Fill histograms from a batch of events stored as columns. Histograms on plain variables,
+    /// gated by nothing or by 1D and 2D cuts, their bindings, and compounds of them, are binned a
+    /// column at a time. Other histograms are filled event by event, as is everything when
+    /// aliases, transforms, calibrations, filters, Parquet sinks, rate meters, recording, or fill
+    /// observers need whole events.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(rows = batch.get_n_rows()))
+    )]
+    pub fn update_batch(&mut self, batch: &ColumnBatch) -> Result<(), ResourceError> {
+        let synced = self.sync_cuts();
+        let mut watch = Stopwatch::new(self.profiler.is_some());
+        let mut times = PerfReport::default();
+        let result = self.update_timed_batch(batch, &mut watch, &mut times);
+        if let Some(profiler) = &mut self.profiler {
+            times.busy = watch.get_total();
+            profiler.add(&times);
+        }
+        self.refresh_derived_on_update();
+        synced.and(result)
+    }
+
+    fn update_timed_batch(
+        &mut self,
+        batch: &ColumnBatch,
+        watch: &mut Stopwatch,
+        times: &mut PerfReport,
+    ) -> Result<(), ResourceError> {
+        let n_rows = batch.get_n_rows();
+        if !self.aliases.is_empty()
+            || !self.transforms.is_empty()
+            || !self.calibrations.is_empty()
+            || !self.filters.is_empty()
+            || !self.rates.is_empty()
+            || self.recorder.is_some()
+            || self.has_parquet_sinks()
+            || self.observers.is_listening(EventKind::Fill)
+        {
+            for row in 0..n_rows {
+                let data = batch.get_row(row);
+                times.lookups += watch.lap();
+                self.process_timed_event(data, watch, times)?;
+            }
+            return Ok(());
+        }
+        if !self.runs.accept_events(n_rows as u64) {
+            self.n_rejected += n_rows as u64;
+            return Ok(());
+        }
+        self.n_events += n_rows as u64;
+        times.events = n_rows as u64;
+
+        let mut masks = FxHashMap::default();
+        let mut by_event = vec![];
+        let mut changed = false;
+        let ids: Vec<Uuid> = self.histograms.keys().copied().collect();
+        for id in ids.iter() {
+            let Some(gram) = self.histograms.get(id) else {
+                continue;
+            };
+            let spec = &gram.spec;
+            let is_plain = |axis: &AxisSpec| {
+                !pattern::is_pattern(&axis.variable) && axis.variable != TIMESTAMP_VARIABLE
+            };
+            // Errors from bad values are found event by event
+            if !is_plain(&spec.x_axis)
+                || !spec.y_axis.as_ref().is_none_or(is_plain)
+                || spec.fill_mode != FillMode::Value
+                || spec.nan_policy == ValuePolicy::Error
+                || spec.missing_policy == ValuePolicy::Error
+                // Event windows advance once per event
+                || spec.window.is_some()
+            {
+                by_event.push(*id);
+                continue;
+            }
+            times.lookups += watch.lap();
+            let mut gate = vec![true; n_rows];
+            let mut is_columnar = true;
+            // Cuts which do not exist never reject events
+            for cut_id in spec.cuts_to_check.iter().filter(|id| self.cut_exists(id)) {
+                match self.get_batch_mask(cut_id, batch, &mut masks) {
+                    Some(mask) => gate.iter_mut().zip(mask).for_each(|(gate, inside)| {
+                        *gate &= inside;
+                    }),
+                    None => {
+                        is_columnar = false;
+                        break;
+                    }
+                }
+            }
+            times.cuts += watch.lap();
+            if !is_columnar {
+                by_event.push(*id);
+                continue;
+            }
+
+            let x_column = batch.get_column(&spec.x_axis.variable);
+            let y_column = spec
+                .y_axis
+                .as_ref()
+                .map(|axis| batch.get_column(&axis.variable));
+            let is_present = |row: usize| {
+                x_column.is_some_and(|column| column.is_valid(row))
+                    && y_column.is_none_or(|column| column.is_some_and(|c| c.is_valid(row)))
+            };
+            let fill: Vec<bool> = (0..n_rows)
+                .map(|row| gate[row] && is_present(row))
+                .collect();
+            let n_rejected = gate.iter().filter(|inside| !**inside).count() as u64;
+            let n_filled = fill.iter().filter(|fill| **fill).count() as u64;
+            let Some(gram) = self.histograms.get_mut(id) else {
+                continue;
+            };
+            gram.stats.offered += n_rows as u64;
+            gram.stats.rejected_by_cuts += n_rejected;
+            if gram.spec.missing_policy != ValuePolicy::Skip {
+                gram.stats.missing_variables += n_rows as u64 - n_rejected - n_filled;
+            }
+            if let Some(x_column) = x_column.filter(|_| n_filled > 0) {
+                let generation = gram.get_generation();
+                let y_values = y_column.flatten().map(|column| column.values);
+                gram.fill_columns(x_column.values, y_values, &fill)?;
+                changed |= gram.get_generation() != generation;
+            }
+            times.fills += watch.lap();
+        }
+        for (id, mask) in masks.iter() {
+            if let Some(mask) = mask {
+                let stats = self.cut_stats.entry(*id).or_default();
+                stats.evaluated += n_rows as u64;
+                stats.accepted += mask.iter().filter(|inside| **inside).count() as u64;
+            }
+        }
+
+        let mut error = None;
+        for row in (0..n_rows).filter(|_| !by_event.is_empty()) {
+            let mut data = batch.get_row(row);
+            let run_start = self.stamp_event(&mut data);
+            times.lookups += watch.lap();
+            let mut cuts = CutEvaluation::new(
+                &mut self.cuts,
+                &self.cut_bindings,
+                &self.compound_cuts,
+                &mut self.evaluated_cuts,
+                &data,
+            );
+            for id in by_event.iter() {
+                if let Some(gram) = self.histograms.get_mut(id) {
+                    let generation = gram.get_generation();
+                    let result =
+                        Self::offer_event(gram, &data, run_start, &mut cuts, None, watch, times);
+                    changed |= gram.get_generation() != generation;
+                    if error.is_none() {
+                        error = result.err().map(|e| (e, gram.spec.name.clone()));
+                    }
+                }
+            }
+            for (id, inside) in self.evaluated_cuts.iter() {
+                let stats = self.cut_stats.entry(*id).or_default();
+                stats.evaluated += 1;
+                stats.accepted += u64::from(*inside);
+            }
+        }
+        if changed {
+            self.bump_generation();
+        }
+        match error {
+            Some((e, name)) => Err(ResourceError::from(e).with_context("fill", &name)),
+            None => Ok(()),
+        }
+    }
+
+    // Evaluate a cut on every row of a batch, or None if the cut can't be evaluated on columns.
+    // Results are kept in masks for other histograms gated by the same cut.
+    fn get_batch_mask(
+        &self,
+        id: &Uuid,
+        batch: &ColumnBatch,
+        masks: &mut FxHashMap<Uuid, Option<Vec<bool>>>,
+    ) -> Option<Vec<bool>> {
+        if let Some(mask) = masks.get(id) {
+            return mask.clone();
+        }
+        let n_rows = batch.get_n_rows();
+        let mask = if let Some(cut) = self.cuts.get(id) {
+            Self::get_shape_mask(cut.as_ref(), cut.get_spec(), batch)
+        } else if let Some(binding) = self.cut_bindings.get(id) {
+            match self.cuts.get(&binding.shape_id) {
+                Some(shape) => Self::get_shape_mask(shape.as_ref(), &binding.spec, batch),
+                None => Some(vec![false; n_rows]),
+            }
+        } else if let Some(compound) = self.compound_cuts.get(id) {
+            // Failing while in progress, as in CutEvaluation
+            masks.insert(*id, Some(vec![false; n_rows]));
+            let members: Option<Vec<Vec<bool>>> = compound
+                .condition
+                .get_cut_ids()
+                .iter()
+                .map(|member| self.get_batch_mask(member, batch, masks))
+                .collect();
+            members.map(|members| {
+                (0..n_rows)
+                    .map(|row| match compound.condition {
+                        GateCondition::All(_) => members.iter().all(|mask| mask[row]),
+                        GateCondition::Any(_) => members.iter().any(|mask| mask[row]),
+                    })
+                    .collect()
+            })
+        } else {
+            // Compound cuts are never satisfied by cuts which do not exist
+            Some(vec![false; n_rows])
+        };
+        masks.insert(*id, mask.clone());
+        mask
+    }
+
+    // Test the columns of the variables in spec against the shape of a cut
+    fn get_shape_mask(cut: &dyn Cut, spec: &CutSpec, batch: &ColumnBatch) -> Option<Vec<bool>> {
+        // Only cuts which can test single points work on columns
+        cut.contains_point(0.0, spec.y_variable.as_ref().map(|_| 0.0))?;
+        let x_column = batch.get_column(&spec.x_variable);
+        let y_column = spec
+            .y_variable
+            .as_ref()
+            .map(|variable| batch.get_column(variable));
+        let mask = (0..batch.get_n_rows())
+            .map(|row| {
+                let Some(x) = x_column.and_then(|column| column.get(row)) else {
+                    return false;
+                };
+                let y = match y_column {
+                    Some(column) => match column.and_then(|column| column.get(row)) {
+                        Some(y) => Some(y),
+                        None => return false,
+                    },
+                    None => None,
+                };
+                cut.contains_point(x, y).unwrap_or(false)
+            })
+            .collect();
+        Some(mask)
+    }
+
+    /// Get the number of events passed to update while accepting data, including events
+    /// dropped by transforms
+    pub fn get_event_count(&self) -> u64 {
+        self.n_events
+    }
+
+    /// Get how often a cut, binding, or compound cut has been evaluated and passed. Cuts are only
+    /// evaluated for events where a histogram or filter asks about them.
+    pub fn get_cut_stats(&self, id: &Uuid) -> Result<CutStats, ResourceError> {
+        if !self.cut_exists(id) {
+            return Err(ResourceError::InvalidCutID(*id));
+        }
+        Ok(self.cut_stats.get(id).copied().unwrap_or_default())
+    }
+
+    /// Get the stats of every cut which has been evaluated, with the name of the cut
+    pub fn list_cut_stats(&self) -> Vec<(String, CutStats)> {
+        self.cut_stats
+            .iter()
+            .filter(|(id, _)| self.cut_exists(id))
+            .map(|(id, stats)| (self.get_cut_name(id), *stats))
+            .collect()
+    }
+
+    // Whether a rate meter or time axis needs every event to have a timestamp
+    fn needs_timestamps(&self) -> bool {
+        let is_time = |axis: &AxisSpec| axis.variable == TIMESTAMP_VARIABLE;
+        !self.rates.is_empty()
+            || self.histograms.values().any(|gram| {
+                is_time(&gram.spec.x_axis) || gram.spec.y_axis.as_ref().is_some_and(is_time)
+            })
+    }
+
+    // Give an event without a timestamp the time on the wall clock if one is needed, and get
+    // the timestamp of the first event of the run. Only time axes read it, which make every
+    // event timestamped.
+    fn stamp_event(&mut self, data: &mut DataBlob) -> f64 {
+        if data.get_timestamp().is_none() && self.needs_timestamps() {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            data.set_timestamp(now);
+        }
+        match data.get_timestamp() {
+            Some(timestamp) => *self.run_start_timestamp.get_or_insert(timestamp),
+            None => self.run_start_timestamp.unwrap_or_default(),
+        }
+    }
+
+    // Gate and fill a single histogram with an event, evaluating its gates until one fails, and
+    // tell the observers of each bin filled if given
+    fn offer_event(
+        gram: &mut Histogram,
+        data: &DataBlob,
+        run_start: f64,
+        cuts: &mut CutEvaluation,
+        mut fill_observers: Option<&mut Observers>,
+        watch: &mut Stopwatch,
+        times: &mut PerfReport,
+    ) -> Result<(), HistogramError> {
+        gram.stats.offered += 1;
+        gram.advance_window();
+        let is_gated_out = gram
+            .spec
+            .cuts_to_check
+            .iter()
+            .any(|cut_id| cuts.check(cut_id) == Some(false));
+        times.cuts += watch.lap();
+        if is_gated_out {
+            gram.stats.rejected_by_cuts += 1;
+            return Ok(());
+        }
+
+        let values = Self::bind_variables(&gram.spec, data, run_start);
+        times.lookups += watch.lap();
+        if values.is_empty() {
+            return match gram.spec.missing_policy {
+                ValuePolicy::Skip => Ok(()),
+                ValuePolicy::Count => {
+                    gram.stats.missing_variables += 1;
+                    Ok(())
+                }
+                ValuePolicy::Error => {
+                    gram.stats.missing_variables += 1;
+                    Err(HistogramError::MissingVariable(gram.spec.name.clone()))
+                }
+            };
+        }
+        let mut error = None;
+        for (x_val, y_val) in values {
+            match gram.fill(x_val, y_val) {
+                Ok(Some(bin)) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(histogram = %gram.spec.name, bin, "filled");
+                    if let Some(observers) = fill_observers.as_deref_mut() {
+                        observers.notify(ManagerEvent::Filled {
+                            histogram_id: gram.spec.id,
+                            bin,
+                        });
+                    }
+                }
+                Ok(None) => (),
+                Err(e @ HistogramError::NonFinite(_)) => error = Some(e),
+                #[cfg(feature = "tracing")]
+                Err(e) => tracing::trace!(histogram = %gram.spec.name, "not filled: {e}"),
+                #[cfg(not(feature = "tracing"))]
+                Err(_) => (),
+            }
+        }
+        times.fills += watch.lap();
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Find the values to fill a histogram with from an event. Axis variables may be glob
+    /// patterns, in which case every matching variable is used, or arrays, in which case every
+    /// entry is used. When both axes are multi-valued, x and y values are paired by what their
+    /// wildcards matched or by array index, so "sipm_*_energy" against "sipm_*_time" pairs the
+    /// energy and time of each channel. Bit mask histograms get one x value per set bit, and
+    /// summary histograms one value per channel matching the y variable. Axes on
+    /// TIMESTAMP_VARIABLE get the time of the event since their origin.
+    fn bind_variables(spec: &HistSpec, data: &DataBlob, run_start: f64) -> Vec<(f32, Option<f32>)> {
+        let is_multi =
+            |variable: &str| pattern::is_pattern(variable) || data.find_array(variable).is_some();
+        let find_all = |variable: &str| -> Vec<(Vec<String>, f32)> {
+            if let Some(values) = data.find_array(variable) {
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, value)| (vec![idx.to_string()], *value))
+                    .collect()
+            } else if pattern::is_pattern(variable) {
+                data.find_all(variable)
+                    .into_iter()
+                    .filter_map(|(name, value)| {
+                        pattern::captures(variable, name).map(|captured| {
+                            (captured.into_iter().map(String::from).collect(), value)
+                        })
+                    })
+                    .collect()
+            } else {
+                data.find(variable)
+                    .map(|value| vec![(vec![], *value)])
+                    .unwrap_or_default()
+            }
+        };
+        let find_axis = |axis: &AxisSpec| -> Vec<(Vec<String>, f32)> {
+            if axis.variable != TIMESTAMP_VARIABLE {
+                return find_all(&axis.variable);
+            }
+            let origin = match axis.time_origin {
+                None => 0.0,
+                Some(TimeOrigin::RunStart) => run_start,
+                Some(TimeOrigin::Fixed(origin)) => origin,
+            };
+            data.get_timestamp()
+                .map(|timestamp| vec![(vec![], (timestamp - origin) as f32)])
+                .unwrap_or_default()
+        };
+        let x_values = match spec.fill_mode {
+            FillMode::Value | FillMode::Symmetric => find_axis(&spec.x_axis),
+            FillMode::Summary => {
+                let Some(y_axis) = &spec.y_axis else {
+                    return vec![];
+                };
+                return find_all(&y_axis.variable)
+                    .into_iter()
+                    .filter_map(|(captured, value)| {
+                        let channel: f32 = captured.first()?.parse().ok()?;
+                        Some((channel, Some(value)))
+                    })
+                    .collect();
+            }
+            FillMode::BitMask => {
+                let words = match data.find_flag(&spec.x_axis.variable) {
+                    Some(bits) => vec![(vec![], bits)],
+                    None => find_all(&spec.x_axis.variable)
+                        .into_iter()
+                        .filter(|(_, value)| *value >= 0.0 && value.is_finite())
+                        .map(|(captured, value)| (captured, value as u64))
+                        .collect(),
+                };
+                words
+                    .into_iter()
+                    .flat_map(|(captured, bits)| {
+                        (0..u64::BITS as usize)
+                            .filter(move |bit| (bits >> bit) & 1 == 1)
+                            .map(move |bit| (captured.clone(), spec.x_axis.get_bin_center(bit)))
+                    })
+                    .collect()
+            }
+        };
+        let y_axis = match &spec.y_axis {
+            Some(y_axis) => y_axis,
+            None => return x_values.into_iter().map(|(_, x)| (x, None)).collect(),
+        };
+        let y_values = find_axis(y_axis);
+        let paired = is_multi(&spec.x_axis.variable) && is_multi(&y_axis.variable);
+        let is_symmetric = spec.fill_mode == FillMode::Symmetric;
+        let mut values = vec![];
+        if is_symmetric && spec.x_axis.variable == y_axis.variable {
+            for (idx, (_, x)) in x_values.iter().enumerate() {
+                for (_, y) in x_values.iter().skip(idx + 1) {
+                    values.push((*x, Some(*y)));
+                }
+            }
+        } else {
+            for (x_captured, x) in x_values.iter() {
+                for (y_captured, y) in y_values.iter() {
+                    if !paired || x_captured == y_captured {
+                        values.push((*x, Some(*y)));
+                    }
+                }
+            }
+        }
+        if is_symmetric && spec.layout != BinLayout::UpperTriangle {
+            let mirrored: Vec<_> = values
+                .iter()
+                .filter_map(|(x, y)| y.map(|y| (y, Some(*x))))
+                .collect();
+            values.extend(mirrored);
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::histogram::{OutOfRangePolicy, RollingWindow};
+    use crate::source::IterSource;
+
+    #[test]
+    fn test_managed_histogram() {
+        let mut manager = ResourceManager::new();
+        let spec1 = HistSpec::new(
+            "test1",
+            AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+            None,
+        );
+        let spec2 = HistSpec::new(
+            "test2",
+            AxisSpec::new("var", "var", 600, 0.0, 600.0).unwrap(),
+            Some(AxisSpec::new("var2", "var2", 600, 0.0, 600.0).unwrap()),
+        );
+
+        manager.add_histogram(spec1.clone()).unwrap();
+        manager.add_histogram(spec2.clone()).unwrap();
+
+        let spec_test = manager.get_histogram_spec(&spec1.id);
+        assert!(spec_test.is_ok());
+        assert!(manager.get_histogram_spec(&Uuid::new_v4()).is_err());
+        assert_eq!(*spec_test.unwrap(), spec1);
+
+        assert!(manager.remove_histogram(&spec1.id).is_ok());
+        let spec_test = manager.get_histogram_spec(&spec2.id);
+        assert!(spec_test.is_ok());
+        assert!(manager.get_histogram_spec(&spec1.id).is_err());
+        assert_eq!(*spec_test.unwrap(), spec2);
+
+        manager.remove_histogram(&spec2.id).unwrap();
+        assert_eq!(manager.histograms.len(), 0);
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("{}.spectlog", Uuid::new_v4()));
+        let mut manager = ResourceManager::new();
+        manager.record_to(&path).unwrap();
+        for value in [1.5, 2.5, 2.7] {
+            let mut blob = DataBlob::new();
+            blob.insert("var", value);
+            manager.update(blob).unwrap();
+        }
+        assert_eq!(manager.stop_recording().unwrap(), 3);
+
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+            None,
+        );
+        let mut replay = ResourceManager::new();
+        replay.add_histogram(spec.clone()).unwrap();
+        assert_eq!(replay.replay_from(&path).unwrap(), 3);
+        let data = replay.get_histogram_data(&spec.id).unwrap();
+        assert_eq!(data[1], 1.0);
+        assert_eq!(data[2], 2.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_filtered_output() {
+        let path = std::env::temp_dir().join(format!("{}.spectlog", Uuid::new_v4()));
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+            None,
+        );
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("gate"),
+            x_variable: String::from("var"),
+            y_variable: None,
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager.add_cut_1d(cut.clone(), 2.0, 4.0, &spec.id).unwrap();
+        assert!(
+            manager
+                .add_filter(GateCondition::All(vec![Uuid::new_v4()]), &path)
+                .is_err()
+        );
+        let filter = manager
+            .add_filter(GateCondition::All(vec![cut.id]), &path)
+            .unwrap();
+        for value in [1.0, 2.5, 3.5, 5.0] {
+            let mut blob = DataBlob::new();
+            blob.insert("var", value);
+            manager.update(blob).unwrap();
+        }
+        assert_eq!(manager.remove_filter(&filter).unwrap(), 2);
+        assert!(manager.remove_filter(&filter).is_err());
+
+        let values: Vec<f32> = EventReader::open(&path)
+            .unwrap()
+            .map(|blob| *blob.unwrap().find("var").unwrap())
+            .collect();
+        assert_eq!(values, vec![2.5, 3.5]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_sink() {
+        use crate::schema::VariableKind;
+        use ::parquet::file::reader::{FileReader, SerializedFileReader};
+        use ::parquet::record::Field;
+
+        let path = std::env::temp_dir().join(format!("{}.parquet", Uuid::new_v4()));
+        let mut manager = ResourceManager::new();
+        manager.add_transform(Box::new(|mut blob: DataBlob| {
+            let raw = *blob.find("raw")?;
+            blob.insert("energy", 2.0 * raw);
+            Some(blob)
+        }));
+        let schema = Schema::new().with("energy", VariableKind::Value);
+        let sink = manager
+            .add_parquet_sink(&ParquetSpec::new(path.clone(), schema))
+            .unwrap();
+        let mut batch = ColumnBatch::new(2);
+        batch.add_column("raw", &[1.0, 2.5], None).unwrap();
+        manager.update_batch(&batch).unwrap();
+        assert_eq!(manager.remove_parquet_sink(&sink).unwrap(), 2);
+        assert!(manager.remove_parquet_sink(&sink).is_err());
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let energies: Vec<Field> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().get_column_iter().last().unwrap().1.clone())
+            .collect();
+        assert_eq!(energies, vec![Field::Float(2.0), Field::Float(5.0)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_event_window() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            window: Some(RollingWindow::Events {
+                count: 2,
+                buckets: 1,
+            }),
+            ..HistSpec::new(
+                "recent",
+                AxisSpec::new("ch_*", "ch", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let mut manager = ResourceManager::new();
+        let id = manager.add_histogram(spec).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("ch_0", 0.5);
+        blob.insert("ch_1", 0.5);
+        // Two fills per event, but the window counts events
+        manager.update(blob.clone()).unwrap();
+        manager.update(blob.clone()).unwrap();
+        assert_eq!(manager.get_histogram_data(&id).unwrap()[0], 4.0);
+        manager.update(blob).unwrap();
+        assert_eq!(manager.get_histogram_data(&id).unwrap()[0], 2.0);
+    }
+
+    #[test]
+    fn test_merge_managers() {
+        let spec = HistSpec::new(
+            "test",
+            AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+            None,
+        );
+        let mut other_spec = spec.clone();
+        other_spec.id = Uuid::new_v4();
+
+        let mut manager = ResourceManager::new();
+        let mut worker = ResourceManager::new();
+        manager.add_histogram(spec.clone()).unwrap();
+        worker.add_histogram(spec.clone()).unwrap();
+        worker.add_histogram(other_spec.clone()).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("var", 1.5);
+        manager.update(blob.clone()).unwrap();
+        worker.update(blob).unwrap();
+
+        manager.merge(worker).unwrap();
+        assert_eq!(manager.get_histogram_data(&spec.id).unwrap()[1], 2.0);
+        assert_eq!(manager.get_histogram_data(&other_spec.id).unwrap()[1], 1.0);
+
+        let mut bad_spec = spec.clone();
+        bad_spec.x_axis = AxisSpec::new("var", "var", 5, 0.0, 10.0).unwrap();
+        let mut worker = ResourceManager::new();
+        worker.add_histogram(bad_spec).unwrap();
+        assert!(manager.merge(worker).is_err());
+        assert_eq!(manager.get_histogram_data(&spec.id).unwrap()[1], 2.0);
+
+        // Moved histograms go through the conflict policy and the memory limit
+        let mut clash_spec = spec.clone();
+        clash_spec.id = Uuid::new_v4();
+        let mut worker = ResourceManager::new();
+        worker.add_histogram(clash_spec.clone()).unwrap();
+        manager.set_conflict_policy(ConflictPolicy::Error);
+        assert!(matches!(
+            manager.merge(worker),
+            Err(ResourceError::DuplicateName(_))
+        ));
+        assert!(manager.get_histogram(&clash_spec.id).is_err());
+
+        let mut worker = ResourceManager::new();
+        worker.add_histogram(clash_spec.clone()).unwrap();
+        manager.set_conflict_policy(ConflictPolicy::Rename);
+        manager.merge(worker).unwrap();
+        let gram = manager.get_histogram(&clash_spec.id).unwrap();
+        assert_eq!(gram.spec.name, "test_1");
+
+        let mut big_spec = spec.clone();
+        big_spec.id = Uuid::new_v4();
+        big_spec.name = String::from("big");
+        let mut worker = ResourceManager::new();
+        worker.add_histogram(big_spec.clone()).unwrap();
+        manager.set_memory_limit(Some(manager.memory_report().total));
+        assert!(matches!(
+            manager.merge(worker),
+            Err(ResourceError::MemoryLimitExceeded(..))
+        ));
+        assert!(manager.get_histogram(&big_spec.id).is_err());
+
+        // Rolling windows are not merged
+        manager.set_memory_limit(None);
+        let mut windowed = spec.clone();
+        windowed.window = Some(RollingWindow::Events {
+            count: 10,
+            buckets: 2,
+        });
+        let mut worker = ResourceManager::new();
+        worker.add_histogram(windowed).unwrap();
+        assert!(manager.merge(worker).is_err());
+        assert_eq!(manager.get_histogram_data(&spec.id).unwrap()[1], 2.0);
+    }
+
+    #[test]
+    fn test_suggest_axes() {
+        let mut source = IterSource::new((0..500).map(|idx| {
+            let mut blob = DataBlob::new();
+            blob.insert("x", idx as f32);
+            blob
+        }));
+        let axes = ResourceManager::suggest_axes(&mut source, &["x", "y"], 100, BinningRule::Scott)
+            .unwrap();
+        assert_eq!(axes.len(), 1);
+        assert_eq!(axes[0].variable, "x");
+        assert_eq!(axes[0].minimum, 0.0);
+        assert!(axes[0].get_bin(99.0).is_ok());
+        assert!(axes[0].get_bin(150.0).is_err());
+    }
+
+    #[test]
+    fn test_histogram_stats() {
+        let mut manager = ResourceManager::new();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("gate"),
+            x_variable: String::from("gate_var"),
+            y_variable: None,
+        };
+        let spec = HistSpec {
+            cuts_to_check: vec![cut.id],
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager.add_cut_1d(cut, 0.0, 1.0, &spec.id).unwrap();
+
+        let events = [
+            (0.5, Some(1.0)),
+            (0.5, Some(20.0)),
+            (0.5, None),
+            (5.0, Some(2.0)),
+        ];
+        for (gate, var) in events {
+            let mut blob = DataBlob::new();
+            blob.insert("gate_var", gate);
+            if let Some(var) = var {
+                blob.insert("var", var);
+            }
+            manager.update(blob).unwrap();
+        }
+        let stats = manager.get_histogram_stats(&spec.id).unwrap();
+        assert_eq!(stats.offered, 4);
+        assert_eq!(stats.rejected_by_cuts, 1);
+        assert_eq!(stats.missing_variables, 1);
+        assert_eq!(stats.out_of_range, 1);
+        assert_eq!(stats.filled, 1);
+    }
+
+    #[test]
+    fn test_value_policies() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            missing_policy: ValuePolicy::Skip,
+            ..HistSpec::new(
+                "lenient",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let strict = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("strict"),
+            nan_policy: ValuePolicy::Error,
+            missing_policy: ValuePolicy::Error,
+            ..spec.clone()
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager.add_histogram(strict.clone()).unwrap();
+
+        let mut blob = DataBlob::new();
+        blob.insert("var", f32::NAN);
+        assert!(manager.update(blob).is_err());
+        assert!(manager.update(DataBlob::new()).is_err());
+        let mut batch = ColumnBatch::new(2);
+        batch.add_column("var", &[1.0, f32::NAN], None).unwrap();
+        assert!(manager.update_batch(&batch).is_err());
+
+        // Every histogram is filled even when another fails
+        let lenient = manager.get_histogram_stats(&spec.id).unwrap();
+        assert_eq!(lenient.missing_variables, 0);
+        assert_eq!(lenient.non_finite, 2);
+        assert_eq!(lenient.filled, 1);
+        let strict = manager.get_histogram_stats(&strict.id).unwrap();
+        assert_eq!(strict.missing_variables, 1);
+        assert_eq!(strict.non_finite, 2);
+        assert_eq!(strict.filled, 1);
+    }
+
+    #[test]
+    fn test_observers() {
+        use std::sync::{Arc, Mutex};
+
+        let mut manager = ResourceManager::new();
+        let added = Arc::new(Mutex::new(vec![]));
+        let added_clone = added.clone();
+        manager.on_histogram_added(move |event| added_clone.lock().unwrap().push(event.clone()));
+        let (fill_id, fills) = manager.subscribe(&[EventKind::Fill]);
+        let (_, cuts) = manager.subscribe(&[EventKind::CutModified]);
+
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("gate"),
+            x_variable: String::from("var"),
+            y_variable: None,
+        };
+        manager.add_cut_1d(cut.clone(), 0.0, 1.0, &spec.id).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("var", 2.5);
+        manager.update(blob.clone()).unwrap();
+
+        assert_eq!(
+            *added.lock().unwrap(),
+            vec![ManagerEvent::HistogramAdded(spec.id)]
+        );
+        assert_eq!(cuts.try_recv().unwrap(), ManagerEvent::CutModified(cut.id));
+        assert_eq!(
+            fills.try_recv().unwrap(),
+            ManagerEvent::Filled {
+                histogram_id: spec.id,
+                bin: 2
+            }
+        );
+        assert!(fills.try_recv().is_err());
+
+        manager.unsubscribe(&fill_id).unwrap();
+        assert!(manager.unsubscribe(&fill_id).is_err());
+        manager.update(blob).unwrap();
+        assert!(fills.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_generations() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "test",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        let after_add = manager.get_generation();
+        assert!(after_add > 0);
+        assert_eq!(manager.get_histogram_generation(&spec.id).unwrap(), 0);
+
+        let mut blob = DataBlob::new();
+        blob.insert("var", 20.0);
+        manager.update(blob).unwrap();
+        assert_eq!(manager.get_generation(), after_add);
+
+        let mut blob = DataBlob::new();
+        blob.insert("var", 2.0);
+        manager.update(blob).unwrap();
+        assert!(manager.get_generation() > after_add);
+        assert_eq!(manager.get_histogram_generation(&spec.id).unwrap(), 1);
+
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("gate"),
+            x_variable: String::from("var"),
+            y_variable: None,
+        };
+        manager.add_cut_1d(cut.clone(), 0.0, 1.0, &spec.id).unwrap();
+        assert_eq!(
+            manager.get_cut_generation(&cut.id).unwrap(),
+            manager.get_generation()
+        );
+        assert_eq!(manager.get_histogram_generation(&spec.id).unwrap(), 2);
+        assert!(manager.get_cut_generation(&Uuid::new_v4()).is_err());
+    }
+
+    #[test]
+    fn test_folders() {
+        let mut manager = ResourceManager::new();
+        let names = [
+            "focal_plane/xavg",
+            "gamma/clover_3",
+            "gamma/clover/0",
+            "scaler",
+        ];
+        let mut ids = vec![];
+        for name in names {
+            let spec = HistSpec {
+                out_of_range: OutOfRangePolicy::Ignore,
+                ..HistSpec::new(
+                    name,
+                    AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                    None,
+                )
+            };
+            ids.push(spec.id);
+            manager.add_histogram(spec).unwrap();
+        }
+
+        assert_eq!(manager.list_histograms("").len(), 4);
+        let gamma: Vec<&str> = manager
+            .list_histograms("gamma")
+            .iter()
+            .map(|spec| spec.name.as_str())
+            .collect();
+        assert_eq!(gamma, vec!["gamma/clover/0", "gamma/clover_3"]);
+        assert_eq!(manager.list_folders(""), vec!["focal_plane", "gamma"]);
+        assert_eq!(manager.list_folders("gamma"), vec!["gamma/clover"]);
+
+        manager.move_histogram(&ids[3], "misc").unwrap();
+        assert_eq!(
+            manager.get_histogram_spec(&ids[3]).unwrap().name,
+            "misc/scaler"
+        );
+        assert_eq!(manager.rename_folder("gamma", "hpge").unwrap(), 2);
+        assert_eq!(
+            manager.get_histogram_spec(&ids[2]).unwrap().name,
+            "hpge/clover/0"
+        );
+
+        assert!(manager.remove_folder("").is_err());
+        assert_eq!(manager.remove_folder("hpge").unwrap(), 2);
+        assert_eq!(manager.list_histograms("").len(), 2);
+    }
+
+    #[test]
+    fn test_book_array() {
+        let mut manager = ResourceManager::new();
+        let template = HistSpec {
+            title: String::from("Anode {i} Energy"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            metadata: FxHashMap::from_iter([(String::from("channel"), String::from("{i}"))]),
+            ..HistSpec::new(
+                "anodes/anode_{i}_energy",
+                AxisSpec::new("anode_{i}_energy", "Energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let group_id = manager.book_array("anodes", &template, 0..32).unwrap();
+        let group = manager.get_group(&group_id).unwrap().clone();
+        assert_eq!(group.members.len(), 32);
+        let spec = manager.get_histogram_spec(&group.members[3]).unwrap();
+        assert_eq!(spec.name, "anodes/anode_3_energy");
+        assert_eq!(spec.title, "Anode 3 Energy");
+        assert_eq!(spec.x_axis.variable, "anode_3_energy");
+        assert_eq!(spec.metadata["channel"], "3");
+
+        let mut blob = DataBlob::new();
+        blob.insert("anode_3_energy", 1.5);
+        manager.update(blob).unwrap();
+        assert_eq!(
+            manager.get_histogram_data(&group.members[3]).unwrap()[1],
+            1.0
+        );
+        manager.clear_group(&group_id).unwrap();
+        assert_eq!(
+            manager.get_histogram_data(&group.members[3]).unwrap()[1],
+            0.0
+        );
+
+        manager.remove_histogram(&group.members[0]).unwrap();
+        manager.remove_group(&group_id).unwrap();
+        assert!(manager.get_group(&group_id).is_err());
+        assert!(manager.list_histograms("").is_empty());
+
+        // A member failing to book rolls back the ones before it, inside an edit or not
+        manager.set_conflict_policy(ConflictPolicy::Error);
+        let taken = group::instantiate(&template, 2);
+        manager.add_histogram(taken).unwrap();
+        assert!(manager.book_array("anodes", &template, 0..4).is_err());
+        assert_eq!(manager.list_histograms("").len(), 1);
+        assert!(!manager.is_editing());
+        manager.begin_edit().unwrap();
+        manager
+            .add_histogram(group::instantiate(&template, 5))
+            .unwrap();
+        assert!(manager.book_array("anodes", &template, 0..4).is_err());
+        assert_eq!(manager.list_histograms("").len(), 2);
+        manager.rollback().unwrap();
+        assert_eq!(manager.list_histograms("").len(), 1);
+    }
+
+    #[test]
+    fn test_wildcard_variables() {
+        let mut manager = ResourceManager::new();
+        let energies = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "sipm_energy",
+                AxisSpec::new("sipm_*_energy", "Energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let energy_time = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "sipm_energy_time",
+                AxisSpec::new("sipm_*_energy", "Energy", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("sipm_*_time", "Time", 10, 0.0, 10.0).unwrap()),
+            )
+        };
+        let energies_id = energies.id;
+        let energy_time_id = energy_time.id;
+        manager.add_histogram(energies).unwrap();
+        manager.add_histogram(energy_time).unwrap();
+
+        let mut blob = DataBlob::new();
+        blob.insert("sipm_0_energy", 1.5);
+        blob.insert("sipm_1_energy", 2.5);
+        blob.insert("sipm_2_energy", 2.5);
+        blob.insert("sipm_0_time", 5.5);
+        blob.insert("sipm_1_time", 6.5);
+        manager.update(blob).unwrap();
+
+        let data = manager.get_histogram_data(&energies_id).unwrap();
+        assert_eq!(data[1], 1.0);
+        assert_eq!(data[2], 2.0);
+        let data = manager.get_histogram_data(&energy_time_id).unwrap();
+        assert_eq!(data.iter().sum::<f64>(), 2.0);
+        assert_eq!(data[5 * 10 + 1], 1.0);
+        assert_eq!(data[6 * 10 + 2], 1.0);
+
+        manager.update(DataBlob::new()).unwrap();
+        assert_eq!(
+            manager
+                .get_histogram_stats(&energies_id)
+                .unwrap()
+                .missing_variables,
+            1
+        );
+    }
+
+    #[test]
+    fn test_summary_spectrum() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            fill_mode: FillMode::Summary,
+            ..HistSpec::new(
+                "clover_summary",
+                AxisSpec::new("crystal", "crystal", 4, 0.0, 4.0).unwrap(),
+                Some(AxisSpec::new("clover.*.energy", "Energy", 10, 0.0, 10.0).unwrap()),
+            )
+        };
+        assert!(
+            manager
+                .add_histogram(HistSpec {
+                    y_axis: None,
+                    ..spec.clone()
+                })
+                .is_err()
+        );
+        let id = manager.add_histogram(spec).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("clover.0.energy", 1.5);
+        blob.insert("clover.3.energy", 7.5);
+        blob.insert("clover.3.time", 2.5);
+        blob.insert("clover.x.energy", 2.5);
+        manager.update(blob).unwrap();
+
+        let data = manager.get_histogram_data(&id).unwrap();
+        assert_eq!(data.iter().sum::<f64>(), 2.0);
+        assert_eq!(data[4], 1.0);
+        assert_eq!(data[7 * 4 + 3], 1.0);
+    }
+
+    #[test]
+    fn test_runs() {
+        let mut manager = ResourceManager::new();
+        let cleared = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "cleared",
+                AxisSpec::new("var", "var", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let mut accumulated = cleared.clone();
+        accumulated.id = Uuid::new_v4();
+        accumulated.name = String::from("accumulated");
+        accumulated.clear_policy = ClearPolicy::Accumulate;
+        let cleared_id = cleared.id;
+        let accumulated_id = accumulated.id;
+        manager.add_histogram(cleared).unwrap();
+        manager.add_histogram(accumulated).unwrap();
+
+        let mut blob = DataBlob::new();
+        blob.insert("var", 1.5);
+        manager.update(blob.clone()).unwrap();
+        manager.begin_run(1, FxHashMap::default()).unwrap();
+        assert_eq!(manager.get_histogram_data(&cleared_id).unwrap()[1], 0.0);
+        assert_eq!(manager.get_histogram_data(&accumulated_id).unwrap()[1], 1.0);
+
+        manager.update(blob.clone()).unwrap();
+        manager.pause_run().unwrap();
+        manager.update(blob.clone()).unwrap();
+        manager.resume_run().unwrap();
+        manager.update(blob.clone()).unwrap();
+        let run = manager.end_run().unwrap();
+        assert_eq!(run.n_events, 2);
+        manager.update(blob.clone()).unwrap();
+        assert_eq!(manager.get_histogram_data(&cleared_id).unwrap()[1], 2.0);
+        assert_eq!(manager.get_histogram_data(&accumulated_id).unwrap()[1], 3.0);
+        assert_eq!(manager.get_run_info().unwrap().number, 1);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("energy", "energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let id = spec.id;
+        manager.add_histogram(spec).unwrap();
+        manager.add_transform(Box::new(|mut data: DataBlob| {
+            let channel = *data.find("channel")?;
+            data.insert("energy", 2.0 * channel + 0.5);
+            Some(data)
+        }));
+        let veto = manager.add_transform(Box::new(|data: DataBlob| {
+            data.find("veto").is_none().then_some(data)
+        }));
+
+        let mut blob = DataBlob::new();
+        blob.insert("channel", 2.0);
+        manager.update(blob.clone()).unwrap();
+        blob.insert("veto", 1.0);
+        manager.update(blob.clone()).unwrap();
+        assert_eq!(manager.get_histogram_data(&id).unwrap()[4], 1.0);
+
+        manager.remove_transform(&veto).unwrap();
+        assert!(manager.remove_transform(&veto).is_err());
+        manager.update(blob).unwrap();
+        assert_eq!(manager.get_histogram_data(&id).unwrap()[4], 2.0);
+    }
+
+    #[test]
+    fn test_export_import_cuts() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "xy",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("y", "y", 10, 0.0, 10.0).unwrap()),
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("box"),
+            x_variable: String::from("x"),
+            y_variable: Some(String::from("y")),
+        };
+        manager
+            .add_cut_2d(
+                cut.clone(),
+                vec![0.0, 1.0, 1.0, 0.0, 0.0],
+                vec![0.0, 0.0, 1.0, 1.0, 0.0],
+                &spec.id,
+            )
+            .unwrap();
+
+        let exported = manager.export_cuts();
+        assert_eq!(exported.len(), 1);
+        let mut other = ResourceManager::new();
+        let ids = other.import_cuts(&exported).unwrap();
+        assert_eq!(ids, vec![cut.id]);
+        assert_eq!(other.list_cuts(), vec![(Cut2D::KIND, &cut)]);
+
+        let mut unknown = exported[0].clone();
+        unknown.kind = String::from("Ellipse");
+        assert!(other.import_cuts(&[unknown]).is_err());
+
+        // A conflict on any cut, including between the cuts imported, imports none
+        other.set_conflict_policy(ConflictPolicy::Error);
+        let mut fresh = exported[0].clone();
+        fresh.spec.id = Uuid::new_v4();
+        fresh.spec.name = String::from("fresh");
+        assert!(
+            other
+                .import_cuts(&[fresh.clone(), exported[0].clone()])
+                .is_err()
+        );
+        let mut twin = fresh.clone();
+        twin.spec.id = Uuid::new_v4();
+        assert!(other.import_cuts(&[fresh.clone(), twin.clone()]).is_err());
+        assert_eq!(other.list_cuts().len(), 1);
+        other.set_conflict_policy(ConflictPolicy::Rename);
+        other.import_cuts(&[fresh, twin]).unwrap();
+        let mut names: Vec<&str> = other
+            .list_cuts()
+            .iter()
+            .map(|(_, spec)| spec.name.as_str())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["box", "fresh", "fresh_1"]);
+    }
+
+    /// A cut which counts how many times it is evaluated
+    #[derive(Debug)]
+    struct CountingCut {
+        spec: CutSpec,
+        inside: bool,
+        evaluations: std::rc::Rc<std::cell::Cell<usize>>,
+    }
+
+    impl Cut for CountingCut {
+        fn is_inside(&mut self, _blob: &DataBlob) {
+            self.evaluations.set(self.evaluations.get() + 1);
+        }
+
+        fn is_valid(&self) -> bool {
+            self.inside
+        }
+
+        fn reset(&mut self) {}
+
+        fn get_spec(&self) -> &CutSpec {
+            &self.spec
+        }
+
+        fn get_kind(&self) -> &str {
+            "Counting"
+        }
+
+        fn get_parameters(&self) -> serde_json::Value {
+            serde_json::Value::Null
+        }
+    }
+
+    #[test]
+    fn test_lazy_cuts() {
+        let mut manager = ResourceManager::new();
+        let mut counters = vec![];
+        let mut cut_ids = vec![];
+        for inside in [false, true, true] {
+            let evaluations = std::rc::Rc::new(std::cell::Cell::new(0));
+            let cut = CountingCut {
+                spec: CutSpec {
+                    id: Uuid::new_v4(),
+                    name: String::from("counting"),
+                    x_variable: String::from("x"),
+                    y_variable: None,
+                },
+                inside,
+                evaluations: evaluations.clone(),
+            };
+            cut_ids.push(manager.add_cut(Box::new(cut)).unwrap());
+            counters.push(evaluations);
+        }
+        let spec = HistSpec {
+            cuts_to_check: vec![cut_ids[0], cut_ids[1]],
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "gated",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let mut second = spec.clone();
+        second.id = Uuid::new_v4();
+        second.cuts_to_check = vec![cut_ids[0]];
+        manager.add_histogram(spec.clone()).unwrap();
+        manager.add_histogram(second.clone()).unwrap();
+        let mut dependents = manager.get_cut_dependents(&cut_ids[0]).unwrap();
+        dependents.sort();
+        let mut expected = vec![spec.id, second.id];
+        expected.sort();
+        assert_eq!(dependents, expected);
+        assert!(manager.get_cut_dependents(&cut_ids[2]).unwrap().is_empty());
+
+        let mut blob = DataBlob::new();
+        blob.insert("x", 1.0);
+        manager.update(blob).unwrap();
+        // The first gate rejects both histograms, so the second is never needed, and the third
+        // gates nothing
+        assert_eq!(counters[0].get(), 1);
+        assert_eq!(counters[1].get(), 0);
+        assert_eq!(counters[2].get(), 0);
+
+        manager.remove_histogram(&second.id).unwrap();
+        assert_eq!(
+            manager.get_cut_dependents(&cut_ids[0]).unwrap(),
+            vec![spec.id]
+        );
+    }
+
+    #[test]
+    fn test_integrate_in_cut() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "xy",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("y", "y", 10, 0.0, 10.0).unwrap()),
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        for (x, y) in [(1.5, 1.5), (2.5, 1.5), (2.5, 2.5), (8.5, 8.5)] {
+            let mut blob = DataBlob::new();
+            blob.insert("x", x);
+            blob.insert("y", y);
+            manager.update(blob).unwrap();
+        }
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("box"),
+            x_variable: String::from("x"),
+            y_variable: Some(String::from("y")),
+        };
+        manager
+            .add_cut_2d(
+                cut.clone(),
+                vec![1.0, 4.0, 4.0, 1.0, 1.0],
+                vec![1.0, 1.0, 3.0, 3.0, 1.0],
+                &spec.id,
+            )
+            .unwrap();
+        assert_eq!(manager.get_bins_in_cut(&spec.id, &cut.id).unwrap().len(), 6);
+        assert_eq!(manager.integrate_in_cut(&spec.id, &cut.id).unwrap(), 3.0);
+
+        let mut other_axes = cut.clone();
+        other_axes.id = Uuid::new_v4();
+        other_axes.x_variable = String::from("z");
+        manager
+            .add_cut_1d(other_axes.clone(), 0.0, 1.0, &spec.id)
+            .unwrap();
+        assert!(manager.get_bins_in_cut(&spec.id, &other_axes.id).is_err());
+    }
+
+    #[test]
+    fn test_cut_bindings() {
+        let mut manager = ResourceManager::new();
+        let pid = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "pid_0",
+                AxisSpec::new("e_0", "E", 10, 0.0, 10.0).unwrap(),
+                Some(AxisSpec::new("de_0", "dE", 10, 0.0, 10.0).unwrap()),
+            )
+        };
+        manager.add_histogram(pid.clone()).unwrap();
+        let shape = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("protons"),
+            x_variable: String::from("e_0"),
+            y_variable: Some(String::from("de_0")),
+        };
+        manager
+            .add_cut_2d(
+                shape.clone(),
+                vec![1.0, 3.0, 3.0, 1.0, 1.0],
+                vec![1.0, 1.0, 3.0, 3.0, 1.0],
+                &pid.id,
+            )
+            .unwrap();
+        let bindings = manager
+            .bind_cut_to_pairs(&shape.id, &[("e_1", Some("de_1")), ("e_2", Some("de_2"))])
+            .unwrap();
+        assert_eq!(manager.get_cut_bindings(&shape.id).len(), 2);
+        assert_eq!(
+            manager.get_cut_binding(&bindings[1]).unwrap().spec.name,
+            "protons[e_2,de_2]"
+        );
+        assert!(
+            manager
+                .bind_cut_to_pairs(&shape.id, &[("e_3", None)])
+                .is_err()
+        );
+
+        let mut gated = pid.clone();
+        gated.id = Uuid::new_v4();
+        gated.x_axis.variable = String::from("x");
+        gated.y_axis = None;
+        gated.cuts_to_check = vec![bindings[1]];
+        manager.add_histogram(gated.clone()).unwrap();
+
+        // Inside the shape for telescope 1 only, so the telescope 2 binding rejects it
+        let mut blob = DataBlob::new();
+        blob.insert("e_1", 2.0);
+        blob.insert("de_1", 2.0);
+        blob.insert("e_2", 5.0);
+        blob.insert("de_2", 5.0);
+        blob.insert("x", 0.5);
+        manager.update(blob.clone()).unwrap();
+        blob.insert("e_2", 2.5);
+        blob.insert("de_2", 1.5);
+        manager.update(blob).unwrap();
+        let stats = manager.get_histogram_stats(&gated.id).unwrap();
+        assert_eq!(stats.rejected_by_cuts, 1);
+        assert_eq!(stats.filled, 1);
+
+        manager.remove_cut_binding(&bindings[0]).unwrap();
+        assert!(manager.get_cut_binding(&bindings[0]).is_err());
+    }
+
+    #[test]
+    fn test_array_variables() {
+        let mut manager = ResourceManager::new();
+        let fold = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("fold_2"),
+            x_variable: String::from("gamma_e"),
+            y_variable: None,
+        };
+        manager
+            .add_cut(Box::new(
+                crate::cut::MultiplicityCut::new(fold.clone(), 2, None).unwrap(),
+            ))
+            .unwrap();
+        let energies = HistSpec {
+            cuts_to_check: vec![fold.id],
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "gamma_e",
+                AxisSpec::new("gamma_e", "Energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let mut energy_time = energies.clone();
+        energy_time.id = Uuid::new_v4();
+        energy_time.cuts_to_check = vec![];
+        energy_time.y_axis = Some(AxisSpec::new("gamma_t", "Time", 10, 0.0, 10.0).unwrap());
+        manager.add_histogram(energies.clone()).unwrap();
+        manager.add_histogram(energy_time.clone()).unwrap();
+
+        let mut blob = DataBlob::new();
+        blob.push("gamma_e", 1.5);
+        blob.push("gamma_t", 5.5);
+        manager.update(blob.clone()).unwrap();
+        blob.push("gamma_e", 2.5);
+        blob.push("gamma_t", 6.5);
+        manager.update(blob).unwrap();
+
+        let data = manager.get_histogram_data(&energies.id).unwrap();
+        assert_eq!(data[1], 1.0);
+        assert_eq!(data[2], 1.0);
+        let data = manager.get_histogram_data(&energy_time.id).unwrap();
+        assert_eq!(data.iter().sum::<f64>(), 3.0);
+        assert_eq!(data[5 * 10 + 1], 2.0);
+        assert_eq!(data[6 * 10 + 2], 1.0);
+    }
+
+    #[test]
+    fn test_compound_cuts() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("x", AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(), None)
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        let mut windows = vec![];
+        for (name, low, high) in [("low", 0.0, 2.0), ("high", 8.0, 10.0)] {
+            let cut = CutSpec {
+                id: Uuid::new_v4(),
+                name: String::from(name),
+                x_variable: String::from("x"),
+                y_variable: None,
+            };
+            manager
+                .add_cut_1d(cut.clone(), low, high, &spec.id)
+                .unwrap();
+            windows.push(cut.id);
+        }
+        let either = CompoundCut {
+            id: Uuid::new_v4(),
+            name: String::from("either"),
+            condition: GateCondition::Any(windows.clone()),
+        };
+        manager.add_compound_cut(either.clone()).unwrap();
+        let outer = CompoundCut {
+            id: Uuid::new_v4(),
+            name: String::from("outer"),
+            condition: GateCondition::All(vec![either.id]),
+        };
+        manager.add_compound_cut(outer.clone()).unwrap();
+
+        let missing = Uuid::new_v4();
+        assert!(matches!(
+            manager.add_compound_cut(CompoundCut {
+                id: Uuid::new_v4(),
+                name: String::from("broken"),
+                condition: GateCondition::All(vec![missing]),
+            }),
+            Err(ResourceError::MissingReference(_, id)) if id == missing
+        ));
+        // Making "either" depend on "outer" would close a loop
+        let mut looped = either.clone();
+        looped.condition = GateCondition::Any(vec![windows[0], outer.id]);
+        match manager.add_compound_cut(looped) {
+            Err(ResourceError::DependencyCycle(names)) => {
+                assert_eq!(names, vec!["either", "outer", "either"])
+            }
+            other => panic!("Expected a cycle, got {other:?}"),
+        }
+        assert_eq!(manager.get_compound_cut(&either.id).unwrap(), &either);
+        manager.validate().unwrap();
+
+        let mut gated = spec.clone();
+        gated.id = Uuid::new_v4();
+        gated.cuts_to_check = vec![outer.id];
+        manager.add_histogram(gated.clone()).unwrap();
+        for x in [1.5, 5.5, 9.5] {
+            let mut blob = DataBlob::new();
+            blob.insert("x", x);
+            manager.update(blob).unwrap();
+        }
+        assert_eq!(manager.get_histogram_stats(&gated.id).unwrap().filled, 2);
+
+        let mut dangling = spec.clone();
+        dangling.id = Uuid::new_v4();
+        dangling.cuts_to_check = vec![missing];
+        manager.add_histogram(dangling).unwrap();
+        assert!(manager.validate().is_err());
+    }
+
+    #[test]
+    fn test_variable_validation() {
+        use crate::calibration::Calibration;
+        use crate::sim::{Generator, SimSource, SimSpec};
+
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("e_cal", "e", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager
+            .add_histogram(HistSpec {
+                id: Uuid::new_v4(),
+                name: String::from("pid"),
+                y_axis: Some(AxisSpec::new("de", "de", 10, 0.0, 10.0).unwrap()),
+                ..spec.clone()
+            })
+            .unwrap();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("gate"),
+            x_variable: String::from("e"),
+            y_variable: None,
+        };
+        manager.add_cut_1d(cut, 0.0, 1.0, &spec.id).unwrap();
+        manager.set_calibration(CalibrationSpec {
+            input: String::from("e"),
+            output: String::from("e_cal"),
+            calibration: Calibration::linear(0.0, 2.0),
+        });
+        // Nothing is declared yet, so only validate_variables reports anything
+        manager.validate().unwrap();
+        assert_eq!(manager.validate_variables().len(), 3);
+
+        let source = SimSource::new(SimSpec {
+            generators: vec![Generator::Gaussian {
+                variable: String::from("e"),
+                mean: 5.0,
+                sigma: 1.0,
+            }],
+            rate: None,
+            n_events: Some(1),
+            seed: 1,
+        });
+        assert!(manager.register_source_schema(&source));
+        let unknown = manager.validate_variables();
+        assert_eq!(unknown.len(), 1);
+        assert!(matches!(
+            &unknown[0],
+            ResourceError::UnknownVariable(owner, variable)
+                if owner == "Histogram 'pid'" && variable == "de"
+        ));
+        assert!(manager.validate().is_err());
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "kept",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        assert!(manager.commit().is_err());
+
+        manager.begin_edit().unwrap();
+        assert!(manager.begin_edit().is_err());
+        let mut booked = spec.clone();
+        booked.id = Uuid::new_v4();
+        booked.name = String::from("booked");
+        manager.add_histogram(booked.clone()).unwrap();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("x"),
+            y_variable: None,
+        };
+        manager.add_cut_1d(cut.clone(), 1.0, 2.0, &spec.id).unwrap();
+        manager.move_histogram(&spec.id, "moved").unwrap();
+        manager.remove_histogram(&booked.id).unwrap();
+        let mut data = DataBlob::new();
+        data.insert("x", 1.5);
+        manager.update(data).unwrap();
+        manager.rollback().unwrap();
+
+        assert!(!manager.is_editing());
+        assert!(manager.get_histogram_spec(&booked.id).is_err());
+        assert!(manager.get_cut_spec(&cut.id).is_err());
+        let restored = manager.get_histogram_spec(&spec.id).unwrap();
+        assert_eq!(restored.name, "kept");
+        assert!(restored.cuts_to_draw.is_empty());
+        // Fills made during the edit are kept
+        assert_eq!(manager.get_histogram_data(&spec.id).unwrap()[1], 1.0);
+
+        manager.begin_edit().unwrap();
+        manager.add_histogram(booked.clone()).unwrap();
+        manager.commit().unwrap();
+        assert!(manager.get_histogram_spec(&booked.id).is_ok());
+    }
+
+    #[test]
+    fn test_histogram_entry() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            id: Uuid::nil(),
+            title: String::from("xavg"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "xavg",
+                AxisSpec::new("xavg", "xavg", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let id = manager.add_histogram(spec.clone()).unwrap();
+        assert!(!id.is_nil());
+        assert_ne!(manager.add_histogram(spec.clone()).unwrap(), id);
+
+        let mut data = DataBlob::default();
+        data.insert("xavg", 2.5);
+        manager.update(data).unwrap();
+
+        manager
+            .histogram_entry(id)
+            .and_modify(|spec| spec.title = String::from("Average position"))
+            .unwrap();
+        assert_eq!(
+            manager.get_histogram_spec(&id).unwrap().title,
+            "Average position"
+        );
+        assert_eq!(manager.get_histogram_data(&id).unwrap()[2], 1.0);
+
+        let mut rebinned = spec.clone();
+        rebinned.x_axis = AxisSpec::new("xavg", "xavg", 20, 0.0, 10.0).unwrap();
+        assert_eq!(manager.histogram_entry(id).insert(rebinned).unwrap(), id);
+        assert_eq!(manager.get_histogram_data(&id).unwrap(), &[0.0; 20]);
+
+        let new_id = Uuid::new_v4();
+        assert_eq!(
+            manager
+                .histogram_entry(new_id)
+                .or_insert(spec.clone())
+                .unwrap(),
+            new_id
+        );
+        assert_eq!(manager.get_histogram_spec(&new_id).unwrap().title, "xavg");
+        manager
+            .histogram_entry(new_id)
+            .or_insert_with(|| panic!("the histogram already exists"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_apply_cut() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "xavg",
+                AxisSpec::new("xavg", "xavg", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("xavg"),
+            y_variable: None,
+        };
+        manager.add_cut_1d(cut.clone(), 0.0, 5.0, &spec.id).unwrap();
+        let fill = |manager: &mut ResourceManager, value: f32| {
+            let mut event = DataBlob::new();
+            event.insert("xavg", value);
+            manager.update(event).unwrap();
+        };
+
+        manager.apply_cut(&spec.id, &cut.id).unwrap();
+        manager.apply_cut(&spec.id, &cut.id).unwrap();
+        assert_eq!(
+            manager.get_histogram_spec(&spec.id).unwrap().cuts_to_check,
+            vec![cut.id]
+        );
+        assert_eq!(manager.get_cut_dependents(&cut.id).unwrap(), vec![spec.id]);
+        fill(&mut manager, 2.5);
+        fill(&mut manager, 7.5);
+        assert_eq!(manager.get_histogram_data(&spec.id).unwrap()[7], 0.0);
+
+        manager
+            .remove_cut_from_histogram(&spec.id, &cut.id)
+            .unwrap();
+        assert!(manager.get_cut_dependents(&cut.id).unwrap().is_empty());
+        fill(&mut manager, 7.5);
+        let data = manager.get_histogram_data(&spec.id).unwrap();
+        assert_eq!((data[2], data[7]), (1.0, 1.0));
+        assert!(manager.get_cut_spec(&cut.id).is_ok());
+
+        assert!(matches!(
+            manager.remove_cut_from_histogram(&spec.id, &cut.id),
+            Err(ResourceError::CutNotApplied(..))
+        ));
+        assert!(matches!(
+            manager.remove_cut_from_histogram(&spec.id, &Uuid::new_v4()),
+            Err(ResourceError::InvalidCutID(_))
+        ));
+        assert!(matches!(
+            manager.apply_cut(&spec.id, &Uuid::new_v4()),
+            Err(ResourceError::InvalidCutID(_))
+        ));
+        assert!(matches!(
+            manager.apply_cut(&Uuid::new_v4(), &cut.id),
+            Err(ResourceError::InvalidHistogramID(_))
+        ));
+    }
+
+    #[test]
+    fn test_duplicate_histogram() {
+        let mut manager = ResourceManager::new();
+        let cut_id = Uuid::new_v4();
+        let spec = HistSpec {
+            cuts_to_check: vec![cut_id],
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "xavg",
+                AxisSpec::new("xavg", "xavg", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager
+            .add_cut(Box::new(
+                Cut1D::new(
+                    CutSpec {
+                        id: cut_id,
+                        name: String::from("window"),
+                        x_variable: String::from("xavg"),
+                        y_variable: None,
+                    },
+                    0.0,
+                    5.0,
+                )
+                .unwrap(),
+            ))
+            .unwrap();
+        let mut event = DataBlob::new();
+        event.insert("xavg", 2.5);
+        manager.update(event).unwrap();
+
+        let copy_id = manager.duplicate_histogram(&spec.id, "xavg_copy").unwrap();
+        let copy = manager.get_histogram(&copy_id).unwrap();
+        assert_ne!(copy_id, spec.id);
+        assert_eq!(copy.spec.name, "xavg_copy");
+        assert_eq!(copy.spec.cuts_to_check, vec![cut_id]);
+        assert!(copy.data.iter().all(|count| *count == 0.0));
+        assert_eq!(manager.get_histogram_data(&spec.id).unwrap()[2], 1.0);
+        let mut dependents = manager.get_cut_dependents(&cut_id).unwrap();
+        dependents.sort();
+        let mut expected = vec![spec.id, copy_id];
+        expected.sort();
+        assert_eq!(dependents, expected);
+
+        manager.set_conflict_policy(ConflictPolicy::Error);
+        assert!(matches!(
+            manager.duplicate_histogram(&spec.id, "xavg"),
+            Err(ResourceError::DuplicateName(_))
+        ));
+        assert!(manager.duplicate_histogram(&Uuid::new_v4(), "x").is_err());
+    }
+
+    #[test]
+    fn test_conflict_policy() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "xavg",
+                AxisSpec::new("xavg", "xavg", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        assert_eq!(manager.add_histogram(spec.clone()).unwrap(), spec.id);
+        assert_eq!(manager.add_histogram(spec.clone()).unwrap(), spec.id);
+
+        manager.set_conflict_policy(ConflictPolicy::Error);
+        assert!(matches!(
+            manager.add_histogram(spec.clone()),
+            Err(ResourceError::DuplicateID(_))
+        ));
+        let mut same_name = spec.clone();
+        same_name.id = Uuid::new_v4();
+        assert!(matches!(
+            manager.add_histogram(same_name.clone()),
+            Err(ResourceError::DuplicateName(_))
+        ));
+
+        manager.set_conflict_policy(ConflictPolicy::Rename);
+        let renamed = manager.add_histogram(spec.clone()).unwrap();
+        assert_ne!(renamed, spec.id);
+        assert_eq!(manager.get_histogram_spec(&renamed).unwrap().name, "xavg_1");
+        let renamed = manager.add_histogram(same_name).unwrap();
+        assert_eq!(manager.get_histogram_spec(&renamed).unwrap().name, "xavg_2");
+
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("xavg"),
+            y_variable: None,
+        };
+        manager.add_cut_1d(cut.clone(), 1.0, 2.0, &spec.id).unwrap();
+        manager.add_cut_1d(cut.clone(), 1.0, 2.0, &spec.id).unwrap();
+        let names: Vec<&str> = manager
+            .list_cuts()
+            .iter()
+            .map(|(_, spec)| spec.name.as_str())
+            .collect();
+        assert!(names.contains(&"window") && names.contains(&"window_1"));
+        assert!(
+            manager
+                .add_cut(Box::new(Cut1D::new(cut, 1.0, 2.0).unwrap()))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_bulk_removal() {
+        let mut manager = ResourceManager::new();
+        let template = HistSpec {
+            id: Uuid::nil(),
+            title: String::from("clover_{i}"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "gamma/clover_{i}",
+                AxisSpec::new("clover_{i}_e", "Energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.book_array("clovers", &template, 0..4).unwrap();
+        let mut kept = template.clone();
+        kept.name = String::from("gamma/sum");
+        let kept = manager.add_histogram(kept).unwrap();
+
+        let mut data = DataBlob::default();
+        data.insert("clover_0_e", 1.5);
+        manager.update(data).unwrap();
+        assert_eq!(manager.reset_histograms_matching("gamma/*"), 5);
+        assert_eq!(manager.get_histogram_data(&kept).unwrap()[1], 0.0);
+
+        let window = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("clover_0_e"),
+            y_variable: None,
+        };
+        let clover_0 = manager
+            .list_histograms("gamma")
+            .into_iter()
+            .find(|spec| spec.name == "gamma/clover_0")
+            .unwrap()
+            .id;
+        manager
+            .add_cut_1d(window.clone(), 1.0, 2.0, &clover_0)
+            .unwrap();
+        let bound = manager
+            .bind_cut_to_pairs(&window.id, &[("clover_1_e", None)])
+            .unwrap();
+        let either = manager
+            .add_compound_cut(CompoundCut {
+                id: Uuid::new_v4(),
+                name: String::from("either"),
+                condition: GateCondition::Any(vec![window.id, bound[0]]),
+            })
+            .unwrap();
+        let clover_1 = manager
+            .list_histograms("gamma")
+            .into_iter()
+            .find(|spec| spec.name == "gamma/clover_1")
+            .unwrap()
+            .id;
+        manager
+            .histogram_entry(clover_1)
+            .and_modify(|spec| spec.cuts_to_check.push(either))
+            .unwrap();
+        assert_eq!(manager.remove_cuts_unreferenced(), 0);
+
+        assert_eq!(
+            manager
+                .remove_histograms_matching("gamma/clover_*")
+                .unwrap(),
+            4
+        );
+        assert_eq!(manager.list_histograms("").len(), 1);
+        assert_eq!(manager.remove_cuts_unreferenced(), 3);
+        assert!(manager.list_cuts().is_empty());
+        assert!(manager.get_cut_binding(&bound[0]).is_err());
+        assert!(manager.get_compound_cut(&either).is_err());
+    }
+
+    #[test]
+    fn test_cut_stats() {
+        let mut manager = ResourceManager::new();
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("x"),
+            y_variable: None,
+        };
+        let spec = HistSpec {
+            cuts_to_check: vec![cut.id],
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "gated",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager.add_cut_1d(cut.clone(), 1.0, 2.0, &spec.id).unwrap();
+        for x in [0.5, 1.5, 1.7, 3.0] {
+            let mut data = DataBlob::default();
+            data.insert("x", x);
+            manager.update(data).unwrap();
+        }
+        assert_eq!(manager.get_event_count(), 4);
+        let stats = manager.get_cut_stats(&cut.id).unwrap();
+        assert_eq!(stats.evaluated, 4);
+        assert_eq!(stats.accepted, 2);
+        assert_eq!(stats.get_acceptance(), Some(0.5));
+        assert_eq!(
+            manager.list_cut_stats(),
+            vec![(String::from("window"), stats)]
+        );
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "small",
+                AxisSpec::new("x", "x", 1000, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.set_memory_limit(Some(2 * spec.estimate_memory()));
+        manager.add_histogram(spec.clone()).unwrap();
+        // Replacing a histogram only needs room for the difference
+        manager.add_histogram(spec.clone()).unwrap();
+
+        let mut huge = spec.clone();
+        huge.id = Uuid::new_v4();
+        huge.name = String::from("huge");
+        huge.y_axis = Some(AxisSpec::new("y", "y", 100_000, 0.0, 10.0).unwrap());
+        huge.x_axis = AxisSpec::new("x", "x", 100_000, 0.0, 10.0).unwrap();
+        assert!(matches!(
+            manager.add_histogram(huge),
+            Err(ResourceError::MemoryLimitExceeded(..))
+        ));
+
+        let report = manager.memory_report();
+        assert_eq!(report.histograms.len(), 1);
+        assert_eq!(report.total, spec.estimate_memory());
+        assert_eq!(report.limit, Some(2 * spec.estimate_memory()));
+    }
+
+    #[test]
+    fn test_update_batch() {
+        let template = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("e", AxisSpec::new("e", "e", 10, 0.0, 10.0).unwrap(), None)
+        };
+        let window = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("window"),
+            x_variable: String::from("t"),
+            y_variable: None,
+        };
+        let fold = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("fold"),
+            x_variable: String::from("hits"),
+            y_variable: None,
+        };
+        let mut gated = template.clone();
+        gated.id = Uuid::new_v4();
+        gated.name = String::from("gated");
+        gated.cuts_to_check = vec![window.id];
+        let mut folded = template.clone();
+        folded.id = Uuid::new_v4();
+        folded.name = String::from("folded");
+        folded.cuts_to_check = vec![fold.id];
+        let mut pid = template.clone();
+        pid.id = Uuid::new_v4();
+        pid.name = String::from("pid");
+        pid.y_axis = Some(AxisSpec::new("t", "t", 10, 0.0, 10.0).unwrap());
+
+        let build = || {
+            let mut manager = ResourceManager::new();
+            for spec in [&template, &gated, &folded, &pid] {
+                manager.add_histogram(spec.clone()).unwrap();
+            }
+            manager
+                .add_cut_1d(window.clone(), 0.0, 5.0, &template.id)
+                .unwrap();
+            manager
+                .add_cut(Box::new(
+                    crate::cut::MultiplicityCut::new(fold.clone(), 1, None).unwrap(),
+                ))
+                .unwrap();
+            manager
+        };
+        let energies = [1.5, 2.5, 3.5, 4.5];
+        let times = [1.0, 7.0, 3.0, 0.0];
+        let has_time = [true, true, true, false];
+        let mut batch = ColumnBatch::new(4);
+        batch.add_column("e", &energies, None).unwrap();
+        batch.add_column("t", &times, Some(&has_time)).unwrap();
+
+        let mut columns = build();
+        columns.update_batch(&batch).unwrap();
+        let mut events = build();
+        for row in 0..4 {
+            events.update(batch.get_row(row)).unwrap();
+        }
+        for spec in [&template, &gated, &folded, &pid] {
+            assert_eq!(
+                columns.get_histogram_data(&spec.id).unwrap(),
+                events.get_histogram_data(&spec.id).unwrap(),
+            );
+            assert_eq!(
+                columns.get_histogram_stats(&spec.id).unwrap(),
+                events.get_histogram_stats(&spec.id).unwrap(),
+            );
+        }
+        assert_eq!(columns.get_histogram_stats(&gated.id).unwrap().filled, 2);
+        assert_eq!(columns.get_event_count(), 4);
+        assert_eq!(columns.get_cut_stats(&window.id).unwrap().accepted, 2);
+    }
+
+    #[test]
+    fn test_update_pooled() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("x", AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(), None)
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        let pool = BlobPool::new(4);
+        for x in [1.5, 2.5] {
+            let mut data = pool.get();
+            data.insert("x", x);
+            manager.update_pooled(data, &pool).unwrap();
+            assert_eq!(pool.get_n_free(), 1);
+        }
+        let data = manager.get_histogram_data(&spec.id).unwrap();
+        assert_eq!((data[1], data[2]), (1.0, 1.0));
+    }
+
+    #[test]
+    fn test_profiling() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("x", AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(), None)
+        };
+        manager.add_histogram(spec).unwrap();
+        assert!(manager.get_perf_report().is_none());
+        manager.set_profiling(true);
+        for _ in 0..10 {
+            let mut data = DataBlob::default();
+            data.insert("x", 1.5);
+            manager.update(data).unwrap();
+        }
+        let report = manager.get_perf_report().unwrap();
+        assert_eq!(report.events, 10);
+        assert!(report.busy >= report.fills + report.lookups + report.cuts);
+        assert!(report.get_mean_latency().is_some());
+        assert!(report.get_events_per_second().is_some());
+
+        manager.reset_perf();
+        assert_eq!(manager.get_perf_report().unwrap().events, 0);
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_shutdown() {
+        use crate::builder::{BuilderSpec, BuiltSource, Hit, IterHitSource};
+
+        let mut manager = ResourceManager::new();
+        let path = std::env::temp_dir().join(format!("spect_shutdown_{}.evt", Uuid::new_v4()));
+        manager.record_to(&path).unwrap();
+        manager.begin_run(1, FxHashMap::default()).unwrap();
+        // A source which never runs out, like a live DAQ
+        let hits = (0..).map(|i| {
+            let hit = Hit {
+                detector: String::from("si"),
+                timestamp: i * 1000,
+                values: vec![(String::from("energy"), i as f32)],
+            };
+            (0, hit)
+        });
+        let spec = BuilderSpec {
+            coincidence_window: 100,
+            tolerance: 10_000,
+            seconds_per_tick: None,
+        };
+        let mut source = BuiltSource::new(IterHitSource::new(hits), spec);
+        // The first event is built once a hit arrives 10100 past its start, leaving 11 hits held
+        assert!(source.next_event().unwrap().is_some());
+
+        let report = manager.shutdown(&mut source).unwrap();
+        assert_eq!(report.drained, 11);
+        assert_eq!(report.events, 11);
+        assert_eq!(report.recorded, 11);
+        assert_eq!(report.run.unwrap().number, 1);
+        assert_eq!(manager.get_run_state(), RunState::Stopped);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_run_snapshots() {
+        let mut manager = ResourceManager::new();
+        for name in ["si_e", "scalers"] {
+            let spec = HistSpec {
+                out_of_range: OutOfRangePolicy::Ignore,
+                ..HistSpec::new(name, AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(), None)
+            };
+            manager.add_histogram(spec).unwrap();
+        }
+        manager.set_run_snapshots(Some("si_*"));
+        for (run, x) in [(1, 2.5), (2, 3.5)] {
+            manager.begin_run(run, FxHashMap::default()).unwrap();
+            for _ in 0..run {
+                let mut data = DataBlob::default();
+                data.insert("x", x);
+                manager.update(data).unwrap();
+            }
+            manager.end_run().unwrap();
+        }
+        assert_eq!(manager.list_run_snapshots(), vec![1, 2]);
+        assert_eq!(manager.get_run_snapshot(1, "si_e").unwrap().data[2], 1.0);
+        assert!(manager.get_run_snapshot(1, "scalers").is_err());
+
+        let difference = manager.get_run_difference("si_e", 1, 2).unwrap();
+        assert_eq!(difference.data[2], -1.0);
+        assert_eq!(difference.data[3], 2.0);
+        assert_eq!(manager.take_run_snapshots(1).len(), 1);
+        assert_eq!(manager.list_run_snapshots(), vec![2]);
+    }
+
+    #[test]
+    fn test_derived_histograms() {
+        let mut manager = ResourceManager::new();
+        manager.set_conflict_policy(ConflictPolicy::Error);
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("e", "e", 4, 0.0, 4.0).unwrap(),
+                None,
+            )
+        };
+        let parent_id = manager.add_histogram(spec).unwrap();
+        let peak_id = manager
+            .add_normalized(&parent_id, "energy_peak", Normalization::Peak)
+            .unwrap();
+        let cumulative_id = manager.add_cumulative(&parent_id, "energy_cdf").unwrap();
+        assert!(manager.add_cumulative(&parent_id, "energy").is_err());
+        assert!(manager.add_cumulative(&Uuid::new_v4(), "other").is_err());
+        assert_eq!(manager.list_derived_histograms(&parent_id).len(), 2);
+
+        for e in [0.5, 1.5, 1.5] {
+            let mut data = DataBlob::default();
+            data.insert("e", e);
+            manager.update(data).unwrap();
+        }
+        let peak = manager.get_derived_histogram(&peak_id).unwrap();
+        assert_eq!(peak.data, vec![0.5, 1.0, 0.0, 0.0]);
+        assert_eq!(peak.spec.name, "energy_peak");
+        let cumulative = manager.get_derived_histogram(&cumulative_id).unwrap();
+        assert_eq!(cumulative.data, vec![1.0, 3.0, 3.0, 3.0]);
+
+        manager.clear_histogram(&parent_id).unwrap();
+        let cumulative = manager.get_derived_histogram(&cumulative_id).unwrap();
+        assert_eq!(cumulative.data, vec![0.0; 4]);
+        manager.remove_derived_histogram(&peak_id).unwrap();
+        assert!(manager.get_derived_histogram(&peak_id).is_err());
+
+        // A background spectrum subtracted as the data come in
+        let background_id = manager
+            .add_histogram(HistSpec {
+                id: Uuid::new_v4(),
+                name: String::from("background"),
+                x_axis: AxisSpec::new("b", "b", 4, 0.0, 4.0).unwrap(),
+                ..manager.get_histogram(&parent_id).unwrap().spec.clone()
+            })
+            .unwrap();
+        let difference_id = manager
+            .add_difference(&parent_id, &background_id, "energy_net", 2.0)
+            .unwrap();
+        assert!(
+            manager
+                .add_ratio(&parent_id, &Uuid::new_v4(), "ratio")
+                .is_err()
+        );
+        manager
+            .set_derived_refresh_mode(&difference_id, RefreshMode::OnUpdate)
+            .unwrap();
+        let mut data = DataBlob::default();
+        data.insert("e", 2.5);
+        data.insert("b", 0.5);
+        manager.update(data).unwrap();
+        let net = manager
+            .get_cached_derived_histogram(&difference_id)
+            .unwrap();
+        assert_eq!(net.data, vec![-2.0, 0.0, 1.0, 0.0]);
+        // The cumulative histogram is only recomputed when it is read
+        let cdf = manager
+            .get_cached_derived_histogram(&cumulative_id)
+            .unwrap();
+        assert_eq!(cdf.data, vec![0.0; 4]);
+        assert_eq!(manager.refresh_derived_histograms().unwrap(), 1);
+        let cdf = manager
+            .get_cached_derived_histogram(&cumulative_id)
+            .unwrap();
+        assert_eq!(cdf.data, vec![0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_load_histogram_data() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            track_errors: true,
+            ..HistSpec::new(
+                "reference",
+                AxisSpec::new("e", "e", 4, 0.0, 4.0).unwrap(),
+                None,
+            )
+        };
+        let id = manager.add_histogram(spec).unwrap();
+        let area_id = manager
+            .add_normalized(&id, "reference_area", Normalization::Area)
+            .unwrap();
+        manager
+            .load_histogram_data(&id, &[1.0, 4.0, 0.0, 5.0])
+            .unwrap();
+        manager.add_bin_content(&id, 2, 0, 9.0).unwrap();
+        manager.set_bin_content(&id, 0, 0, 0.0).unwrap();
+        let gram = manager.get_histogram(&id).unwrap();
+        assert_eq!(gram.data, vec![0.0, 4.0, 9.0, 5.0]);
+        assert_eq!(gram.bin_error(2).unwrap(), 3.0);
+        let area = manager.get_derived_histogram(&area_id).unwrap();
+        assert_eq!(area.data[2], 0.5);
+        assert!(manager.load_histogram_data(&id, &[1.0, 2.0]).is_err());
+        assert!(manager.set_bin_content(&Uuid::new_v4(), 0, 0, 1.0).is_err());
+
+        let spectrum = Spectrum {
+            name: String::from("source"),
+            counts: vec![2.0, 0.0, 3.5],
+            ..Default::default()
+        };
+        let source_id = manager.import_spectrum(&spectrum, "e").unwrap();
+        let source = manager.get_histogram(&source_id).unwrap();
+        assert_eq!(source.data, vec![2.0, 0.0, 3.5]);
+        assert_eq!(source.spec.x_axis.maximum, 3.0);
+    }
+
+    #[test]
+    fn test_time_axes() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "rate",
+                AxisSpec::new_time("run time", 10, 0.0, 10.0, TimeOrigin::RunStart).unwrap(),
+                None,
+            )
+        };
+        let rate_id = manager.add_histogram(spec.clone()).unwrap();
+        // A day of Unix time is too long for f32 seconds without an origin close by
+        let midnight = 1.7e9;
+        let day_id = manager
+            .add_histogram(HistSpec {
+                id: Uuid::new_v4(),
+                name: String::from("day"),
+                x_axis: AxisSpec::new_time("time", 24, 0.0, 86400.0, TimeOrigin::Fixed(midnight))
+                    .unwrap(),
+                ..spec
+            })
+            .unwrap();
+        manager.begin_run(1, FxHashMap::default()).unwrap();
+        for offset in [3600.0, 3602.5, 3609.5] {
+            let mut data = DataBlob::default();
+            data.set_timestamp(midnight + offset);
+            manager.update(data).unwrap();
+        }
+        let rate = manager.get_histogram(&rate_id).unwrap();
+        assert_eq!(rate.data[0], 1.0);
+        assert_eq!(rate.data[2], 1.0);
+        assert_eq!(rate.data[9], 1.0);
+        assert_eq!(manager.get_histogram(&day_id).unwrap().data[1], 3.0);
+
+        // Events without a timestamp are stamped with the wall clock
+        manager.end_run().unwrap();
+        manager.begin_run(2, FxHashMap::default()).unwrap();
+        let mut batch = ColumnBatch::new(2);
+        batch.add_column("e", &[1.0, 2.0], None).unwrap();
+        manager.update_batch(&batch).unwrap();
+        assert_eq!(manager.get_histogram(&rate_id).unwrap().data[0], 2.0);
+    }
+
+    #[test]
+    fn test_rates() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("e", AxisSpec::new("e", "e", 10, 0.0, 10.0).unwrap(), None)
+        };
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("gate"),
+            x_variable: String::from("e"),
+            y_variable: None,
+        };
+        manager.add_histogram(spec.clone()).unwrap();
+        manager.add_cut_1d(cut.clone(), 2.0, 4.0, &spec.id).unwrap();
+        let gated_id = manager.add_rate(RateSpec {
+            id: Uuid::nil(),
+            name: String::from("gated"),
+            cut_id: Some(cut.id),
+            variable: None,
+            bucket_width: 1.0,
+            n_buckets: 10,
+        });
+        let any_id = manager.add_rate(RateSpec {
+            id: Uuid::nil(),
+            name: String::from("any"),
+            cut_id: None,
+            variable: Some(String::from("e")),
+            bucket_width: 1.0,
+            n_buckets: 10,
+        });
+        assert!(!gated_id.is_nil());
+        assert_eq!(manager.list_rates().len(), 2);
+
+        manager.begin_run(1, FxHashMap::default()).unwrap();
+        for (timestamp, value) in [(0.5, Some(3.0)), (1.2, Some(5.0)), (1.7, None)] {
+            let mut data = DataBlob::default();
+            data.set_timestamp(timestamp);
+            if let Some(value) = value {
+                data.insert("e", value);
+            }
+            manager.update(data).unwrap();
+        }
+        assert_eq!(
+            manager.get_rate(&gated_id).unwrap().get_rates(),
+            vec![(0.0, 1.0)]
+        );
+        assert_eq!(
+            manager.get_rate(&any_id).unwrap().get_rates(),
+            vec![(0.0, 1.0), (1.0, 1.0)]
+        );
+
+        manager.end_run().unwrap();
+        manager.begin_run(2, FxHashMap::default()).unwrap();
+        assert_eq!(manager.get_rate(&any_id).unwrap().get_total(), 0);
+        manager.remove_rate(&any_id).unwrap();
+        assert!(manager.get_rate(&any_id).is_err());
+    }
+
+    #[test]
+    fn test_aliases() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "si_e",
+                AxisSpec::new("si_e", "Energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let id = manager.add_histogram(spec).unwrap();
+        manager.set_alias(Alias::new("adc_3", "si_e").with_scale(0.01, 0.5));
+        let mut blob = DataBlob::new();
+        blob.insert("adc_3", 200.0);
+        manager.update(blob).unwrap();
+        let mut batch = ColumnBatch::new(1);
+        batch.add_column("adc_3", &[400.0], None).unwrap();
+        manager.update_batch(&batch).unwrap();
+
+        let data = manager.get_histogram_data(&id).unwrap();
+        assert_eq!(data[2], 1.0);
+        assert_eq!(data[4], 1.0);
+        assert_eq!(manager.list_aliases().len(), 1);
+        assert_eq!(manager.get_alias("adc_3").unwrap().canonical, "si_e");
+        manager.remove_alias("adc_3").unwrap();
+        assert!(manager.remove_alias("adc_3").is_err());
+    }
+
+    #[test]
+    fn test_calibrations() {
+        let mut manager = ResourceManager::new();
+        let raw_spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "raw",
+                AxisSpec::new("adc", "adc", 100, 0.0, 1000.0).unwrap(),
+                None,
+            )
+        };
+        let raw_id = manager.add_histogram(raw_spec.clone()).unwrap();
+        let energy_id = manager
+            .add_histogram(HistSpec {
+                id: Uuid::new_v4(),
+                name: String::from("energy"),
+                x_axis: AxisSpec::new("energy", "energy", 10, 0.0, 100.0).unwrap(),
+                ..raw_spec
+            })
+            .unwrap();
+        let fit = manager
+            .calibrate_histogram(
+                &raw_id,
+                "energy",
+                &[100.0, 300.0, 500.0],
+                &[10.0, 30.0, 50.0],
+                1,
+            )
+            .unwrap();
+        assert!(fit.get_rms_residual() < 1e-9);
+        assert_eq!(manager.get_calibration("energy").unwrap().input, "adc");
+        assert!(
+            manager
+                .calibrate_histogram(&raw_id, "e", &[1.0], &[1.0], 1)
+                .is_err()
+        );
+
+        let mut data = DataBlob::default();
+        data.insert("adc", 255.0);
+        manager.update(data).unwrap();
+        let energy = manager.get_histogram(&energy_id).unwrap();
+        assert_eq!(energy.get_bin_content(2, 0).unwrap(), 1.0);
+        manager.remove_calibration("energy").unwrap();
+        assert!(manager.remove_calibration("energy").is_err());
+        assert!(manager.list_calibrations().is_empty());
+
+        // The raw spectrum has its one event at 255, and the reference peaks at 510
+        let mut data = DataBlob::default();
+        data.insert("energy", 51.0);
+        manager.update(data).unwrap();
+        let fits = manager
+            .gain_match(
+                &[raw_id],
+                &energy_id,
+                &[PeakRegion::same((0.0, 100.0))],
+                "{variable}_matched",
+            )
+            .unwrap_err();
+        assert!(matches!(fits, ResourceError::CalibrationFailed(_)));
+        let fits = manager
+            .gain_match(
+                &[energy_id],
+                &raw_id,
+                &[PeakRegion {
+                    reference: (200.0, 300.0),
+                    channel: (0.0, 100.0),
+                }],
+                "{variable}_matched",
+            )
+            .unwrap();
+        assert_eq!(fits.len(), 1);
+        let matched = manager.get_calibration("energy_matched").unwrap();
+        assert_eq!(matched.input, "energy");
+        assert!((matched.calibration.apply(55.0) - 255.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_contour_cut() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "pid",
+                AxisSpec::new("de", "de", 20, 0.0, 20.0).unwrap(),
+                Some(AxisSpec::new("e", "e", 20, 0.0, 20.0).unwrap()),
+            )
+        };
+        let histogram_id = manager.add_histogram(spec).unwrap();
+        for (de, e, n) in [(5.5, 5.5, 10), (6.5, 5.5, 10), (15.5, 15.5, 3)] {
+            for _ in 0..n {
+                let mut data = DataBlob::default();
+                data.insert("de", de);
+                data.insert("e", e);
+                manager.update(data).unwrap();
+            }
+        }
+        let cut_id = manager
+            .add_contour_cut(&histogram_id, "protons", 2.0, Some((15.5, 15.5)))
+            .unwrap();
+        let blob_id = manager
+            .add_containment_cut(&histogram_id, "alphas", 0.8, None)
+            .unwrap();
+        assert!(
+            manager.cuts[&blob_id]
+                .contains_point(6.0, Some(5.5))
+                .unwrap()
+        );
+        let spec = manager.get_cut_spec(&cut_id).unwrap();
+        assert_eq!(spec.y_variable.as_deref(), Some("e"));
+        assert!(
+            manager.cuts[&cut_id]
+                .contains_point(15.5, Some(15.5))
+                .unwrap()
+        );
+        assert!(
+            !manager.cuts[&cut_id]
+                .contains_point(6.0, Some(5.5))
+                .unwrap()
+        );
+        assert!(
+            manager
+                .add_contour_cut(&histogram_id, "none", 20.0, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_bit_mask() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Overflow,
+            fill_mode: FillMode::BitMask,
+            ..HistSpec::new(
+                "trigger_bits",
+                AxisSpec::new("trigger", "trigger", 8, 0.0, 8.0).unwrap(),
+                None,
+            )
+        };
+        let id = manager.add_histogram(spec).unwrap();
+        let mut data = DataBlob::default();
+        data.insert_flag("trigger", 0b1000_0101 | 1 << 40);
+        manager.update(data).unwrap();
+        let mut data = DataBlob::default();
+        data.insert("trigger", 5.0);
+        manager.update(data).unwrap();
+
+        let gram = manager.get_histogram(&id).unwrap();
+        assert_eq!(gram.data, vec![2.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(gram.get_overflow().x_overflow, 1);
+
+        let triggers = [2.0, 3.0];
+        let mut batch = ColumnBatch::new(2);
+        batch.add_column("trigger", &triggers, None).unwrap();
+        manager.update_batch(&batch).unwrap();
+        assert_eq!(manager.get_histogram(&id).unwrap().data[1], 2.0);
+    }
+
+    #[test]
+    fn test_symmetric_matrix() {
+        let mut manager = ResourceManager::new();
+        let mut ids = vec![];
+        for layout in [BinLayout::RowMajor, BinLayout::UpperTriangle] {
+            let spec = HistSpec {
+                title: String::from("gamma-gamma"),
+                layout,
+                out_of_range: OutOfRangePolicy::Ignore,
+                fill_mode: FillMode::Symmetric,
+                ..HistSpec::new(
+                    &format!("gg_{layout:?}"),
+                    AxisSpec::new("gamma_e", "e1", 4, 0.0, 4.0).unwrap(),
+                    Some(AxisSpec::new("gamma_e", "e2", 4, 0.0, 4.0).unwrap()),
+                )
+            };
+            ids.push(manager.add_histogram(spec).unwrap());
+        }
+        let mut data = DataBlob::default();
+        data.insert_array("gamma_e", vec![0.5, 1.5, 3.5]);
+        manager.update(data).unwrap();
+
+        let full = manager.get_histogram(&ids[0]).unwrap();
+        let triangle = manager.get_histogram(&ids[1]).unwrap();
+        assert_eq!(full.data.len(), 16);
+        assert_eq!(triangle.data.len(), 10);
+        assert_eq!(full.data.iter().sum::<f64>(), 6.0);
+        assert_eq!(triangle.data.iter().sum::<f64>(), 3.0);
+        for (x_bin, y_bin) in [(0, 1), (1, 0), (3, 1), (0, 3)] {
+            assert_eq!(full.get_bin_content(x_bin, y_bin).unwrap(), 1.0);
+            assert_eq!(triangle.get_bin_content(x_bin, y_bin).unwrap(), 1.0);
+        }
+        assert_eq!(full.get_bin_content(1, 1).unwrap(), 0.0);
+        assert_eq!(triangle.downsample(4, 4).data, full.data);
+        assert_eq!(triangle.bin_coordinates(9), (3, 3));
+        assert_eq!(triangle.bin_coordinates(7), (1, 3));
+
+        let mut spec = manager.get_histogram_spec(&ids[1]).unwrap().clone();
+        spec.id = Uuid::new_v4();
+        spec.y_axis = Some(AxisSpec::new("gamma_e", "e2", 8, 0.0, 4.0).unwrap());
+        assert!(manager.add_histogram(spec).is_err());
     }
 }
@@ -0,0 +1,271 @@
+//! Exposes the state of a ResourceManager in the Prometheus text format, so that shift-takers can
+//! plot event rates, fill rates, and cut acceptance in Grafana. Rates are left to Prometheus,
+//! which computes them from the counters here.
+use super::manager::ResourceManager;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+        }
+    }
+}
+
+// Escape a label value for the text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Builds a page of metrics in the Prometheus text format
+#[derive(Debug, Default)]
+struct Page {
+    text: String,
+}
+
+impl Page {
+    fn header(&mut self, name: &str, help: &str, kind: MetricType) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {}", kind.as_str());
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {value}");
+    }
+}
+
+/// Render the metrics of a manager, followed by any extra gauges given as (name, help, value)
+pub fn render(manager: &ResourceManager, gauges: &[(String, String, f64)]) -> String {
+    let mut page = Page::default();
+    page.header(
+        "spect_events_total",
+        "Events accepted by the manager",
+        MetricType::Counter,
+    );
+    page.sample("spect_events_total", &[], manager.get_event_count() as f64);
+
+    let histograms = manager.list_histograms("");
+    page.header(
+        "spect_histogram_offered_total",
+        "Events offered to each histogram",
+        MetricType::Counter,
+    );
+    for spec in histograms.iter() {
+        if let Ok(stats) = manager.get_histogram_stats(&spec.id) {
+            let labels = [("histogram", spec.name.as_str())];
+            page.sample(
+                "spect_histogram_offered_total",
+                &labels,
+                stats.offered as f64,
+            );
+        }
+    }
+    page.header(
+        "spect_histogram_filled_total",
+        "Fills which incremented a bin of each histogram",
+        MetricType::Counter,
+    );
+    for spec in histograms.iter() {
+        if let Ok(stats) = manager.get_histogram_stats(&spec.id) {
+            let labels = [("histogram", spec.name.as_str())];
+            page.sample("spect_histogram_filled_total", &labels, stats.filled as f64);
+        }
+    }
+
+    let cut_stats = manager.list_cut_stats();
+    page.header(
+        "spect_cut_evaluated_total",
+        "Events each cut was evaluated for",
+        MetricType::Counter,
+    );
+    for (name, stats) in cut_stats.iter() {
+        let labels = [("cut", name.as_str())];
+        page.sample("spect_cut_evaluated_total", &labels, stats.evaluated as f64);
+    }
+    page.header(
+        "spect_cut_acceptance",
+        "Fraction of evaluated events inside each cut",
+        MetricType::Gauge,
+    );
+    for (name, stats) in cut_stats.iter() {
+        if let Some(acceptance) = stats.get_acceptance() {
+            page.sample(
+                "spect_cut_acceptance",
+                &[("cut", name.as_str())],
+                acceptance,
+            );
+        }
+    }
+
+    page.header(
+        "spect_histogram_memory_bytes",
+        "Memory used by histograms",
+        MetricType::Gauge,
+    );
+    let report = manager.memory_report();
+    page.sample("spect_histogram_memory_bytes", &[], report.total as f64);
+    if let Some(limit) = report.limit {
+        page.header(
+            "spect_histogram_memory_limit_bytes",
+            "Memory histograms may use",
+            MetricType::Gauge,
+        );
+        page.sample("spect_histogram_memory_limit_bytes", &[], limit as f64);
+    }
+
+    for (name, help, value) in gauges.iter() {
+        page.header(name, help, MetricType::Gauge);
+        page.sample(name, &[], *value);
+    }
+    page.text
+}
+
+/// Serves the latest rendered metrics over HTTP at /metrics from a background thread. The
+/// manager stays on the analysis thread, which calls update whenever it wants the page
+/// refreshed, e.g. once per second.
+#[derive(Debug)]
+pub struct MetricsExporter {
+    page: Arc<Mutex<String>>,
+    gauges: Vec<(String, String, f64)>,
+    address: SocketAddr,
+}
+
+impl MetricsExporter {
+    /// Start serving on an address, e.g. "0.0.0.0:9184"
+    pub fn serve(address: impl ToSocketAddrs) -> std::io::Result<(Self, JoinHandle<()>)> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let page = Arc::new(Mutex::new(String::new()));
+        let served = page.clone();
+        let handle = std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A client hanging up early is not a problem for the exporter
+                #[cfg(feature = "tracing")]
+                if let Err(e) = respond(stream, &served) {
+                    tracing::debug!("Metrics client hung up: {e}");
+                }
+                #[cfg(not(feature = "tracing"))]
+                let _ = respond(stream, &served);
+            }
+        });
+        Ok((
+            Self {
+                page,
+                gauges: vec![],
+                address,
+            },
+            handle,
+        ))
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Set a gauge for something outside the manager, such as the depth of a queue between
+    /// threads. The gauge is published on the next update.
+    pub fn set_gauge(&mut self, name: &str, help: &str, value: f64) {
+        match self.gauges.iter_mut().find(|(gauge, _, _)| gauge == name) {
+            Some(gauge) => gauge.2 = value,
+            None => self
+                .gauges
+                .push((name.to_string(), help.to_string(), value)),
+        }
+    }
+
+    /// Render the metrics of the manager as the page served to scrapers
+    pub fn update(&self, manager: &ResourceManager) {
+        let text = render(manager, &self.gauges);
+        if let Ok(mut page) = self.page.lock() {
+            *page = text;
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, page: &Mutex<String>) -> std::io::Result<()> {
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if request.starts_with("GET ") && path == "/metrics" {
+        let body = page.lock().map(|page| page.clone()).unwrap_or_default();
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", String::new())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+    use std::io::Read;
+
+    #[test]
+    fn test_exporter() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            title: String::from("si"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "si \"front\"",
+                AxisSpec::new("si_e", "Energy", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        manager.add_histogram(spec).unwrap();
+        let mut data = DataBlob::default();
+        data.insert("si_e", 1.0);
+        manager.update(data).unwrap();
+
+        let (mut exporter, _) = MetricsExporter::serve("127.0.0.1:0").unwrap();
+        exporter.set_gauge("spect_queue_depth", "Events waiting to be processed", 12.0);
+        exporter.update(&manager);
+
+        let mut stream = TcpStream::connect(exporter.get_address()).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("spect_events_total 1\n"));
+        assert!(
+            response.contains("spect_histogram_filled_total{histogram=\"si \\\"front\\\"\"} 1\n")
+        );
+        let memory = format!(
+            "spect_histogram_memory_bytes {}\n",
+            manager.memory_report().total
+        );
+        assert!(response.contains(&memory));
+        assert!(response.contains("# TYPE spect_queue_depth gauge\nspect_queue_depth 12\n"));
+    }
+}
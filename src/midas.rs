@@ -0,0 +1,303 @@
+//! Decodes MIDAS event streams, as written to .mid files by mlogger, into events. The banks of
+//! each event are turned into variables by a BankMap configured by the user. Online buffers can
+//! be read through any byte stream carrying raw events, such as a pipe or socket fed by a MIDAS
+//! frontend or relay; attaching to a shared memory buffer directly needs the MIDAS library.
+use super::compression::{self, Input};
+use super::data_blob::DataBlob;
+use super::error::SourceError;
+use super::source::{DataSource, read_or_eof};
+use rustc_hash::FxHashMap;
+use std::io::Read;
+use std::path::Path;
+
+pub const BEGIN_OF_RUN: u16 = 0x8000;
+pub const END_OF_RUN: u16 = 0x8001;
+pub const MESSAGE: u16 = 0x8002;
+
+// Flags of the bank header saying how the banks of an event are laid out
+const BANK_32: u32 = 0x10;
+const BANK_32A: u32 = 0x20;
+// Events larger than this are taken to be corrupt rather than allocated
+const MAX_EVENT_SIZE: usize = 1 << 28;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventHeader {
+    pub event_id: u16,
+    pub trigger_mask: u16,
+    pub serial_number: u32,
+    pub timestamp: u32,
+}
+
+/// How the values of a bank become variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BankMapping {
+    /// Each value becomes "{prefix}_{index}", e.g. one variable per ADC channel
+    Indexed(String),
+    /// The values are named in order. Values past the end of the list are dropped.
+    Named(Vec<String>),
+    /// The values become one array variable, e.g. for hit lists of varying length
+    Array(String),
+}
+
+/// Maps banks, by their four character name, to variables. Banks without a mapping are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct BankMap {
+    mappings: FxHashMap<String, BankMapping>,
+}
+
+impl BankMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, bank: &str, mapping: BankMapping) {
+        self.mappings.insert(bank.to_string(), mapping);
+    }
+
+    fn apply(&self, bank: &str, values: Vec<f32>, event: &mut DataBlob) {
+        match self.mappings.get(bank) {
+            Some(BankMapping::Indexed(prefix)) => {
+                for (idx, value) in values.iter().enumerate() {
+                    event.insert(&format!("{prefix}_{idx}"), *value);
+                }
+            }
+            Some(BankMapping::Named(names)) => {
+                for (name, value) in names.iter().zip(values.iter()) {
+                    event.insert(name, *value);
+                }
+            }
+            Some(BankMapping::Array(name)) => event.insert_array(name, values),
+            None => (),
+        }
+    }
+
+    fn is_mapped(&self, bank: &str) -> bool {
+        self.mappings.contains_key(bank)
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// Convert the data of a bank to values by its MIDAS type ID. Strings and structures are not
+// numbers, so give None.
+fn decode_values(type_id: u32, data: &[u8]) -> Option<Vec<f32>> {
+    fn convert<const N: usize>(data: &[u8], f: impl Fn([u8; N]) -> f32) -> Option<Vec<f32>> {
+        Some(
+            data.chunks_exact(N)
+                .map(|chunk| f(chunk.try_into().expect("Chunk has N bytes")))
+                .collect(),
+        )
+    }
+    match type_id {
+        1 => convert(data, |b: [u8; 1]| b[0] as f32),
+        2 => convert(data, |b: [u8; 1]| b[0] as i8 as f32),
+        4 => convert(data, |b| u16::from_le_bytes(b) as f32),
+        5 => convert(data, |b| i16::from_le_bytes(b) as f32),
+        6 | 8 | 11 => convert(data, |b| u32::from_le_bytes(b) as f32),
+        7 => convert(data, |b| i32::from_le_bytes(b) as f32),
+        9 => convert(data, f32::from_le_bytes),
+        10 => convert(data, |b| f64::from_le_bytes(b) as f32),
+        17 => convert(data, |b| i64::from_le_bytes(b) as f32),
+        18 => convert(data, |b| u64::from_le_bytes(b) as f32),
+        _ => None,
+    }
+}
+
+fn corrupt(reason: &str) -> SourceError {
+    SourceError::Decode(format!("MIDAS event is corrupt: {reason}"))
+}
+
+/// A DataSource producing one event per MIDAS event with a mapped bank. Begin and end of run
+/// records are skipped, keeping their run number; see get_run_number.
+#[derive(Debug)]
+pub struct MidasSource<R: Read> {
+    reader: R,
+    banks: BankMap,
+    // Only events with these IDs are decoded, if set
+    event_ids: Option<Vec<u16>>,
+    header: Option<EventHeader>,
+    run_number: Option<u32>,
+}
+
+impl MidasSource<Input> {
+    /// Open a .mid file, or a compressed one such as a .mid.lz4 file
+    pub fn open(path: &Path, banks: BankMap) -> Result<Self, SourceError> {
+        Ok(Self::new(compression::open(path)?, banks))
+    }
+}
+
+impl<R: Read> MidasSource<R> {
+    pub fn new(reader: R, banks: BankMap) -> Self {
+        Self {
+            reader,
+            banks,
+            event_ids: None,
+            header: None,
+            run_number: None,
+        }
+    }
+
+    /// Only decode events with these IDs, e.g. to leave out periodic scaler events
+    pub fn set_event_ids(&mut self, event_ids: &[u16]) {
+        self.event_ids = Some(event_ids.to_vec());
+    }
+
+    /// The header of the last event returned
+    pub fn get_header(&self) -> Option<&EventHeader> {
+        self.header.as_ref()
+    }
+
+    /// The run number of the last begin or end of run record read
+    pub fn get_run_number(&self) -> Option<u32> {
+        self.run_number
+    }
+
+    // Read the next event, returning its header and data
+    fn read_event(&mut self) -> Result<Option<(EventHeader, Vec<u8>)>, SourceError> {
+        let mut header = [0u8; 16];
+        if !read_or_eof(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+        let size = read_u32(&header, 12).unwrap_or(0) as usize;
+        if size > MAX_EVENT_SIZE {
+            return Err(corrupt("event is too large"));
+        }
+        let mut data = vec![0u8; size];
+        self.reader.read_exact(&mut data)?;
+        let header = EventHeader {
+            event_id: read_u16(&header, 0).unwrap_or(0),
+            trigger_mask: read_u16(&header, 2).unwrap_or(0),
+            serial_number: read_u32(&header, 4).unwrap_or(0),
+            timestamp: read_u32(&header, 8).unwrap_or(0),
+        };
+        Ok(Some((header, data)))
+    }
+
+    fn decode_banks(&self, data: &[u8], event: &mut DataBlob) -> Result<bool, SourceError> {
+        let banks_size = read_u32(data, 0).ok_or_else(|| corrupt("no bank header"))? as usize;
+        let flags = read_u32(data, 4).unwrap_or(0);
+        let end = (8 + banks_size).min(data.len());
+        let (header_size, is_32) = match (flags & BANK_32A != 0, flags & BANK_32 != 0) {
+            (true, _) => (16, true),
+            (false, true) => (12, true),
+            (false, false) => (8, false),
+        };
+        let mut is_mapped = false;
+        let mut offset = 8;
+        while offset + header_size <= end {
+            let name = String::from_utf8_lossy(&data[offset..offset + 4]).into_owned();
+            let (type_id, size) = match is_32 {
+                true => (read_u32(data, offset + 4), read_u32(data, offset + 8)),
+                false => (
+                    read_u16(data, offset + 4).map(u32::from),
+                    read_u16(data, offset + 6).map(u32::from),
+                ),
+            };
+            let (type_id, size) = (type_id.unwrap_or(0), size.unwrap_or(0) as usize);
+            let start = offset + header_size;
+            let bank = data
+                .get(start..start + size)
+                .ok_or_else(|| corrupt("bank runs past the end of the event"))?;
+            if self.banks.is_mapped(&name)
+                && let Some(values) = decode_values(type_id, bank)
+            {
+                self.banks.apply(&name, values, event);
+                is_mapped = true;
+            }
+            // Banks are padded to 8 bytes
+            offset = start + size.div_ceil(8) * 8;
+        }
+        Ok(is_mapped)
+    }
+}
+
+impl<R: Read> DataSource for MidasSource<R> {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        while let Some((header, data)) = self.read_event()? {
+            match header.event_id {
+                BEGIN_OF_RUN | END_OF_RUN => self.run_number = Some(header.serial_number),
+                MESSAGE => (),
+                id if self
+                    .event_ids
+                    .as_ref()
+                    .is_some_and(|ids| !ids.contains(&id)) => {}
+                _ => {
+                    let mut event = DataBlob::new();
+                    // MIDAS event times are Unix seconds
+                    event.set_timestamp(header.timestamp as f64);
+                    if self.decode_banks(&data, &mut event)? {
+                        self.header = Some(header);
+                        return Ok(Some(event));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_id: u16, serial_number: u32, banks: &[(&str, u32, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![];
+        for (name, type_id, bank) in banks {
+            data.extend(name.as_bytes());
+            data.extend(type_id.to_le_bytes());
+            data.extend((bank.len() as u32).to_le_bytes());
+            data.extend(bank);
+            data.extend(vec![0; bank.len().div_ceil(8) * 8 - bank.len()]);
+        }
+        let mut bytes = vec![];
+        bytes.extend(event_id.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend(serial_number.to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((data.len() as u32 + 8).to_le_bytes());
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend((1 | BANK_32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_midas_banks() {
+        let adc: Vec<u8> = [100u16, 200, 300]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let tdc: Vec<u8> = [1.5f32, 2.5].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut stream = event(BEGIN_OF_RUN, 77, &[]);
+        stream.extend(event(1, 0, &[("ADC0", 4, adc), ("TDC0", 9, tdc.clone())]));
+        stream.extend(event(2, 1, &[("TDC0", 9, tdc.clone())]));
+        stream.extend(event(1, 2, &[("SCLR", 6, vec![0; 4])]));
+        stream.extend(event(1, 3, &[("TDC0", 9, tdc)]));
+
+        let mut banks = BankMap::new();
+        banks.add("ADC0", BankMapping::Indexed(String::from("adc")));
+        banks.add("TDC0", BankMapping::Named(vec![String::from("t_left")]));
+        let mut source = MidasSource::new(stream.as_slice(), banks);
+        source.set_event_ids(&[1]);
+
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(source.get_run_number(), Some(77));
+        assert_eq!(event.find("adc_2"), Some(&300.0));
+        assert_eq!(event.find("t_left"), Some(&1.5));
+        assert_eq!(event.len(), 4);
+        // Event 2 is not selected and the SCLR bank is not mapped
+        source.next_event().unwrap().unwrap();
+        assert_eq!(source.get_header().unwrap().serial_number, 3);
+        assert!(source.next_event().unwrap().is_none());
+    }
+}
@@ -0,0 +1,414 @@
+//! Decodes NSCLDAQ ring items, as stored in .evt files or streamed live from a ring buffer by
+//! ringselector. Physics items are turned into events by a user Unpacker, since their bodies
+//! are whatever the readout of the experiment wrote.
+use super::compression::{self, Input};
+use super::data_blob::DataBlob;
+use super::deadtime::LossCounts;
+use super::error::SourceError;
+use super::source::{DataSource, read_or_eof};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+pub const BEGIN_RUN: u32 = 1;
+pub const END_RUN: u32 = 2;
+pub const PAUSE_RUN: u32 = 3;
+pub const RESUME_RUN: u32 = 4;
+pub const RING_FORMAT: u32 = 12;
+pub const PHYSICS_EVENT: u32 = 30;
+pub const PHYSICS_EVENT_COUNT: u32 = 31;
+
+// Items larger than this are taken to be corrupt rather than allocated
+const MAX_ITEM_SIZE: usize = 1 << 26;
+
+/// The version of NSCLDAQ which wrote the items, which decides whether their bodies start with
+/// a body header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RingFormat {
+    V10,
+    V11,
+    #[default]
+    V12,
+}
+
+impl RingFormat {
+    /// Get the format given by a ring format item, which NSCLDAQ 11 and later write first
+    pub fn from_item(item: &RingItem) -> Result<Self, SourceError> {
+        // NSCLDAQ 12 puts a zero word before the version
+        let major = match read_u32(&item.body, 0) {
+            Some(0) => read_u32(&item.body, 4),
+            word => word,
+        };
+        match major.map(|word| word & 0xffff) {
+            Some(11) => Ok(Self::V11),
+            Some(12) => Ok(Self::V12),
+            major => Err(SourceError::Decode(format!(
+                "ring format item has version {major:?}"
+            ))),
+        }
+    }
+}
+
+/// The header NSCLDAQ 11 and later put on items produced by the event builder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyHeader {
+    pub timestamp: u64,
+    pub source_id: u32,
+    pub barrier: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingItem {
+    pub item_type: u32,
+    pub body_header: Option<BodyHeader>,
+    pub body: Vec<u8>,
+}
+
+impl RingItem {
+    /// Get the 16-bit little-endian words of the body, the unit most readouts write in
+    pub fn get_words(&self) -> impl Iterator<Item = u16> + '_ {
+        self.body
+            .chunks_exact(2)
+            .map(|word| u16::from_le_bytes([word[0], word[1]]))
+    }
+}
+
+/// Turns the body of a physics item into the variables of an event
+pub trait Unpacker {
+    fn unpack(&mut self, item: &RingItem, event: &mut DataBlob) -> Result<(), SourceError>;
+}
+
+impl<F: FnMut(&RingItem, &mut DataBlob) -> Result<(), SourceError>> Unpacker for F {
+    fn unpack(&mut self, item: &RingItem, event: &mut DataBlob) -> Result<(), SourceError> {
+        self(item, event)
+    }
+}
+
+impl Unpacker for Box<dyn Unpacker> {
+    fn unpack(&mut self, item: &RingItem, event: &mut DataBlob) -> Result<(), SourceError> {
+        self.as_mut().unpack(item, event)
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Read the next ring item, returning None at a clean end of the stream
+pub fn read_item(
+    reader: &mut impl Read,
+    format: RingFormat,
+) -> Result<Option<RingItem>, SourceError> {
+    let mut header = [0u8; 8];
+    if !read_or_eof(reader, &mut header)? {
+        return Ok(None);
+    }
+    let size = read_u32(&header, 0).unwrap_or(0) as usize;
+    let item_type = read_u32(&header, 4).unwrap_or(0);
+    if !(header.len()..=MAX_ITEM_SIZE).contains(&size) {
+        return Err(SourceError::Decode(format!("ring item has size {size}")));
+    }
+    let mut body = vec![0u8; size - header.len()];
+    reader.read_exact(&mut body)?;
+
+    // NSCLDAQ 10 has no body headers, and ring format items never have one. Otherwise 11 and
+    // 12 mark a missing body header with a size of 4 and 0 respectively
+    let mut body_header = None;
+    if format != RingFormat::V10 && item_type != RING_FORMAT {
+        let header_size = read_u32(&body, 0).ok_or_else(|| {
+            SourceError::Decode(format!(
+                "ring item of type {item_type} has no body header size"
+            ))
+        })? as usize;
+        if (20..=body.len()).contains(&header_size) {
+            body_header = Some(BodyHeader {
+                timestamp: u64::from(read_u32(&body, 4).unwrap_or(0))
+                    | u64::from(read_u32(&body, 8).unwrap_or(0)) << 32,
+                source_id: read_u32(&body, 12).unwrap_or(0),
+                barrier: read_u32(&body, 16).unwrap_or(0),
+            });
+            body.drain(..header_size);
+        } else if header_size == 0 || header_size == 4 {
+            body.drain(..4);
+        } else {
+            return Err(SourceError::Decode(format!(
+                "ring item has body header size {header_size}"
+            )));
+        }
+    }
+    Ok(Some(RingItem {
+        item_type,
+        body_header,
+        body,
+    }))
+}
+
+/// A DataSource producing one event per physics item. Other items are skipped, apart from run
+/// state changes, whose run number is kept; see get_run_number, and physics event counts, from
+/// which the triggers never read are reported as source overruns by get_losses. Items are read
+/// as NSCLDAQ 12 wrote them unless set_format is called, or a ring format item says otherwise.
+#[derive(Debug)]
+pub struct NscldaqSource<R: Read, U: Unpacker> {
+    reader: R,
+    unpacker: U,
+    format: RingFormat,
+    run_number: Option<u32>,
+    // Physics items read this run, and how many had been by the last count item
+    n_read: u64,
+    n_read_at_count: u64,
+    // Triggers of this run as of the last count item
+    n_counted: u64,
+    // Triggers lost in earlier runs
+    n_missed: u64,
+    // The ringselector feeding the reader, if reading a live ring
+    child: Option<Child>,
+}
+
+impl<U: Unpacker> NscldaqSource<Input, U> {
+    pub fn open(path: &Path, unpacker: U) -> Result<Self, SourceError> {
+        Ok(Self::new(compression::open(path)?, unpacker))
+    }
+}
+
+impl<U: Unpacker> NscldaqSource<BufReader<ChildStdout>, U> {
+    /// Read physics items live from a ring, e.g. "tcp://spdaq20/ring", by running NSCLDAQ's
+    /// ringselector, which must be on the PATH
+    pub fn attach(ring: &str, unpacker: U) -> Result<Self, SourceError> {
+        let mut child = Command::new("ringselector")
+            .arg(format!("--source={ring}"))
+            .arg("--sample=PHYSICS_EVENT")
+            .arg("--non-blocking")
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| SourceError::Decode(String::from("ringselector has no output")))?;
+        let mut source = Self::new(BufReader::new(stdout), unpacker);
+        source.child = Some(child);
+        Ok(source)
+    }
+}
+
+impl<R: Read, U: Unpacker> NscldaqSource<R, U> {
+    pub fn new(reader: R, unpacker: U) -> Self {
+        Self {
+            reader,
+            unpacker,
+            format: RingFormat::default(),
+            run_number: None,
+            n_read: 0,
+            n_read_at_count: 0,
+            n_counted: 0,
+            n_missed: 0,
+            child: None,
+        }
+    }
+
+    /// Set the version of NSCLDAQ which wrote the items, e.g. to read NSCLDAQ 10 data, which has
+    /// no ring format item
+    pub fn set_format(&mut self, format: RingFormat) {
+        self.format = format;
+    }
+
+    pub fn get_format(&self) -> RingFormat {
+        self.format
+    }
+
+    /// The run number of the last state change item read
+    pub fn get_run_number(&self) -> Option<u32> {
+        self.run_number
+    }
+}
+
+impl<R: Read, U: Unpacker> DataSource for NscldaqSource<R, U> {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        while let Some(item) = read_item(&mut self.reader, self.format)? {
+            match item.item_type {
+                RING_FORMAT => self.format = RingFormat::from_item(&item)?,
+                PHYSICS_EVENT => {
+                    self.n_read += 1;
+                    let mut event = DataBlob::new();
+                    self.unpacker.unpack(&item, &mut event)?;
+                    return Ok(Some(event));
+                }
+                // The count follows the time offset, the offset divisor from NSCLDAQ 11, and
+                // the timestamp
+                PHYSICS_EVENT_COUNT => {
+                    let offset = match self.format {
+                        RingFormat::V10 => 8,
+                        RingFormat::V11 | RingFormat::V12 => 12,
+                    };
+                    let low = read_u32(&item.body, offset);
+                    let high = read_u32(&item.body, offset + 4);
+                    if let (Some(low), Some(high)) = (low, high) {
+                        self.n_counted = u64::from(low) | u64::from(high) << 32;
+                        self.n_read_at_count = self.n_read;
+                    }
+                }
+                BEGIN_RUN | END_RUN | PAUSE_RUN | RESUME_RUN => {
+                    // Counts start again with each run
+                    if item.item_type == BEGIN_RUN {
+                        self.n_missed += self.n_counted.saturating_sub(self.n_read_at_count);
+                        self.n_read = 0;
+                        self.n_read_at_count = 0;
+                        self.n_counted = 0;
+                    }
+                    self.run_number = read_u32(&item.body, 0).or(self.run_number);
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(item_type = item.item_type, run = ?self.run_number, "Run state changed");
+                }
+                _ => (),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Triggers counted by the DAQ, as of the last physics event count item, which were never
+    /// read, e.g. because ringselector sampled the ring while the analysis was behind
+    fn get_losses(&self) -> LossCounts {
+        LossCounts {
+            source_overrun: self.n_missed + self.n_counted.saturating_sub(self.n_read_at_count),
+            ..LossCounts::default()
+        }
+    }
+
+    /// Stop a live ringselector; items it already sent are still read
+    fn stop(&mut self) {
+        if let Some(child) = &mut self.child {
+            // The process may already have exited
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.child = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(item_type: u32, body_header: Option<u64>, body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let header: Vec<u8> = match body_header {
+            Some(timestamp) => [20u32.to_le_bytes().as_slice(), &timestamp.to_le_bytes()]
+                .concat()
+                .into_iter()
+                .chain([0u8; 8])
+                .collect(),
+            None => 0u32.to_le_bytes().to_vec(),
+        };
+        let size = 8 + header.len() + body.len();
+        bytes.extend((size as u32).to_le_bytes());
+        bytes.extend(item_type.to_le_bytes());
+        bytes.extend(header);
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn test_ring_items() {
+        let mut stream = item(BEGIN_RUN, None, &42u32.to_le_bytes());
+        stream.extend(item(PHYSICS_EVENT, Some(1000), &[1, 0, 2, 0]));
+        stream.extend(item(PHYSICS_EVENT_COUNT, None, &[0; 16]));
+        stream.extend(item(PHYSICS_EVENT, None, &[3, 0]));
+
+        let unpacker = |item: &RingItem, event: &mut DataBlob| {
+            for (channel, word) in item.get_words().enumerate() {
+                event.insert(&format!("adc_{channel}"), word as f32);
+            }
+            if let Some(header) = item.body_header {
+                event.insert("timestamp", header.timestamp as f32);
+            }
+            Ok(())
+        };
+        let mut source = NscldaqSource::new(stream.as_slice(), unpacker);
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("adc_1"), Some(&2.0));
+        assert_eq!(event.find("timestamp"), Some(&1000.0));
+        assert_eq!(source.get_run_number(), Some(42));
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.len(), 1);
+        assert!(source.next_event().unwrap().is_none());
+
+        let truncated = &item(PHYSICS_EVENT, None, &[1, 0])[..10];
+        assert!(read_item(&mut &truncated[..], RingFormat::V12).is_err());
+    }
+
+    #[test]
+    fn test_ring_formats() {
+        // NSCLDAQ 10 items have no body header, so their bodies may start with any word
+        let v10_item = |item_type: u32, body: &[u8]| {
+            let mut bytes = ((8 + body.len()) as u32).to_le_bytes().to_vec();
+            bytes.extend(item_type.to_le_bytes());
+            bytes.extend(body);
+            bytes
+        };
+        let small = [2u32.to_le_bytes(), 7u32.to_le_bytes()].concat();
+        // Long enough to pass for a body header
+        let large = [24u32.to_le_bytes(), [0; 4], [0; 4], [0; 4], [0; 4], [0; 4]].concat();
+        let mut stream = v10_item(BEGIN_RUN, &5u32.to_le_bytes());
+        stream.extend(v10_item(PHYSICS_EVENT, &small));
+        stream.extend(v10_item(PHYSICS_EVENT, &large));
+        let count = [[0u8; 8].as_slice(), &3u64.to_le_bytes()].concat();
+        stream.extend(v10_item(PHYSICS_EVENT_COUNT, &count));
+
+        let unpacker = |item: &RingItem, event: &mut DataBlob| {
+            assert!(item.body_header.is_none());
+            event.insert("first", read_u32(&item.body, 0).unwrap() as f32);
+            event.insert("length", item.body.len() as f32);
+            Ok(())
+        };
+        let mut source = NscldaqSource::new(stream.as_slice(), unpacker);
+        source.set_format(RingFormat::V10);
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("first"), Some(&2.0));
+        assert_eq!(event.find("length"), Some(&8.0));
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("first"), Some(&24.0));
+        assert_eq!(event.find("length"), Some(&24.0));
+        assert_eq!(source.get_run_number(), Some(5));
+        assert!(source.next_event().unwrap().is_none());
+        assert_eq!(source.get_losses().source_overrun, 1);
+
+        // Read as NSCLDAQ 12 data, the small word is not a body header size
+        let mut source = NscldaqSource::new(stream.as_slice(), unpacker);
+        assert!(source.next_event().is_err());
+
+        // A ring format item sets the format of the items after it
+        let mut v11_format = 11u16.to_le_bytes().to_vec();
+        v11_format.extend(0u16.to_le_bytes());
+        let mut stream = v10_item(RING_FORMAT, &v11_format);
+        stream.extend(item(PHYSICS_EVENT, None, &[1, 0]));
+        let mut source =
+            NscldaqSource::new(stream.as_slice(), |_: &RingItem, _: &mut DataBlob| Ok(()));
+        source.set_format(RingFormat::V10);
+        assert!(source.next_event().unwrap().is_some());
+        assert_eq!(source.get_format(), RingFormat::V11);
+    }
+
+    #[test]
+    fn test_source_overrun() {
+        let count = |n_triggers: u64| {
+            let body = [[0u8; 12].as_slice(), &n_triggers.to_le_bytes()].concat();
+            item(PHYSICS_EVENT_COUNT, None, &body)
+        };
+        let mut stream = item(BEGIN_RUN, None, &1u32.to_le_bytes());
+        stream.extend(item(PHYSICS_EVENT, None, &[]));
+        stream.extend(count(4));
+        stream.extend(item(PHYSICS_EVENT, None, &[]));
+        stream.extend(item(BEGIN_RUN, None, &2u32.to_le_bytes()));
+        stream.extend(item(PHYSICS_EVENT, None, &[]));
+        stream.extend(count(2));
+
+        let mut source =
+            NscldaqSource::new(stream.as_slice(), |_: &RingItem, _: &mut DataBlob| Ok(()));
+        source.next_event().unwrap();
+        source.next_event().unwrap();
+        assert_eq!(source.get_losses().source_overrun, 3);
+        source.next_event().unwrap();
+        assert!(source.next_event().unwrap().is_none());
+        assert_eq!(source.get_losses().source_overrun, 4);
+    }
+}
@@ -0,0 +1,124 @@
+use super::alert::Alert;
+use super::watchdog::SourceAlert;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use uuid::Uuid;
+
+/// The kinds of change a ResourceManager can notify observers of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Fill,
+    HistogramAdded,
+    HistogramRemoved,
+    CutModified,
+    Alert,
+}
+
+/// A change to the state of a ResourceManager
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManagerEvent {
+    Filled {
+        histogram_id: Uuid,
+        bin: usize,
+    },
+    HistogramAdded(Uuid),
+    HistogramRemoved(Uuid),
+    /// A cut was created, changed, or removed
+    CutModified(Uuid),
+    /// A monitoring check started or stopped failing
+    Alert(Alert),
+    /// A watched source stalled or recovered
+    SourceAlert(SourceAlert),
+}
+
+impl ManagerEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Filled { .. } => EventKind::Fill,
+            Self::HistogramAdded(_) => EventKind::HistogramAdded,
+            Self::HistogramRemoved(_) => EventKind::HistogramRemoved,
+            Self::CutModified(_) => EventKind::CutModified,
+            Self::Alert(_) | Self::SourceAlert(_) => EventKind::Alert,
+        }
+    }
+}
+
+pub type ObserverCallback = Box<dyn FnMut(&ManagerEvent) + Send>;
+
+enum Sink {
+    Callback(ObserverCallback),
+    Channel(Sender<ManagerEvent>),
+}
+
+struct Observer {
+    id: Uuid,
+    kinds: Vec<EventKind>,
+    sink: Sink,
+}
+
+/// The set of callbacks and channels listening to a ResourceManager
+#[derive(Default)]
+pub struct Observers {
+    observers: Vec<Observer>,
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Observers")
+            .field("n_observers", &self.observers.len())
+            .finish()
+    }
+}
+
+impl Observers {
+    pub fn add_callback(&mut self, kinds: &[EventKind], callback: ObserverCallback) -> Uuid {
+        self.add(kinds, Sink::Callback(callback))
+    }
+
+    pub fn add_channel(&mut self, kinds: &[EventKind]) -> (Uuid, Receiver<ManagerEvent>) {
+        let (sender, receiver) = channel();
+        (self.add(kinds, Sink::Channel(sender)), receiver)
+    }
+
+    fn add(&mut self, kinds: &[EventKind], sink: Sink) -> Uuid {
+        let id = Uuid::new_v4();
+        self.observers.push(Observer {
+            id,
+            kinds: kinds.to_vec(),
+            sink,
+        });
+        id
+    }
+
+    /// Remove an observer, returning false if it did not exist
+    pub fn remove(&mut self, id: &Uuid) -> bool {
+        let n_observers = self.observers.len();
+        self.observers.retain(|observer| observer.id != *id);
+        n_observers != self.observers.len()
+    }
+
+    /// Check if anything is listening for a kind of event, so that hot paths can skip building
+    /// events nobody will see
+    pub fn is_listening(&self, kind: EventKind) -> bool {
+        self.observers
+            .iter()
+            .any(|observer| observer.kinds.contains(&kind))
+    }
+
+    /// Send an event to every interested observer. Channels whose receiver has been dropped are
+    /// removed.
+    pub fn notify(&mut self, event: ManagerEvent) {
+        let kind = event.kind();
+        self.observers.retain_mut(|observer| {
+            if !observer.kinds.contains(&kind) {
+                return true;
+            }
+            match &mut observer.sink {
+                Sink::Callback(callback) => {
+                    callback(&event);
+                    true
+                }
+                Sink::Channel(sender) => sender.send(event.clone()).is_ok(),
+            }
+        });
+    }
+}
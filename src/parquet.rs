@@ -0,0 +1,350 @@
+//! Processed events written to Parquet, after aliases, transforms, and calibrations, so that an
+//! analysis configuration can convert data for offline tools as well as fill histograms.
+//!
+//! The columns come from a Schema rather than from the events, so that every file written with
+//! the same schema has the same columns: the event timestamp first, as DOUBLE, then every
+//! declared variable in name order. Values are FLOAT, flags are unsigned INT64, and arrays are
+//! LISTs of FLOAT. Every column is nullable, and is null for events without the variable.
+use super::cut::CutEvaluation;
+use super::data_blob::{DataBlob, TIMESTAMP_VARIABLE};
+use super::error::RecordError;
+use super::filter::GateCondition;
+use super::schema::{Schema, VariableKind};
+use ::parquet::basic::{Compression, IntType, LogicalType, Repetition, Type as PhysicalType};
+use ::parquet::data_type::{DataType, DoubleType, FloatType, Int64Type};
+use ::parquet::errors::ParquetError;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::{SerializedColumnWriter, SerializedFileWriter};
+use ::parquet::schema::types::{Type, TypePtr};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The number of events buffered before they are written as a row group
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetSpec {
+    pub path: PathBuf,
+    /// The variables written. Variables of an event which are not declared, or are of another
+    /// kind than declared, are not written.
+    pub schema: Schema,
+    /// Only events satisfying the condition are written, if given
+    pub condition: Option<GateCondition>,
+    pub row_group_size: usize,
+}
+
+impl ParquetSpec {
+    pub fn new(path: PathBuf, schema: Schema) -> Self {
+        Self {
+            path,
+            schema,
+            condition: None,
+            row_group_size: DEFAULT_ROW_GROUP_SIZE,
+        }
+    }
+}
+
+// The values of one column for the buffered events, with the definition levels marking nulls
+// and, for lists, the repetition levels marking where each event starts
+#[derive(Debug, Default)]
+struct Column<T> {
+    values: Vec<T>,
+    definitions: Vec<i16>,
+    repetitions: Vec<i16>,
+}
+
+impl<T: Copy> Column<T> {
+    fn push(&mut self, value: Option<T>) {
+        if let Some(value) = value {
+            self.values.push(value);
+        }
+        self.definitions.push(i16::from(value.is_some()));
+    }
+
+    // A null list has level 0, an empty one 1, and each entry 2
+    fn push_list(&mut self, values: Option<&[T]>) {
+        match values {
+            None | Some([]) => {
+                self.definitions.push(i16::from(values.is_some()));
+                self.repetitions.push(0);
+            }
+            Some(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    self.values.push(*value);
+                    self.definitions.push(2);
+                    self.repetitions.push(i16::from(index > 0));
+                }
+            }
+        }
+    }
+
+    fn write<D: DataType<T = T>>(
+        &mut self,
+        writer: &mut SerializedColumnWriter<'_>,
+    ) -> Result<(), ParquetError> {
+        let repetitions = (!self.repetitions.is_empty()).then_some(self.repetitions.as_slice());
+        writer
+            .typed::<D>()
+            .write_batch(&self.values, Some(&self.definitions), repetitions)?;
+        self.values.clear();
+        self.definitions.clear();
+        self.repetitions.clear();
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Buffer {
+    Timestamp(Column<f64>),
+    Value(String, Column<f32>),
+    Flag(String, Column<i64>),
+    Array(String, Column<f32>),
+}
+
+impl Buffer {
+    fn push(&mut self, blob: &DataBlob) {
+        match self {
+            Self::Timestamp(column) => column.push(blob.get_timestamp()),
+            Self::Value(name, column) => column.push(blob.find(name).copied()),
+            // The bits are kept as they are, in a column marked unsigned
+            Self::Flag(name, column) => column.push(blob.find_flag(name).map(|bits| bits as i64)),
+            Self::Array(name, column) => column.push_list(blob.find_array(name)),
+        }
+    }
+
+    fn write(&mut self, writer: &mut SerializedColumnWriter<'_>) -> Result<(), ParquetError> {
+        match self {
+            Self::Timestamp(column) => column.write::<DoubleType>(writer),
+            Self::Value(_, column) | Self::Array(_, column) => column.write::<FloatType>(writer),
+            Self::Flag(_, column) => column.write::<Int64Type>(writer),
+        }
+    }
+}
+
+fn primitive(
+    name: &str,
+    physical: PhysicalType,
+    repetition: Repetition,
+) -> Result<TypePtr, ParquetError> {
+    Ok(Arc::new(
+        Type::primitive_type_builder(name, physical)
+            .with_repetition(repetition)
+            .build()?,
+    ))
+}
+
+fn build_schema(schema: &Schema) -> Result<(TypePtr, Vec<Buffer>), ParquetError> {
+    let mut fields = vec![primitive(
+        TIMESTAMP_VARIABLE,
+        PhysicalType::DOUBLE,
+        Repetition::OPTIONAL,
+    )?];
+    let mut buffers = vec![Buffer::Timestamp(Column::default())];
+    let mut variables: Vec<(&str, VariableKind)> = schema.iter().collect();
+    variables.sort_unstable_by_key(|(name, _)| *name);
+    for (name, kind) in variables {
+        let (field, buffer) = match kind {
+            VariableKind::Value => (
+                primitive(name, PhysicalType::FLOAT, Repetition::OPTIONAL)?,
+                Buffer::Value(name.to_string(), Column::default()),
+            ),
+            VariableKind::Flag => (
+                Arc::new(
+                    Type::primitive_type_builder(name, PhysicalType::INT64)
+                        .with_repetition(Repetition::OPTIONAL)
+                        .with_logical_type(Some(LogicalType::Integer(IntType {
+                            bit_width: 64,
+                            is_signed: false,
+                        })))
+                        .build()?,
+                ),
+                Buffer::Flag(name.to_string(), Column::default()),
+            ),
+            VariableKind::Array => {
+                let element = primitive("element", PhysicalType::FLOAT, Repetition::REQUIRED)?;
+                let list = Type::group_type_builder("list")
+                    .with_repetition(Repetition::REPEATED)
+                    .with_fields(vec![element])
+                    .build()?;
+                (
+                    Arc::new(
+                        Type::group_type_builder(name)
+                            .with_repetition(Repetition::OPTIONAL)
+                            .with_logical_type(Some(LogicalType::List))
+                            .with_fields(vec![Arc::new(list)])
+                            .build()?,
+                    ),
+                    Buffer::Array(name.to_string(), Column::default()),
+                )
+            }
+        };
+        fields.push(field);
+        buffers.push(buffer);
+    }
+    let message = Type::group_type_builder("event")
+        .with_fields(fields)
+        .build()?;
+    Ok((Arc::new(message), buffers))
+}
+
+/// Writes events to a Parquet file, a row group at a time. The file is only complete once the
+/// sink is finished.
+pub struct ParquetSink {
+    condition: Option<GateCondition>,
+    writer: SerializedFileWriter<BufWriter<File>>,
+    buffers: Vec<Buffer>,
+    row_group_size: usize,
+    n_buffered: usize,
+    n_events: usize,
+}
+
+impl std::fmt::Debug for ParquetSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ParquetSink")
+            .field("condition", &self.condition)
+            .field("n_columns", &self.buffers.len())
+            .field("n_events", &self.n_events)
+            .finish()
+    }
+}
+
+impl ParquetSink {
+    pub fn create(spec: &ParquetSpec) -> Result<Self, RecordError> {
+        let (schema, buffers) = build_schema(&spec.schema)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let file = BufWriter::new(File::create(&spec.path)?);
+        Ok(Self {
+            condition: spec.condition.clone(),
+            writer: SerializedFileWriter::new(file, schema, Arc::new(properties))?,
+            buffers,
+            row_group_size: spec.row_group_size.max(1),
+            n_buffered: 0,
+            n_events: 0,
+        })
+    }
+
+    /// Write an event if it satisfies the condition of the sink
+    pub fn process(
+        &mut self,
+        blob: &DataBlob,
+        cuts: &mut CutEvaluation,
+    ) -> Result<(), RecordError> {
+        match &self.condition {
+            Some(condition) if !condition.is_satisfied(cuts) => Ok(()),
+            _ => self.write(blob),
+        }
+    }
+
+    /// Write an event, whatever the condition of the sink
+    pub fn write(&mut self, blob: &DataBlob) -> Result<(), RecordError> {
+        for buffer in self.buffers.iter_mut() {
+            buffer.push(blob);
+        }
+        self.n_buffered += 1;
+        self.n_events += 1;
+        if self.n_buffered >= self.row_group_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_n_events(&self) -> usize {
+        self.n_events
+    }
+
+    /// Write the buffered events as a row group
+    pub fn flush(&mut self) -> Result<(), RecordError> {
+        if self.n_buffered == 0 {
+            return Ok(());
+        }
+        let mut row_group = self.writer.next_row_group()?;
+        for buffer in self.buffers.iter_mut() {
+            let Some(mut column) = row_group.next_column()? else {
+                break;
+            };
+            buffer.write(&mut column)?;
+            column.close()?;
+        }
+        row_group.close()?;
+        self.n_buffered = 0;
+        Ok(())
+    }
+
+    /// Write the buffered events and close the file, returning the number of events written
+    pub fn finish(mut self) -> Result<usize, RecordError> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(self.n_events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use ::parquet::record::Field;
+
+    #[test]
+    fn test_parquet_sink() {
+        let path = std::env::temp_dir().join(format!("{}.parquet", uuid::Uuid::new_v4()));
+        let schema = Schema::new()
+            .with("si_e", VariableKind::Value)
+            .with("pileup", VariableKind::Flag)
+            .with("gamma_e", VariableKind::Array);
+        let mut spec = ParquetSpec::new(path.clone(), schema);
+        spec.row_group_size = 2;
+        let mut sink = ParquetSink::create(&spec).unwrap();
+
+        let mut first = DataBlob::new();
+        first.set_timestamp(10.0);
+        first.insert("si_e", 1.5);
+        first.insert("ignored", 3.0);
+        first.insert_flag("pileup", u64::MAX);
+        first.insert_array("gamma_e", vec![511.0, 1274.5]);
+        let mut second = DataBlob::new();
+        second.insert_array("gamma_e", vec![]);
+        sink.write(&first).unwrap();
+        sink.write(&second).unwrap();
+        sink.write(&DataBlob::new()).unwrap();
+        assert_eq!(sink.finish().unwrap(), 3);
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        let columns: Vec<String> = reader
+            .metadata()
+            .file_metadata()
+            .schema_descr()
+            .root_schema()
+            .get_fields()
+            .iter()
+            .map(|field| field.name().to_string())
+            .collect();
+        assert_eq!(
+            columns,
+            vec![TIMESTAMP_VARIABLE, "gamma_e", "pileup", "si_e"]
+        );
+        let rows: Vec<Vec<Field>> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.unwrap()
+                    .get_column_iter()
+                    .map(|(_, field)| field.clone())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0][0], Field::Double(10.0));
+        assert!(matches!(&rows[0][1], Field::ListInternal(list) if list.len() == 2));
+        assert_eq!(rows[0][2], Field::ULong(u64::MAX));
+        assert_eq!(rows[0][3], Field::Float(1.5));
+        assert!(matches!(&rows[1][1], Field::ListInternal(list) if list.len() == 0));
+        assert_eq!(rows[1][3], Field::Null);
+        assert_eq!(rows[2][1], Field::Null);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
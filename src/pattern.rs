@@ -0,0 +1,91 @@
+//! Glob patterns over variable and histogram names. A '*' matches any run of characters
+//! (including none) and a '?' matches exactly one character; everything else matches itself.
+//! A '*' also matches the separators of dotted variable paths, so "clover.*" matches every
+//! variable below clover.
+
+pub const WILDCARD: char = '*';
+pub const SINGLE: char = '?';
+
+/// Check if a name contains any wildcards
+pub fn is_pattern(name: &str) -> bool {
+    name.contains([WILDCARD, SINGLE])
+}
+
+pub fn matches(pattern: &str, name: &str) -> bool {
+    captures(pattern, name).is_some()
+}
+
+/// Match a name against a pattern, returning the text matched by each wildcard in order
+pub fn captures<'a>(pattern: &str, name: &'a str) -> Option<Vec<&'a str>> {
+    let mut captured = vec![];
+    if capture_from(pattern, name, 0, &mut captured) {
+        Some(captured)
+    } else {
+        None
+    }
+}
+
+fn capture_from<'a>(
+    pattern: &str,
+    name: &'a str,
+    offset: usize,
+    captured: &mut Vec<&'a str>,
+) -> bool {
+    let rest = &name[offset..];
+    let mut chars = pattern.chars();
+    match chars.next() {
+        None => rest.is_empty(),
+        Some(WILDCARD) => {
+            let pattern = chars.as_str();
+            // Prefer the shortest match so that "a_*_b" against "a_1_b_2_b" captures "1"
+            for (idx, _) in rest
+                .char_indices()
+                .chain(std::iter::once((rest.len(), ' ')))
+            {
+                captured.push(&rest[..idx]);
+                if capture_from(pattern, name, offset + idx, captured) {
+                    return true;
+                }
+                captured.truncate(captured.len() - 1);
+            }
+            false
+        }
+        Some(SINGLE) => match rest.chars().next() {
+            Some(c) => {
+                captured.push(&rest[..c.len_utf8()]);
+                if capture_from(chars.as_str(), name, offset + c.len_utf8(), captured) {
+                    return true;
+                }
+                captured.pop();
+                false
+            }
+            None => false,
+        },
+        Some(literal) => {
+            rest.starts_with(literal)
+                && capture_from(chars.as_str(), name, offset + literal.len_utf8(), captured)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        assert!(is_pattern("sipm_*_energy"));
+        assert!(!is_pattern("sipm_0_energy"));
+        assert!(matches("sipm_*_energy", "sipm_12_energy"));
+        assert!(matches("sipm_*_energy", "sipm__energy"));
+        assert!(!matches("sipm_*_energy", "sipm_12_time"));
+        assert!(matches("sipm_?", "sipm_3"));
+        assert!(!matches("sipm_?", "sipm_31"));
+        assert_eq!(
+            captures("sipm_*_energy", "sipm_12_energy"),
+            Some(vec!["12"])
+        );
+        assert_eq!(captures("*_*", "a_b_c"), Some(vec!["a", "b_c"]));
+        assert_eq!(captures("exact", "exact"), Some(vec![]));
+    }
+}
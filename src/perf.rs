@@ -0,0 +1,112 @@
+use super::time::{Duration, Instant};
+
+/// Where the time spent processing events went, to find the bottlenecks of a configuration
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PerfReport {
+    /// Events processed, not counting events rejected by run control
+    pub events: u64,
+    /// Wall-clock time since profiling started or was reset
+    pub elapsed: Duration,
+    /// Time spent processing events
+    pub busy: Duration,
+    pub recording: Duration,
+    pub transforms: Duration,
+    /// Time spent in filters, including evaluating the cuts they ask about
+    pub filters: Duration,
+    /// Time spent evaluating the cuts gating histograms
+    pub cuts: Duration,
+    /// Time spent finding the variables of histograms in events
+    pub lookups: Duration,
+    /// Time spent filling histograms and notifying fill observers
+    pub fills: Duration,
+}
+
+impl PerfReport {
+    pub fn get_events_per_second(&self) -> Option<f64> {
+        let seconds = self.elapsed.as_secs_f64();
+        (seconds > 0.0).then(|| self.events as f64 / seconds)
+    }
+
+    /// Get the mean time spent processing an event
+    pub fn get_mean_latency(&self) -> Option<Duration> {
+        let events = u32::try_from(self.events)
+            .ok()
+            .filter(|events| *events > 0)?;
+        Some(self.busy / events)
+    }
+
+    fn add(&mut self, other: &PerfReport) {
+        self.events += other.events;
+        self.busy += other.busy;
+        self.recording += other.recording;
+        self.transforms += other.transforms;
+        self.filters += other.filters;
+        self.cuts += other.cuts;
+        self.lookups += other.lookups;
+        self.fills += other.fills;
+    }
+}
+
+/// Accumulates the PerfReports of processed events
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    report: PerfReport,
+    started: Instant,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            report: PerfReport::default(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Profiler {
+    pub fn add(&mut self, times: &PerfReport) {
+        self.report.add(times);
+    }
+
+    pub fn get_report(&self) -> PerfReport {
+        PerfReport {
+            elapsed: self.started.elapsed(),
+            ..self.report
+        }
+    }
+}
+
+/// Measures the time between laps when profiling, and does nothing otherwise so that unprofiled
+/// managers don't pay for reading the clock
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    times: Option<(Instant, Instant)>,
+}
+
+impl Stopwatch {
+    pub fn new(enabled: bool) -> Self {
+        let now = enabled.then(Instant::now);
+        Self {
+            times: now.map(|now| (now, now)),
+        }
+    }
+
+    /// Get the time since the last lap
+    pub fn lap(&mut self) -> Duration {
+        match &mut self.times {
+            Some((_, last)) => {
+                let now = Instant::now();
+                let lap = now - *last;
+                *last = now;
+                lap
+            }
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Get the time since the stopwatch was made
+    pub fn get_total(&self) -> Duration {
+        self.times
+            .map_or(Duration::ZERO, |(start, _)| start.elapsed())
+    }
+}
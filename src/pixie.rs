@@ -0,0 +1,170 @@
+//! Decodes XIA Pixie-16 list-mode data into hits for an EventBuilder. Each hit is from detector
+//! "c{crate}_s{slot}_ch{channel}" with the values energy, pileup, and, where recorded, cfd (the
+//! sub-sample time correction in ns), esum_0 to esum_3, and qdc_0 to qdc_7. Timestamps are in
+//! ns so that coincidence windows do not depend on the module type. Traces are skipped.
+use super::builder::{Hit, HitSource};
+use super::compression::{self, Input};
+use super::error::SourceError;
+use super::source::read_or_eof;
+use std::io::Read;
+use std::path::Path;
+
+/// The ADC sampling rate of the modules, which sets the clock of the timestamps and the layout
+/// of the CFD word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixieFrequency {
+    Mhz100,
+    Mhz250,
+    Mhz500,
+}
+
+impl PixieFrequency {
+    // The time per timestamp tick, in ns
+    fn get_tick(&self) -> u64 {
+        match self {
+            Self::Mhz100 | Self::Mhz500 => 10,
+            Self::Mhz250 => 8,
+        }
+    }
+
+    // Get the CFD correction in ns from the top half of the third header word, or None if the
+    // CFD was forced to trigger
+    fn get_cfd(&self, word: u32) -> Option<f32> {
+        let cfd = word >> 16;
+        match self {
+            Self::Mhz100 => (cfd & 0x8000 == 0).then(|| (cfd & 0x7fff) as f32 / 32768.0 * 10.0),
+            Self::Mhz250 => (cfd & 0x8000 == 0).then(|| {
+                let source = ((cfd >> 14) & 0x1) as f32;
+                ((cfd & 0x3fff) as f32 / 16384.0 - source) * 4.0
+            }),
+            Self::Mhz500 => {
+                let source = (cfd >> 13) & 0x7;
+                (source != 7).then(|| ((cfd & 0x1fff) as f32 / 8192.0 + source as f32 - 1.0) * 2.0)
+            }
+        }
+    }
+}
+
+/// Reads hits from a stream of Pixie-16 list-mode events, such as the output of a module FIFO
+/// read or a file of them. Hits are tagged with crate * 16 + slot as the source for the event
+/// builder.
+#[derive(Debug)]
+pub struct PixieReader<R: Read> {
+    reader: R,
+    frequency: PixieFrequency,
+}
+
+impl PixieReader<Input> {
+    pub fn open(path: &Path, frequency: PixieFrequency) -> Result<Self, SourceError> {
+        Ok(Self::new(compression::open(path)?, frequency))
+    }
+}
+
+impl<R: Read> PixieReader<R> {
+    pub fn new(reader: R, frequency: PixieFrequency) -> Self {
+        Self { reader, frequency }
+    }
+
+    fn read_words(&mut self, n_words: usize) -> Result<Vec<u32>, SourceError> {
+        let mut bytes = vec![0u8; n_words * 4];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect())
+    }
+}
+
+impl<R: Read> HitSource for PixieReader<R> {
+    fn next_hit(&mut self) -> Result<Option<(usize, Hit)>, SourceError> {
+        let mut first = [0u8; 4];
+        if !read_or_eof(&mut self.reader, &mut first)? {
+            return Ok(None);
+        }
+        let first = u32::from_le_bytes(first);
+        let channel = first & 0xf;
+        let slot = (first >> 4) & 0xf;
+        let crate_id = (first >> 8) & 0xf;
+        let header_length = ((first >> 12) & 0x1f) as usize;
+        let event_length = ((first >> 17) & 0x3fff) as usize;
+        let pileup = first >> 31;
+        // The extra header words are the energy sums, QDC sums, and external timestamp, each
+        // optional
+        let n_extra = header_length.wrapping_sub(4);
+        if header_length < 4 || event_length < header_length || !n_extra.is_multiple_of(2) {
+            return Err(SourceError::Decode(format!(
+                "Pixie event has header length {header_length} and event length {event_length}"
+            )));
+        }
+
+        let words = self.read_words(header_length - 1)?;
+        let ticks = u64::from(words[0]) | u64::from(words[1] & 0xffff) << 32;
+        let mut values = vec![
+            (String::from("energy"), (words[2] & 0xffff) as f32),
+            (String::from("pileup"), pileup as f32),
+        ];
+        if let Some(cfd) = self.frequency.get_cfd(words[1]) {
+            values.push((String::from("cfd"), cfd));
+        }
+        let has_external_time = n_extra % 4 == 2;
+        let n_sums = n_extra - if has_external_time { 2 } else { 0 };
+        let mut extra = words[3..].iter();
+        if n_sums == 4 || n_sums == 12 {
+            for (i, sum) in extra.by_ref().take(4).enumerate() {
+                values.push((format!("esum_{i}"), *sum as f32));
+            }
+        }
+        if n_sums >= 8 {
+            for (i, sum) in extra.by_ref().take(8).enumerate() {
+                values.push((format!("qdc_{i}"), *sum as f32));
+            }
+        }
+
+        let trace_bytes = (event_length - header_length) as u64 * 4;
+        std::io::copy(
+            &mut (&mut self.reader).take(trace_bytes),
+            &mut std::io::sink(),
+        )?;
+        let hit = Hit {
+            detector: format!("c{crate_id}_s{slot}_ch{channel}"),
+            timestamp: ticks * self.frequency.get_tick(),
+            values,
+        };
+        Ok(Some(((crate_id * 16 + slot) as usize, hit)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(header_length: u32, trace_words: u32, extra: &[u32]) -> Vec<u8> {
+        let event_length = header_length + trace_words;
+        let first = 5 | 2 << 4 | header_length << 12 | event_length << 17;
+        let cfd = 16384u32;
+        let mut words = vec![first, 100, 1 | cfd << 16, 1234 | (trace_words * 2) << 16];
+        words.extend(extra);
+        words.extend(vec![0; trace_words as usize]);
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_pixie_events() {
+        let mut stream = event(4, 10, &[]);
+        stream.extend(event(16, 0, &(0..12).collect::<Vec<u32>>()));
+        let mut reader = PixieReader::new(stream.as_slice(), PixieFrequency::Mhz100);
+
+        let (source, hit) = reader.next_hit().unwrap().unwrap();
+        assert_eq!(source, 2);
+        assert_eq!(hit.detector, "c0_s2_ch5");
+        assert_eq!(hit.timestamp, ((1u64 << 32) + 100) * 10);
+        assert_eq!(hit.values[0], (String::from("energy"), 1234.0));
+        assert_eq!(hit.values[2], (String::from("cfd"), 5.0));
+
+        let (_, hit) = reader.next_hit().unwrap().unwrap();
+        assert_eq!(hit.values.len(), 15);
+        assert_eq!(hit.values[3], (String::from("esum_0"), 0.0));
+        assert_eq!(hit.values[14], (String::from("qdc_7"), 11.0));
+        assert!(reader.next_hit().unwrap().is_none());
+    }
+}
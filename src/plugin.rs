@@ -0,0 +1,217 @@
+//! Unpackers and transforms defined outside this crate, found by name so that lab-specific code
+//! can be driven by the pipeline without living in it.
+//!
+//! A plugin is a crate with a function registering its factories with a PluginRegistry, exported
+//! with declare_plugin!. Linked statically, the function is added with PluginRegistry::add. Built
+//! as a cdylib, the library is loaded with PluginRegistry::load when the plugins feature is
+//! enabled. Rust has no stable ABI, so a dynamic plugin must be built with the same compiler and
+//! version of this crate as the program loading it; PLUGIN_API_VERSION guards against the most
+//! common mismatch.
+use super::error::PluginError;
+use super::nscldaq::Unpacker;
+use super::transform::EventTransform;
+use rustc_hash::FxHashMap;
+
+/// Bumped whenever Unpacker, EventTransform, or PluginRegistry change incompatibly
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+/// The symbols declare_plugin! exports, which PluginRegistry::load looks up
+pub const VERSION_SYMBOL: &str = "specter_plugin_version";
+pub const REGISTER_SYMBOL: &str = "specter_register_plugin";
+
+pub type UnpackerFactory = fn(&serde_json::Value) -> Result<Box<dyn Unpacker>, PluginError>;
+pub type TransformFactory = fn(&serde_json::Value) -> Result<Box<dyn EventTransform>, PluginError>;
+
+/// Export the function registering the factories of a plugin, so that the plugin can be loaded
+/// as a dynamic library. The function takes a &mut PluginRegistry.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($register:path) => {
+        #[unsafe(no_mangle)]
+        pub static specter_plugin_version: u32 = $crate::plugin::PLUGIN_API_VERSION;
+
+        #[unsafe(no_mangle)]
+        pub fn specter_register_plugin(registry: &mut $crate::plugin::PluginRegistry) {
+            $register(registry)
+        }
+    };
+}
+
+/// Maps names to the factories building unpackers and transforms from JSON parameters
+#[derive(Debug, Default)]
+pub struct PluginRegistry {
+    unpackers: FxHashMap<String, UnpackerFactory>,
+    transforms: FxHashMap<String, TransformFactory>,
+    #[cfg(feature = "plugins")]
+    // Kept open for as long as the factories they registered may be called
+    libraries: Vec<libloading::Library>,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a statically linked plugin by its registering function
+    pub fn add(&mut self, register: fn(&mut PluginRegistry)) {
+        register(self);
+    }
+
+    /// Load a plugin built as a dynamic library with declare_plugin!
+    ///
+    /// # Safety
+    /// The library is trusted to export the symbols of declare_plugin!, to have been built with
+    /// the same compiler and version of this crate, and to run nothing unsound when loaded.
+    #[cfg(feature = "plugins")]
+    pub unsafe fn load(&mut self, path: &std::path::Path) -> Result<(), PluginError> {
+        let load_error = |e: libloading::Error| PluginError::Load(path.display().to_string(), e);
+        // Safety: upheld by the caller
+        unsafe {
+            let library = libloading::Library::new(path).map_err(load_error)?;
+            let version = **library
+                .get::<*const u32>(VERSION_SYMBOL.as_bytes())
+                .map_err(load_error)?;
+            if version != PLUGIN_API_VERSION {
+                return Err(PluginError::WrongVersion(
+                    path.display().to_string(),
+                    version,
+                    PLUGIN_API_VERSION,
+                ));
+            }
+            let register = *library
+                .get::<fn(&mut PluginRegistry)>(REGISTER_SYMBOL.as_bytes())
+                .map_err(load_error)?;
+            register(self);
+            self.libraries.push(library);
+        }
+        Ok(())
+    }
+
+    /// Register an unpacker factory, replacing any registered under the same name
+    pub fn register_unpacker(&mut self, name: &str, factory: UnpackerFactory) {
+        self.unpackers.insert(name.to_string(), factory);
+    }
+
+    /// Register a transform factory, replacing any registered under the same name
+    pub fn register_transform(&mut self, name: &str, factory: TransformFactory) {
+        self.transforms.insert(name.to_string(), factory);
+    }
+
+    /// The names of the registered unpackers, in order
+    pub fn get_unpacker_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.unpackers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// The names of the registered transforms, in order
+    pub fn get_transform_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.transforms.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn build_unpacker(
+        &self,
+        name: &str,
+        parameters: &serde_json::Value,
+    ) -> Result<Box<dyn Unpacker>, PluginError> {
+        match self.unpackers.get(name) {
+            Some(factory) => factory(parameters),
+            None => Err(PluginError::UnknownUnpacker(name.to_string())),
+        }
+    }
+
+    pub fn build_transform(
+        &self,
+        name: &str,
+        parameters: &serde_json::Value,
+    ) -> Result<Box<dyn EventTransform>, PluginError> {
+        match self.transforms.get(name) {
+            Some(factory) => factory(parameters),
+            None => Err(PluginError::UnknownTransform(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::nscldaq::{NscldaqSource, RingItem};
+    use crate::source::DataSource;
+
+    fn register(registry: &mut PluginRegistry) {
+        registry.register_unpacker("first_word", |parameters| {
+            let variable = parameters["variable"]
+                .as_str()
+                .ok_or(PluginError::BadParameters(String::from("No variable")))?
+                .to_string();
+            Ok(Box::new(move |item: &RingItem, event: &mut DataBlob| {
+                if let Some(word) = item.get_words().next() {
+                    event.insert(&variable, word as f32);
+                }
+                Ok(())
+            }))
+        });
+        registry.register_transform("double", |_| {
+            Ok(Box::new(|mut event: DataBlob| {
+                let x = *event.find("x")?;
+                event.insert("x", 2.0 * x);
+                Some(event)
+            }))
+        });
+    }
+
+    crate::declare_plugin!(register);
+
+    #[test]
+    fn test_plugin_registry() {
+        let mut registry = PluginRegistry::new();
+        registry.add(specter_register_plugin);
+        assert_eq!(specter_plugin_version, PLUGIN_API_VERSION);
+        assert_eq!(registry.get_unpacker_names(), vec!["first_word"]);
+        assert_eq!(registry.get_transform_names(), vec!["double"]);
+        assert!(matches!(
+            registry.build_unpacker("missing", &serde_json::Value::Null),
+            Err(PluginError::UnknownUnpacker(_))
+        ));
+        assert!(
+            registry
+                .build_unpacker("first_word", &serde_json::Value::Null)
+                .is_err()
+        );
+
+        let mut transform = registry
+            .build_transform("double", &serde_json::Value::Null)
+            .unwrap();
+        let mut event = DataBlob::new();
+        event.insert("x", 1.5);
+        assert_eq!(transform.transform(event).unwrap().find("x"), Some(&3.0));
+
+        // A physics item holding the single word 7
+        let mut item = vec![];
+        item.extend_from_slice(&14u32.to_le_bytes());
+        item.extend_from_slice(&30u32.to_le_bytes());
+        item.extend_from_slice(&0u32.to_le_bytes());
+        item.extend_from_slice(&7u16.to_le_bytes());
+        let unpacker = registry
+            .build_unpacker("first_word", &serde_json::json!({ "variable": "adc" }))
+            .unwrap();
+        let mut source = NscldaqSource::new(std::io::Cursor::new(item), unpacker);
+        let event = source.next_event().unwrap().unwrap();
+        assert_eq!(event.find("adc"), Some(&7.0));
+    }
+
+    #[cfg(feature = "plugins")]
+    #[test]
+    fn test_load_missing() {
+        let mut registry = PluginRegistry::new();
+        let path = std::path::Path::new("/nonexistent/libplugin.so");
+        // Safety: the library does not exist, so nothing is run
+        assert!(matches!(
+            unsafe { registry.load(path) },
+            Err(PluginError::Load(..))
+        ));
+    }
+}
@@ -0,0 +1,24 @@
+//! The types most applications need, for a single glob import
+pub use super::alias::Alias;
+pub use super::batch::ColumnBatch;
+pub use super::calibration::{Calibration, CalibrationSpec, PeakRegion};
+#[cfg(feature = "io")]
+pub use super::command::CommandServer;
+pub use super::cut::{Cut, Cut1D, Cut2D, CutSpec};
+pub use super::data_blob::{DataBlob, TIMESTAMP_VARIABLE};
+pub use super::error::{CommandError, ErrorCode, ResourceError, SpectError};
+pub use super::filter::GateCondition;
+pub use super::histogram::{
+    AxisSpec, BinLayout, FillMode, HistSpec, Histogram, OutOfRangePolicy, TimeOrigin, ValuePolicy,
+};
+pub use super::manager::ResourceManager;
+pub use super::rate::RateSpec;
+pub use super::run::ClearPolicy;
+pub use super::schema::{Schema, VariableKind};
+#[cfg(feature = "io")]
+pub use super::snapshot::{SnapshotFormat, SnapshotSpec, SnapshotTarget};
+pub use super::source::{DataSource, MergeOrder, MergedSource};
+pub use super::spect::{SOURCE_VARIABLE, Spect, SpectBuilder};
+pub use super::watchdog::{Watchdog, WatchdogSpec};
+pub use rustc_hash::FxHashMap;
+pub use uuid::Uuid;
@@ -0,0 +1,255 @@
+use super::data_blob::DataBlob;
+use super::deadtime::LossCounts;
+use super::error::SourceError;
+use super::source::DataSource;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+/// What an EventQueue does with events pushed while it is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Wait for the analysis to take an event, slowing the source down
+    #[default]
+    Block,
+    /// Drop the oldest queued event to make room, keeping the display as live as possible
+    DropOldest,
+    /// Wait for room for one in every n events pushed while full and drop the rest, so that a
+    /// sample of the data keeps flowing without stalling the source for every event
+    Sample(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueueStats {
+    pub pushed: u64,
+    pub popped: u64,
+    pub dropped: u64,
+    /// The most events the queue has held at once
+    pub high_water: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    events: VecDeque<DataBlob>,
+    stats: QueueStats,
+    // Events pushed while full, for sampling
+    n_overflowed: u64,
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+/// A bounded queue of events between a source thread and the thread running the manager. Clones
+/// share the same queue. The queue reads as a DataSource which blocks until an event arrives and
+/// is exhausted once the queue is closed and empty.
+#[derive(Debug, Clone)]
+pub struct EventQueue {
+    shared: Arc<Shared>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            shared: Arc::new(Shared::default()),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    // A panic elsewhere cannot leave the queue inconsistent, so keep using it
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Add an event, applying the overflow policy if the queue is full. Returns false if the
+    /// event was not queued, either because it was dropped or because the queue is closed.
+    pub fn push(&self, event: DataBlob) -> bool {
+        let mut state = self.lock();
+        if state.closed {
+            return false;
+        }
+        if state.events.len() >= self.capacity {
+            state.n_overflowed += 1;
+            let wait = match self.policy {
+                OverflowPolicy::Block => true,
+                OverflowPolicy::DropOldest => {
+                    state.events.pop_front();
+                    state.stats.dropped += 1;
+                    false
+                }
+                OverflowPolicy::Sample(n) => {
+                    if !state.n_overflowed.is_multiple_of(n.max(1)) {
+                        state.stats.dropped += 1;
+                        return false;
+                    }
+                    true
+                }
+            };
+            if wait {
+                while state.events.len() >= self.capacity && !state.closed {
+                    state = self
+                        .shared
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                }
+                if state.closed {
+                    return false;
+                }
+            }
+        }
+        state.events.push_back(event);
+        state.stats.pushed += 1;
+        state.stats.high_water = state.stats.high_water.max(state.events.len());
+        self.shared.not_empty.notify_one();
+        true
+    }
+
+    /// Take the oldest event, waiting for one unless the queue is closed and empty
+    pub fn pop(&self) -> Option<DataBlob> {
+        let mut state = self.lock();
+        loop {
+            if let Some(event) = state.events.pop_front() {
+                state.stats.popped += 1;
+                self.shared.not_full.notify_one();
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self
+                .shared
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Take the oldest event if there is one, without waiting
+    pub fn try_pop(&self) -> Option<DataBlob> {
+        let mut state = self.lock();
+        let event = state.events.pop_front()?;
+        state.stats.popped += 1;
+        self.shared.not_full.notify_one();
+        Some(event)
+    }
+
+    /// Stop accepting events. Queued events can still be popped.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().events.is_empty()
+    }
+
+    pub fn get_stats(&self) -> QueueStats {
+        self.lock().stats
+    }
+
+    /// Push every event from a source on a background thread, closing the queue once the
+    /// source is exhausted or fails. The thread returns the number of events read.
+    pub fn feed<S: DataSource + Send + 'static>(
+        &self,
+        mut source: S,
+    ) -> JoinHandle<Result<u64, SourceError>> {
+        let queue = self.clone();
+        std::thread::spawn(move || {
+            let mut n_events = 0;
+            let result = loop {
+                match source.next_event() {
+                    Ok(Some(event)) => {
+                        n_events += 1;
+                        if !queue.push(event) && queue.is_closed() {
+                            break Ok(n_events);
+                        }
+                    }
+                    Ok(None) => break Ok(n_events),
+                    Err(e) => break Err(e),
+                }
+            };
+            queue.close();
+            result
+        })
+    }
+}
+
+impl DataSource for EventQueue {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        Ok(self.pop())
+    }
+
+    fn stop(&mut self) {
+        self.close();
+    }
+
+    fn get_losses(&self) -> LossCounts {
+        LossCounts {
+            buffer_overflow: self.get_stats().dropped,
+            ..LossCounts::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::IterSource;
+
+    fn event(value: f32) -> DataBlob {
+        let mut event = DataBlob::new();
+        event.insert("x", value);
+        event
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let queue = EventQueue::new(2, OverflowPolicy::DropOldest);
+        for i in 0..5 {
+            assert!(queue.push(event(i as f32)));
+        }
+        assert_eq!(queue.pop().unwrap().find("x"), Some(&3.0));
+        assert_eq!(queue.get_stats().dropped, 3);
+
+        let queue = EventQueue::new(1, OverflowPolicy::Sample(4));
+        queue.push(event(0.0));
+        let accepted = (1..4).filter(|i| queue.push(event(*i as f32))).count();
+        assert_eq!(accepted, 0);
+        assert_eq!(queue.get_stats().dropped, 3);
+
+        let mut queue = EventQueue::new(4, OverflowPolicy::Block);
+        let events = (0..100).map(|i| event(i as f32));
+        let feeder = queue.feed(IterSource::new(events));
+        let mut n_events = 0;
+        while let Some(event) = queue.next_event().unwrap() {
+            assert_eq!(event.find("x"), Some(&(n_events as f32)));
+            n_events += 1;
+        }
+        assert_eq!(n_events, 100);
+        assert_eq!(feeder.join().unwrap().unwrap(), 100);
+        let stats = queue.get_stats();
+        assert_eq!(stats.dropped, 0);
+        assert!(stats.high_water <= 4);
+    }
+}
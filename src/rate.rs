@@ -0,0 +1,137 @@
+//! Count rates against time: the events passing a condition counted in fixed buckets of event
+//! time. Scalers give totals; a rate meter gives the time structure, e.g. beam spills or trips.
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateSpec {
+    pub id: Uuid,
+    pub name: String,
+    /// Only events inside this cut are counted, if given
+    pub cut_id: Option<Uuid>,
+    /// Only events with this variable are counted, if given
+    pub variable: Option<String>,
+    /// The width of a bucket in seconds of event time
+    pub bucket_width: f64,
+    /// The number of buckets kept; the oldest are dropped as new ones start
+    pub n_buckets: usize,
+}
+
+/// The counts of one bucket, which covers [start, start + bucket_width)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateBucket {
+    pub start: f64,
+    pub counts: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateMeter {
+    spec: RateSpec,
+    buckets: VecDeque<RateBucket>,
+    total: u64,
+}
+
+impl RateMeter {
+    pub fn new(spec: RateSpec) -> Self {
+        Self {
+            spec,
+            buckets: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    pub fn get_spec(&self) -> &RateSpec {
+        &self.spec
+    }
+
+    /// Every event counted, including those in buckets which have been dropped
+    pub fn get_total(&self) -> u64 {
+        self.total
+    }
+
+    /// Count an event at a time in seconds. Later times start new buckets, with empty buckets
+    /// for any gap. Earlier times count in the bucket they fall in, and are dropped if it is no
+    /// longer kept.
+    pub fn count(&mut self, timestamp: f64) {
+        let width = self.spec.bucket_width;
+        if !(width > 0.0 && timestamp.is_finite()) || self.spec.n_buckets == 0 {
+            return;
+        }
+        self.total += 1;
+        let start = (timestamp / width).floor() * width;
+        let last_start = match self.buckets.back() {
+            Some(last) => last.start,
+            None => {
+                self.buckets.push_back(RateBucket { start, counts: 1 });
+                return;
+            }
+        };
+        if start > last_start {
+            // Gaps longer than the buckets kept only need the buckets which are kept
+            let n_new = ((start - last_start) / width).round() as usize;
+            for idx in n_new.saturating_sub(self.spec.n_buckets)..n_new {
+                self.buckets.push_back(RateBucket {
+                    start: start - (n_new - 1 - idx) as f64 * width,
+                    counts: 0,
+                });
+            }
+            while self.buckets.len() > self.spec.n_buckets {
+                self.buckets.pop_front();
+            }
+        }
+        let newest = self.buckets.back().map_or(start, |bucket| bucket.start);
+        let offset = ((newest - start) / width).round() as usize;
+        if let Some(idx) = (self.buckets.len() - 1).checked_sub(offset) {
+            self.buckets[idx].counts += 1;
+        }
+    }
+
+    pub fn get_buckets(&self) -> &VecDeque<RateBucket> {
+        &self.buckets
+    }
+
+    /// The (start, counts per second) of every bucket kept, oldest first, for plotting. The
+    /// newest bucket is usually still filling, so its rate is low until it is complete.
+    pub fn get_rates(&self) -> Vec<(f64, f64)> {
+        self.buckets
+            .iter()
+            .map(|bucket| (bucket.start, bucket.counts as f64 / self.spec.bucket_width))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.buckets.clear();
+        self.total = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_meter() {
+        let mut meter = RateMeter::new(RateSpec {
+            id: Uuid::new_v4(),
+            name: String::from("trigger"),
+            cut_id: None,
+            variable: None,
+            bucket_width: 2.0,
+            n_buckets: 3,
+        });
+        for timestamp in [10.0, 11.5, 12.0, 16.5, 11.0] {
+            meter.count(timestamp);
+        }
+        // The bucket at 10 was dropped for the one at 16, so the late event at 11 is not kept
+        assert_eq!(
+            meter.get_rates(),
+            vec![(12.0, 0.5), (14.0, 0.0), (16.0, 0.5)]
+        );
+        assert_eq!(meter.get_total(), 5);
+        meter.count(100.0);
+        assert_eq!(meter.get_buckets().len(), 3);
+        assert_eq!(meter.get_buckets()[0].start, 96.0);
+        assert_eq!(meter.get_buckets()[2].counts, 1);
+    }
+}
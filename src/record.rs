@@ -0,0 +1,387 @@
+#[cfg(feature = "io")]
+use super::compression::{self, Input};
+use super::data_blob::DataBlob;
+use super::error::RecordError;
+use rustc_hash::{FxHashMap, FxHashSet};
+#[cfg(feature = "io")]
+use std::fs::File;
+#[cfg(feature = "io")]
+use std::io::BufWriter;
+use std::io::{ErrorKind, Read, Write};
+#[cfg(feature = "io")]
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"SPECTLOG";
+const VERSION: u16 = 4;
+// Logs from before flags and arrays were added can still be read
+const MIN_VERSION: u16 = 1;
+
+// Records are tagged. A name record assigns a compact id to a variable the first time it is
+// seen, after which events only store (id, value) pairs. The flags and arrays of an event are
+// written in records just before it. From version 4, a mark declares every name seen so far
+// again, so that a reader starting at the mark resolves them.
+const TAG_NAME: u8 = 0;
+const TAG_EVENT: u8 = 1;
+const TAG_FLAGS: u8 = 2;
+const TAG_ARRAYS: u8 = 3;
+
+/// Writes DataBlobs to a compact binary event log
+#[derive(Debug)]
+pub struct EventRecorder<W: Write> {
+    writer: W,
+    names: FxHashMap<String, u16>,
+    n_events: usize,
+    // The bytes written, which is the offset of the next record
+    position: u64,
+}
+
+#[cfg(feature = "io")]
+impl EventRecorder<BufWriter<File>> {
+    pub fn create(path: &Path) -> Result<Self, RecordError> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> EventRecorder<W> {
+    pub fn new(writer: W) -> Result<Self, RecordError> {
+        let mut recorder = Self {
+            writer,
+            names: FxHashMap::default(),
+            n_events: 0,
+            position: 0,
+        };
+        recorder.write_all(MAGIC)?;
+        recorder.write_all(&VERSION.to_le_bytes())?;
+        Ok(recorder)
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), RecordError> {
+        self.writer.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Get the offset of the next record from the start of the log
+    pub fn get_position(&self) -> u64 {
+        self.position
+    }
+
+    /// Declare every name seen so far again and return the offset of the mark. A Replay opened
+    /// at a mark resolves every name, so the offsets of marks make an index of the log.
+    pub fn mark(&mut self) -> Result<u64, RecordError> {
+        let offset = self.position;
+        let mut names: Vec<(u16, String)> = self
+            .names
+            .iter()
+            .map(|(name, id)| (*id, name.clone()))
+            .collect();
+        names.sort_unstable();
+        for (id, name) in names {
+            self.write_name(id, &name)?;
+        }
+        Ok(offset)
+    }
+
+    fn write_name(&mut self, id: u16, name: &str) -> Result<(), RecordError> {
+        self.write_all(&[TAG_NAME])?;
+        self.write_all(&id.to_le_bytes())?;
+        self.write_all(&(name.len() as u16).to_le_bytes())?;
+        self.write_all(name.as_bytes())
+    }
+
+    // Get a count as it is written, which must fit in a u16
+    fn encode_count(count: usize, what: &'static str) -> Result<[u8; 2], RecordError> {
+        u16::try_from(count)
+            .map(u16::to_le_bytes)
+            .map_err(|_| RecordError::TooManyEntries(what))
+    }
+
+    pub fn record(&mut self, blob: &DataBlob) -> Result<(), RecordError> {
+        // Everything is checked before writing, so that a failed event leaves no partial records
+        let n_flags = Self::encode_count(blob.iter_flags().count(), "flags")?;
+        let n_arrays = Self::encode_count(blob.iter_arrays().count(), "arrays")?;
+        let n_values = Self::encode_count(blob.len(), "values")?;
+        if let Some((name, _)) = blob
+            .iter_arrays()
+            .find(|(_, values)| values.len() > u16::MAX as usize)
+        {
+            return Err(RecordError::ArrayTooLong(name.to_string()));
+        }
+        let names = blob
+            .iter_flags()
+            .map(|(name, _)| name)
+            .chain(blob.iter_arrays().map(|(name, _)| name))
+            .chain(blob.iter().map(|(name, _)| name));
+        let mut new_names: FxHashSet<&str> = FxHashSet::default();
+        for name in names {
+            if self.names.contains_key(name) {
+                continue;
+            } else if name.len() > u16::MAX as usize {
+                return Err(RecordError::NameTooLong(name.to_string()));
+            }
+            new_names.insert(name);
+        }
+        // Ids run from 0 to u16::MAX
+        if self.names.len() + new_names.len() > u16::MAX as usize + 1 {
+            return Err(RecordError::TooManyVariables);
+        }
+
+        let mut flags: Vec<(u16, u64)> = vec![];
+        for (name, bits) in blob.iter_flags() {
+            flags.push((self.get_name_id(name)?, bits));
+        }
+        if !flags.is_empty() {
+            self.write_all(&[TAG_FLAGS])?;
+            self.write_all(&n_flags)?;
+            for (id, bits) in flags {
+                self.write_all(&id.to_le_bytes())?;
+                self.write_all(&bits.to_le_bytes())?;
+            }
+        }
+
+        let mut arrays: Vec<(u16, &[f32])> = vec![];
+        for (name, values) in blob.iter_arrays() {
+            arrays.push((self.get_name_id(name)?, values));
+        }
+        if !arrays.is_empty() {
+            self.write_all(&[TAG_ARRAYS])?;
+            self.write_all(&n_arrays)?;
+            for (id, values) in arrays {
+                self.write_all(&id.to_le_bytes())?;
+                self.write_all(&(values.len() as u16).to_le_bytes())?;
+                for value in values {
+                    self.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+
+        let mut entries: Vec<(u16, f32)> = Vec::with_capacity(blob.len());
+        for (name, value) in blob.iter() {
+            entries.push((self.get_name_id(name)?, *value));
+        }
+
+        self.write_all(&[TAG_EVENT])?;
+        self.write_all(&n_values)?;
+        for (id, value) in entries {
+            self.write_all(&id.to_le_bytes())?;
+            self.write_all(&value.to_le_bytes())?;
+        }
+        self.n_events += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), RecordError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn get_n_events(&self) -> usize {
+        self.n_events
+    }
+
+    // Get the id of a name, declaring it if it is new. Names are checked by record beforehand.
+    fn get_name_id(&mut self, name: &str) -> Result<u16, RecordError> {
+        if let Some(id) = self.names.get(name) {
+            return Ok(*id);
+        }
+        let id = self.names.len() as u16;
+        self.write_name(id, name)?;
+        self.names.insert(name.to_string(), id);
+        Ok(id)
+    }
+}
+
+/// Reads DataBlobs back out of an event log written by an EventRecorder
+#[derive(Debug)]
+pub struct EventReader<R: Read> {
+    reader: R,
+    names: Vec<String>,
+}
+
+#[cfg(feature = "io")]
+impl EventReader<Input> {
+    pub fn open(path: &Path) -> Result<Self, RecordError> {
+        Self::new(compression::open(path)?)
+    }
+}
+
+impl<R: Read> EventReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, RecordError> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RecordError::BadHeader);
+        }
+        let mut version = [0u8; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(RecordError::UnsupportedVersion(version));
+        }
+        Ok(Self {
+            reader,
+            names: vec![],
+        })
+    }
+
+    /// Read the next event from the log, returning None at a clean end of file
+    pub fn read_event(&mut self) -> Result<Option<DataBlob>, RecordError> {
+        let mut blob = DataBlob::new();
+        loop {
+            let mut tag = [0u8; 1];
+            match self.reader.read_exact(&mut tag) {
+                Ok(()) => (),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+
+            match tag[0] {
+                TAG_NAME => {
+                    let id = self.read_u16()?;
+                    let length = self.read_u16()? as usize;
+                    let mut bytes = vec![0u8; length];
+                    self.read_exact(&mut bytes)?;
+                    let name = String::from_utf8(bytes).map_err(|_| RecordError::Corrupt)?;
+                    // Names are declared again at marks, and must not change
+                    match self.names.get(id as usize) {
+                        Some(known) if *known == name => (),
+                        Some(_) => return Err(RecordError::Corrupt),
+                        None if id as usize == self.names.len() => self.names.push(name),
+                        None => return Err(RecordError::Corrupt),
+                    }
+                }
+                TAG_FLAGS => {
+                    let n_entries = self.read_u16()?;
+                    for _ in 0..n_entries {
+                        let id = self.read_u16()? as usize;
+                        let mut bits = [0u8; 8];
+                        self.read_exact(&mut bits)?;
+                        match self.names.get(id) {
+                            Some(name) => blob.insert_flag(name, u64::from_le_bytes(bits)),
+                            None => return Err(RecordError::Corrupt),
+                        }
+                    }
+                }
+                TAG_ARRAYS => {
+                    let n_arrays = self.read_u16()?;
+                    for _ in 0..n_arrays {
+                        let id = self.read_u16()? as usize;
+                        let length = self.read_u16()? as usize;
+                        let mut values = Vec::with_capacity(length);
+                        for _ in 0..length {
+                            let mut value = [0u8; 4];
+                            self.read_exact(&mut value)?;
+                            values.push(f32::from_le_bytes(value));
+                        }
+                        match self.names.get(id) {
+                            Some(name) => blob.insert_array(name, values),
+                            None => return Err(RecordError::Corrupt),
+                        }
+                    }
+                }
+                TAG_EVENT => {
+                    let n_entries = self.read_u16()?;
+                    for _ in 0..n_entries {
+                        let id = self.read_u16()? as usize;
+                        let mut value = [0u8; 4];
+                        self.read_exact(&mut value)?;
+                        match self.names.get(id) {
+                            Some(name) => blob.insert(name, f32::from_le_bytes(value)),
+                            None => return Err(RecordError::Corrupt),
+                        }
+                    }
+                    return Ok(Some(blob));
+                }
+                _ => return Err(RecordError::Corrupt),
+            }
+        }
+    }
+
+    fn read_u16(&mut self) -> Result<u16, RecordError> {
+        let mut bytes = [0u8; 2];
+        self.read_exact(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    // Running out of data inside of a record means the log was truncated
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), RecordError> {
+        match self.reader.read_exact(buffer) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(RecordError::Corrupt),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<R: Read> Iterator for EventReader<R> {
+    type Item = Result<DataBlob, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_roundtrip() {
+        let mut recorder = EventRecorder::new(Vec::new()).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("x", 1.5);
+        blob.insert("y", -2.0);
+        recorder.record(&blob).unwrap();
+        let mut blob = DataBlob::new();
+        blob.insert("y", 3.0);
+        blob.insert("z", 4.0);
+        blob.insert_flag("trigger", 0b101);
+        blob.insert_array("gamma", vec![511.0, 1332.5]);
+        recorder.record(&blob).unwrap();
+        assert_eq!(recorder.get_n_events(), 2);
+
+        let bytes = recorder.writer;
+        let mut reader = EventReader::new(bytes.as_slice()).unwrap();
+        let first = reader.read_event().unwrap().unwrap();
+        assert_eq!(first.find("x"), Some(&1.5));
+        assert_eq!(first.find("y"), Some(&-2.0));
+        let second = reader.read_event().unwrap().unwrap();
+        assert_eq!(second.find("x"), None);
+        assert_eq!(second.find("z"), Some(&4.0));
+        assert_eq!(second.find_flag("trigger"), Some(0b101));
+        assert_eq!(first.find_flag("trigger"), None);
+        assert_eq!(second.find_array("gamma"), Some(&[511.0, 1332.5][..]));
+        assert!(reader.read_event().unwrap().is_none());
+
+        let truncated = &bytes[..bytes.len() - 2];
+        let reader = EventReader::new(truncated).unwrap();
+        assert!(reader.last().unwrap().is_err());
+        assert!(EventReader::new(&b"NOTALOG!\x01\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn test_too_many_entries() {
+        let mut recorder = EventRecorder::new(Vec::new()).unwrap();
+        let mut blob = DataBlob::new();
+        for i in 0..=u16::MAX as usize {
+            blob.insert(&format!("channel_{i}"), 1.0);
+        }
+        let position = recorder.get_position();
+        assert!(matches!(
+            recorder.record(&blob),
+            Err(RecordError::TooManyEntries("values"))
+        ));
+        assert_eq!(recorder.get_position(), position);
+        assert_eq!(recorder.get_n_events(), 0);
+
+        // A bad name fails before the flags record is written
+        let mut blob = DataBlob::new();
+        blob.insert_flag("trigger", 1);
+        blob.insert(&"x".repeat(u16::MAX as usize + 1), 1.0);
+        assert!(matches!(
+            recorder.record(&blob),
+            Err(RecordError::NameTooLong(_))
+        ));
+        assert_eq!(recorder.get_position(), position);
+    }
+}
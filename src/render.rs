@@ -0,0 +1,368 @@
+//! Images of histograms for logbooks and papers. 1D spectra are drawn as steps and 2D spectra as
+//! heatmaps, with their axes, title, and the outlines of the cuts drawn on them, into either a
+//! PNG or an SVG file.
+use super::cut::{Cut1D, Cut2D};
+use super::cut_registry::SavedCut;
+use super::error::RenderError;
+use super::histogram::Histogram;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use std::path::Path;
+
+// Colors of the heatmaps, from empty to full bins
+const PALETTE: [RGBColor; 4] = [
+    RGBColor(20, 20, 60),
+    RGBColor(40, 120, 200),
+    RGBColor(240, 220, 40),
+    RGBColor(250, 60, 30),
+];
+
+// Colors of the cut outlines, cycled through in order
+const CUT_COLORS: [RGBColor; 4] = [RED, MAGENTA, GREEN, CYAN];
+
+const FONT: &str = "sans-serif";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+impl ImageFormat {
+    /// The format named by the extension of a path
+    pub fn from_path(path: &Path) -> Result<Self, RenderError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => Ok(Self::Png),
+            Some(ext) if ext.eq_ignore_ascii_case("svg") => Ok(Self::Svg),
+            _ => Err(RenderError::UnknownFormat(path.display().to_string())),
+        }
+    }
+
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSpec {
+    /// Size of the image in pixels
+    pub width: u32,
+    pub height: u32,
+    /// Show contents on a log scale
+    pub log_scale: bool,
+    /// Format of images written by render_folder
+    pub format: ImageFormat,
+}
+
+impl Default for RenderSpec {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            log_scale: false,
+            format: ImageFormat::Png,
+        }
+    }
+}
+
+// A content shown on a log scale, where empty bins sit at zero
+fn scale(content: f64, log_scale: bool) -> f64 {
+    if log_scale {
+        content.max(0.0).ln_1p() / std::f64::consts::LN_10
+    } else {
+        content
+    }
+}
+
+// The color of a bin, interpolated along the palette
+fn get_color(fraction: f64) -> RGBColor {
+    let position = fraction.clamp(0.0, 1.0) * (PALETTE.len() - 1) as f64;
+    let idx = (position as usize).min(PALETTE.len() - 2);
+    let t = position - idx as f64;
+    let (low, high) = (PALETTE[idx], PALETTE[idx + 1]);
+    let mix = |a: u8, b: u8| (a as f64 + t * (b as f64 - a as f64)).round() as u8;
+    RGBColor(mix(low.0, high.0), mix(low.1, high.1), mix(low.2, high.2))
+}
+
+fn get_cut_points(cut: &SavedCut) -> Vec<(f64, f64)> {
+    let values = |key: &str| -> Vec<f64> {
+        cut.parameters[key]
+            .as_array()
+            .map(|values| values.iter().filter_map(|v| v.as_f64()).collect())
+            .unwrap_or_default()
+    };
+    values("x_values")
+        .into_iter()
+        .zip(values("y_values"))
+        .collect()
+}
+
+fn draw_err<E: std::error::Error + Send + Sync>(error: DrawingAreaErrorKind<E>) -> RenderError {
+    RenderError::Draw(error.to_string())
+}
+
+/// Render a histogram and the outlines of cuts on it to an image at path, in the format named
+/// by its extension
+pub fn render_histogram(
+    gram: &Histogram,
+    cuts: &[SavedCut],
+    path: &Path,
+    spec: &RenderSpec,
+) -> Result<(), RenderError> {
+    let size = (spec.width, spec.height);
+    match ImageFormat::from_path(path)? {
+        ImageFormat::Png => draw(
+            gram,
+            cuts,
+            spec,
+            BitMapBackend::new(path, size).into_drawing_area(),
+        ),
+        ImageFormat::Svg => draw(
+            gram,
+            cuts,
+            spec,
+            SVGBackend::new(path, size).into_drawing_area(),
+        ),
+    }
+}
+
+/// Render a histogram to an SVG document in memory, e.g. to serve it to a web page
+pub fn render_svg(
+    gram: &Histogram,
+    cuts: &[SavedCut],
+    spec: &RenderSpec,
+) -> Result<String, RenderError> {
+    let mut document = String::new();
+    let size = (spec.width, spec.height);
+    draw(
+        gram,
+        cuts,
+        spec,
+        SVGBackend::with_string(&mut document, size).into_drawing_area(),
+    )?;
+    Ok(document)
+}
+
+/// Render a histogram to a PNG image in memory
+pub fn render_png(
+    gram: &Histogram,
+    cuts: &[SavedCut],
+    spec: &RenderSpec,
+) -> Result<Vec<u8>, RenderError> {
+    let mut pixels = vec![0; 3 * spec.width as usize * spec.height as usize];
+    let size = (spec.width, spec.height);
+    draw(
+        gram,
+        cuts,
+        spec,
+        BitMapBackend::with_buffer(&mut pixels, size).into_drawing_area(),
+    )?;
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&pixels, spec.width, spec.height, ColorType::Rgb8)?;
+    Ok(png)
+}
+
+fn draw<DB: DrawingBackend>(
+    gram: &Histogram,
+    cuts: &[SavedCut],
+    spec: &RenderSpec,
+    root: DrawingArea<DB, Shift>,
+) -> Result<(), RenderError>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE).map_err(draw_err)?;
+    let x_axis = &gram.spec.x_axis;
+    let x_range = x_axis.minimum as f64..x_axis.maximum as f64;
+    let mut builder = ChartBuilder::on(&root);
+    builder
+        .caption(&gram.spec.title, (FONT, 24))
+        .margin(16)
+        .x_label_area_size(48)
+        .y_label_area_size(64);
+
+    match &gram.spec.y_axis {
+        None => {
+            let width = x_axis.get_bin_width() as f64;
+            let mut steps = Vec::with_capacity(2 * x_axis.bins);
+            for bin in 0..x_axis.bins {
+                let low = x_axis.get_bin_low_edge(bin) as f64;
+                let content = scale(gram.get_bin_content(bin, 0).unwrap_or(0.0), spec.log_scale);
+                steps.push((low, content));
+                steps.push((low + width, content));
+            }
+            let maximum = steps.iter().map(|(_, y)| *y).fold(1.0, f64::max) * 1.05;
+            let mut chart = builder
+                .build_cartesian_2d(x_range, 0.0..maximum)
+                .map_err(draw_err)?;
+            let y_desc = if spec.log_scale {
+                "log10(1 + counts)"
+            } else {
+                "Counts"
+            };
+            chart
+                .configure_mesh()
+                .x_desc(x_axis.get_label())
+                .y_desc(y_desc)
+                .draw()
+                .map_err(draw_err)?;
+            chart
+                .draw_series(LineSeries::new(steps, &BLUE))
+                .map_err(draw_err)?;
+            for (cut, color) in cuts
+                .iter()
+                .filter(|cut| cut.kind == Cut1D::KIND)
+                .zip(CUT_COLORS.iter().cycle())
+            {
+                for edge in ["low", "high"] {
+                    if let Some(x) = cut.parameters[edge].as_f64() {
+                        chart
+                            .draw_series(LineSeries::new([(x, 0.0), (x, maximum)], color))
+                            .map_err(draw_err)?;
+                    }
+                }
+            }
+        }
+        Some(y_axis) => {
+            let y_range = y_axis.minimum as f64..y_axis.maximum as f64;
+            let mut chart = builder
+                .build_cartesian_2d(x_range, y_range)
+                .map_err(draw_err)?;
+            chart
+                .configure_mesh()
+                .disable_mesh()
+                .x_desc(x_axis.get_label())
+                .y_desc(y_axis.get_label())
+                .draw()
+                .map_err(draw_err)?;
+            // More bins than pixels would only slow down drawing
+            let (plot_width, plot_height) = chart.plotting_area().dim_in_pixel();
+            let downsampled = gram.downsample(plot_width as usize, plot_height as usize);
+            let layout = gram.spec.layout.get_dense();
+            let (x_bins, y_bins) = (downsampled.x_bins, downsampled.y_bins);
+            let x_width = (x_axis.maximum - x_axis.minimum) as f64 / x_bins as f64;
+            let y_width = (y_axis.maximum - y_axis.minimum) as f64 / y_bins as f64;
+            let maximum = downsampled
+                .data
+                .iter()
+                .map(|value| scale(*value, spec.log_scale))
+                .fold(0.0, f64::max);
+            let mut bins = Vec::new();
+            for y_bin in 0..y_bins {
+                for x_bin in 0..x_bins {
+                    let content = downsampled.data[layout.index(x_bin, y_bin, x_bins, y_bins)];
+                    // Empty bins are left white, as in most published spectra
+                    if content <= 0.0 || maximum <= 0.0 {
+                        continue;
+                    }
+                    let x_low = x_axis.minimum as f64 + x_bin as f64 * x_width;
+                    let y_low = y_axis.minimum as f64 + y_bin as f64 * y_width;
+                    let color = get_color(scale(content, spec.log_scale) / maximum);
+                    bins.push(Rectangle::new(
+                        [(x_low, y_low), (x_low + x_width, y_low + y_width)],
+                        color.filled(),
+                    ));
+                }
+            }
+            chart.draw_series(bins).map_err(draw_err)?;
+            for (cut, color) in cuts
+                .iter()
+                .filter(|cut| cut.kind == Cut2D::KIND)
+                .zip(CUT_COLORS.iter().cycle())
+            {
+                let mut points = get_cut_points(cut);
+                if let Some(first) = points.first().copied() {
+                    points.push(first);
+                }
+                chart
+                    .draw_series(LineSeries::new(points, color.stroke_width(2)))
+                    .map_err(draw_err)?;
+            }
+        }
+    }
+    root.present().map_err(draw_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cut::CutSpec;
+    use crate::data_blob::DataBlob;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+    use crate::manager::ResourceManager;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_render() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            title: String::from("Silicon energy"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "si/e",
+                AxisSpec::new("e", "E", 16, 0.0, 16.0).unwrap(),
+                None,
+            )
+        };
+        let pid = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("si/pid"),
+            title: String::from("Particle ID"),
+            y_axis: Some(AxisSpec::new("de", "dE", 16, 0.0, 16.0).unwrap()),
+            ..spec.clone()
+        };
+        let other = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("ic/e"),
+            ..spec.clone()
+        };
+        manager.add_histogram(spec).unwrap();
+        let pid = manager.add_histogram(pid).unwrap();
+        manager.add_histogram(other).unwrap();
+        manager
+            .add_cut_2d(
+                CutSpec {
+                    id: Uuid::new_v4(),
+                    name: String::from("alphas"),
+                    x_variable: String::from("e"),
+                    y_variable: Some(String::from("de")),
+                },
+                vec![1.0, 8.0, 8.0, 1.0, 1.0],
+                vec![1.0, 1.0, 8.0, 8.0, 1.0],
+                &pid,
+            )
+            .unwrap();
+        let mut data = DataBlob::new();
+        data.insert("e", 3.5);
+        data.insert("de", 4.5);
+        manager.update(data).unwrap();
+
+        assert_eq!(get_color(0.0), PALETTE[0]);
+        assert_eq!(get_color(1.0), PALETTE[3]);
+        assert!(ImageFormat::from_path(Path::new("pid.jpg")).is_err());
+
+        let gram = manager.get_histogram(&pid).unwrap();
+        let document = render_svg(gram, &manager.export_cuts(), &RenderSpec::default()).unwrap();
+        assert!(document.contains("Particle ID"));
+        assert!(document.contains("<polyline"));
+        let png = render_png(gram, &[], &RenderSpec::default()).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+
+        let directory = std::env::temp_dir().join(format!("render_{}", Uuid::new_v4()));
+        let spec = RenderSpec {
+            width: 320,
+            height: 240,
+            ..RenderSpec::default()
+        };
+        assert_eq!(manager.render_folder("si", &directory, &spec).unwrap(), 2);
+        assert!(directory.join("pid.png").exists());
+        assert!(directory.join("e.png").exists());
+        assert!(!directory.join("ic").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
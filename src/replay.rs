@@ -0,0 +1,403 @@
+//! Replays files through any file-based DataSource while reporting progress, so that long
+//! offline replays can be monitored, cancelled, started part way through, or sampled.
+use super::compression::{self, Input};
+use super::data_blob::DataBlob;
+use super::deadtime::LossCounts;
+use super::error::SourceError;
+use super::schema::Schema;
+use super::source::DataSource;
+use super::time::{Duration, Instant};
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// How far a replay has got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Bytes read from the file, before any decompression
+    pub bytes: u64,
+    pub total_bytes: u64,
+    /// Events handed out by the replay
+    pub events: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// The fraction of the file read, from 0 to 1
+    pub fn get_fraction(&self) -> f64 {
+        match self.total_bytes {
+            0 => 1.0,
+            total => (self.bytes as f64 / total as f64).min(1.0),
+        }
+    }
+
+    /// Estimate the time left from the rate the file has been read at so far
+    pub fn get_eta(&self) -> Option<Duration> {
+        let fraction = self.get_fraction();
+        (fraction > 0.0).then(|| self.elapsed.mul_f64((1.0 - fraction) / fraction))
+    }
+
+    pub fn get_events_per_second(&self) -> Option<f64> {
+        let seconds = self.elapsed.as_secs_f64();
+        (seconds > 0.0).then(|| self.events as f64 / seconds)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    bytes: AtomicU64,
+    events: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+/// Watches and cancels a Replay from another thread, e.g. a GUI showing a progress bar
+#[derive(Debug, Clone)]
+pub struct ReplayMonitor {
+    shared: Arc<Shared>,
+    total_bytes: u64,
+    started: Instant,
+}
+
+impl ReplayMonitor {
+    pub fn get_progress(&self) -> Progress {
+        Progress {
+            bytes: self.shared.bytes.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+            events: self.shared.events.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// Stop the replay. It is exhausted from its next event on.
+    pub fn cancel(&self) {
+        self.shared.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// Counts the bytes read from the file for the progress
+struct CountingReader<R: Read> {
+    reader: R,
+    shared: Arc<Shared>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n_read = self.reader.read(buf)?;
+        self.shared
+            .bytes
+            .fetch_add(n_read as u64, Ordering::Relaxed);
+        Ok(n_read)
+    }
+}
+
+impl<R: Read + Seek> Seek for CountingReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> std::io::Result<u64> {
+        let position = self.reader.seek(position)?;
+        self.shared.bytes.store(position, Ordering::Relaxed);
+        Ok(position)
+    }
+}
+
+// The decompressed data of a file
+enum Data {
+    Plain(BufReader<CountingReader<File>>),
+    Compressed(Input),
+}
+
+impl Read for Data {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(reader) => reader.read(buf),
+            Self::Compressed(input) => input.read(buf),
+        }
+    }
+}
+
+// Hands the source the header of a file, then the data from an offset. Until the source has
+// been made, bytes are passed one at a time, so that nothing past the header is left read ahead
+// in the buffers of the source when the data jumps.
+struct SplicedReader {
+    data: Data,
+    // The position in the decompressed data
+    position: u64,
+    jump_to: Option<u64>,
+    is_made: Arc<AtomicBool>,
+}
+
+impl SplicedReader {
+    fn jump(&mut self, offset: u64) -> std::io::Result<()> {
+        if offset < self.position {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "offset {offset} is inside the header of the file, which ends at {}",
+                    self.position
+                ),
+            ));
+        }
+        match &mut self.data {
+            Data::Plain(reader) => {
+                reader.seek(SeekFrom::Start(offset))?;
+            }
+            // Compressed data cannot be seeked, so decompress up to the offset
+            Data::Compressed(input) => {
+                std::io::copy(
+                    &mut input.take(offset - self.position),
+                    &mut std::io::sink(),
+                )?;
+            }
+        }
+        self.position = offset;
+        Ok(())
+    }
+}
+
+impl Read for SplicedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(offset) = self.jump_to {
+            if !self.is_made.load(Ordering::Relaxed) {
+                let n_wanted = buf.len().min(1);
+                let n_read = self.data.read(&mut buf[..n_wanted])?;
+                self.position += n_read as u64;
+                return Ok(n_read);
+            }
+            self.jump(offset)?;
+            self.jump_to = None;
+        }
+        let n_read = self.data.read(buf)?;
+        self.position += n_read as u64;
+        Ok(n_read)
+    }
+}
+
+/// A DataSource reading a file, with progress, cancellation, and sampling
+#[derive(Debug)]
+pub struct Replay<S: DataSource> {
+    source: S,
+    monitor: ReplayMonitor,
+    limit: Option<u64>,
+    // Events are kept whenever the accumulated fraction passes one
+    fraction: f64,
+    accumulated: f64,
+}
+
+impl<S: DataSource> Replay<S> {
+    /// Open a file, which may be compressed, and make the source reading it
+    pub fn open(
+        path: &Path,
+        make_source: impl FnOnce(Input) -> Result<S, SourceError>,
+    ) -> Result<Self, SourceError> {
+        Self::open_at(path, 0, make_source)
+    }
+
+    /// Open a file starting at a byte offset into its (decompressed) data, which must be the
+    /// start of an event or item of its format past any header, e.g. a mark of an
+    /// EventRecorder. The source is made from the start of the file, so that it reads the
+    /// header, and must read no further until its first event. Plain files are then seeked to
+    /// the offset, and compressed ones decompressed up to it.
+    pub fn open_at(
+        path: &Path,
+        offset: u64,
+        make_source: impl FnOnce(Input) -> Result<S, SourceError>,
+    ) -> Result<Self, SourceError> {
+        let mut file = File::open(path)?;
+        let total_bytes = file.metadata()?.len();
+        let shared = Arc::new(Shared {
+            bytes: AtomicU64::new(0),
+            events: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+        });
+
+        let mut start = [0u8; 4];
+        let n_read = file.read(&mut start)?;
+        let is_plain =
+            compression::Compression::detect(&start[..n_read]) == compression::Compression::None;
+        file.seek(SeekFrom::Start(0))?;
+        let counted = BufReader::new(CountingReader {
+            reader: file,
+            shared: shared.clone(),
+        });
+        let data = match is_plain {
+            true => Data::Plain(counted),
+            false => Data::Compressed(compression::decompress(counted)?),
+        };
+        let is_made = Arc::new(AtomicBool::new(false));
+        let spliced = SplicedReader {
+            data,
+            position: 0,
+            // Every event starts past the header, so an offset of 0 is the whole file
+            jump_to: (offset > 0).then_some(offset),
+            is_made: is_made.clone(),
+        };
+        let source = make_source(BufReader::new(Box::new(spliced)))?;
+        is_made.store(true, Ordering::Relaxed);
+        Ok(Self {
+            source,
+            monitor: ReplayMonitor {
+                shared,
+                total_bytes,
+                started: Instant::now(),
+            },
+            limit: None,
+            fraction: 1.0,
+            accumulated: 0.0,
+        })
+    }
+
+    pub fn get_monitor(&self) -> ReplayMonitor {
+        self.monitor.clone()
+    }
+
+    pub fn get_progress(&self) -> Progress {
+        self.monitor.get_progress()
+    }
+
+    /// Stop after this many events
+    pub fn set_event_limit(&mut self, limit: u64) {
+        self.limit = Some(limit);
+    }
+
+    /// Replay only this fraction of the events, spread evenly through the file
+    pub fn set_sample_fraction(&mut self, fraction: f64) {
+        self.fraction = fraction.clamp(0.0, 1.0);
+    }
+
+    /// Read and discard events, returning the number skipped
+    pub fn skip_events(&mut self, n_events: u64) -> Result<u64, SourceError> {
+        let mut n_skipped = 0;
+        while n_skipped < n_events && self.source.next_event()?.is_some() {
+            n_skipped += 1;
+        }
+        Ok(n_skipped)
+    }
+
+    pub fn get_source(&self) -> &S {
+        &self.source
+    }
+}
+
+impl<S: DataSource> DataSource for Replay<S> {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        let shared = &self.monitor.shared;
+        loop {
+            let n_events = shared.events.load(Ordering::Relaxed);
+            if self.monitor.is_cancelled() || self.limit.is_some_and(|limit| n_events >= limit) {
+                return Ok(None);
+            }
+            let Some(event) = self.source.next_event()? else {
+                return Ok(None);
+            };
+            self.accumulated += self.fraction;
+            if self.accumulated >= 1.0 {
+                self.accumulated -= 1.0;
+                shared.events.fetch_add(1, Ordering::Relaxed);
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    fn get_schema(&self) -> Option<Schema> {
+        self.source.get_schema()
+    }
+
+    /// The losses of the source replayed. Events skipped to replay a fraction of the data were
+    /// skipped deliberately and are not losses.
+    fn get_losses(&self) -> LossCounts {
+        self.source.get_losses()
+    }
+
+    fn stop(&mut self) {
+        self.monitor.cancel();
+        self.source.stop();
+    }
+}
+
+// Every test writes an event log to a file
+#[cfg(all(test, feature = "io"))]
+mod tests {
+    use super::*;
+    use crate::record::{EventReader, EventRecorder};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_replay_progress() {
+        let path = std::env::temp_dir().join(format!("spect_replay_{}.evt", Uuid::new_v4()));
+        let mut recorder = EventRecorder::create(&path).unwrap();
+        for i in 0..100 {
+            let mut blob = DataBlob::new();
+            blob.insert("x", i as f32);
+            recorder.record(&blob).unwrap();
+        }
+        recorder.flush().unwrap();
+        drop(recorder);
+
+        let open = || Replay::open(&path, |input| Ok(EventReader::new(input)?));
+        let mut replay = open().unwrap();
+        assert_eq!(replay.skip_events(10).unwrap(), 10);
+        replay.set_sample_fraction(0.25);
+        let mut values = vec![];
+        while let Some(event) = replay.next_event().unwrap() {
+            values.push(*event.find("x").unwrap());
+        }
+        assert_eq!(values.len(), 22);
+        assert_eq!(values[..2], [13.0, 17.0]);
+        let progress = replay.get_progress();
+        assert_eq!(progress.events, 22);
+        assert_eq!(progress.bytes, progress.total_bytes);
+        assert_eq!(progress.get_fraction(), 1.0);
+
+        let mut replay = open().unwrap();
+        replay.set_event_limit(5);
+        let monitor = replay.get_monitor();
+        assert!(replay.next_event().unwrap().is_some());
+        monitor.cancel();
+        assert!(replay.next_event().unwrap().is_none());
+        assert_eq!(monitor.get_progress().events, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replay_at_mark() {
+        let path = std::env::temp_dir().join(format!("spect_replay_{}.evt", Uuid::new_v4()));
+        let mut recorder = EventRecorder::create(&path).unwrap();
+        let mut marks = vec![];
+        for i in 0..100 {
+            if i % 25 == 0 {
+                marks.push(recorder.mark().unwrap());
+            }
+            let mut blob = DataBlob::new();
+            blob.insert("x", i as f32);
+            if i == 10 {
+                blob.insert("y", 1.0);
+            }
+            blob.insert_flag("even", u64::from(i % 2 == 0));
+            recorder.record(&blob).unwrap();
+        }
+        recorder.flush().unwrap();
+        drop(recorder);
+
+        // The names were declared before the mark, so the reader needs the mark to resolve them
+        let mut replay =
+            Replay::open_at(&path, marks[2], |input| Ok(EventReader::new(input)?)).unwrap();
+        let first = replay.next_event().unwrap().unwrap();
+        assert_eq!(first.find("x"), Some(&50.0));
+        assert_eq!(first.find_flag("even"), Some(1));
+        let mut n_events = 1;
+        while replay.next_event().unwrap().is_some() {
+            n_events += 1;
+        }
+        assert_eq!(n_events, 50);
+        let progress = replay.get_progress();
+        assert_eq!(progress.bytes, progress.total_bytes);
+
+        let mut replay = Replay::open_at(&path, 4, |input| Ok(EventReader::new(input)?)).unwrap();
+        assert!(replay.next_event().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
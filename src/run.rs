@@ -0,0 +1,190 @@
+use super::error::RunError;
+use super::time::{Duration, SystemTime};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+/// What a histogram does with its contents when a new run begins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ClearPolicy {
+    #[default]
+    OnNewRun,
+    /// Keep accumulating across runs
+    Accumulate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RunState {
+    #[default]
+    Stopped,
+    Running,
+    Paused,
+}
+
+/// The bookkeeping for one run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub number: u32,
+    pub started: SystemTime,
+    pub ended: Option<SystemTime>,
+    /// Total time spent paused
+    pub paused_for: Duration,
+    pub n_events: u64,
+    pub metadata: FxHashMap<String, String>,
+}
+
+impl RunInfo {
+    /// Time spent running, excluding pauses. Open runs are measured up to now.
+    pub fn get_live_time(&self) -> Duration {
+        let end = self.ended.unwrap_or_else(SystemTime::now);
+        end.duration_since(self.started)
+            .unwrap_or_default()
+            .saturating_sub(self.paused_for)
+    }
+}
+
+/// The begin/pause/resume/end state machine for runs. Events are only accepted while running or
+/// when no run has ever been started.
+#[derive(Debug, Default)]
+pub struct RunControl {
+    state: RunState,
+    current: Option<RunInfo>,
+    paused_at: Option<SystemTime>,
+    history: Vec<RunInfo>,
+}
+
+impl RunControl {
+    pub fn begin(
+        &mut self,
+        number: u32,
+        metadata: FxHashMap<String, String>,
+    ) -> Result<&RunInfo, RunError> {
+        if self.state != RunState::Stopped {
+            return Err(RunError::AlreadyRunning);
+        }
+        if self.history.iter().any(|run| run.number == number) {
+            return Err(RunError::DuplicateRunNumber(number));
+        }
+        self.state = RunState::Running;
+        Ok(self.current.insert(RunInfo {
+            number,
+            started: SystemTime::now(),
+            ended: None,
+            paused_for: Duration::ZERO,
+            n_events: 0,
+            metadata,
+        }))
+    }
+
+    pub fn pause(&mut self) -> Result<(), RunError> {
+        if self.state != RunState::Running {
+            return Err(RunError::NotRunning);
+        }
+        self.state = RunState::Paused;
+        self.paused_at = Some(SystemTime::now());
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<(), RunError> {
+        if self.state != RunState::Paused {
+            return Err(RunError::NotPaused);
+        }
+        self.state = RunState::Running;
+        self.close_pause();
+        Ok(())
+    }
+
+    /// End the current run, returning its final info. The info is also kept in the history.
+    pub fn end(&mut self) -> Result<RunInfo, RunError> {
+        if self.state == RunState::Stopped {
+            return Err(RunError::NotRunning);
+        }
+        self.close_pause();
+        self.state = RunState::Stopped;
+        let mut run = self.current.take().ok_or(RunError::NotRunning)?;
+        run.ended = Some(SystemTime::now());
+        self.history.push(run.clone());
+        Ok(run)
+    }
+
+    fn close_pause(&mut self) {
+        if let (Some(paused_at), Some(run)) = (self.paused_at.take(), &mut self.current) {
+            run.paused_for += paused_at.elapsed().unwrap_or_default();
+        }
+    }
+
+    /// Check if an event should be processed, counting it against the current run if so
+    pub fn accept_event(&mut self) -> bool {
+        self.accept_events(1)
+    }
+
+    /// Check if a batch of events should be processed, counting them towards the current run
+    pub fn accept_events(&mut self, n_events: u64) -> bool {
+        match self.state {
+            RunState::Paused => false,
+            RunState::Running => {
+                if let Some(run) = &mut self.current {
+                    run.n_events += n_events;
+                }
+                true
+            }
+            // Runs are optional, so managers that never start one accept everything
+            RunState::Stopped => self.history.is_empty(),
+        }
+    }
+
+    pub fn get_state(&self) -> RunState {
+        self.state
+    }
+
+    pub fn get_current(&self) -> Option<&RunInfo> {
+        self.current.as_ref()
+    }
+
+    /// The current run if there is one, otherwise the last completed run
+    pub fn get_latest(&self) -> Option<&RunInfo> {
+        self.current.as_ref().or(self.history.last())
+    }
+
+    /// Completed runs, oldest first
+    pub fn get_history(&self) -> &[RunInfo] {
+        &self.history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_control() {
+        let mut control = RunControl::default();
+        assert!(control.accept_event());
+        assert!(control.pause().is_err());
+        assert!(control.end().is_err());
+
+        let mut metadata = FxHashMap::default();
+        metadata.insert(String::from("target"), String::from("CD2"));
+        control.begin(12, metadata).unwrap();
+        assert!(control.begin(13, FxHashMap::default()).is_err());
+        assert!(control.accept_event());
+        control.pause().unwrap();
+        assert_eq!(control.get_state(), RunState::Paused);
+        assert!(!control.accept_event());
+        assert!(control.pause().is_err());
+        control.resume().unwrap();
+        assert!(control.accept_event());
+
+        let run = control.end().unwrap();
+        assert_eq!(run.number, 12);
+        assert_eq!(run.n_events, 2);
+        assert_eq!(run.metadata["target"], "CD2");
+        assert!(run.ended.is_some());
+        assert_eq!(control.get_state(), RunState::Stopped);
+        assert!(!control.accept_event());
+        assert_eq!(control.get_latest(), Some(&run));
+        assert!(matches!(
+            control.begin(12, FxHashMap::default()),
+            Err(RunError::DuplicateRunNumber(12))
+        ));
+    }
+}
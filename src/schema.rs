@@ -0,0 +1,95 @@
+//! Declarations of the variables sources produce, so that a configuration can be checked for
+//! references to variables which will never arrive before any data is taken
+use super::pattern;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+/// How a variable is stored in a DataBlob
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VariableKind {
+    /// One value per event, set with DataBlob::insert
+    Value,
+    /// Condition bits, set with DataBlob::insert_flag
+    Flag,
+    /// Any number of values per event, set with DataBlob::insert_array or push
+    Array,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    variables: FxHashMap<String, VariableKind>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a variable, replacing the kind of any variable with the same name
+    pub fn declare(&mut self, name: &str, kind: VariableKind) {
+        self.variables.insert(name.to_string(), kind);
+    }
+
+    /// Declare a variable while building a schema, as with declare
+    pub fn with(mut self, name: &str, kind: VariableKind) -> Self {
+        self.declare(name, kind);
+        self
+    }
+
+    pub fn get_kind(&self, name: &str) -> Option<VariableKind> {
+        self.variables.get(name).copied()
+    }
+
+    /// Check if a variable is declared. A glob pattern is known if any declared variable
+    /// matches it.
+    pub fn is_known(&self, variable: &str) -> bool {
+        if pattern::is_pattern(variable) {
+            self.variables
+                .keys()
+                .any(|name| pattern::matches(variable, name))
+        } else {
+            self.variables.contains_key(variable)
+        }
+    }
+
+    /// Declare every variable of other, whose kinds win over those already declared
+    pub fn merge(&mut self, other: &Schema) {
+        for (name, kind) in other.iter() {
+            self.declare(name, kind);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, VariableKind)> {
+        self.variables
+            .iter()
+            .map(|(name, kind)| (name.as_str(), *kind))
+    }
+
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema() {
+        let mut schema = Schema::new()
+            .with("sipm_0_energy", VariableKind::Value)
+            .with("gamma_e", VariableKind::Array);
+        assert!(schema.is_known("gamma_e"));
+        assert!(schema.is_known("sipm_*_energy"));
+        assert!(!schema.is_known("sipm_*_time"));
+        assert!(!schema.is_known("gamma"));
+
+        schema.merge(&Schema::new().with("gamma_e", VariableKind::Value));
+        assert_eq!(schema.len(), 2);
+        assert_eq!(schema.get_kind("gamma_e"), Some(VariableKind::Value));
+    }
+}
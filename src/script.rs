@@ -0,0 +1,385 @@
+//! Analysis logic written in Rhai and loaded at runtime, so that variables, conditions, and
+//! selections can change mid-experiment without a rebuild.
+//!
+//! A script is run once per event. It sees the values and arrays of the event as the map
+//! `event`, arrays as arrays of floats, and the flags as the read-only map `flags` of integers.
+//! A ScriptTransform writes whatever the script leaves in `event` back to the event, so scripts
+//! define variables by assigning them, e.g. `event.e_total = event.e1 + event.e2;`, and remove
+//! them with `event.remove("name")`. A transform script ending in `false` drops the event. A
+//! ScriptCut is a script ending in a condition, e.g. `event.tof > 10.0 && flags.pileup == 0`.
+use super::cut::{Cut, CutSpec};
+use super::data_blob::DataBlob;
+use super::error::{CutError, ScriptError};
+use super::time::{Duration, Instant};
+use super::transform::EventTransform;
+use rhai::{AST, Array, Dynamic, Engine, FLOAT, INT, Map, Scope};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How often a script loaded from a file is checked for changes
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A compiled script, recompiled from its file when the file changes
+#[derive(Debug)]
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl Script {
+    pub fn new(source: &str) -> Result<Self, ScriptError> {
+        let engine = Engine::new();
+        let ast = compile(&engine, source)?;
+        Ok(Self {
+            engine,
+            ast,
+            path: None,
+            modified: None,
+        })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ScriptError> {
+        let modified = std::fs::metadata(path)?.modified().ok();
+        let mut script = Self::new(&std::fs::read_to_string(path)?)?;
+        script.path = Some(path.to_path_buf());
+        script.modified = modified;
+        Ok(script)
+    }
+
+    pub fn get_path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Recompile the script if its file changed since it was last read, returning whether it
+    /// was. A script which fails to compile is kept as it was, so a mistake while editing does
+    /// not stop the analysis.
+    pub fn reload(&mut self) -> Result<bool, ScriptError> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)?.modified().ok();
+        if modified.is_some() && modified == self.modified {
+            return Ok(false);
+        }
+        self.modified = modified;
+        self.ast = compile(&self.engine, &std::fs::read_to_string(path)?)?;
+        Ok(true)
+    }
+
+    /// Run the script against an event, returning its value and the scope it left behind
+    fn run(&self, blob: &DataBlob) -> Result<(Dynamic, Scope<'static>), ScriptError> {
+        let mut scope = Scope::new();
+        scope.push("event", to_map(blob));
+        let flags: Map = blob
+            .iter_flags()
+            .map(|(name, bits)| (name.into(), Dynamic::from_int(bits as INT)))
+            .collect();
+        scope.push_constant("flags", flags);
+        let result = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|e| ScriptError::Failed(e.to_string()))?;
+        Ok((result, scope))
+    }
+}
+
+fn compile(engine: &Engine, source: &str) -> Result<AST, ScriptError> {
+    engine
+        .compile(source)
+        .map_err(|e| ScriptError::Compile(e.to_string()))
+}
+
+fn to_map(blob: &DataBlob) -> Map {
+    let mut map: Map = blob
+        .iter()
+        .map(|(name, value)| (name.into(), Dynamic::from_float(*value as FLOAT)))
+        .collect();
+    for (name, values) in blob.iter_arrays() {
+        let values: Array = values
+            .iter()
+            .map(|value| Dynamic::from_float(*value as FLOAT))
+            .collect();
+        map.insert(name.into(), Dynamic::from_array(values));
+    }
+    map
+}
+
+fn to_number(name: &str, value: &Dynamic) -> Result<f32, ScriptError> {
+    match (value.as_float(), value.as_int()) {
+        (Ok(value), _) => Ok(value as f32),
+        (_, Ok(value)) => Ok(value as f32),
+        _ => Err(ScriptError::NotNumber(name.to_string())),
+    }
+}
+
+/// Runs a script on every event, replacing the variables of the event with those the script
+/// leaves in `event`. A script which fails leaves the event as it was and is counted, so that a
+/// bad event does not stop the analysis.
+#[derive(Debug)]
+pub struct ScriptTransform {
+    script: Script,
+    last_check: Instant,
+    n_failures: u64,
+}
+
+impl ScriptTransform {
+    pub fn new(script: Script) -> Self {
+        Self {
+            script,
+            last_check: Instant::now(),
+            n_failures: 0,
+        }
+    }
+
+    /// A transform running a script file, which is reloaded within RELOAD_INTERVAL of changing
+    pub fn from_file(path: &Path) -> Result<Self, ScriptError> {
+        Ok(Self::new(Script::from_file(path)?))
+    }
+
+    pub fn get_script(&self) -> &Script {
+        &self.script
+    }
+
+    /// The number of events the script failed on, and of failed reloads
+    pub fn get_n_failures(&self) -> u64 {
+        self.n_failures
+    }
+
+    fn fail(&mut self, _error: ScriptError) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(path = ?self.script.get_path(), "Script failed: {_error}");
+        self.n_failures += 1;
+    }
+
+    // Check every value before touching the event, so a failure leaves it as it was
+    fn apply(event: Map, data: &mut DataBlob) -> Result<(), ScriptError> {
+        let mut values = vec![];
+        let mut arrays = vec![];
+        for (name, value) in event.iter() {
+            if value.is_array() {
+                let array = value
+                    .read_lock::<Array>()
+                    .ok_or(ScriptError::NotNumber(name.to_string()))?;
+                let entries = array
+                    .iter()
+                    .map(|entry| to_number(name, entry))
+                    .collect::<Result<Vec<f32>, _>>()?;
+                arrays.push((name.as_str(), entries));
+            } else {
+                values.push((name.as_str(), to_number(name, value)?));
+            }
+        }
+        let old_values: Vec<String> = data.iter().map(|(name, _)| name.to_string()).collect();
+        for name in old_values {
+            data.remove(&name);
+        }
+        let old_arrays: Vec<String> = data
+            .iter_arrays()
+            .map(|(name, _)| name.to_string())
+            .collect();
+        for name in old_arrays {
+            data.remove_array(&name);
+        }
+        for (name, value) in values {
+            data.insert(name, value);
+        }
+        for (name, entries) in arrays {
+            data.insert_array(name, entries);
+        }
+        Ok(())
+    }
+}
+
+impl EventTransform for ScriptTransform {
+    fn transform(&mut self, mut data: DataBlob) -> Option<DataBlob> {
+        if self.last_check.elapsed() >= RELOAD_INTERVAL {
+            self.last_check = Instant::now();
+            if let Err(e) = self.script.reload() {
+                self.fail(e);
+            }
+        }
+        let result = self.script.run(&data).and_then(|(result, scope)| {
+            let event = scope
+                .get_value::<Map>("event")
+                .ok_or(ScriptError::LostEvent)?;
+            Self::apply(event, &mut data)?;
+            Ok(result)
+        });
+        match result {
+            Ok(result) => (result.as_bool() != Ok(false)).then_some(data),
+            Err(e) => {
+                self.fail(e);
+                Some(data)
+            }
+        }
+    }
+}
+
+/// A cut whose condition is a script. The variables of its spec are not used; the script reads
+/// whatever it needs from the event. Events the script fails on, or which it does not evaluate
+/// to a bool for, are outside the cut.
+#[derive(Debug)]
+pub struct ScriptCut {
+    spec: CutSpec,
+    source: String,
+    script: Script,
+    is_valid: bool,
+    n_failures: u64,
+}
+
+impl Cut for ScriptCut {
+    fn is_valid(&self) -> bool {
+        self.is_valid
+    }
+
+    fn is_inside(&mut self, blob: &DataBlob) {
+        self.is_valid = match self.script.run(blob).map(|(result, _)| result.as_bool()) {
+            Ok(Ok(is_inside)) => is_inside,
+            _ => {
+                self.n_failures += 1;
+                false
+            }
+        };
+    }
+
+    fn reset(&mut self) {
+        self.is_valid = false;
+    }
+
+    fn get_spec(&self) -> &CutSpec {
+        &self.spec
+    }
+
+    fn get_kind(&self) -> &str {
+        Self::KIND
+    }
+
+    fn get_parameters(&self) -> serde_json::Value {
+        serde_json::json!({ "source": self.source })
+    }
+}
+
+impl ScriptCut {
+    pub const KIND: &str = "ScriptCut";
+
+    pub fn new(spec: CutSpec, source: &str) -> Result<Self, ScriptError> {
+        Ok(Self {
+            spec,
+            source: source.to_string(),
+            script: Script::new(source)?,
+            is_valid: false,
+            n_failures: 0,
+        })
+    }
+
+    /// A cut with the condition in a script file. The source is kept with the cut, so a saved
+    /// cut does not depend on the file.
+    pub fn from_file(spec: CutSpec, path: &Path) -> Result<Self, ScriptError> {
+        Self::new(spec, &std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_parameters(
+        spec: CutSpec,
+        parameters: &serde_json::Value,
+    ) -> Result<Self, CutError> {
+        let source = parameters["source"]
+            .as_str()
+            .ok_or(CutError::BadParameters(String::from(
+                "A script cut needs its source",
+            )))?;
+        Self::new(spec, source).map_err(|e| CutError::BadParameters(e.to_string()))
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    /// The number of events the script failed on
+    pub fn get_n_failures(&self) -> u64 {
+        self.n_failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cut_registry::{CutRegistry, SavedCut};
+    use uuid::Uuid;
+
+    fn event() -> DataBlob {
+        let mut blob = DataBlob::new();
+        blob.insert("e1", 1.5);
+        blob.insert("e2", 2.0);
+        blob.insert_array("gamma", vec![511.0, 1274.5]);
+        blob.insert_flag("pileup", 0b10);
+        blob
+    }
+
+    #[test]
+    fn test_script_transform() {
+        let script = Script::new(
+            r#"
+            if flags.pileup == 1 { return false; }
+            event.e_total = event.e1 + event.e2;
+            event.gamma.push(100);
+            event.remove("e2");
+            "#,
+        )
+        .unwrap();
+        let mut transform = ScriptTransform::new(script);
+        let blob = transform.transform(event()).unwrap();
+        assert_eq!(blob.find("e_total"), Some(&3.5));
+        assert_eq!(blob.find("e1"), Some(&1.5));
+        assert_eq!(blob.find("e2"), None);
+        assert_eq!(blob.find_array("gamma"), Some(&[511.0, 1274.5, 100.0][..]));
+        assert_eq!(blob.find_flag("pileup"), Some(0b10));
+
+        let mut dropping = ScriptTransform::new(Script::new("event.e1 < 1.0").unwrap());
+        assert!(dropping.transform(event()).is_none());
+
+        let mut failing = ScriptTransform::new(Script::new("event.e1 = \"text\";").unwrap());
+        let blob = failing.transform(event()).unwrap();
+        assert_eq!(blob.find("e1"), Some(&1.5));
+        assert_eq!(failing.get_n_failures(), 1);
+        assert!(Script::new("event.e1 = ").is_err());
+    }
+
+    #[test]
+    fn test_script_reload() {
+        let path = std::env::temp_dir().join(format!("{}.rhai", Uuid::new_v4()));
+        std::fs::write(&path, "event.x = 1.0;").unwrap();
+        let mut script = Script::from_file(&path).unwrap();
+        assert!(!script.reload().unwrap());
+        script.modified = None;
+        std::fs::write(&path, "event.x = 2.0;").unwrap();
+        assert!(script.reload().unwrap());
+        let mut transform = ScriptTransform::new(script);
+        let blob = transform.transform(DataBlob::new()).unwrap();
+        assert_eq!(blob.find("x"), Some(&2.0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_script_cut() {
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("sum"),
+            x_variable: String::new(),
+            y_variable: None,
+        };
+        let mut cut =
+            ScriptCut::new(spec, "event.e1 + event.e2 > 3.0 && flags.pileup == 2").unwrap();
+        cut.is_inside(&event());
+        assert!(cut.is_valid());
+        cut.is_inside(&DataBlob::new());
+        assert!(!cut.is_valid());
+        assert_eq!(cut.get_n_failures(), 1);
+
+        let saved = SavedCut::from_cut(&cut);
+        let mut restored = CutRegistry::default().build(&saved).unwrap();
+        restored.is_inside(&event());
+        assert!(restored.is_valid());
+    }
+}
@@ -0,0 +1,216 @@
+use super::error::HistogramError;
+use super::histogram::{HistSpec, Histogram};
+use super::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+// A poisoned shard still holds valid counts, so keep using it
+fn lock(gram: &Mutex<Histogram>) -> MutexGuard<'_, Histogram> {
+    gram.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// One worker's private copy of a ShardedHistogram. Its lock is only contended while the
+/// contents are collected, which is a swap rather than a copy.
+#[derive(Debug, Clone)]
+pub struct Shard {
+    gram: Arc<Mutex<Histogram>>,
+}
+
+impl Shard {
+    pub fn fill(
+        &self,
+        x_value: f32,
+        y_value: Option<f32>,
+    ) -> Result<Option<usize>, HistogramError> {
+        lock(&self.gram).fill(x_value, y_value)
+    }
+
+    pub fn fill_weighted(
+        &self,
+        x_value: f32,
+        y_value: Option<f32>,
+        weight: f64,
+    ) -> Result<Option<usize>, HistogramError> {
+        lock(&self.gram).fill_weighted(x_value, y_value, weight)
+    }
+}
+
+/// A histogram filled through per-worker shards which are periodically merged into a display
+/// copy. Unlike an AtomicHistogram every fill is uncontended and every histogram feature except
+/// auto ranging and rolling windows works, but the display lags the fills by up to one merge
+/// interval.
+#[derive(Debug)]
+pub struct ShardedHistogram {
+    spec: HistSpec,
+    shards: Mutex<Vec<Arc<Mutex<Histogram>>>>,
+    display: Mutex<Histogram>,
+}
+
+impl ShardedHistogram {
+    pub fn new(spec: HistSpec) -> Result<Self, HistogramError> {
+        if spec.auto_range.is_some() {
+            return Err(HistogramError::Unsupported(
+                spec.name.clone(),
+                String::from("auto ranging"),
+            ));
+        } else if spec.window.is_some() {
+            return Err(HistogramError::Unsupported(
+                spec.name.clone(),
+                String::from("rolling windows"),
+            ));
+        }
+        Ok(Self {
+            display: Mutex::new(Histogram::new(spec.clone())),
+            shards: Mutex::new(vec![]),
+            spec,
+        })
+    }
+
+    pub fn get_spec(&self) -> &HistSpec {
+        &self.spec
+    }
+
+    /// Make a new shard for a worker to fill
+    pub fn add_shard(&self) -> Shard {
+        let gram = Arc::new(Mutex::new(Histogram::new(self.spec.clone())));
+        self.shards
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(gram.clone());
+        Shard { gram }
+    }
+
+    /// Take everything filled into the shards since the last collection, leaving them empty.
+    /// Shards whose workers have dropped them are removed once emptied.
+    pub fn collect(&self) -> Histogram {
+        let mut total = Histogram::new(self.spec.clone());
+        let mut shards = self
+            .shards
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // A shard is only known to be finished if its worker dropped it before it was emptied,
+        // as the worker may fill it again until then
+        shards.retain(|shard| {
+            let is_dropped = Arc::strong_count(shard) == 1;
+            let filled = std::mem::replace(&mut *lock(shard), Histogram::new(self.spec.clone()));
+            // Every shard shares the spec of the total, so they are always compatible
+            let _ = total.merge_from(&filled);
+            !is_dropped
+        });
+        total
+    }
+
+    /// Collect the shards into the display copy
+    pub fn merge(&self) {
+        let filled = self.collect();
+        let _ = lock(&self.display).merge_from(&filled);
+    }
+
+    /// Get a copy of the display histogram
+    pub fn snapshot(&self) -> Histogram {
+        lock(&self.display).clone()
+    }
+
+    /// Zero the display copy. Fills not yet merged are kept.
+    pub fn clear(&self) {
+        lock(&self.display).clear();
+    }
+
+    /// Merge the shards into the display copy from a background thread every interval
+    pub fn spawn_merger(self: &Arc<Self>, interval: Duration) -> Merger {
+        let stop = Arc::new(AtomicBool::new(false));
+        let sharded = self.clone();
+        let stopped = stop.clone();
+        let handle = std::thread::spawn(move || {
+            loop {
+                std::thread::park_timeout(interval);
+                // Read the flag before merging, so that the last merge starts after the stop
+                let is_stopped = stopped.load(Ordering::Acquire);
+                sharded.merge();
+                if is_stopped {
+                    break;
+                }
+            }
+        });
+        Merger {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+/// The background thread started by ShardedHistogram::spawn_merger. It runs one final merge and
+/// exits when stopped or dropped.
+#[derive(Debug)]
+pub struct Merger {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Merger {
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            self.stop.store(true, Ordering::Release);
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Merger {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_shards() {
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            track_errors: true,
+            ..HistSpec::new(
+                "sharded",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        };
+        let sharded = Arc::new(ShardedHistogram::new(spec).unwrap());
+        let merger = sharded.spawn_merger(Duration::from_millis(1));
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let shard = sharded.add_shard();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        shard.fill((i % 10) as f32 + 0.5, None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        merger.stop();
+
+        let display = sharded.snapshot();
+        assert_eq!(display.data, vec![400.0; 10]);
+        assert_eq!(display.stats.filled, 4000);
+        assert_eq!(display.bin_error(0).unwrap(), 20.0);
+        // The workers are gone, so their shards were dropped by the final merge
+        assert!(sharded.shards.lock().unwrap().is_empty());
+
+        let shard = sharded.add_shard();
+        shard.fill(0.5, None).unwrap();
+        assert_eq!(sharded.collect().data[0], 1.0);
+        assert_eq!(sharded.collect().data[0], 0.0);
+    }
+}
@@ -0,0 +1,269 @@
+//! Synthetic events drawn from simple distributions, for exercising pipelines, cuts and displays
+//! without detector data
+use super::data_blob::DataBlob;
+use super::error::SourceError;
+use super::schema::{Schema, VariableKind};
+use super::source::DataSource;
+use super::time::{Duration, Instant};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// A distribution of one or more variables
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+    Gaussian {
+        variable: String,
+        mean: f32,
+        sigma: f32,
+    },
+    /// Uniform over [low, high)
+    Uniform {
+        variable: String,
+        low: f32,
+        high: f32,
+    },
+    /// A 2D Gaussian blob whose x and y have the given correlation, from -1 to 1
+    Blob {
+        x_variable: String,
+        y_variable: String,
+        x_mean: f32,
+        y_mean: f32,
+        x_sigma: f32,
+        y_sigma: f32,
+        correlation: f32,
+    },
+    /// Pick one of the generators at random by its weight, e.g. for several particle groups in
+    /// one identification plot
+    Mixture(Vec<(f64, Generator)>),
+}
+
+// A standard normal pair by the Box-Muller transform
+fn normal_pair(rng: &mut impl Rng) -> (f32, f32) {
+    let u: f32 = 1.0 - rng.random::<f32>();
+    let v: f32 = rng.random();
+    let r = (-2.0 * u.ln()).sqrt();
+    let angle = std::f32::consts::TAU * v;
+    (r * angle.cos(), r * angle.sin())
+}
+
+impl Generator {
+    /// Declare the variables the generator makes, including those of every mixture component
+    fn declare(&self, schema: &mut Schema) {
+        match self {
+            Self::Gaussian { variable, .. } | Self::Uniform { variable, .. } => {
+                schema.declare(variable, VariableKind::Value)
+            }
+            Self::Blob {
+                x_variable,
+                y_variable,
+                ..
+            } => {
+                schema.declare(x_variable, VariableKind::Value);
+                schema.declare(y_variable, VariableKind::Value);
+            }
+            Self::Mixture(generators) => {
+                for (_, generator) in generators {
+                    generator.declare(schema);
+                }
+            }
+        }
+    }
+
+    fn generate(&self, rng: &mut impl Rng, event: &mut DataBlob) {
+        match self {
+            Self::Gaussian {
+                variable,
+                mean,
+                sigma,
+            } => event.insert(variable, mean + sigma * normal_pair(rng).0),
+            Self::Uniform {
+                variable,
+                low,
+                high,
+            } => event.insert(variable, low + (high - low) * rng.random::<f32>()),
+            Self::Blob {
+                x_variable,
+                y_variable,
+                x_mean,
+                y_mean,
+                x_sigma,
+                y_sigma,
+                correlation,
+            } => {
+                let rho = correlation.clamp(-1.0, 1.0);
+                let (z1, z2) = normal_pair(rng);
+                event.insert(x_variable, x_mean + x_sigma * z1);
+                event.insert(
+                    y_variable,
+                    y_mean + y_sigma * (rho * z1 + (1.0 - rho * rho).sqrt() * z2),
+                );
+            }
+            Self::Mixture(generators) => {
+                let total: f64 = generators.iter().map(|(weight, _)| weight.max(0.0)).sum();
+                let mut target = rng.random::<f64>() * total;
+                for (weight, generator) in generators {
+                    target -= weight.max(0.0);
+                    if target < 0.0 {
+                        generator.generate(rng, event);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimSpec {
+    /// Every generator contributes to every event
+    pub generators: Vec<Generator>,
+    /// Events per second, or as fast as they are taken if None
+    pub rate: Option<f64>,
+    /// The number of events to make, or unlimited if None
+    pub n_events: Option<u64>,
+    /// The same seed always makes the same events
+    pub seed: u64,
+}
+
+/// A DataSource of synthetic events following a SimSpec
+#[derive(Debug)]
+pub struct SimSource {
+    spec: SimSpec,
+    rng: StdRng,
+    n_made: u64,
+    start: Option<Instant>,
+    stopped: bool,
+}
+
+impl SimSource {
+    pub fn new(spec: SimSpec) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(spec.seed),
+            spec,
+            n_made: 0,
+            start: None,
+            stopped: false,
+        }
+    }
+
+    pub fn get_n_made(&self) -> u64 {
+        self.n_made
+    }
+
+    // Sleep until the next event is due at the configured rate
+    fn pace(&mut self) {
+        let Some(rate) = self.spec.rate.filter(|rate| *rate > 0.0) else {
+            return;
+        };
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let due = start + Duration::from_secs_f64(self.n_made as f64 / rate);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
+
+impl DataSource for SimSource {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        if self.stopped || self.spec.n_events.is_some_and(|n| self.n_made >= n) {
+            return Ok(None);
+        }
+        self.pace();
+        let mut event = DataBlob::new();
+        for generator in &self.spec.generators {
+            generator.generate(&mut self.rng, &mut event);
+        }
+        self.n_made += 1;
+        Ok(Some(event))
+    }
+
+    fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    fn get_schema(&self) -> Option<Schema> {
+        let mut schema = Schema::new();
+        for generator in &self.spec.generators {
+            generator.declare(&mut schema);
+        }
+        Some(schema)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sim_source() {
+        let spec = SimSpec {
+            generators: vec![
+                Generator::Gaussian {
+                    variable: String::from("e"),
+                    mean: 100.0,
+                    sigma: 5.0,
+                },
+                Generator::Uniform {
+                    variable: String::from("t"),
+                    low: 0.0,
+                    high: 10.0,
+                },
+                Generator::Blob {
+                    x_variable: String::from("x"),
+                    y_variable: String::from("y"),
+                    x_mean: 0.0,
+                    y_mean: 0.0,
+                    x_sigma: 1.0,
+                    y_sigma: 2.0,
+                    correlation: -1.0,
+                },
+                Generator::Mixture(vec![
+                    (
+                        1.0,
+                        Generator::Gaussian {
+                            variable: String::from("a"),
+                            mean: 0.0,
+                            sigma: 1.0,
+                        },
+                    ),
+                    (
+                        0.0,
+                        Generator::Gaussian {
+                            variable: String::from("b"),
+                            mean: 0.0,
+                            sigma: 1.0,
+                        },
+                    ),
+                ]),
+            ],
+            rate: None,
+            n_events: Some(2000),
+            seed: 7,
+        };
+        let mut source = SimSource::new(spec.clone());
+        let mut energies = vec![];
+        while let Some(event) = source.next_event().unwrap() {
+            energies.push(*event.find("e").unwrap());
+            assert!((0.0..10.0).contains(event.find("t").unwrap()));
+            let (x, y) = (event.find("x").unwrap(), event.find("y").unwrap());
+            assert!((y + 2.0 * x).abs() < 1e-3);
+            assert!(event.find("a").is_some() && event.find("b").is_none());
+        }
+        assert_eq!(energies.len(), 2000);
+        let schema = source.get_schema().unwrap();
+        assert_eq!(schema.len(), 6);
+        assert!(schema.is_known("b"));
+        let mean = energies.iter().sum::<f32>() / 2000.0;
+        assert!((mean - 100.0).abs() < 0.5);
+
+        let mut again = SimSource::new(spec);
+        assert_eq!(
+            again.next_event().unwrap().unwrap().find("e"),
+            Some(&energies[0])
+        );
+        again.stop();
+        assert!(again.next_event().unwrap().is_none());
+    }
+}
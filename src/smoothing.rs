@@ -0,0 +1,148 @@
+//! Smoothing kernels for 1D spectra, e.g. to steady peak finding on low statistics data
+use super::error::HistogramError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Smoothing {
+    /// Average each bin with half_width bins on either side
+    MovingAverage { half_width: usize },
+    /// Fit a polynomial of the given order to each window of 2 * half_width + 1 bins, which
+    /// keeps peak heights and widths better than averaging
+    SavitzkyGolay { half_width: usize, order: usize },
+    /// Convolve with a Gaussian of this sigma, in axis units. The kernel is cut off at 3 sigma.
+    Gaussian { sigma: f32 },
+}
+
+impl Smoothing {
+    /// Get the weights applied to the bins from -half_width to +half_width around each bin
+    pub fn kernel(&self, bin_width: f32) -> Result<Vec<f64>, HistogramError> {
+        match self {
+            Self::MovingAverage { half_width } => {
+                let n = 2 * half_width + 1;
+                Ok(vec![1.0 / n as f64; n])
+            }
+            Self::SavitzkyGolay { half_width, order } => {
+                if *order > 2 * half_width {
+                    return Err(HistogramError::BadSmoothing(format!(
+                        "Savitzky-Golay order {order} needs more than {} bins",
+                        2 * half_width + 1
+                    )));
+                }
+                Ok(savitzky_golay(*half_width, *order))
+            }
+            Self::Gaussian { sigma } => {
+                if !(*sigma > 0.0 && bin_width > 0.0) {
+                    return Err(HistogramError::BadSmoothing(format!(
+                        "Gaussian sigma must be positive, not {sigma}"
+                    )));
+                }
+                let sigma = (sigma / bin_width) as f64;
+                let half_width = (3.0 * sigma).ceil() as i64;
+                let weights: Vec<f64> = (-half_width..=half_width)
+                    .map(|offset| (-0.5 * (offset as f64 / sigma).powi(2)).exp())
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                Ok(weights.iter().map(|weight| weight / total).collect())
+            }
+        }
+    }
+}
+
+// The weights giving the value at the centre of a least squares polynomial fit to the window
+fn savitzky_golay(half_width: usize, order: usize) -> Vec<f64> {
+    let offsets: Vec<f64> = (0..2 * half_width + 1)
+        .map(|idx| idx as f64 - half_width as f64)
+        .collect();
+    // Solve the normal equations of the fit for the constant term
+    let n = order + 1;
+    let mut matrix: Vec<Vec<f64>> = (0..n)
+        .map(|row| {
+            let mut equation: Vec<f64> = (0..n)
+                .map(|col| offsets.iter().map(|x| x.powi((row + col) as i32)).sum())
+                .collect();
+            equation.push((row == 0) as u8 as f64);
+            equation
+        })
+        .collect();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|a, b| matrix[*a][col].abs().total_cmp(&matrix[*b][col].abs()))
+            .unwrap_or(col);
+        matrix.swap(col, pivot);
+        let pivot_equation = matrix[col].clone();
+        for (row, equation) in matrix.iter_mut().enumerate() {
+            if row != col {
+                let factor = equation[col] / pivot_equation[col];
+                for (value, pivot_value) in equation.iter_mut().zip(&pivot_equation).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    let coefficients: Vec<f64> = (0..n)
+        .map(|row| matrix[row][n] / matrix[row][row])
+        .collect();
+    offsets
+        .iter()
+        .map(|x| {
+            coefficients
+                .iter()
+                .enumerate()
+                .map(|(power, c)| c * x.powi(power as i32))
+                .sum()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, HistSpec, Histogram, OutOfRangePolicy};
+
+    #[test]
+    fn test_kernels() {
+        let average = Smoothing::MovingAverage { half_width: 1 };
+        assert_eq!(average.kernel(1.0).unwrap(), vec![1.0 / 3.0; 3]);
+
+        // The classic 5 point quadratic kernel, (-3, 12, 17, 12, -3) / 35
+        let sg = Smoothing::SavitzkyGolay {
+            half_width: 2,
+            order: 2,
+        };
+        let expected = [-3.0, 12.0, 17.0, 12.0, -3.0];
+        for (weight, expected) in sg.kernel(1.0).unwrap().iter().zip(expected) {
+            assert!((weight - expected / 35.0).abs() < 1e-12);
+        }
+        let too_high = Smoothing::SavitzkyGolay {
+            half_width: 1,
+            order: 3,
+        };
+        assert!(too_high.kernel(1.0).is_err());
+
+        let gaussian = Smoothing::Gaussian { sigma: 2.0 }.kernel(2.0).unwrap();
+        assert_eq!(gaussian.len(), 7);
+        assert!((gaussian.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(gaussian[3] > gaussian[2] && gaussian[2] == gaussian[4]);
+        assert!(Smoothing::Gaussian { sigma: 0.0 }.kernel(1.0).is_err());
+    }
+
+    #[test]
+    fn test_smoothed_histogram() {
+        let mut gram = Histogram::new(HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "energy",
+                AxisSpec::new("e", "e", 5, 0.0, 5.0).unwrap(),
+                None,
+            )
+        });
+        gram.fill_weighted(0.5, None, 3.0).unwrap();
+        gram.fill_weighted(2.5, None, 9.0).unwrap();
+        let smoothed = gram
+            .smoothed(&Smoothing::MovingAverage { half_width: 1 })
+            .unwrap();
+        assert_eq!(smoothed.data, vec![2.0, 4.0, 3.0, 3.0, 0.0]);
+        // The middle bin sees one bin of 9 counts with a weight of 1/3
+        assert!((smoothed.bin_error(3).unwrap() - 1.0).abs() < 1e-12);
+    }
+}
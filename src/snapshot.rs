@@ -0,0 +1,379 @@
+//! Periodic snapshots of histograms for monitoring pages which refresh themselves during an
+//! experiment. The analysis thread takes the snapshots, every interval, as JSON data files or
+//! images; a background thread writes them to a directory or posts them to a webhook, so slow
+//! disks and networks never hold up the analysis.
+use super::error::SnapshotError;
+use super::histogram::Histogram;
+use super::manager::ResourceManager;
+use super::pattern;
+#[cfg(feature = "render")]
+use super::render::{self, ImageFormat, RenderSpec};
+use super::time::{Duration, Instant};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread::JoinHandle;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotFormat {
+    /// The axes and contents of a histogram, with x varying fastest
+    Json,
+    /// An image in the format of the spec
+    #[cfg(feature = "render")]
+    Image(RenderSpec),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotTarget {
+    /// Files named by the histograms, in subdirectories for their folders. Each file is replaced
+    /// at once, so a page never loads one half written.
+    Directory(PathBuf),
+    /// A URL such as "http://monitor:8080/upload", which each file is posted to with its name in
+    /// the X-Snapshot-Name header
+    Webhook(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotSpec {
+    /// The histograms to export, by name or pattern, e.g. "si/*"
+    pub pattern: String,
+    pub interval: Duration,
+    pub formats: Vec<SnapshotFormat>,
+    pub target: SnapshotTarget,
+}
+
+/// One exported file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub content_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+fn get_json(gram: &Histogram, manager: &ResourceManager) -> Vec<u8> {
+    let axis = |axis: &super::histogram::AxisSpec| {
+        json!({
+            "variable": axis.variable,
+            "title": axis.get_label(),
+            "bins": axis.bins,
+            "minimum": axis.minimum,
+            "maximum": axis.maximum,
+        })
+    };
+    let y_bins = gram.spec.y_axis.as_ref().map_or(1, |axis| axis.bins);
+    let mut data = Vec::with_capacity(gram.spec.x_axis.bins * y_bins);
+    for y_bin in 0..y_bins {
+        for x_bin in 0..gram.spec.x_axis.bins {
+            data.push(gram.get_bin_content(x_bin, y_bin).unwrap_or(0.0));
+        }
+    }
+    json!({
+        "name": gram.spec.name,
+        "title": gram.spec.title,
+        "x_axis": axis(&gram.spec.x_axis),
+        "y_axis": gram.spec.y_axis.as_ref().map(axis),
+        "events": manager.get_event_count(),
+        "run": manager.get_run_info().map(|run| run.number),
+        "data": data,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Take a snapshot of every histogram matching the spec, in each of its formats
+pub fn take_snapshots(
+    manager: &ResourceManager,
+    spec: &SnapshotSpec,
+) -> Result<Vec<Snapshot>, SnapshotError> {
+    let mut snapshots = Vec::new();
+    for hist_spec in manager.list_histograms("") {
+        if !pattern::matches(&spec.pattern, &hist_spec.name) {
+            continue;
+        }
+        let gram = manager
+            .get_histogram(&hist_spec.id)
+            .expect("Listed histograms exist");
+        for format in spec.formats.iter() {
+            let snapshot = match format {
+                SnapshotFormat::Json => Snapshot {
+                    name: format!("{}.json", hist_spec.name),
+                    content_type: "application/json",
+                    data: get_json(gram, manager),
+                },
+                #[cfg(feature = "render")]
+                SnapshotFormat::Image(render_spec) => {
+                    let cuts: Vec<_> = manager
+                        .export_cuts()
+                        .into_iter()
+                        .filter(|cut| hist_spec.cuts_to_draw.contains(&cut.spec.id))
+                        .collect();
+                    let extension = render_spec.format.get_extension();
+                    let (content_type, data) = match render_spec.format {
+                        ImageFormat::Png => {
+                            ("image/png", render::render_png(gram, &cuts, render_spec)?)
+                        }
+                        ImageFormat::Svg => (
+                            "image/svg+xml",
+                            render::render_svg(gram, &cuts, render_spec)?.into_bytes(),
+                        ),
+                    };
+                    Snapshot {
+                        name: format!("{}.{extension}", hist_spec.name),
+                        content_type,
+                        data,
+                    }
+                }
+            };
+            snapshots.push(snapshot);
+        }
+    }
+    Ok(snapshots)
+}
+
+// Where the background thread sends snapshots
+enum Destination {
+    Directory(PathBuf),
+    Webhook {
+        address: String,
+        host: String,
+        path: String,
+    },
+}
+
+impl Destination {
+    fn new(target: &SnapshotTarget) -> Result<Self, SnapshotError> {
+        match target {
+            SnapshotTarget::Directory(directory) => Ok(Self::Directory(directory.clone())),
+            SnapshotTarget::Webhook(url) => {
+                let rest = url
+                    .strip_prefix("http://")
+                    .ok_or_else(|| SnapshotError::BadUrl(url.clone()))?;
+                let (host, path) = match rest.find('/') {
+                    Some(idx) => (&rest[..idx], &rest[idx..]),
+                    None => (rest, "/"),
+                };
+                if host.is_empty() {
+                    return Err(SnapshotError::BadUrl(url.clone()));
+                }
+                let address = if host.contains(':') {
+                    host.to_string()
+                } else {
+                    format!("{host}:80")
+                };
+                Ok(Self::Webhook {
+                    address,
+                    host: host.to_string(),
+                    path: path.to_string(),
+                })
+            }
+        }
+    }
+
+    fn send(&self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        match self {
+            Self::Directory(directory) => {
+                let path = directory.join(&snapshot.name);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                // Replace the file at once by renaming a complete copy over it
+                let mut partial = path.clone().into_os_string();
+                partial.push(".partial");
+                std::fs::write(&partial, &snapshot.data)?;
+                std::fs::rename(&partial, &path)?;
+                Ok(())
+            }
+            Self::Webhook {
+                address,
+                host,
+                path,
+            } => {
+                let mut stream = TcpStream::connect(address)?;
+                write!(
+                    stream,
+                    "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: {}\r\nContent-Length: {}\r\nX-Snapshot-Name: {}\r\nConnection: close\r\n\r\n",
+                    snapshot.content_type,
+                    snapshot.data.len(),
+                    snapshot.name
+                )?;
+                stream.write_all(&snapshot.data)?;
+                stream.flush()?;
+                let mut status = String::new();
+                BufReader::new(stream).read_line(&mut status)?;
+                match status.split_whitespace().nth(1) {
+                    Some(code) if code.starts_with('2') => Ok(()),
+                    _ => Err(SnapshotError::Rejected(status.trim().to_string())),
+                }
+            }
+        }
+    }
+}
+
+/// Exports snapshots every interval of the spec. The analysis thread calls process regularly,
+/// which takes the snapshots once the interval has passed.
+#[derive(Debug)]
+pub struct SnapshotExporter {
+    spec: SnapshotSpec,
+    last_export: Option<Instant>,
+    snapshots: Sender<Vec<Snapshot>>,
+    // Failures of the background thread, reported by the next call to process
+    errors: Receiver<SnapshotError>,
+}
+
+impl SnapshotExporter {
+    /// Start the background thread sending snapshots to the target of spec. The thread stops
+    /// once the exporter is dropped.
+    pub fn start(spec: SnapshotSpec) -> Result<(Self, JoinHandle<()>), SnapshotError> {
+        let destination = Destination::new(&spec.target)?;
+        let (snapshots, received) = channel::<Vec<Snapshot>>();
+        let (failed, errors) = channel();
+        let handle = std::thread::spawn(move || {
+            for batch in received {
+                for snapshot in batch.iter() {
+                    if let Err(e) = destination.send(snapshot) {
+                        #[cfg(feature = "tracing")]
+                        tracing::warn!(snapshot = %snapshot.name, "Snapshot not sent: {e}");
+                        let _ = failed.send(e);
+                    }
+                }
+            }
+        });
+        Ok((
+            Self {
+                spec,
+                last_export: None,
+                snapshots,
+                errors,
+            },
+            handle,
+        ))
+    }
+
+    pub fn get_spec(&self) -> &SnapshotSpec {
+        &self.spec
+    }
+
+    /// Take snapshots if the interval has passed since the last ones, returning the number
+    /// taken. A failure to send earlier snapshots is returned instead, once.
+    pub fn process(&mut self, manager: &ResourceManager) -> Result<usize, SnapshotError> {
+        if let Ok(e) = self.errors.try_recv() {
+            return Err(e);
+        }
+        match self.last_export {
+            Some(time) if time.elapsed() < self.spec.interval => Ok(0),
+            _ => self.export(manager),
+        }
+    }
+
+    /// Take snapshots now, whatever the interval, returning the number taken
+    pub fn export(&mut self, manager: &ResourceManager) -> Result<usize, SnapshotError> {
+        self.last_export = Some(Instant::now());
+        let snapshots = take_snapshots(manager, &self.spec)?;
+        let n_snapshots = snapshots.len();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(n_snapshots, "Took snapshots");
+        // The thread only stops once the exporter is dropped
+        let _ = self.snapshots.send(snapshots);
+        Ok(n_snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+    use std::io::Read;
+    use std::net::TcpListener;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_snapshots() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            title: String::from("Silicon energy"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new("si/e", AxisSpec::new("e", "E", 4, 0.0, 4.0).unwrap(), None)
+        };
+        let other = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("ic/e"),
+            ..spec.clone()
+        };
+        manager.add_histogram(spec).unwrap();
+        manager.add_histogram(other).unwrap();
+        let mut data = DataBlob::new();
+        data.insert("e", 2.5);
+        manager.update(data).unwrap();
+
+        let directory = std::env::temp_dir().join(format!("snapshot_{}", Uuid::new_v4()));
+        let spec = SnapshotSpec {
+            pattern: String::from("si/*"),
+            interval: Duration::from_secs(3600),
+            formats: vec![SnapshotFormat::Json],
+            target: SnapshotTarget::Directory(directory.clone()),
+        };
+        let (mut exporter, handle) = SnapshotExporter::start(spec).unwrap();
+        assert_eq!(exporter.process(&manager).unwrap(), 1);
+        // The interval has not passed
+        assert_eq!(exporter.process(&manager).unwrap(), 0);
+        drop(exporter);
+        handle.join().unwrap();
+        let written: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(directory.join("si/e.json")).unwrap()).unwrap();
+        assert_eq!(written["data"], json!([0.0, 0.0, 1.0, 0.0]));
+        assert_eq!(written["events"], 1);
+        assert!(!directory.join("ic").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/upload", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_string());
+            }
+            let length: usize = headers
+                .iter()
+                .find_map(|header| header.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            (headers, body)
+        });
+        assert!(matches!(
+            Destination::new(&SnapshotTarget::Webhook(String::from("ftp://monitor"))),
+            Err(SnapshotError::BadUrl(_))
+        ));
+        let webhook = Destination::new(&SnapshotTarget::Webhook(url)).unwrap();
+        let snapshots = take_snapshots(
+            &manager,
+            &SnapshotSpec {
+                pattern: String::from("si/e"),
+                interval: Duration::ZERO,
+                formats: vec![SnapshotFormat::Json],
+                target: SnapshotTarget::Directory(directory),
+            },
+        )
+        .unwrap();
+        webhook.send(&snapshots[0]).unwrap();
+        let (headers, body) = server.join().unwrap();
+        assert_eq!(headers[0], "POST /upload HTTP/1.1");
+        assert!(headers.contains(&String::from("X-Snapshot-Name: si/e.json")));
+        assert_eq!(body, snapshots[0].data);
+    }
+}
@@ -0,0 +1,249 @@
+use super::data_blob::DataBlob;
+use super::deadtime::LossCounts;
+use super::error::SourceError;
+use super::record::EventReader;
+use super::schema::{Schema, VariableKind};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read};
+
+/// A producer of events to be fed through a ResourceManager
+pub trait DataSource {
+    /// Get the next event, returning None once the source is exhausted
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError>;
+
+    /// Stop taking in new data at the end of a run. Events already read or buffered by the
+    /// source are still returned by next_event until it is exhausted.
+    fn stop(&mut self) {}
+
+    /// The variables the source produces, if it knows them before reading any data
+    fn get_schema(&self) -> Option<Schema> {
+        None
+    }
+
+    /// The events the source knows were lost before it could return them
+    fn get_losses(&self) -> LossCounts {
+        LossCounts::default()
+    }
+}
+
+/// Fill buf from a reader, returning false if the reader was already at a clean end of file.
+/// Running out part way through buf is an error, as the data was truncated.
+pub(crate) fn read_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, SourceError> {
+    let mut n_read = 0;
+    while n_read < buf.len() {
+        match reader.read(&mut buf[n_read..]) {
+            Ok(0) if n_read == 0 => return Ok(false),
+            Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(n) => n_read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+impl<R: Read> DataSource for EventReader<R> {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        Ok(self.read_event()?)
+    }
+}
+
+/// Adapts any iterator of DataBlobs into a DataSource
+#[derive(Debug)]
+pub struct IterSource<I: Iterator<Item = DataBlob>> {
+    iter: I,
+}
+
+impl<I: Iterator<Item = DataBlob>> IterSource<I> {
+    pub fn new(iter: I) -> Self {
+        Self { iter }
+    }
+}
+
+impl<I: Iterator<Item = DataBlob>> DataSource for IterSource<I> {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        Ok(self.iter.next())
+    }
+}
+
+/// How a MergedSource interleaves the events of its sources
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeOrder {
+    /// Take one event from each source in turn
+    RoundRobin,
+    /// Take the event with the earliest value of this variable. Each source must already be time
+    /// ordered; events without the variable are taken as soon as they are read.
+    Timestamp(String),
+}
+
+// A source of a MergedSource, with the next event read from it when merging by timestamp
+struct Tagged {
+    source: Box<dyn DataSource>,
+    tag: f32,
+    head: Option<DataBlob>,
+    exhausted: bool,
+}
+
+/// Combines several sources into one, e.g. a DAQ stream with a pulser stream. Each event gets the
+/// tag of its source injected as a variable, so that histograms can be gated on where events came
+/// from.
+pub struct MergedSource {
+    sources: Vec<Tagged>,
+    tag_variable: String,
+    order: MergeOrder,
+    // The source to take from next when merging round-robin
+    next: usize,
+}
+
+impl std::fmt::Debug for MergedSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MergedSource")
+            .field("n_sources", &self.sources.len())
+            .field("tag_variable", &self.tag_variable)
+            .field("order", &self.order)
+            .finish()
+    }
+}
+
+impl MergedSource {
+    pub fn new(tag_variable: &str, order: MergeOrder) -> Self {
+        Self {
+            sources: vec![],
+            tag_variable: tag_variable.to_string(),
+            order,
+            next: 0,
+        }
+    }
+
+    /// Add a source whose events are tagged with tag
+    pub fn add(&mut self, source: Box<dyn DataSource>, tag: f32) {
+        self.sources.push(Tagged {
+            source,
+            tag,
+            head: None,
+            exhausted: false,
+        });
+    }
+
+    fn next_round_robin(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        let n_sources = self.sources.len();
+        for _ in 0..n_sources {
+            let idx = self.next % n_sources;
+            self.next = idx + 1;
+            let tagged = &mut self.sources[idx];
+            if tagged.exhausted {
+                continue;
+            }
+            match tagged.source.next_event()? {
+                Some(mut event) => {
+                    event.insert(&self.tag_variable, tagged.tag);
+                    return Ok(Some(event));
+                }
+                None => tagged.exhausted = true,
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_by_timestamp(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        let MergeOrder::Timestamp(variable) = &self.order else {
+            return Ok(None);
+        };
+        for tagged in self.sources.iter_mut() {
+            if tagged.head.is_none() && !tagged.exhausted {
+                tagged.head = tagged.source.next_event()?;
+                tagged.exhausted = tagged.head.is_none();
+            }
+        }
+        let earliest = self
+            .sources
+            .iter_mut()
+            .filter(|tagged| tagged.head.is_some())
+            .min_by(|a, b| {
+                let time = |tagged: &Tagged| {
+                    tagged
+                        .head
+                        .as_ref()
+                        .and_then(|head| head.find(variable).copied())
+                        .unwrap_or(f32::NEG_INFINITY)
+                };
+                time(a).total_cmp(&time(b))
+            });
+        Ok(earliest.and_then(|tagged| {
+            let mut event = tagged.head.take()?;
+            event.insert(&self.tag_variable, tagged.tag);
+            Some(event)
+        }))
+    }
+}
+
+impl DataSource for MergedSource {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        match self.order {
+            MergeOrder::RoundRobin => self.next_round_robin(),
+            MergeOrder::Timestamp(_) => self.next_by_timestamp(),
+        }
+    }
+
+    fn stop(&mut self) {
+        for tagged in self.sources.iter_mut() {
+            tagged.source.stop();
+        }
+    }
+
+    /// Known only if every source declares its schema
+    fn get_schema(&self) -> Option<Schema> {
+        let mut schema = Schema::new().with(&self.tag_variable, VariableKind::Value);
+        for tagged in self.sources.iter() {
+            schema.merge(&tagged.source.get_schema()?);
+        }
+        Some(schema)
+    }
+
+    fn get_losses(&self) -> LossCounts {
+        let mut losses = LossCounts::default();
+        for tagged in self.sources.iter() {
+            losses.add(&tagged.source.get_losses());
+        }
+        losses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(variable: &str, times: &[f32]) -> Box<dyn DataSource> {
+        let variable = variable.to_string();
+        let events: Vec<DataBlob> = times
+            .iter()
+            .map(|time| {
+                let mut event = DataBlob::new();
+                event.insert(&variable, *time);
+                event
+            })
+            .collect();
+        Box::new(IterSource::new(events.into_iter()))
+    }
+
+    #[test]
+    fn test_merged_source() {
+        let mut merged = MergedSource::new("source", MergeOrder::RoundRobin);
+        merged.add(stream("t", &[1.0, 2.0, 3.0]), 0.0);
+        merged.add(stream("t", &[10.0]), 1.0);
+        let mut tags = vec![];
+        while let Some(event) = merged.next_event().unwrap() {
+            tags.push(*event.find("source").unwrap());
+        }
+        assert_eq!(tags, vec![0.0, 1.0, 0.0, 0.0]);
+
+        let mut merged = MergedSource::new("source", MergeOrder::Timestamp(String::from("t")));
+        merged.add(stream("t", &[1.0, 5.0, 6.0]), 0.0);
+        merged.add(stream("t", &[2.0, 3.0, 7.0]), 1.0);
+        let mut times = vec![];
+        while let Some(event) = merged.next_event().unwrap() {
+            times.push(*event.find("t").unwrap());
+        }
+        assert_eq!(times, vec![1.0, 2.0, 3.0, 5.0, 6.0, 7.0]);
+    }
+}
@@ -0,0 +1,522 @@
+//! A facade assembling an application from its parts: sources merged into one stream, the
+//! transforms and configuration of a manager, and the servers publishing its state, e.g.
+//! Spect::builder().source(source).config(book_histograms).begin_run(1).build()?.run()
+#[cfg(feature = "io")]
+use super::command::CommandServer;
+use super::deadtime::DeadTimeReport;
+use super::error::{ResourceError, SpectError};
+#[cfg(feature = "grpc")]
+use super::grpc::GrpcServer;
+use super::manager::{ResourceManager, ShutdownReport};
+#[cfg(feature = "metrics")]
+use super::metrics::MetricsExporter;
+#[cfg(feature = "io")]
+use super::snapshot::{SnapshotExporter, SnapshotSpec};
+use super::source::{DataSource, MergeOrder, MergedSource};
+use super::transform::EventTransform;
+use super::watchdog::{Watchdog, WatchdogMonitor, WatchdogSpec};
+#[cfg(feature = "xamine")]
+use super::xamine::XamineMemory;
+use rustc_hash::FxHashMap;
+#[cfg(any(feature = "io", feature = "grpc", feature = "metrics"))]
+use std::net::SocketAddr;
+
+/// The variable each event of an application with several sources is tagged with, holding the
+/// index of its source in the order they were given, unless SpectBuilder::merge says otherwise
+pub const SOURCE_VARIABLE: &str = "__source";
+
+type Config = Box<dyn FnOnce(&mut ResourceManager) -> Result<(), ResourceError>>;
+
+/// Collects the parts of a Spect, see Spect::builder
+#[derive(Default)]
+pub struct SpectBuilder {
+    sources: Vec<Box<dyn DataSource>>,
+    merge: Option<(String, MergeOrder)>,
+    manager: Option<ResourceManager>,
+    transforms: Vec<Box<dyn EventTransform>>,
+    configs: Vec<Config>,
+    run: Option<u32>,
+    update_interval: Option<usize>,
+    #[cfg(feature = "io")]
+    commands_address: Option<String>,
+    #[cfg(feature = "io")]
+    snapshots: Vec<SnapshotSpec>,
+    watchdogs: Vec<WatchdogMonitor>,
+    #[cfg(feature = "grpc")]
+    grpc_address: Option<String>,
+    #[cfg(feature = "metrics")]
+    metrics_address: Option<String>,
+    #[cfg(feature = "xamine")]
+    xamine: Option<(String, usize)>,
+}
+
+impl std::fmt::Debug for SpectBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SpectBuilder")
+            .field("n_sources", &self.sources.len())
+            .field("n_transforms", &self.transforms.len())
+            .field("n_configs", &self.configs.len())
+            .field("run", &self.run)
+            .finish()
+    }
+}
+
+impl SpectBuilder {
+    /// Add a source. Several sources are merged round-robin and tagged in SOURCE_VARIABLE.
+    pub fn source(mut self, source: impl DataSource + 'static) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Add a source whose liveness is checked whenever the servers are updated. Alerts are
+    /// raised to the observers of the manager when it stalls or recovers.
+    pub fn watch(self, source: impl DataSource + 'static, spec: WatchdogSpec) -> Self {
+        self.watchdog(Watchdog::new(Box::new(source), spec))
+    }
+
+    /// Add a source already wrapped in a Watchdog, e.g. one which reconnects
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdogs.push(watchdog.get_monitor());
+        self.source(watchdog)
+    }
+
+    /// Merge several sources in this order, tagging events in tag_variable instead
+    pub fn merge(mut self, tag_variable: &str, order: MergeOrder) -> Self {
+        self.merge = Some((tag_variable.to_string(), order));
+        self
+    }
+
+    /// Start from a manager which has already been set up, instead of an empty one
+    pub fn manager(mut self, manager: ResourceManager) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
+    /// Append a stage to the transform pipeline of the manager
+    pub fn transform(mut self, transform: impl EventTransform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    /// Set up the manager, e.g. booking histograms and cuts. Configurations run in the order
+    /// they were given, after the transforms are added.
+    pub fn config(
+        mut self,
+        config: impl FnOnce(&mut ResourceManager) -> Result<(), ResourceError> + 'static,
+    ) -> Self {
+        self.configs.push(Box::new(config));
+        self
+    }
+
+    /// Begin a run with this number once the manager is set up
+    pub fn begin_run(mut self, number: u32) -> Self {
+        self.run = Some(number);
+        self
+    }
+
+    /// Update the servers every n events, 1000 by default
+    pub fn update_interval(mut self, n_events: usize) -> Self {
+        self.update_interval = Some(n_events);
+        self
+    }
+
+    /// Accept SpecTcl-style commands over TCP on an address, e.g. "0.0.0.0:8001". Commands are
+    /// run whenever the servers are updated.
+    #[cfg(feature = "io")]
+    pub fn serve_commands(mut self, address: &str) -> Self {
+        self.commands_address = Some(address.to_string());
+        self
+    }
+
+    /// Export snapshots of histograms every interval of the spec. Snapshots are taken when the
+    /// servers are updated, so an interval shorter than an update only exports once per update.
+    #[cfg(feature = "io")]
+    pub fn export_snapshots(mut self, spec: SnapshotSpec) -> Self {
+        self.snapshots.push(spec);
+        self
+    }
+
+    /// Serve the gRPC service on an address, e.g. "0.0.0.0:50051". Requests are handled, and
+    /// watched histograms streamed, whenever the servers are updated.
+    #[cfg(feature = "grpc")]
+    pub fn serve_grpc(mut self, address: &str) -> Self {
+        self.grpc_address = Some(address.to_string());
+        self
+    }
+
+    /// Serve Prometheus metrics over HTTP on an address, e.g. "0.0.0.0:9184"
+    #[cfg(feature = "metrics")]
+    pub fn serve_metrics(mut self, address: &str) -> Self {
+        self.metrics_address = Some(address.to_string());
+        self
+    }
+
+    /// Publish the spectra bound with the sbind command in an Xamine shared memory region named
+    /// by 4 characters, e.g. "XA00", with room for spectrum_bytes of channels. The spectra are
+    /// copied whenever the servers are updated.
+    #[cfg(feature = "xamine")]
+    pub fn serve_xamine(mut self, name: &str, spectrum_bytes: usize) -> Self {
+        self.xamine = Some((name.to_string(), spectrum_bytes));
+        self
+    }
+
+    /// Assemble the application. The schemas of the sources are registered, and the
+    /// configuration is validated before the run begins and the servers start.
+    pub fn build(self) -> Result<Spect, SpectError> {
+        let mut sources = self.sources;
+        let source = match (sources.len(), self.merge) {
+            (0, _) => return Err(SpectError::NoSource),
+            (1, None) => sources.remove(0),
+            (_, merge) => {
+                let (tag_variable, order) =
+                    merge.unwrap_or((SOURCE_VARIABLE.to_string(), MergeOrder::RoundRobin));
+                let mut merged = MergedSource::new(&tag_variable, order);
+                for (idx, source) in sources.into_iter().enumerate() {
+                    merged.add(source, idx as f32);
+                }
+                Box::new(merged)
+            }
+        };
+        let mut manager = self.manager.unwrap_or_default();
+        manager.register_source_schema(source.as_ref());
+        for transform in self.transforms {
+            manager.add_transform(transform);
+        }
+        for config in self.configs {
+            config(&mut manager)?;
+        }
+        manager.validate()?;
+        if let Some(number) = self.run {
+            manager.begin_run(number, FxHashMap::default())?;
+        }
+        #[cfg(feature = "io")]
+        let commands = match self.commands_address {
+            Some(address) => Some(CommandServer::serve(address).map_err(SpectError::Server)?.0),
+            None => None,
+        };
+        // The threads stop once the exporters are dropped
+        #[cfg(feature = "io")]
+        let snapshots = self
+            .snapshots
+            .into_iter()
+            .map(|spec| Ok(SnapshotExporter::start(spec)?.0))
+            .collect::<Result<Vec<_>, SpectError>>()?;
+        #[cfg(feature = "grpc")]
+        let grpc = match self.grpc_address {
+            Some(address) => Some(GrpcServer::serve(address).map_err(SpectError::Server)?),
+            None => None,
+        };
+        #[cfg(feature = "metrics")]
+        let metrics = match self.metrics_address {
+            // The serving thread lives as long as the process
+            Some(address) => Some(
+                MetricsExporter::serve(address)
+                    .map_err(SpectError::Server)?
+                    .0,
+            ),
+            None => None,
+        };
+        #[cfg(feature = "xamine")]
+        let xamine = match self.xamine {
+            Some((name, spectrum_bytes)) => Some(XamineMemory::create(&name, spectrum_bytes)?),
+            None => None,
+        };
+        Ok(Spect {
+            manager,
+            source,
+            update_interval: self.update_interval.unwrap_or(1000).max(1),
+            #[cfg(feature = "io")]
+            commands,
+            #[cfg(feature = "io")]
+            snapshots,
+            watchdogs: self.watchdogs,
+            #[cfg(feature = "grpc")]
+            grpc,
+            #[cfg(feature = "metrics")]
+            metrics,
+            #[cfg(feature = "xamine")]
+            xamine,
+        })
+    }
+}
+
+/// A running application: a source feeding a manager, whose state is published by servers
+pub struct Spect {
+    manager: ResourceManager,
+    source: Box<dyn DataSource>,
+    update_interval: usize,
+    #[cfg(feature = "io")]
+    commands: Option<CommandServer>,
+    #[cfg(feature = "io")]
+    snapshots: Vec<SnapshotExporter>,
+    watchdogs: Vec<WatchdogMonitor>,
+    #[cfg(feature = "grpc")]
+    grpc: Option<GrpcServer>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<MetricsExporter>,
+    #[cfg(feature = "xamine")]
+    xamine: Option<XamineMemory>,
+}
+
+impl std::fmt::Debug for Spect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Spect")
+            .field("manager", &self.manager)
+            .field("update_interval", &self.update_interval)
+            .finish()
+    }
+}
+
+impl Spect {
+    pub fn builder() -> SpectBuilder {
+        SpectBuilder::default()
+    }
+
+    pub fn get_manager(&self) -> &ResourceManager {
+        &self.manager
+    }
+
+    pub fn get_manager_mut(&mut self) -> &mut ResourceManager {
+        &mut self.manager
+    }
+
+    #[cfg(feature = "io")]
+    pub fn get_commands(&self) -> Option<&CommandServer> {
+        self.commands.as_ref()
+    }
+
+    #[cfg(feature = "io")]
+    pub fn get_commands_address(&self) -> Option<SocketAddr> {
+        self.commands
+            .as_ref()
+            .map(|commands| commands.get_address())
+    }
+
+    /// The monitors of the watched sources, which may be checked from another thread while
+    /// the source blocks
+    pub fn get_watchdogs(&self) -> &[WatchdogMonitor] {
+        &self.watchdogs
+    }
+
+    #[cfg(feature = "grpc")]
+    pub fn get_grpc_address(&self) -> Option<SocketAddr> {
+        self.grpc.as_ref().map(|grpc| grpc.get_address())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn get_metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics.as_ref().map(|metrics| metrics.get_address())
+    }
+
+    /// Process at most n_events from the source and update the servers, returning the number of
+    /// events read. Zero means the source is exhausted.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn step(&mut self, n_events: usize) -> Result<usize, SpectError> {
+        let mut n_read = 0;
+        while n_read < n_events {
+            let event = match self.source.next_event() {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(e) => {
+                    self.manager.count_source_error(&e);
+                    return Err(ResourceError::from(e).into());
+                }
+            };
+            self.manager.update(event)?;
+            n_read += 1;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(n_read, "read events");
+        self.publish()?;
+        Ok(n_read)
+    }
+
+    /// Get the events processed and lost so far, by the source and the manager
+    pub fn get_dead_time(&self) -> DeadTimeReport {
+        self.manager.get_dead_time(Some(self.source.as_ref()))
+    }
+
+    /// Process events until the source is exhausted, returning the number read
+    pub fn run(&mut self) -> Result<usize, SpectError> {
+        let mut n_events = 0;
+        loop {
+            match self.step(self.update_interval)? {
+                0 => return Ok(n_events),
+                n_read => n_events += n_read,
+            }
+        }
+    }
+
+    /// Stop the source and finish taking data as ResourceManager::shutdown does, publishing the
+    /// final state
+    pub fn shutdown(&mut self) -> Result<ShutdownReport, SpectError> {
+        #[cfg(feature = "tracing")]
+        tracing::info!("Shutting down");
+        let report = self.manager.shutdown(self.source.as_mut())?;
+        self.publish()?;
+        Ok(report)
+    }
+
+    // Commands run first so that their effects are published at once
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all))]
+    fn publish(&mut self) -> Result<(), SpectError> {
+        for monitor in self.watchdogs.iter() {
+            if let Some(alert) = monitor.check() {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(source = %alert.source, active = alert.active, "{}", alert.message);
+                self.manager.raise_source_alert(alert);
+            }
+        }
+        #[cfg(feature = "io")]
+        if let Some(commands) = &mut self.commands {
+            commands.process(&mut self.manager);
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(grpc) = &mut self.grpc {
+            grpc.process(&mut self.manager);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &mut self.metrics {
+            if let Some(livetime) = self
+                .manager
+                .get_dead_time(Some(self.source.as_ref()))
+                .get_livetime()
+            {
+                metrics.set_gauge(
+                    "spect_livetime",
+                    "Fraction of the events offered by the DAQ which were processed",
+                    livetime,
+                );
+            }
+            metrics.update(&self.manager);
+        }
+        #[cfg(feature = "xamine")]
+        if let Some(xamine) = &mut self.xamine {
+            #[cfg(feature = "io")]
+            let bindings = match &self.commands {
+                Some(commands) => commands.get_interpreter().get_bindings(&self.manager),
+                None => vec![],
+            };
+            // Spectra are only bound by commands
+            #[cfg(not(feature = "io"))]
+            let bindings = vec![];
+            xamine.update(&self.manager, &bindings)?;
+        }
+        #[cfg(feature = "io")]
+        for exporter in self.snapshots.iter_mut() {
+            exporter.process(&self.manager)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::*;
+    use crate::sim::{Generator, SimSource, SimSpec};
+
+    fn sim(variable: &str, n_events: u64) -> SimSource {
+        SimSource::new(SimSpec {
+            generators: vec![Generator::Uniform {
+                variable: variable.to_string(),
+                low: 0.0,
+                high: 10.0,
+            }],
+            rate: None,
+            n_events: Some(n_events),
+            seed: 3,
+        })
+    }
+
+    #[test]
+    fn test_watch() {
+        use crate::observer::{EventKind, ManagerEvent};
+        use crate::time::Duration;
+        let spec = WatchdogSpec::new("sim", Duration::ZERO);
+        let mut spect = Spect::builder().watch(sim("x", 10), spec).build().unwrap();
+        let (_, alerts) = spect.get_manager_mut().subscribe(&[EventKind::Alert]);
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(spect.step(5).unwrap(), 5);
+        let Ok(ManagerEvent::SourceAlert(alert)) = alerts.try_recv() else {
+            panic!("No source alert");
+        };
+        assert_eq!(alert.source, "sim");
+        assert!(alert.active);
+        assert!(spect.get_watchdogs()[0].get_liveness().stalled);
+    }
+
+    #[test]
+    fn test_dead_time() {
+        use crate::queue::{EventQueue, OverflowPolicy};
+        let queue = EventQueue::new(2, OverflowPolicy::DropOldest);
+        for value in 0..5 {
+            let mut event = DataBlob::new();
+            event.insert("e", value as f32);
+            queue.push(event);
+        }
+        queue.close();
+        let mut spect = Spect::builder().source(queue).build().unwrap();
+        assert_eq!(spect.run().unwrap(), 2);
+        let report = spect.get_dead_time();
+        assert_eq!(report.processed, 2);
+        assert_eq!(report.losses.buffer_overflow, 3);
+        assert_eq!(report.get_livetime(), Some(0.4));
+    }
+
+    #[test]
+    fn test_spect() {
+        assert!(matches!(
+            Spect::builder().build(),
+            Err(SpectError::NoSource)
+        ));
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "source",
+                AxisSpec::new(SOURCE_VARIABLE, "source", 2, 0.0, 2.0).unwrap(),
+                None,
+            )
+        };
+        let id = spec.id;
+        let typo = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("typo"),
+            x_axis: AxisSpec::new("ee", "e", 2, 0.0, 2.0).unwrap(),
+            ..spec.clone()
+        };
+        let rejected = Spect::builder()
+            .source(sim("e", 10))
+            .config(move |manager| manager.add_histogram(typo).map(|_| ()))
+            .build();
+        assert!(matches!(
+            rejected,
+            Err(SpectError::Resource(ResourceError::UnknownVariable(..)))
+        ));
+
+        let builder = Spect::builder()
+            .source(sim("e", 30))
+            .source(sim("t", 20))
+            .config(move |manager| manager.add_histogram(spec).map(|_| ()))
+            .begin_run(1)
+            .update_interval(7);
+        #[cfg(feature = "io")]
+        let builder = builder.serve_commands("127.0.0.1:0");
+        #[cfg(feature = "metrics")]
+        let builder = builder.serve_metrics("127.0.0.1:0");
+        #[cfg(feature = "grpc")]
+        let builder = builder.serve_grpc("127.0.0.1:0");
+        let mut spect = builder.build().unwrap();
+        #[cfg(feature = "metrics")]
+        assert!(spect.get_metrics_address().is_some());
+        #[cfg(feature = "io")]
+        assert!(spect.get_commands_address().is_some());
+        #[cfg(feature = "grpc")]
+        assert!(spect.get_grpc_address().is_some());
+        assert_eq!(spect.step(4).unwrap(), 4);
+        assert_eq!(spect.run().unwrap(), 46);
+        let data = spect.get_manager().get_histogram_data(&id).unwrap();
+        assert_eq!(data, [30.0, 20.0]);
+        assert!(spect.shutdown().unwrap().run.is_some());
+    }
+}
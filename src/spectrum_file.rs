@@ -0,0 +1,438 @@
+//! Reading 1D spectra in the formats of other tools, since reference and calibration spectra
+//! usually live in them: RadWare .spe files, ORTEC .Chn and .Spc files, and IAEA SPE text files.
+//!
+//! RadWare .spe files are two Fortran unformatted records, an 8 character name with the number of
+//! channels and then the counts as 32 bit floats, in either byte order. ORTEC .Chn files are a 32
+//! byte header, the counts as 32 bit integers, and a trailer holding the energy calibration.
+//! ORTEC .Spc files are 128 byte records, the first of which points to the records holding the
+//! sample description, the calibration, and the counts. IAEA SPE files are text, in sections
+//! headed by `$KEY:` lines.
+
+use super::error::{HistogramError, SpectrumFileError};
+use super::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+use super::run::ClearPolicy;
+use rustc_hash::FxHashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// The counts of a 1D spectrum read from a file, where counts[i] is channel i. Counts are kept
+/// as they are in the file, which may be fractional or negative, e.g. in a RadWare spectrum after
+/// background subtraction.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Spectrum {
+    pub name: String,
+    pub counts: Vec<f64>,
+    /// The energy of channel c is the sum of calibration[i] * c^i, if the file has a calibration
+    pub calibration: Vec<f64>,
+    /// In seconds
+    pub live_time: Option<f64>,
+    pub real_time: Option<f64>,
+}
+
+impl Spectrum {
+    /// Get the spec of a histogram holding the spectrum, with one bin per channel. The axis is in
+    /// energy if the calibration is linear, and in channels otherwise. The times are kept in the
+    /// metadata, and the histogram accumulates across runs so that it is not cleared.
+    pub fn to_spec(&self, variable: &str) -> Result<HistSpec, HistogramError> {
+        let n_channels = self.counts.len();
+        let is_linear = self.calibration.len() >= 2
+            && self.calibration[1] > 0.0
+            && self.calibration[2..].iter().all(|c| *c == 0.0);
+        let (min, max) = if is_linear {
+            let energy =
+                |channel: usize| self.calibration[0] + self.calibration[1] * channel as f64;
+            (energy(0) as f32, energy(n_channels) as f32)
+        } else {
+            (0.0, n_channels as f32)
+        };
+        let mut metadata = FxHashMap::default();
+        if let Some(live_time) = self.live_time {
+            metadata.insert(String::from("live_time"), live_time.to_string());
+        }
+        if let Some(real_time) = self.real_time {
+            metadata.insert(String::from("real_time"), real_time.to_string());
+        }
+        Ok(HistSpec {
+            title: self.name.clone(),
+            out_of_range: OutOfRangePolicy::Ignore,
+            clear_policy: ClearPolicy::Accumulate,
+            metadata,
+            ..HistSpec::new(
+                &self.name,
+                AxisSpec::new(variable, variable, n_channels, min, max)?,
+                None,
+            )
+        })
+    }
+}
+
+// Read n bytes at offset, or fail with a message naming what was being read
+fn bytes_at<'a>(
+    data: &'a [u8],
+    offset: usize,
+    n: usize,
+    what: &str,
+) -> Result<&'a [u8], SpectrumFileError> {
+    data.get(offset..offset + n)
+        .ok_or_else(|| SpectrumFileError::Format(format!("file ends before the {what}")))
+}
+
+/// Read a RadWare .spe file
+pub fn read_radware_spe<R: Read>(mut reader: R) -> Result<Spectrum, SpectrumFileError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let header = bytes_at(&data, 0, 4, "header")?;
+    let header: [u8; 4] = header.try_into().expect("Slice has four bytes");
+    let is_little = u32::from_le_bytes(header) == 24;
+    if !is_little && u32::from_be_bytes(header) != 24 {
+        return Err(SpectrumFileError::Format(String::from(
+            "not a RadWare spectrum header",
+        )));
+    }
+    let word = |offset: usize, what: &str| -> Result<[u8; 4], SpectrumFileError> {
+        Ok(bytes_at(&data, offset, 4, what)?
+            .try_into()
+            .expect("Slice has four bytes"))
+    };
+    let to_u32 = |bytes: [u8; 4]| match is_little {
+        true => u32::from_le_bytes(bytes),
+        false => u32::from_be_bytes(bytes),
+    };
+    let name = String::from_utf8_lossy(bytes_at(&data, 4, 8, "name")?)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string();
+    let n_channels = to_u32(word(12, "number of channels")?) as usize;
+    if to_u32(word(32, "counts record")?) as usize != 4 * n_channels {
+        return Err(SpectrumFileError::Format(String::from(
+            "counts record does not match the number of channels",
+        )));
+    }
+    let mut counts = Vec::with_capacity(n_channels);
+    for channel in 0..n_channels {
+        let count = f32::from_bits(to_u32(word(36 + 4 * channel, "end of the counts")?));
+        counts.push(f64::from(count));
+    }
+    Ok(Spectrum {
+        name,
+        counts,
+        ..Default::default()
+    })
+}
+
+/// Read an ORTEC .Chn file. The file holds no name, so the spectrum has none.
+pub fn read_ortec_chn<R: Read>(mut reader: R) -> Result<Spectrum, SpectrumFileError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let i16_at = |offset: usize, what: &str| -> Result<i16, SpectrumFileError> {
+        let bytes = bytes_at(&data, offset, 2, what)?;
+        Ok(i16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let u32_at = |offset: usize, what: &str| -> Result<u32, SpectrumFileError> {
+        let bytes = bytes_at(&data, offset, 4, what)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("Slice has four bytes"),
+        ))
+    };
+    if i16_at(0, "header")? != -1 {
+        return Err(SpectrumFileError::Format(String::from(
+            "not an ORTEC Chn header",
+        )));
+    }
+    // Times are in ticks of 20 ms
+    let real_time = u32_at(8, "real time")? as f64 * 0.02;
+    let live_time = u32_at(12, "live time")? as f64 * 0.02;
+    let offset = i16_at(28, "channel offset")? as u16 as usize;
+    let n_channels = i16_at(30, "number of channels")? as u16 as usize;
+    let mut counts = vec![0.0; offset];
+    for channel in 0..n_channels {
+        counts.push(f64::from(u32_at(32 + 4 * channel, "end of the counts")?));
+    }
+    let trailer = 32 + 4 * n_channels;
+    let mut calibration = vec![];
+    if let Ok(kind @ (-101 | -102)) = i16_at(trailer, "trailer") {
+        let f32_at =
+            |at: usize| u32_at(trailer + at, "calibration").map(|v| f32::from_bits(v) as f64);
+        calibration = vec![f32_at(4)?, f32_at(8)?];
+        if kind == -102 {
+            calibration.push(f32_at(12)?);
+        }
+    }
+    Ok(Spectrum {
+        name: String::new(),
+        counts,
+        calibration,
+        live_time: Some(live_time),
+        real_time: Some(real_time),
+    })
+}
+
+// The bytes in a record of an ORTEC .Spc file, which points to records by 1-based numbers
+const SPC_RECORD: usize = 128;
+
+/// Read an ORTEC integer .Spc file. The sample description, if any, names the spectrum.
+pub fn read_ortec_spc<R: Read>(mut reader: R) -> Result<Spectrum, SpectrumFileError> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let u16_at = |offset: usize, what: &str| -> Result<usize, SpectrumFileError> {
+        let bytes = bytes_at(&data, offset, 2, what)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let f32_at = |offset: usize, what: &str| -> Result<f64, SpectrumFileError> {
+        let bytes = bytes_at(&data, offset, 4, what)?;
+        Ok(f32::from_le_bytes(bytes.try_into().expect("Slice has four bytes")) as f64)
+    };
+    let record = |pointer: usize| pointer.saturating_sub(1) * SPC_RECORD;
+    // Integer spectra have both the information and file types 1
+    if u16_at(0, "header")? != 1 || u16_at(2, "file type")? != 1 {
+        return Err(SpectrumFileError::Format(String::from(
+            "not an ORTEC integer Spc header",
+        )));
+    }
+    let sample = u16_at(10, "sample description pointer")?;
+    let calibration_record = u16_at(34, "calibration pointer")?;
+    let counts_record = u16_at(58, "spectrum pointer")?;
+    let n_channels = u16_at(62, "number of channels")?;
+    let offset = u16_at(64, "start channel")?;
+    let real_time = f32_at(88, "real time")?;
+    let live_time = f32_at(92, "live time")?;
+
+    let name = match sample {
+        0 => String::new(),
+        pointer => {
+            let text = bytes_at(&data, record(pointer), SPC_RECORD, "sample description")?;
+            let text = text.split(|byte| *byte == 0).next().unwrap_or_default();
+            String::from_utf8_lossy(text).trim().to_string()
+        }
+    };
+    let mut counts = vec![0.0; offset];
+    let start = record(counts_record);
+    for channel in 0..n_channels {
+        let bytes = bytes_at(&data, start + 4 * channel, 4, "end of the counts")?;
+        let count = i32::from_le_bytes(bytes.try_into().expect("Slice has four bytes"));
+        counts.push(f64::from(count));
+    }
+    // The energy calibration is the polynomial in words 3 to 8 of the calibration record
+    let calibration = match calibration_record {
+        0 => vec![],
+        pointer => (0..3)
+            .map(|term| f32_at(record(pointer) + 4 + 4 * term, "calibration"))
+            .collect::<Result<_, _>>()?,
+    };
+    Ok(Spectrum {
+        name,
+        counts,
+        calibration,
+        live_time: Some(live_time),
+        real_time: Some(real_time),
+    })
+}
+
+/// Read an IAEA SPE text file, using its $SPEC_ID, $MEAS_TIM, $DATA, and $ENER_FIT or $MCA_CAL
+/// sections. Other sections are skipped.
+pub fn read_iaea_spe<R: Read>(reader: R) -> Result<Spectrum, SpectrumFileError> {
+    let mut sections: Vec<(String, Vec<String>)> = vec![];
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(key) = line.strip_prefix('$') {
+            sections.push((key.trim_end_matches(':').to_string(), vec![]));
+        } else if let Some((_, lines)) = sections.last_mut()
+            && !line.is_empty()
+        {
+            lines.push(line.to_string());
+        }
+    }
+    if sections.is_empty() {
+        return Err(SpectrumFileError::Format(String::from(
+            "not an IAEA SPE file",
+        )));
+    }
+    let numbers = |line: &str| -> Result<Vec<f64>, SpectrumFileError> {
+        line.split_whitespace()
+            // Calibrations may end with their unit
+            .filter(|token| token.chars().any(|c| c.is_ascii_digit()))
+            .map(|token| {
+                token
+                    .parse::<f64>()
+                    .map_err(|_| SpectrumFileError::Format(format!("bad number '{token}'")))
+            })
+            .collect()
+    };
+    let mut spectrum = Spectrum::default();
+    let mut has_data = false;
+    for (key, lines) in sections.iter() {
+        match key.as_str() {
+            "SPEC_ID" => spectrum.name = lines.first().cloned().unwrap_or_default(),
+            "MEAS_TIM" => {
+                if let Some(line) = lines.first() {
+                    let times = numbers(line)?;
+                    spectrum.live_time = times.first().copied();
+                    spectrum.real_time = times.get(1).copied();
+                }
+            }
+            "DATA" => {
+                let range = numbers(lines.first().map(String::as_str).unwrap_or_default())?;
+                let [first, last] = range[..] else {
+                    return Err(SpectrumFileError::Format(String::from(
+                        "$DATA needs a first and last channel",
+                    )));
+                };
+                spectrum.counts = vec![0.0; first as usize];
+                for line in lines[1..].iter() {
+                    spectrum.counts.extend(numbers(line)?);
+                }
+                if spectrum.counts.len() != last as usize + 1 {
+                    return Err(SpectrumFileError::Format(format!(
+                        "$DATA has {} channels instead of {}",
+                        spectrum.counts.len() - first as usize,
+                        last - first + 1.0
+                    )));
+                }
+                has_data = true;
+            }
+            "ENER_FIT" if spectrum.calibration.is_empty() => {
+                if let Some(line) = lines.first() {
+                    spectrum.calibration = numbers(line)?;
+                }
+            }
+            // The number of coefficients, then the coefficients
+            "MCA_CAL" => {
+                if let Some(line) = lines.get(1) {
+                    spectrum.calibration = numbers(line)?;
+                }
+            }
+            _ => (),
+        }
+    }
+    if !has_data {
+        return Err(SpectrumFileError::Format(String::from("no $DATA section")));
+    }
+    Ok(spectrum)
+}
+
+/// Read a spectrum file by its extension: .spe files are IAEA SPE if they start with a $ and
+/// RadWare otherwise, and .chn and .spc files are ORTEC. A spectrum without a name is named for
+/// the file.
+pub fn read_spectrum_file(path: &Path) -> Result<Spectrum, SpectrumFileError> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let data = std::fs::read(path)?;
+    let mut spectrum = match extension.as_str() {
+        "spe" if data.trim_ascii_start().starts_with(b"$") => read_iaea_spe(data.as_slice())?,
+        "spe" => read_radware_spe(data.as_slice())?,
+        "chn" => read_ortec_chn(data.as_slice())?,
+        "spc" => read_ortec_spc(data.as_slice())?,
+        _ => return Err(SpectrumFileError::UnknownFormat(path.display().to_string())),
+    };
+    if spectrum.name.is_empty() {
+        spectrum.name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+    }
+    Ok(spectrum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radware_and_ortec() {
+        let mut spe = vec![];
+        spe.extend(24u32.to_be_bytes());
+        spe.extend(b"ge1     ");
+        for value in [3i32, 1, 1, 1] {
+            spe.extend(value.to_be_bytes());
+        }
+        spe.extend(24u32.to_be_bytes());
+        spe.extend(12u32.to_be_bytes());
+        for count in [1.0f32, 20.0, 3.4] {
+            spe.extend(count.to_be_bytes());
+        }
+        spe.extend(12u32.to_be_bytes());
+        let spectrum = read_radware_spe(spe.as_slice()).unwrap();
+        assert_eq!(spectrum.name, "ge1");
+        assert_eq!(spectrum.counts, vec![1.0, 20.0, f64::from(3.4f32)]);
+        assert!(read_radware_spe(&spe[..40]).is_err());
+
+        let mut chn = vec![];
+        chn.extend((-1i16).to_le_bytes());
+        chn.extend([0; 6]);
+        chn.extend(500u32.to_le_bytes());
+        chn.extend(450u32.to_le_bytes());
+        chn.extend([0; 12]);
+        chn.extend(0u16.to_le_bytes());
+        chn.extend(2u16.to_le_bytes());
+        chn.extend(7u32.to_le_bytes());
+        chn.extend(9u32.to_le_bytes());
+        chn.extend((-101i16).to_le_bytes());
+        chn.extend([0; 2]);
+        chn.extend(1.0f32.to_le_bytes());
+        chn.extend(0.5f32.to_le_bytes());
+        let spectrum = read_ortec_chn(chn.as_slice()).unwrap();
+        assert_eq!(spectrum.counts, vec![7.0, 9.0]);
+        assert_eq!(spectrum.calibration, vec![1.0, 0.5]);
+        assert_eq!(spectrum.live_time, Some(9.0));
+        let spec = spectrum.to_spec("energy").unwrap();
+        assert_eq!((spec.x_axis.minimum, spec.x_axis.maximum), (1.0, 2.0));
+        assert_eq!(spec.metadata["real_time"], "10");
+    }
+
+    #[test]
+    fn test_ortec_spc() {
+        // A header, a sample description, a calibration, and one record of counts
+        let mut spc = vec![0; 4 * SPC_RECORD];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            spc[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, &1u16.to_le_bytes());
+        put(2, &1u16.to_le_bytes());
+        put(10, &2u16.to_le_bytes());
+        put(34, &3u16.to_le_bytes());
+        put(58, &4u16.to_le_bytes());
+        put(62, &3u16.to_le_bytes());
+        put(64, &1u16.to_le_bytes());
+        put(88, &12.5f32.to_le_bytes());
+        put(92, &10.0f32.to_le_bytes());
+        put(SPC_RECORD, b"Co-60 reference\0");
+        put(2 * SPC_RECORD + 4, &0.5f32.to_le_bytes());
+        put(2 * SPC_RECORD + 8, &2.0f32.to_le_bytes());
+        for (channel, count) in [4i32, 16, 9].iter().enumerate() {
+            put(3 * SPC_RECORD + 4 * channel, &count.to_le_bytes());
+        }
+        let spectrum = read_ortec_spc(spc.as_slice()).unwrap();
+        assert_eq!(spectrum.name, "Co-60 reference");
+        assert_eq!(spectrum.counts, vec![0.0, 4.0, 16.0, 9.0]);
+        assert_eq!(spectrum.calibration, vec![0.5, 2.0, 0.0]);
+        assert_eq!(spectrum.live_time, Some(10.0));
+        assert_eq!(spectrum.real_time, Some(12.5));
+        let spec = spectrum.to_spec("energy").unwrap();
+        assert_eq!((spec.x_axis.minimum, spec.x_axis.maximum), (0.5, 8.5));
+
+        assert!(read_ortec_spc(&spc[..3 * SPC_RECORD + 8]).is_err());
+        spc[2] = 5;
+        assert!(read_ortec_spc(spc.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_iaea_spe() {
+        let text = "$SPEC_ID:\nCs-137 reference\n$MEAS_TIM:\n100 120\n$DATA:\n1 4\n5 6\n7\n8\n\
+            $MCA_CAL:\n3\n0.5 2.0 0.0 keV\n$ENDRECORD:\n";
+        let spectrum = read_iaea_spe(text.as_bytes()).unwrap();
+        assert_eq!(spectrum.name, "Cs-137 reference");
+        assert_eq!(spectrum.counts, vec![0.0, 5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(spectrum.calibration, vec![0.5, 2.0, 0.0]);
+        assert_eq!(spectrum.real_time, Some(120.0));
+        let spec = spectrum.to_spec("energy").unwrap();
+        assert_eq!(spec.x_axis.bins, 5);
+        assert_eq!(spec.x_axis.maximum, 10.5);
+
+        let short = "$DATA:\n0 3\n1 2\n";
+        assert!(read_iaea_spe(short.as_bytes()).is_err());
+        assert!(read_iaea_spe("counts".as_bytes()).is_err());
+    }
+}
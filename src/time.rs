@@ -0,0 +1,6 @@
+//! The clocks used throughout the crate. They are those of std::time, except in browsers, where
+//! std::time panics and the clocks come from JavaScript instead.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use web_time::{Duration, Instant, SystemTime, UNIX_EPOCH};
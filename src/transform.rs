@@ -0,0 +1,57 @@
+use super::data_blob::DataBlob;
+use uuid::Uuid;
+
+/// A user stage run on every event before cuts and fills, e.g. for calibration, channel mapping,
+/// or computing physics variables. Returning None drops the event.
+pub trait EventTransform {
+    fn transform(&mut self, data: DataBlob) -> Option<DataBlob>;
+}
+
+impl<F: FnMut(DataBlob) -> Option<DataBlob>> EventTransform for F {
+    fn transform(&mut self, data: DataBlob) -> Option<DataBlob> {
+        self(data)
+    }
+}
+
+/// An ordered list of transforms applied to each event
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<(Uuid, Box<dyn EventTransform>)>,
+}
+
+impl std::fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("n_stages", &self.stages.len())
+            .finish()
+    }
+}
+
+impl Pipeline {
+    /// Append a stage, returning its ID
+    pub fn add(&mut self, transform: Box<dyn EventTransform>) -> Uuid {
+        let id = Uuid::new_v4();
+        self.stages.push((id, transform));
+        id
+    }
+
+    /// Remove a stage, returning false if it did not exist
+    pub fn remove(&mut self, id: &Uuid) -> bool {
+        let n_stages = self.stages.len();
+        self.stages.retain(|(stage_id, _)| stage_id != id);
+        n_stages != self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Run an event through every stage in order, stopping if any stage drops it
+    pub fn run(&mut self, data: DataBlob) -> Option<DataBlob> {
+        let mut data = data;
+        for (_, transform) in self.stages.iter_mut() {
+            data = transform.transform(data)?;
+        }
+        Some(data)
+    }
+}
@@ -0,0 +1,416 @@
+//! A terminal browser for the histograms of a manager, for monitoring over SSH where no window
+//! can be opened: 1D spectra are plotted in braille, 2D spectra as heatmaps of half blocks, and
+//! the event rate and rate meters are shown below. Keys: up and down (or k and j) select a
+//! histogram, l toggles the log scale, c clears the selected histogram, and q quits.
+use super::histogram::{BinLayout, Histogram};
+use super::manager::ResourceManager;
+use super::spect::Spect;
+use super::time::{Duration, Instant};
+use ratatui::Frame;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{
+    Axis, Block, Chart, Dataset, GraphType, List, ListItem, ListState, Paragraph,
+};
+
+// How long to wait for a key before stepping the source again
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// A content shown on a log scale, where empty bins sit at zero
+fn scale(content: f64, log_scale: bool) -> f64 {
+    if log_scale {
+        content.max(0.0).ln_1p() / std::f64::consts::LN_10
+    } else {
+        content
+    }
+}
+
+// The color of a bin, from dark blue when empty to red when full
+fn get_color(fraction: f64) -> Color {
+    if fraction.is_nan() || fraction <= 0.0 {
+        return Color::Rgb(10, 10, 30);
+    }
+    let fraction = fraction.min(1.0);
+    let red = (255.0 * fraction) as u8;
+    let green = (255.0 * (1.0 - (2.0 * fraction - 1.0).abs())) as u8;
+    let blue = (255.0 * (1.0 - fraction)) as u8;
+    Color::Rgb(red, green, blue)
+}
+
+// The outline of a 1D spectrum, stepping across each bin
+fn get_steps(gram: &Histogram, log_scale: bool) -> Vec<(f64, f64)> {
+    let axis = &gram.spec.x_axis;
+    let width = axis.get_bin_width() as f64;
+    let mut points = Vec::with_capacity(2 * axis.bins);
+    for bin in 0..axis.bins {
+        let low = axis.get_bin_low_edge(bin) as f64;
+        let content = scale(gram.get_bin_content(bin, 0).unwrap_or(0.0), log_scale);
+        points.push((low, content));
+        points.push((low + width, content));
+    }
+    points
+}
+
+// The rows of a heatmap filling width by height cells. Each cell shows two bins, the upper one in
+// the foreground of a half block and the lower one in its background.
+fn get_heatmap(gram: &Histogram, width: u16, height: u16, log_scale: bool) -> Vec<Line<'static>> {
+    let downsampled = gram.downsample(width as usize, 2 * height as usize);
+    let layout: BinLayout = gram.spec.layout.get_dense();
+    let (x_bins, y_bins) = (downsampled.x_bins, downsampled.y_bins);
+    let content = |x_bin: usize, y_bin: usize| {
+        scale(
+            downsampled.data[layout.index(x_bin, y_bin, x_bins, y_bins)],
+            log_scale,
+        )
+    };
+    let maximum = downsampled
+        .data
+        .iter()
+        .map(|value| scale(*value, log_scale))
+        .fold(0.0, f64::max);
+    let color = |x_bin: usize, y_bin: Option<usize>| match y_bin {
+        Some(y_bin) if maximum > 0.0 => get_color(content(x_bin, y_bin) / maximum),
+        _ => get_color(0.0),
+    };
+    // The top row holds the highest y bins
+    (0..y_bins.div_ceil(2))
+        .rev()
+        .map(|row| {
+            let upper = (2 * row + 1 < y_bins).then_some(2 * row + 1);
+            let spans: Vec<Span> = (0..x_bins)
+                .map(|x_bin| {
+                    let style = Style::default()
+                        .fg(color(x_bin, upper))
+                        .bg(color(x_bin, Some(2 * row)));
+                    Span::styled("▀", style)
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}
+
+// Where the manager driven by the browser comes from
+enum Model {
+    Live(Box<Spect>),
+    Static(Box<ResourceManager>),
+}
+
+/// The terminal browser. A browser of a Spect steps it while waiting for keys, so the spectra
+/// fill live; a browser of a manager only browses it.
+pub struct Browser {
+    model: Model,
+    events_per_step: usize,
+    selected: ListState,
+    log_scale: bool,
+    // The event count and time of the last rate measurement, and the rate measured
+    last_count: (u64, Instant),
+    event_rate: f64,
+    message: String,
+}
+
+impl std::fmt::Debug for Browser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Browser")
+            .field("selected", &self.selected.selected())
+            .field("log_scale", &self.log_scale)
+            .finish()
+    }
+}
+
+impl Browser {
+    /// Browse a running application, processing at most events_per_step events between
+    /// checks for keys
+    pub fn new(spect: Spect, events_per_step: usize) -> Self {
+        Self::with_model(Model::Live(Box::new(spect)), events_per_step)
+    }
+
+    /// Browse a manager which is not taking data, e.g. one loaded from files
+    pub fn from_manager(manager: ResourceManager) -> Self {
+        Self::with_model(Model::Static(Box::new(manager)), 0)
+    }
+
+    fn with_model(model: Model, events_per_step: usize) -> Self {
+        let mut browser = Self {
+            model,
+            events_per_step,
+            selected: ListState::default(),
+            log_scale: false,
+            last_count: (0, Instant::now()),
+            event_rate: 0.0,
+            message: String::new(),
+        };
+        browser.last_count.0 = browser.get_manager().get_event_count();
+        browser.selected.select_first();
+        browser
+    }
+
+    pub fn get_manager(&self) -> &ResourceManager {
+        match &self.model {
+            Model::Live(spect) => spect.get_manager(),
+            Model::Static(manager) => manager,
+        }
+    }
+
+    pub fn get_manager_mut(&mut self) -> &mut ResourceManager {
+        match &mut self.model {
+            Model::Live(spect) => spect.get_manager_mut(),
+            Model::Static(manager) => manager,
+        }
+    }
+
+    /// Take over the terminal until q is pressed
+    pub fn run(mut self) -> std::io::Result<()> {
+        let mut terminal = ratatui::init();
+        let result = loop {
+            self.step();
+            if let Err(e) = terminal.draw(|frame| self.draw(frame)) {
+                break Err(e);
+            }
+            match self.poll_key() {
+                Ok(true) => break Ok(()),
+                Ok(false) => (),
+                Err(e) => break Err(e),
+            }
+        };
+        ratatui::restore();
+        result
+    }
+
+    // Process events from the source and measure the event rate about once per second
+    fn step(&mut self) {
+        if let Model::Live(spect) = &mut self.model
+            && let Err(e) = spect.step(self.events_per_step)
+        {
+            self.message = e.to_string();
+        }
+        let (count, time) = self.last_count;
+        let elapsed = time.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            let n_events = self.get_manager().get_event_count();
+            self.event_rate = n_events.saturating_sub(count) as f64 / elapsed;
+            self.last_count = (n_events, Instant::now());
+        }
+    }
+
+    // Wait for a key and handle it, returning true if the browser should quit
+    fn poll_key(&mut self) -> std::io::Result<bool> {
+        if !event::poll(POLL_INTERVAL)? {
+            return Ok(false);
+        }
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => Ok(self.handle_key(key.code)),
+            _ => Ok(false),
+        }
+    }
+
+    /// Handle a key press, returning true if the browser should quit
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Down | KeyCode::Char('j') => self.selected.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.selected.select_previous(),
+            KeyCode::Char('l') => self.log_scale = !self.log_scale,
+            KeyCode::Char('c') => {
+                if let Some(id) = self.get_selected() {
+                    let _ = self.get_manager_mut().clear_histogram(&id);
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+
+    fn get_selected(&self) -> Option<uuid::Uuid> {
+        let specs = self.get_manager().list_histograms("");
+        let idx = self.selected.selected()?.min(specs.len().checked_sub(1)?);
+        Some(specs[idx].id)
+    }
+
+    /// Draw the browser into a frame of the terminal
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [body, rates] =
+            Layout::vertical([Constraint::Min(8), Constraint::Length(6)]).areas(frame.area());
+        let [list, plot] =
+            Layout::horizontal([Constraint::Length(28), Constraint::Min(20)]).areas(body);
+
+        let names: Vec<ListItem> = self
+            .get_manager()
+            .list_histograms("")
+            .iter()
+            .map(|spec| ListItem::new(spec.name.clone()))
+            .collect();
+        let list_widget = List::new(names)
+            .block(Block::bordered().title("Histograms"))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list_widget, list, &mut self.selected);
+
+        self.draw_plot(frame, plot);
+        self.draw_rates(frame, rates);
+    }
+
+    fn draw_plot(&self, frame: &mut Frame, area: Rect) {
+        let manager = self.get_manager();
+        let Some(gram) = self
+            .get_selected()
+            .and_then(|id| manager.get_histogram(&id).ok())
+        else {
+            frame.render_widget(
+                Paragraph::new("No histograms").block(Block::bordered()),
+                area,
+            );
+            return;
+        };
+        let scale_name = if self.log_scale { " (log)" } else { "" };
+        let block = Block::bordered().title(format!("{}{scale_name}", gram.spec.title));
+        let x_axis = &gram.spec.x_axis;
+        match &gram.spec.y_axis {
+            None => {
+                let steps = get_steps(gram, self.log_scale);
+                let maximum = steps.iter().map(|(_, y)| *y).fold(1.0, f64::max);
+                let dataset = Dataset::default()
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::Cyan))
+                    .data(&steps);
+                let chart = Chart::new(vec![dataset])
+                    .block(block)
+                    .x_axis(
+                        Axis::default()
+                            .title(x_axis.get_label())
+                            .bounds([x_axis.minimum as f64, x_axis.maximum as f64])
+                            .labels([format!("{}", x_axis.minimum), format!("{}", x_axis.maximum)]),
+                    )
+                    .y_axis(
+                        Axis::default()
+                            .bounds([0.0, maximum])
+                            .labels([String::from("0"), format!("{maximum:.3}")]),
+                    );
+                frame.render_widget(chart, area);
+            }
+            Some(y_axis) => {
+                let inner = block.inner(area);
+                let mut lines = get_heatmap(
+                    gram,
+                    inner.width,
+                    inner.height.saturating_sub(1),
+                    self.log_scale,
+                );
+                lines.push(Line::from(format!(
+                    "x: {} [{}, {}]  y: {} [{}, {}]",
+                    x_axis.get_label(),
+                    x_axis.minimum,
+                    x_axis.maximum,
+                    y_axis.get_label(),
+                    y_axis.minimum,
+                    y_axis.maximum
+                )));
+                frame.render_widget(Paragraph::new(lines).block(block), area);
+            }
+        }
+    }
+
+    fn draw_rates(&self, frame: &mut Frame, area: Rect) {
+        let manager = self.get_manager();
+        let mut lines = vec![Line::from(format!(
+            "Events: {}  Rate: {:.1}/s{}",
+            manager.get_event_count(),
+            self.event_rate,
+            manager
+                .get_run_info()
+                .map_or(String::new(), |run| format!("  Run: {}", run.number)),
+        ))];
+        let mut rates = manager.list_rates();
+        rates.sort_by(|a, b| a.name.cmp(&b.name));
+        for spec in rates {
+            let Ok(meter) = manager.get_rate(&spec.id) else {
+                continue;
+            };
+            // The newest bucket is still filling, so the one before it is shown
+            let buckets = meter.get_rates();
+            let latest = buckets.iter().rev().nth(1).or(buckets.last());
+            lines.push(Line::from(format!(
+                "{}: {:.1}/s (total {})",
+                spec.name,
+                latest.map_or(0.0, |(_, rate)| *rate),
+                meter.get_total()
+            )));
+        }
+        if !self.message.is_empty() {
+            lines.push(Line::from(self.message.clone()));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Rates")),
+            area,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+    use ratatui::Terminal;
+    use ratatui::backend::TestBackend;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_browser() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            title: String::from("Particle ID"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "pid",
+                AxisSpec::new("e", "E", 8, 0.0, 8.0).unwrap(),
+                Some(AxisSpec::new("de", "dE", 8, 0.0, 8.0).unwrap()),
+            )
+        };
+        let si_e = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("si_e"),
+            title: String::from("Silicon energy"),
+            y_axis: None,
+            ..spec.clone()
+        };
+        manager.add_histogram(spec).unwrap();
+        let si_e = manager.add_histogram(si_e).unwrap();
+        let mut data = DataBlob::new();
+        data.insert("e", 1.5);
+        data.insert("de", 6.5);
+        manager.update(data).unwrap();
+
+        let gram = manager.get_histogram(&si_e).unwrap();
+        assert_eq!(get_steps(gram, false)[2], (1.0, 1.0));
+        let pid = manager
+            .get_histogram(&manager.list_histograms("")[0].id)
+            .unwrap();
+        // Two y bins per row, with the highest first
+        let heatmap = get_heatmap(pid, 8, 4, false);
+        assert_eq!(heatmap.len(), 4);
+        assert_eq!(heatmap[0].spans[1].style.bg, Some(get_color(1.0)));
+        assert_eq!(heatmap[0].spans[0].style.bg, Some(get_color(0.0)));
+
+        let mut browser = Browser::from_manager(manager);
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        terminal.draw(|frame| browser.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Particle ID"));
+        assert!(screen.contains("Events: 1"));
+
+        assert!(!browser.handle_key(KeyCode::Down));
+        assert_eq!(browser.get_selected(), Some(si_e));
+        terminal.draw(|frame| browser.draw(frame)).unwrap();
+        assert!(browser.handle_key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,403 @@
+//! A window for browsing the histograms of a manager while data is taken: 1D spectra are drawn as
+//! steps and 2D spectra as heatmaps, either on a log scale, with the cuts drawn on them. Cuts
+//! drawn with the mouse are added to the manager, so an analysis can be gated without leaving
+//! the display.
+use super::cut::{Cut1D, Cut2D, CutSpec};
+use super::cut_registry::SavedCut;
+use super::error::{CutError, ResourceError};
+use super::histogram::Histogram;
+use super::manager::ResourceManager;
+use super::spect::Spect;
+use eframe::egui;
+use egui_plot::{Heatmap, Line, Plot, PlotPoint, PlotPoints, VLine};
+use uuid::Uuid;
+
+// Colors of the heatmaps, from empty to full bins
+const PALETTE: [egui::Color32; 4] = [
+    egui::Color32::from_rgb(20, 20, 60),
+    egui::Color32::from_rgb(40, 120, 200),
+    egui::Color32::from_rgb(240, 220, 40),
+    egui::Color32::from_rgb(250, 60, 30),
+];
+
+// A content shown on a log scale, where empty bins sit at zero
+fn scale(content: f64, log_scale: bool) -> f64 {
+    if log_scale {
+        content.max(0.0).ln_1p() / std::f64::consts::LN_10
+    } else {
+        content
+    }
+}
+
+// The outline of a 1D spectrum, stepping across each bin
+fn get_steps(gram: &Histogram, log_scale: bool) -> Vec<[f64; 2]> {
+    let axis = &gram.spec.x_axis;
+    let width = axis.get_bin_width() as f64;
+    let mut points = Vec::with_capacity(2 * axis.bins);
+    for bin in 0..axis.bins {
+        let low = axis.get_bin_low_edge(bin) as f64;
+        let content = scale(gram.get_bin_content(bin, 0).unwrap_or(0.0), log_scale);
+        points.push([low, content]);
+        points.push([low + width, content]);
+    }
+    points
+}
+
+/// Add a cut drawn on a histogram from the points clicked, in the variables of its axes: two
+/// points give a 1D cut between their x values, and three or more give a 2D cut inside the
+/// polygon they outline. Returns the ID of the cut.
+pub fn add_drawn_cut(
+    manager: &mut ResourceManager,
+    histogram_id: &Uuid,
+    name: &str,
+    points: &[[f64; 2]],
+) -> Result<Uuid, ResourceError> {
+    let spec = manager.get_histogram_spec(histogram_id)?;
+    let cut = CutSpec {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        x_variable: spec.x_axis.variable.clone(),
+        y_variable: spec.y_axis.as_ref().map(|axis| axis.variable.clone()),
+    };
+    let id = cut.id;
+    if cut.y_variable.is_none() {
+        let [[first, _], [second, _]] = points else {
+            return Err(CutError::BadParameters(String::from("A 1D cut needs two points")).into());
+        };
+        let (low, high) = (first.min(*second) as f32, first.max(*second) as f32);
+        manager.add_cut_1d(cut, low, high, histogram_id)?;
+    } else {
+        let mut x_values: Vec<f32> = points.iter().map(|point| point[0] as f32).collect();
+        let mut y_values: Vec<f32> = points.iter().map(|point| point[1] as f32).collect();
+        // Close the polygon
+        if let (Some(&x), Some(&y)) = (x_values.first(), y_values.first()) {
+            x_values.push(x);
+            y_values.push(y);
+        }
+        manager.add_cut_2d(cut, x_values, y_values, histogram_id)?;
+    }
+    Ok(id)
+}
+
+// Where the manager driven by the viewer comes from
+enum Model {
+    Live(Box<Spect>),
+    Static(Box<ResourceManager>),
+}
+
+/// The viewer window. A viewer of a Spect steps it between frames, so the spectra fill live;
+/// a viewer of a manager only browses it.
+pub struct Viewer {
+    model: Model,
+    events_per_frame: usize,
+    filter: String,
+    selected: Option<Uuid>,
+    log_scale: bool,
+    cut_name: String,
+    // The points clicked so far while drawing a cut
+    drawing: Option<Vec<[f64; 2]>>,
+    message: String,
+}
+
+impl std::fmt::Debug for Viewer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Viewer")
+            .field("selected", &self.selected)
+            .field("log_scale", &self.log_scale)
+            .finish()
+    }
+}
+
+impl Viewer {
+    /// View a running application, processing at most events_per_frame events between frames
+    pub fn new(spect: Spect, events_per_frame: usize) -> Self {
+        Self::with_model(Model::Live(Box::new(spect)), events_per_frame)
+    }
+
+    /// Browse a manager which is not taking data, e.g. one loaded from files
+    pub fn from_manager(manager: ResourceManager) -> Self {
+        Self::with_model(Model::Static(Box::new(manager)), 0)
+    }
+
+    fn with_model(model: Model, events_per_frame: usize) -> Self {
+        Self {
+            model,
+            events_per_frame,
+            filter: String::new(),
+            selected: None,
+            log_scale: false,
+            cut_name: String::from("cut"),
+            drawing: None,
+            message: String::new(),
+        }
+    }
+
+    pub fn get_manager(&self) -> &ResourceManager {
+        match &self.model {
+            Model::Live(spect) => spect.get_manager(),
+            Model::Static(manager) => manager,
+        }
+    }
+
+    pub fn get_manager_mut(&mut self) -> &mut ResourceManager {
+        match &mut self.model {
+            Model::Live(spect) => spect.get_manager_mut(),
+            Model::Static(manager) => manager,
+        }
+    }
+
+    /// Open the window, returning once it is closed
+    pub fn run(self, title: &str) -> eframe::Result {
+        eframe::run_native(
+            title,
+            eframe::NativeOptions::default(),
+            Box::new(|_| Ok(Box::new(self))),
+        )
+    }
+
+    fn show_toolbar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("Events: {}", self.get_manager().get_event_count()));
+            if let Some(run) = self.get_manager().get_run_info() {
+                ui.label(format!("Run: {}", run.number));
+            }
+            ui.checkbox(&mut self.log_scale, "Log scale");
+            if let Some(id) = self.selected
+                && ui.button("Clear").clicked()
+            {
+                let _ = self.get_manager_mut().clear_histogram(&id);
+            }
+            ui.separator();
+            ui.label("Cut name");
+            ui.text_edit_singleline(&mut self.cut_name);
+            match &self.drawing {
+                None => {
+                    if self.selected.is_some() && ui.button("Draw cut").clicked() {
+                        self.drawing = Some(vec![]);
+                        self.message = String::from("Click the points of the cut");
+                    }
+                }
+                Some(points) => {
+                    let n_points = points.len();
+                    if ui.button("Finish").clicked() {
+                        self.finish_cut();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.drawing = None;
+                        self.message.clear();
+                    }
+                    ui.label(format!("{n_points} points"));
+                }
+            }
+            ui.label(&self.message);
+        });
+    }
+
+    fn show_list(&mut self, ui: &mut egui::Ui) {
+        ui.text_edit_singleline(&mut self.filter);
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let pattern = format!("*{}*", self.filter);
+            let specs: Vec<(Uuid, String)> = self
+                .get_manager()
+                .list_histograms("")
+                .into_iter()
+                .filter(|spec| super::pattern::matches(&pattern, &spec.name))
+                .map(|spec| (spec.id, spec.name.clone()))
+                .collect();
+            for (id, name) in specs {
+                if ui
+                    .selectable_label(self.selected == Some(id), name)
+                    .clicked()
+                {
+                    self.selected = Some(id);
+                    self.drawing = None;
+                }
+            }
+        });
+    }
+
+    fn show_plot(&mut self, ui: &mut egui::Ui) {
+        let Some(id) = self.selected else {
+            ui.label("Select a histogram");
+            return;
+        };
+        let manager = self.get_manager();
+        let Ok(gram) = manager.get_histogram(&id) else {
+            self.selected = None;
+            return;
+        };
+        let saved: Vec<SavedCut> = manager
+            .export_cuts()
+            .into_iter()
+            .filter(|cut| gram.spec.cuts_to_draw.contains(&cut.spec.id))
+            .collect();
+        let log_scale = self.log_scale;
+        let drawing = self.drawing.clone();
+        let plot = Plot::new("spectrum")
+            .x_axis_label(gram.spec.x_axis.get_label())
+            .allow_drag(drawing.is_none());
+        let plot = match &gram.spec.y_axis {
+            Some(axis) => plot.y_axis_label(axis.get_label()),
+            None if log_scale => plot.y_axis_label("log10(counts + 1)"),
+            None => plot.y_axis_label("Counts"),
+        };
+        let response = plot.show(ui, |plot_ui| {
+            match &gram.spec.y_axis {
+                None => {
+                    let steps = PlotPoints::new(get_steps(gram, log_scale));
+                    plot_ui.line(Line::new(gram.spec.name.clone(), steps));
+                }
+                Some(y_axis) => {
+                    let (x_bins, y_bins) = gram.get_dimensions();
+                    let mut values = Vec::with_capacity(x_bins * y_bins);
+                    for y_bin in 0..y_bins {
+                        for x_bin in 0..x_bins {
+                            let content = gram.get_bin_content(x_bin, y_bin).unwrap_or(0.0);
+                            values.push(scale(content, log_scale));
+                        }
+                    }
+                    plot_ui.heatmap(
+                        Heatmap::new(values, x_bins)
+                            .palette(&PALETTE)
+                            .at(PlotPoint::new(gram.spec.x_axis.minimum, y_axis.minimum))
+                            .tile_size(gram.spec.x_axis.get_bin_width(), y_axis.get_bin_width()),
+                    );
+                }
+            }
+            for cut in saved.iter() {
+                show_cut(plot_ui, cut);
+            }
+            if let Some(points) = &drawing {
+                match gram.spec.y_axis {
+                    Some(_) => plot_ui.line(Line::new("drawing", PlotPoints::new(points.clone()))),
+                    None => {
+                        for point in points {
+                            plot_ui.vline(VLine::new("drawing", point[0]));
+                        }
+                    }
+                }
+            }
+            plot_ui.pointer_coordinate()
+        });
+        let is_1d = gram.spec.y_axis.is_none();
+        if response.response.clicked()
+            && let (Some(points), Some(pointer)) = (&mut self.drawing, response.inner)
+        {
+            points.push([pointer.x, pointer.y]);
+            // A 1D cut is complete with its second edge
+            if is_1d && points.len() == 2 {
+                self.finish_cut();
+            }
+        }
+    }
+
+    fn finish_cut(&mut self) {
+        let (Some(points), Some(id)) = (self.drawing.take(), self.selected) else {
+            return;
+        };
+        let name = self.cut_name.clone();
+        self.message = match add_drawn_cut(self.get_manager_mut(), &id, &name, &points) {
+            Ok(_) => format!("Added cut {name}"),
+            Err(e) => e.to_string(),
+        };
+    }
+}
+
+fn show_cut(plot_ui: &mut egui_plot::PlotUi, cut: &SavedCut) {
+    let name = cut.spec.name.clone();
+    match cut.kind.as_str() {
+        Cut1D::KIND => {
+            for edge in ["low", "high"] {
+                if let Some(x) = cut.parameters[edge].as_f64() {
+                    plot_ui.vline(VLine::new(name.clone(), x));
+                }
+            }
+        }
+        Cut2D::KIND => {
+            let values = |key: &str| -> Vec<f64> {
+                cut.parameters[key]
+                    .as_array()
+                    .map(|values| values.iter().filter_map(|v| v.as_f64()).collect())
+                    .unwrap_or_default()
+            };
+            let points: Vec<[f64; 2]> = values("x_values")
+                .into_iter()
+                .zip(values("y_values"))
+                .map(|(x, y)| [x, y])
+                .collect();
+            plot_ui.line(Line::new(name, PlotPoints::new(points)));
+        }
+        // Other kinds of cut have no outline to draw
+        _ => (),
+    }
+}
+
+impl eframe::App for Viewer {
+    fn logic(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if let Model::Live(spect) = &mut self.model {
+            if let Err(e) = spect.step(self.events_per_frame) {
+                self.message = e.to_string();
+            }
+            ctx.request_repaint();
+        }
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        egui::Panel::top("toolbar").show(ui, |ui| self.show_toolbar(ui));
+        egui::Panel::left("histograms")
+            .default_size(220.0)
+            .show(ui, |ui| self.show_list(ui));
+        egui::CentralPanel::default_margins().show(ui, |ui| self.show_plot(ui));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+
+    #[test]
+    fn test_drawn_cuts() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "si_e",
+                AxisSpec::new("si_e", "Energy", 4, 0.0, 4.0).unwrap(),
+                None,
+            )
+        };
+        let pid = HistSpec {
+            id: Uuid::new_v4(),
+            name: String::from("pid"),
+            y_axis: Some(AxisSpec::new("de", "dE", 4, 0.0, 4.0).unwrap()),
+            ..spec.clone()
+        };
+        let (si_e, pid) = (
+            manager.add_histogram(spec).unwrap(),
+            manager.add_histogram(pid).unwrap(),
+        );
+
+        let window =
+            add_drawn_cut(&mut manager, &si_e, "window", &[[3.0, 1.0], [1.0, 5.0]]).unwrap();
+        assert_eq!(manager.get_cut_spec(&window).unwrap().x_variable, "si_e");
+        assert!(add_drawn_cut(&mut manager, &si_e, "bad", &[[1.0, 1.0]]).is_err());
+        let blob = add_drawn_cut(
+            &mut manager,
+            &pid,
+            "blob",
+            &[[0.0, 0.0], [2.0, 0.0], [2.0, 2.0]],
+        )
+        .unwrap();
+        assert_eq!(
+            manager.get_histogram_spec(&pid).unwrap().cuts_to_draw,
+            vec![blob]
+        );
+
+        let gram = manager.get_histogram(&si_e).unwrap();
+        let steps = get_steps(gram, true);
+        assert_eq!(steps.len(), 8);
+        assert_eq!(steps[1], [1.0, 0.0]);
+        assert_eq!(scale(99.0, true), 2.0);
+    }
+}
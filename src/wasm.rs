@@ -0,0 +1,210 @@
+//! Bindings of a manager for JavaScript, so spectra can be built in a browser from data files
+//! the user uploads. Histograms are booked from axes, cuts are drawn on them, and events are
+//! filled a batch of columns at a time or replayed from an event log; the contents come back as
+//! arrays for plotting. Files and servers are not available in a browser.
+use super::batch::ColumnBatch;
+use super::compression;
+use super::cut::CutSpec;
+use super::error::ResourceError;
+use super::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+use super::manager::ResourceManager;
+use super::record::EventReader;
+use std::io::Cursor;
+use uuid::Uuid;
+use wasm_bindgen::prelude::*;
+
+fn parse_id(id: &str) -> Result<Uuid, JsError> {
+    Uuid::parse_str(id).map_err(|_| JsError::new(&format!("'{id}' is not a valid ID")))
+}
+
+/// An axis of a histogram booked from JavaScript
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct WasmAxis {
+    axis: AxisSpec,
+}
+
+#[wasm_bindgen]
+impl WasmAxis {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        variable: &str,
+        title: &str,
+        bins: usize,
+        minimum: f32,
+        maximum: f32,
+    ) -> Result<WasmAxis, JsError> {
+        Ok(Self {
+            axis: AxisSpec::new(variable, title, bins, minimum, maximum)?,
+        })
+    }
+}
+
+/// A manager driven from JavaScript. IDs cross into JavaScript as strings.
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct WasmManager {
+    manager: ResourceManager,
+}
+
+#[wasm_bindgen]
+impl WasmManager {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Book a histogram, 2D if a y axis is given, returning its ID
+    pub fn add_histogram(
+        &mut self,
+        name: &str,
+        x_axis: &WasmAxis,
+        y_axis: Option<WasmAxis>,
+    ) -> Result<String, JsError> {
+        let spec = HistSpec {
+            title: name.to_string(),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(name, x_axis.axis.clone(), y_axis.map(|axis| axis.axis))
+        };
+        Ok(self.manager.add_histogram(spec)?.to_string())
+    }
+
+    pub fn remove_histogram(&mut self, id: &str) -> Result<(), JsError> {
+        Ok(self.manager.remove_histogram(&parse_id(id)?)?)
+    }
+
+    pub fn clear_histogram(&mut self, id: &str) -> Result<(), JsError> {
+        Ok(self.manager.clear_histogram(&parse_id(id)?)?)
+    }
+
+    /// The histograms as a JSON array of objects with their id, name, and x_bins and y_bins
+    pub fn list_histograms(&self) -> String {
+        let specs: Vec<serde_json::Value> = self
+            .manager
+            .list_histograms("")
+            .iter()
+            .map(|spec| {
+                serde_json::json!({
+                    "id": spec.id.to_string(),
+                    "name": spec.name,
+                    "x_bins": spec.x_axis.bins,
+                    "y_bins": spec.y_axis.as_ref().map_or(0, |axis| axis.bins),
+                })
+            })
+            .collect();
+        serde_json::Value::from(specs).to_string()
+    }
+
+    /// The contents of a histogram with x varying fastest
+    pub fn get_histogram_data(&self, id: &str) -> Result<Vec<f64>, JsError> {
+        let id = parse_id(id)?;
+        let gram = self.manager.get_histogram(&id)?;
+        let y_bins = gram.spec.y_axis.as_ref().map_or(1, |axis| axis.bins);
+        let mut data = Vec::with_capacity(gram.spec.x_axis.bins * y_bins);
+        for y_bin in 0..y_bins {
+            for x_bin in 0..gram.spec.x_axis.bins {
+                data.push(gram.get_bin_content(x_bin, y_bin)?);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Draw a cut on a histogram and gate it on the cut, returning the ID of the cut. Two x
+    /// values make an interval on a 1D histogram; a polygon of x and y values gates a 2D one.
+    pub fn add_cut(
+        &mut self,
+        histogram_id: &str,
+        name: &str,
+        x_values: Vec<f32>,
+        y_values: Vec<f32>,
+    ) -> Result<String, JsError> {
+        let histogram_id = parse_id(histogram_id)?;
+        let hist_spec = self.manager.get_histogram_spec(&histogram_id)?;
+        let spec = CutSpec {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            x_variable: hist_spec.x_axis.variable.clone(),
+            y_variable: hist_spec.y_axis.as_ref().map(|axis| axis.variable.clone()),
+        };
+        let id = spec.id;
+        match (&spec.y_variable, x_values.as_slice()) {
+            (None, [low, high]) => {
+                self.manager
+                    .add_cut_1d(spec, low.min(*high), low.max(*high), &histogram_id)?
+            }
+            (None, _) => return Err(JsError::new("A 1D cut needs two x values")),
+            (Some(_), _) => self
+                .manager
+                .add_cut_2d(spec, x_values, y_values, &histogram_id)?,
+        }
+        self.manager.apply_cut(&histogram_id, &id)?;
+        Ok(id.to_string())
+    }
+
+    /// Fill a batch of events given as columns, one per variable, laid end to end in values.
+    /// Each variable has values.len() / variables.len() events.
+    pub fn fill_columns(&mut self, variables: Vec<String>, values: &[f32]) -> Result<(), JsError> {
+        if variables.is_empty() || !values.len().is_multiple_of(variables.len()) {
+            return Err(JsError::new(
+                "The values must hold a column of equal length for each variable",
+            ));
+        }
+        let n_rows = values.len() / variables.len();
+        let mut batch = ColumnBatch::new(n_rows);
+        for (variable, column) in variables.iter().zip(values.chunks(n_rows.max(1))) {
+            batch
+                .add_column(variable, column, None)
+                .map_err(ResourceError::from)?;
+        }
+        Ok(self.manager.update_batch(&batch)?)
+    }
+
+    /// Replay an uploaded event log, which may be compressed, returning the number of events
+    pub fn replay_event_log(&mut self, bytes: Vec<u8>) -> Result<u32, JsError> {
+        let input = compression::decompress(Cursor::new(bytes))?;
+        let mut n_events = 0;
+        for blob in EventReader::new(input)? {
+            self.manager.update(blob?)?;
+            n_events += 1;
+        }
+        Ok(n_events)
+    }
+
+    pub fn get_event_count(&self) -> f64 {
+        self.manager.get_event_count() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_manager() {
+        let mut manager = WasmManager::new();
+        let energy = WasmAxis::new("e", "Energy", 4, 0.0, 4.0).unwrap();
+        let de = WasmAxis::new("de", "dE", 2, 0.0, 2.0).unwrap();
+        let si_e = manager.add_histogram("si_e", &energy, None).unwrap();
+        let pid = manager.add_histogram("pid", &energy, Some(de)).unwrap();
+        manager
+            .add_cut(&si_e, "low", vec![2.0, 0.0], vec![])
+            .unwrap();
+
+        let variables = vec![String::from("e"), String::from("de")];
+        manager
+            .fill_columns(variables, &[0.5, 1.5, 3.5, 0.5, 0.5, 1.5])
+            .unwrap();
+        assert_eq!(manager.get_event_count(), 3.0);
+        assert_eq!(
+            manager.get_histogram_data(&si_e).unwrap(),
+            vec![1.0, 1.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            manager.get_histogram_data(&pid).unwrap(),
+            vec![1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]
+        );
+        let listed: serde_json::Value = serde_json::from_str(&manager.list_histograms()).unwrap();
+        assert_eq!(listed[0]["name"], "pid");
+        assert_eq!(listed[0]["y_bins"], 2);
+    }
+}
@@ -0,0 +1,391 @@
+//! Liveness monitoring of data sources, so that a network source which silently died is not
+//! mistaken for a quiet detector. A Watchdog wraps a source, timing the events passing through
+//! it. Its WatchdogMonitor can be checked from another thread, so that a stall raises an alert
+//! even while the analysis thread is blocked waiting on the source.
+//!
+//! A Watchdog given a Connector connects the source again when it fails or ends. A stall found
+//! by a check is only acted on at the next read, since a read blocked inside the source cannot
+//! be interrupted: sources which may block indefinitely should time out their reads, returning
+//! an error or the end of the stream, so that the Watchdog can reconnect them.
+use super::data_blob::DataBlob;
+use super::deadtime::LossCounts;
+use super::error::SourceError;
+use super::schema::Schema;
+use super::source::DataSource;
+use super::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WatchdogSpec {
+    /// The name of the source in alerts
+    pub name: String,
+    /// The source is stalled once it has gone this long without an event
+    pub max_silence: Duration,
+    /// The source is also stalled if its rate over a window of max_silence falls below this,
+    /// in events per second
+    pub min_rate: Option<f64>,
+    /// Reconnections tried in a row without an event, including failed connections, before the
+    /// failure is returned
+    pub max_reconnects: u32,
+    /// The wait before each reconnection
+    pub reconnect_delay: Duration,
+}
+
+impl WatchdogSpec {
+    pub fn new(name: &str, max_silence: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            max_silence,
+            min_rate: None,
+            max_reconnects: 3,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A source starting or stopping to be stalled
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceAlert {
+    pub source: String,
+    /// True when the source stalls and false once events arrive again
+    pub active: bool,
+    pub message: String,
+}
+
+/// How a watched source is doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Liveness {
+    pub since_last_event: Duration,
+    /// The rate over the last complete window, in events per second
+    pub rate: Option<f64>,
+    pub stalled: bool,
+    /// The connections made again, not counting those which failed
+    pub n_reconnects: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    last_event: Instant,
+    window_start: Instant,
+    n_window: u64,
+    rate: Option<f64>,
+    stalled: bool,
+    // Set when a check finds the source stalled, so that the next read connects it again
+    reconnect: bool,
+    n_reconnects: u64,
+}
+
+/// A handle on the liveness of a watched source. Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct WatchdogMonitor {
+    spec: Arc<WatchdogSpec>,
+    state: Arc<Mutex<State>>,
+}
+
+impl WatchdogMonitor {
+    fn new(spec: WatchdogSpec) -> Self {
+        let now = Instant::now();
+        Self {
+            spec: Arc::new(spec),
+            state: Arc::new(Mutex::new(State {
+                last_event: now,
+                window_start: now,
+                n_window: 0,
+                rate: None,
+                stalled: false,
+                reconnect: false,
+                n_reconnects: 0,
+            })),
+        }
+    }
+
+    // A panic elsewhere cannot leave the state inconsistent, so keep using it
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn get_spec(&self) -> &WatchdogSpec {
+        &self.spec
+    }
+
+    pub fn get_liveness(&self) -> Liveness {
+        let state = self.lock();
+        Liveness {
+            since_last_event: state.last_event.elapsed(),
+            rate: state.rate,
+            stalled: state.stalled,
+            n_reconnects: state.n_reconnects,
+        }
+    }
+
+    /// Check the source against the limits of the spec, e.g. from a timer once a second,
+    /// returning an alert if it started or stopped being stalled
+    pub fn check(&self) -> Option<SourceAlert> {
+        let mut state = self.lock();
+        let silence = state.last_event.elapsed();
+        let slow = match (self.spec.min_rate, state.rate) {
+            (Some(min_rate), Some(rate)) if rate < min_rate => Some(rate),
+            _ => None,
+        };
+        let stalled = silence > self.spec.max_silence || slow.is_some();
+        if stalled == state.stalled {
+            return None;
+        }
+        state.stalled = stalled;
+        state.reconnect |= stalled;
+        let message = match (stalled, slow) {
+            (false, _) => format!("Source {} is receiving events again", self.spec.name),
+            (true, Some(rate)) => format!(
+                "Source {} is receiving {rate:.2} events/s, below the minimum of {:.2}",
+                self.spec.name,
+                self.spec.min_rate.unwrap_or_default()
+            ),
+            (true, None) => format!(
+                "Source {} has had no events for {:.1} s",
+                self.spec.name,
+                silence.as_secs_f64()
+            ),
+        };
+        Some(SourceAlert {
+            source: self.spec.name.clone(),
+            active: stalled,
+            message,
+        })
+    }
+
+    fn record_event(&self) {
+        let mut state = self.lock();
+        let now = Instant::now();
+        state.last_event = now;
+        state.n_window += 1;
+        let window = now.duration_since(state.window_start);
+        if window >= self.spec.max_silence {
+            state.rate = Some(state.n_window as f64 / window.as_secs_f64());
+            state.window_start = now;
+            state.n_window = 0;
+        }
+    }
+
+    fn take_reconnect(&self) -> bool {
+        std::mem::take(&mut self.lock().reconnect)
+    }
+
+    fn record_reconnect(&self) {
+        let mut state = self.lock();
+        state.n_reconnects += 1;
+        // A fresh connection gets a full window before its rate is judged
+        state.window_start = Instant::now();
+        state.n_window = 0;
+        state.rate = None;
+    }
+}
+
+/// Makes a new connection to a source, for a Watchdog to reconnect with
+pub type Connector = Box<dyn FnMut() -> Result<Box<dyn DataSource>, SourceError>>;
+
+/// A source whose liveness is monitored. With a Connector, the source is connected again when
+/// it fails or ends, or at the first read after a check found it stalled, up to max_reconnects
+/// times in a row.
+pub struct Watchdog {
+    // None once disconnected, until a connection succeeds
+    source: Option<Box<dyn DataSource>>,
+    connect: Option<Connector>,
+    monitor: WatchdogMonitor,
+    stopped: bool,
+}
+
+impl std::fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("monitor", &self.monitor)
+            .field("reconnects", &self.connect.is_some())
+            .field("stopped", &self.stopped)
+            .finish()
+    }
+}
+
+impl Watchdog {
+    /// Watch a source which is not reconnected
+    pub fn new(source: Box<dyn DataSource>, spec: WatchdogSpec) -> Self {
+        Self {
+            source: Some(source),
+            connect: None,
+            monitor: WatchdogMonitor::new(spec),
+            stopped: false,
+        }
+    }
+
+    /// Watch a source made by connect, which is called again to reconnect it
+    pub fn connect(spec: WatchdogSpec, mut connect: Connector) -> Result<Self, SourceError> {
+        let mut watchdog = Self::new(connect()?, spec);
+        watchdog.connect = Some(connect);
+        Ok(watchdog)
+    }
+
+    pub fn get_monitor(&self) -> WatchdogMonitor {
+        self.monitor.clone()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        self.connect.is_some() && !self.stopped
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(mut source) = self.source.take() {
+            source.stop();
+        }
+    }
+
+    // Replace the source with a new connection, leaving it disconnected if that fails
+    fn reconnect(&mut self) -> Result<(), SourceError> {
+        self.disconnect();
+        let Some(connect) = &mut self.connect else {
+            return Ok(());
+        };
+        std::thread::sleep(self.monitor.spec.reconnect_delay);
+        #[cfg(feature = "tracing")]
+        tracing::warn!(source = %self.monitor.spec.name, "Reconnecting source");
+        self.source = Some(connect()?);
+        self.monitor.record_reconnect();
+        Ok(())
+    }
+}
+
+impl DataSource for Watchdog {
+    fn next_event(&mut self) -> Result<Option<DataBlob>, SourceError> {
+        if self.monitor.take_reconnect()
+            && self.can_reconnect()
+            && self.monitor.spec.max_reconnects > 0
+        {
+            self.disconnect();
+        }
+        let mut n_attempts = 0;
+        loop {
+            // A disconnected source reads as ended, so that it is connected again
+            let result = match &mut self.source {
+                Some(source) => source.next_event(),
+                None => Ok(None),
+            };
+            if let Ok(Some(_)) = &result {
+                self.monitor.record_event();
+                return result;
+            }
+            if !self.can_reconnect() || n_attempts >= self.monitor.spec.max_reconnects {
+                return result;
+            }
+            n_attempts += 1;
+            if let Err(e) = self.reconnect()
+                && n_attempts >= self.monitor.spec.max_reconnects
+            {
+                return Err(e);
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stopped = true;
+        if let Some(source) = &mut self.source {
+            source.stop();
+        }
+    }
+
+    fn get_schema(&self) -> Option<Schema> {
+        self.source.as_ref().and_then(|source| source.get_schema())
+    }
+
+    fn get_losses(&self) -> LossCounts {
+        self.source
+            .as_ref()
+            .map(|source| source.get_losses())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::IterSource;
+
+    fn spec(max_silence: Duration) -> WatchdogSpec {
+        WatchdogSpec {
+            reconnect_delay: Duration::ZERO,
+            ..WatchdogSpec::new("daq", max_silence)
+        }
+    }
+
+    #[test]
+    fn test_stall() {
+        let source = IterSource::new(vec![DataBlob::new()].into_iter());
+        let mut watchdog = Watchdog::new(Box::new(source), spec(Duration::from_millis(20)));
+        let monitor = watchdog.get_monitor();
+        assert!(watchdog.next_event().unwrap().is_some());
+        assert!(monitor.check().is_none());
+        std::thread::sleep(Duration::from_millis(30));
+        let alert = monitor.check().unwrap();
+        assert!(alert.active);
+        assert!(monitor.get_liveness().stalled);
+        assert!(monitor.check().is_none());
+        // Without a connector the stall only raises alerts
+        assert!(watchdog.next_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let mut n_connections = 0;
+        let connect: Connector = Box::new(move || {
+            n_connections += 1;
+            // The fifth connection onwards is dead on arrival
+            let n_events = if n_connections <= 4 { n_connections } else { 0 };
+            let events = vec![DataBlob::new(); n_events];
+            Ok(Box::new(IterSource::new(events.into_iter())) as Box<dyn DataSource>)
+        });
+        let mut watchdog = Watchdog::connect(spec(Duration::from_secs(60)), connect).unwrap();
+        let monitor = watchdog.get_monitor();
+        let mut n_events = 0;
+        while watchdog.next_event().unwrap().is_some() {
+            n_events += 1;
+        }
+        // Connections 2 to 4 follow ends, then three dead connections in a row give up
+        assert_eq!(n_events, 1 + 2 + 3 + 4);
+        assert_eq!(monitor.get_liveness().n_reconnects, 6);
+    }
+
+    #[test]
+    fn test_failed_connection() {
+        let mut n_connections = 0;
+        let connect: Connector = Box::new(move || {
+            n_connections += 1;
+            // The network is down for the second and third connections, and after the fourth
+            match n_connections {
+                1 | 4 => Ok(
+                    Box::new(IterSource::new(vec![DataBlob::new(); 2].into_iter()))
+                        as Box<dyn DataSource>,
+                ),
+                _ => Err(SourceError::Io(std::io::Error::from(
+                    std::io::ErrorKind::ConnectionRefused,
+                ))),
+            }
+        });
+        let mut watchdog = Watchdog::connect(spec(Duration::from_secs(60)), connect).unwrap();
+        let monitor = watchdog.get_monitor();
+        let mut n_events = 0;
+        let error = loop {
+            match watchdog.next_event() {
+                Ok(Some(_)) => n_events += 1,
+                Ok(None) => panic!("The source should fail rather than end"),
+                Err(e) => break e,
+            }
+        };
+        // The failed connections counted as attempts, and the fourth connection was read
+        assert_eq!(n_events, 4);
+        assert!(matches!(error, SourceError::Io(_)));
+        assert_eq!(monitor.get_liveness().n_reconnects, 1);
+        // Still disconnected, the next read tries again rather than reading a dead source
+        assert!(watchdog.next_event().is_err());
+        watchdog.stop();
+        assert!(watchdog.next_event().unwrap().is_none());
+    }
+}
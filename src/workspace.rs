@@ -0,0 +1,265 @@
+//! Several independent managers in one process, e.g. one filled online and one replaying data,
+//! sharing their cut definitions so that a gate refined in one applies at once in the others.
+//!
+//! Cuts are shared as SavedCuts through a SharedCuts store. A manager which joins a store with
+//! ResourceManager::share_cuts publishes every cut created or changed in it, and imports those
+//! published by the others before its next update. Only the shapes of cuts are shared: bindings
+//! and compound cuts stay with the manager they were made in, keeping the IDs of the shapes they
+//! refer to. Removing a cut from one manager leaves it in the others.
+use super::cut::Cut;
+use super::cut_registry::SavedCut;
+use super::error::ResourceError;
+use super::manager::ResourceManager;
+use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+#[derive(Debug)]
+struct SharedCut {
+    saved: SavedCut,
+    generation: u64,
+    // The manager which published the cut, which need not import it again
+    publisher: Uuid,
+}
+
+/// A store of cut definitions shared between managers, which may live on other threads. Clones
+/// share the same store.
+#[derive(Debug, Clone, Default)]
+pub struct SharedCuts {
+    cuts: Arc<Mutex<FxHashMap<Uuid, SharedCut>>>,
+    // Bumped on every publish, so that managers can check for changes without locking
+    generation: Arc<AtomicU64>,
+}
+
+impl SharedCuts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Nothing is left half-changed by a panic while locked, so keep using the store
+    fn lock(&self) -> MutexGuard<'_, FxHashMap<Uuid, SharedCut>> {
+        self.cuts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Get the generation of the store, which increases whenever a cut is published
+    pub fn get_generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Publish a cut to every manager sharing the store, replacing any with the same ID
+    pub fn publish(&self, saved: SavedCut) {
+        self.publish_from(saved, Uuid::nil());
+    }
+
+    fn publish_from(&self, saved: SavedCut, publisher: Uuid) {
+        let mut cuts = self.lock();
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        cuts.insert(
+            saved.spec.id,
+            SharedCut {
+                saved,
+                generation,
+                publisher,
+            },
+        );
+    }
+
+    /// Stop sharing a cut. The managers which imported it keep their copies.
+    pub fn remove(&self, id: &Uuid) -> Result<SavedCut, ResourceError> {
+        match self.lock().remove(id) {
+            Some(shared) => Ok(shared.saved),
+            None => Err(ResourceError::InvalidCutID(*id)),
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Result<SavedCut, ResourceError> {
+        match self.lock().get(id) {
+            Some(shared) => Ok(shared.saved.clone()),
+            None => Err(ResourceError::InvalidCutID(*id)),
+        }
+    }
+
+    /// Get every shared cut
+    pub fn list(&self) -> Vec<SavedCut> {
+        self.lock()
+            .values()
+            .map(|shared| shared.saved.clone())
+            .collect()
+    }
+
+    // The cuts published after a generation by anyone but subscriber, and the generation of
+    // the store they were taken at
+    fn changes_since(&self, generation: u64, subscriber: &Uuid) -> (Vec<SavedCut>, u64) {
+        let cuts = self.lock();
+        let changes = cuts
+            .values()
+            .filter(|shared| shared.generation > generation && shared.publisher != *subscriber)
+            .map(|shared| shared.saved.clone())
+            .collect();
+        (changes, self.get_generation())
+    }
+}
+
+/// A manager's membership of a SharedCuts store
+#[derive(Debug)]
+pub(crate) struct CutSubscription {
+    cuts: SharedCuts,
+    id: Uuid,
+    // The generation of the store the manager has imported up to
+    generation: u64,
+}
+
+impl CutSubscription {
+    pub(crate) fn new(cuts: &SharedCuts) -> Self {
+        Self {
+            cuts: cuts.clone(),
+            id: Uuid::new_v4(),
+            generation: 0,
+        }
+    }
+
+    pub(crate) fn get_cuts(&self) -> &SharedCuts {
+        &self.cuts
+    }
+
+    pub(crate) fn publish(&self, cut: &dyn Cut) {
+        self.cuts.publish_from(SavedCut::from_cut(cut), self.id);
+    }
+
+    /// The cuts published by others since the last call, or None without locking the store if
+    /// nothing was published
+    pub(crate) fn take_changes(&mut self) -> Option<Vec<SavedCut>> {
+        if self.cuts.get_generation() == self.generation {
+            return None;
+        }
+        let (changes, generation) = self.cuts.changes_since(self.generation, &self.id);
+        self.generation = generation;
+        Some(changes)
+    }
+}
+
+/// Named managers sharing one store of cuts
+#[derive(Debug, Default)]
+pub struct Workspaces {
+    managers: BTreeMap<String, ResourceManager>,
+    cuts: SharedCuts,
+}
+
+impl Workspaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The store the workspaces share, e.g. to share it with a manager on another thread
+    pub fn get_shared_cuts(&self) -> &SharedCuts {
+        &self.cuts
+    }
+
+    /// Create an empty workspace
+    pub fn create(&mut self, name: &str) -> Result<&mut ResourceManager, ResourceError> {
+        self.insert(name, ResourceManager::new())
+    }
+
+    /// Add a manager which has already been set up as a workspace. Its cuts are shared with the
+    /// other workspaces, and theirs imported into it.
+    pub fn insert(
+        &mut self,
+        name: &str,
+        mut manager: ResourceManager,
+    ) -> Result<&mut ResourceManager, ResourceError> {
+        if self.managers.contains_key(name) {
+            return Err(ResourceError::DuplicateName(name.to_string()));
+        }
+        manager.share_cuts(&self.cuts)?;
+        Ok(self.managers.entry(name.to_string()).or_insert(manager))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&ResourceManager, ResourceError> {
+        self.managers
+            .get(name)
+            .ok_or(ResourceError::InvalidWorkspace(name.to_string()))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut ResourceManager, ResourceError> {
+        self.managers
+            .get_mut(name)
+            .ok_or(ResourceError::InvalidWorkspace(name.to_string()))
+    }
+
+    /// Take a workspace out, leaving it with its cuts but no longer sharing them
+    pub fn remove(&mut self, name: &str) -> Result<ResourceManager, ResourceError> {
+        let mut manager = self
+            .managers
+            .remove(name)
+            .ok_or(ResourceError::InvalidWorkspace(name.to_string()))?;
+        manager.unshare_cuts();
+        Ok(manager)
+    }
+
+    /// The names of the workspaces, in order
+    pub fn get_names(&self) -> Vec<&str> {
+        self.managers.keys().map(String::as_str).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cut::Cut1D;
+    use crate::prelude::*;
+
+    fn gated_spec(cut_id: Uuid) -> HistSpec {
+        HistSpec {
+            cuts_to_check: vec![cut_id],
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "x_gated",
+                AxisSpec::new("x", "x", 10, 0.0, 10.0).unwrap(),
+                None,
+            )
+        }
+    }
+
+    #[test]
+    fn test_shared_cuts() {
+        let cut = CutSpec {
+            id: Uuid::new_v4(),
+            name: String::from("peak"),
+            x_variable: String::from("x"),
+            y_variable: None,
+        };
+        let mut workspaces = Workspaces::new();
+        let online = workspaces.create("online").unwrap();
+        online
+            .add_cut(Box::new(Cut1D::new(cut.clone(), 0.0, 2.0).unwrap()))
+            .unwrap();
+        let gram_id = online.add_histogram(gated_spec(cut.id)).unwrap();
+        workspaces.create("replay").unwrap();
+        assert!(workspaces.create("online").is_err());
+        assert_eq!(workspaces.get_names(), vec!["online", "replay"]);
+        // The replay imported the online cut when it joined
+        let replay = workspaces.get_mut("replay").unwrap();
+        assert!(replay.get_cut_spec(&cut.id).is_ok());
+
+        // Refined offline, the gate applies to the next online event
+        replay
+            .add_cut(Box::new(Cut1D::new(cut.clone(), 4.0, 6.0).unwrap()))
+            .unwrap();
+        let online = workspaces.get_mut("online").unwrap();
+        let mut event = DataBlob::new();
+        event.insert("x", 5.0);
+        online.update(event).unwrap();
+        assert_eq!(online.get_histogram_data(&gram_id).unwrap()[5], 1.0);
+        assert!(online.sync_cuts().unwrap().is_empty());
+
+        let mut replay = workspaces.remove("replay").unwrap();
+        assert!(replay.get_cut_spec(&cut.id).is_ok());
+        assert!(workspaces.get("replay").is_err());
+        workspaces.get_shared_cuts().remove(&cut.id).unwrap();
+        assert!(replay.sync_cuts().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! Live spectra in an Xamine shared memory region, so that displayers written for SpecTcl, such
+//! as Xamine and CutiePie, can show the histograms of a manager. The region is a System V shared
+//! memory segment keyed by a 4 character name, holding a header describing every binding slot
+//! followed by the channels of the bound spectra as 32 bit counts. Spectra are bound to slots
+//! with the sbind command of a CommandInterpreter.
+use super::error::XamineError;
+use super::histogram::Histogram;
+use super::manager::ResourceManager;
+use uuid::Uuid;
+
+/// The number of binding slots in the header
+pub const MAX_SPECTRA: usize = 10000;
+const TITLE_LENGTH: usize = 72;
+// xmin, xmax, ymin, ymax, then the x and y labels
+const MAP_LENGTH: usize = 16 + 2 * TITLE_LENGTH;
+// The overflows, then the underflows, of x and y
+const STATISTICS_LENGTH: usize = 16;
+
+// The header arrays, each with an entry per slot, in the order of Xamine_shared
+const XY_OFFSET: usize = 0;
+const TITLES_OFFSET: usize = XY_OFFSET + 8 * MAX_SPECTRA;
+const INFO_OFFSET: usize = TITLES_OFFSET + TITLE_LENGTH * MAX_SPECTRA;
+const OFFSETS_OFFSET: usize = INFO_OFFSET + TITLE_LENGTH * MAX_SPECTRA;
+const TYPES_OFFSET: usize = OFFSETS_OFFSET + 4 * MAX_SPECTRA;
+const MAP_OFFSET: usize = TYPES_OFFSET + 4 * MAX_SPECTRA;
+const STATISTICS_OFFSET: usize = MAP_OFFSET + MAP_LENGTH * MAX_SPECTRA;
+/// The bytes of the header, after which the channels of the spectra start
+pub const HEADER_SIZE: usize = STATISTICS_OFFSET + STATISTICS_LENGTH * MAX_SPECTRA;
+
+// The spec_type values of Xamine for spectra of 32 bit channels
+const TYPE_UNDEFINED: u32 = 0;
+const TYPE_1D_LONG: u32 = 4;
+const TYPE_2D_LONG: u32 = 5;
+
+fn write_u32(buffer: &mut [u8], offset: usize, value: u32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+fn write_f32(buffer: &mut [u8], offset: usize, value: f32) {
+    buffer[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+// Write a string as a C string of TITLE_LENGTH bytes, truncating it to fit
+fn write_title(buffer: &mut [u8], offset: usize, title: &str) {
+    let field = &mut buffer[offset..offset + TITLE_LENGTH];
+    field.fill(0);
+    let n_bytes = title.len().min(TITLE_LENGTH - 1);
+    field[..n_bytes].copy_from_slice(&title.as_bytes()[..n_bytes]);
+}
+
+fn to_count(content: f64) -> u32 {
+    content.round().clamp(0.0, u32::MAX as f64) as u32
+}
+
+// Describe a spectrum in its slot and copy its channels from channel offset on
+fn write_spectrum(buffer: &mut [u8], slot: usize, offset: usize, gram: &Histogram) {
+    let (x_bins, y_bins) = gram.get_dimensions();
+    let spec = &gram.spec;
+    write_u32(buffer, XY_OFFSET + 8 * slot, x_bins as u32);
+    // Xamine gives 1D spectra zero y channels
+    let y_chans = if spec.y_axis.is_some() { y_bins } else { 0 };
+    write_u32(buffer, XY_OFFSET + 8 * slot + 4, y_chans as u32);
+    write_title(buffer, TITLES_OFFSET + TITLE_LENGTH * slot, &spec.name);
+    write_title(buffer, INFO_OFFSET + TITLE_LENGTH * slot, &spec.title);
+    write_u32(buffer, OFFSETS_OFFSET + 4 * slot, offset as u32);
+    let kind = match spec.y_axis {
+        Some(_) => TYPE_2D_LONG,
+        None => TYPE_1D_LONG,
+    };
+    write_u32(buffer, TYPES_OFFSET + 4 * slot, kind);
+
+    let map = MAP_OFFSET + MAP_LENGTH * slot;
+    write_f32(buffer, map, spec.x_axis.minimum);
+    write_f32(buffer, map + 4, spec.x_axis.maximum);
+    let (y_minimum, y_maximum) = spec
+        .y_axis
+        .as_ref()
+        .map_or((0.0, 0.0), |axis| (axis.minimum, axis.maximum));
+    write_f32(buffer, map + 8, y_minimum);
+    write_f32(buffer, map + 12, y_maximum);
+    write_title(buffer, map + 16, &spec.x_axis.get_label());
+    let y_label = spec
+        .y_axis
+        .as_ref()
+        .map_or(String::new(), |axis| axis.get_label());
+    write_title(buffer, map + 16 + TITLE_LENGTH, &y_label);
+
+    let overflow = gram.get_overflow();
+    let statistics = STATISTICS_OFFSET + STATISTICS_LENGTH * slot;
+    write_u32(buffer, statistics, overflow.x_overflow as u32);
+    write_u32(buffer, statistics + 4, overflow.y_overflow as u32);
+    write_u32(buffer, statistics + 8, overflow.x_underflow as u32);
+    write_u32(buffer, statistics + 12, overflow.y_underflow as u32);
+
+    // Channels run along x first, whatever the layout of the histogram
+    let channels = HEADER_SIZE + 4 * offset;
+    for y_bin in 0..y_bins {
+        for x_bin in 0..x_bins {
+            let content = gram.get_bin_content(x_bin, y_bin).unwrap_or(0.0);
+            write_u32(
+                buffer,
+                channels + 4 * (y_bin * x_bins + x_bin),
+                to_count(content),
+            );
+        }
+    }
+}
+
+// Describe every slot and copy the bound spectra, packed in slot order. Slots without a spectrum
+// in the manager are left undefined.
+fn write_bindings(
+    buffer: &mut [u8],
+    manager: &ResourceManager,
+    bindings: &[(usize, Uuid)],
+) -> Result<(), XamineError> {
+    let mut grams: Vec<(usize, &Histogram)> = vec![];
+    for (slot, id) in bindings.iter() {
+        if *slot >= MAX_SPECTRA {
+            return Err(XamineError::BadSlot(*slot));
+        }
+        if let Ok(gram) = manager.get_histogram(id) {
+            grams.push((*slot, gram));
+        }
+    }
+    grams.sort_by_key(|(slot, _)| *slot);
+
+    for slot in 0..MAX_SPECTRA {
+        write_u32(buffer, TYPES_OFFSET + 4 * slot, TYPE_UNDEFINED);
+    }
+    let mut offset = 0;
+    for (slot, gram) in grams {
+        let (x_bins, y_bins) = gram.get_dimensions();
+        let n_channels = x_bins * y_bins;
+        if HEADER_SIZE + 4 * (offset + n_channels) > buffer.len() {
+            return Err(XamineError::NoSpace(gram.spec.name.clone()));
+        }
+        write_spectrum(buffer, slot, offset, gram);
+        offset += n_channels;
+    }
+    Ok(())
+}
+
+/// An Xamine shared memory region owned by this process. The segment is removed once the memory
+/// is dropped and every displayer has detached from it.
+#[derive(Debug)]
+pub struct XamineMemory {
+    name: String,
+    id: i32,
+    address: *mut u8,
+    size: usize,
+}
+
+impl XamineMemory {
+    /// Create a region named by 4 ASCII characters, e.g. "XA00", with room for
+    /// spectrum_bytes of channels after the header
+    pub fn create(name: &str, spectrum_bytes: usize) -> Result<Self, XamineError> {
+        let key: [u8; 4] = name
+            .as_bytes()
+            .try_into()
+            .ok()
+            .filter(|_| name.is_ascii())
+            .ok_or_else(|| XamineError::BadName(name.to_string()))?;
+        // SpecTcl makes the key from the bytes of the name as they lie in memory
+        let key = i32::from_ne_bytes(key);
+        let size = HEADER_SIZE + spectrum_bytes;
+        // SAFETY: shmget only reads its arguments
+        let id = unsafe { libc::shmget(key, size, libc::IPC_CREAT | 0o666) };
+        if id < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // SAFETY: the segment has just been created or found, and is attached anywhere
+        let address = unsafe { libc::shmat(id, std::ptr::null(), 0) };
+        if address as isize == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut memory = Self {
+            name: name.to_string(),
+            id,
+            address: address as *mut u8,
+            size,
+        };
+        memory.get_buffer().fill(0);
+        Ok(memory)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_buffer(&mut self) -> &mut [u8] {
+        // SAFETY: the segment is attached at address for size bytes until the memory is dropped
+        unsafe { std::slice::from_raw_parts_mut(self.address, self.size) }
+    }
+
+    /// Copy the spectra bound to slots, as given by CommandInterpreter::get_bindings, into the
+    /// region. Displayers see the new contents on their next refresh.
+    pub fn update(
+        &mut self,
+        manager: &ResourceManager,
+        bindings: &[(usize, Uuid)],
+    ) -> Result<(), XamineError> {
+        write_bindings(self.get_buffer(), manager, bindings)
+    }
+}
+
+impl Drop for XamineMemory {
+    fn drop(&mut self) {
+        // SAFETY: the segment was attached at address by create and is not used after this
+        unsafe {
+            libc::shmdt(self.address as *const libc::c_void);
+            libc::shmctl(self.id, libc::IPC_RMID, std::ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_blob::DataBlob;
+    use crate::histogram::{AxisSpec, HistSpec, OutOfRangePolicy};
+
+    fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes(buffer[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_bindings() {
+        let mut manager = ResourceManager::new();
+        let spec = HistSpec {
+            title: String::from("Silicon energy"),
+            out_of_range: OutOfRangePolicy::Ignore,
+            ..HistSpec::new(
+                "si_e",
+                AxisSpec::new("si_e", "Energy", 4, 0.0, 4.0).unwrap(),
+                None,
+            )
+        };
+        let (si_e, pid) = (spec.id, Uuid::new_v4());
+        manager.add_histogram(spec.clone()).unwrap();
+        manager
+            .add_histogram(HistSpec {
+                id: pid,
+                name: String::from("pid"),
+                y_axis: Some(AxisSpec::new("de", "dE", 2, 0.0, 2.0).unwrap()),
+                ..spec
+            })
+            .unwrap();
+        let mut data = DataBlob::new();
+        data.insert("si_e", 2.5);
+        data.insert("de", 1.5);
+        manager.update(data).unwrap();
+
+        let mut buffer = vec![0; HEADER_SIZE + 4 * 12];
+        write_bindings(&mut buffer, &manager, &[(3, pid), (1, si_e)]).unwrap();
+        assert_eq!(read_u32(&buffer, TYPES_OFFSET + 4), TYPE_1D_LONG);
+        assert_eq!(read_u32(&buffer, TYPES_OFFSET + 12), TYPE_2D_LONG);
+        assert_eq!(read_u32(&buffer, TYPES_OFFSET), TYPE_UNDEFINED);
+        assert_eq!(read_u32(&buffer, XY_OFFSET + 24), 4);
+        assert_eq!(read_u32(&buffer, XY_OFFSET + 28), 2);
+        assert_eq!(&buffer[TITLES_OFFSET + 72..TITLES_OFFSET + 77], b"si_e\0");
+        // si_e takes channels 0 to 3, and pid the 8 after them
+        assert_eq!(read_u32(&buffer, OFFSETS_OFFSET + 12), 4);
+        assert_eq!(read_u32(&buffer, HEADER_SIZE + 4 * 2), 1);
+        assert_eq!(read_u32(&buffer, HEADER_SIZE + 4 * (4 + 4 + 2)), 1);
+
+        let mut small = vec![0; HEADER_SIZE + 4 * 8];
+        assert!(matches!(
+            write_bindings(&mut small, &manager, &[(0, si_e), (1, pid)]),
+            Err(XamineError::NoSpace(_))
+        ));
+        assert!(matches!(
+            XamineMemory::create("XAMINE", 0),
+            Err(XamineError::BadName(_))
+        ));
+    }
+}